@@ -0,0 +1,83 @@
+#ifndef QTEST_H
+#define QTEST_H
+
+/* Generated with cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Opaque handle to a qtest parser and the runtime that drives it
+typedef struct QtestParser QtestParser;
+
+// IRQ event reported by [`qtest_irq_poll`]
+typedef struct QtestIrq {
+  // The line of the IRQ event
+  uintptr_t line;
+  // 1 if the line was raised, 0 if it was lowered
+  int raised;
+} QtestIrq;
+
+// Creates a new parser listening on the given TCP address (e.g. `"localhost:3000"`).
+//
+// Returns NULL on error. The handle must be released with [`qtest_parser_free`].
+QtestParser *qtest_parser_new_tcp(const char *url);
+
+// Creates a new parser listening on the given UNIX socket path.
+//
+// Returns NULL on error. The handle must be released with [`qtest_parser_free`].
+QtestParser *qtest_parser_new_unix(const char *path);
+
+// Waits until QEMU connects to the parser.
+int qtest_parser_attach(QtestParser *handle);
+
+// Releases a parser handle. Passing NULL is a no-op.
+void qtest_parser_free(QtestParser *handle);
+
+// Steps the virtual clock by the given number of nanoseconds.
+// If `ns` is 0, the clock is stepped to the next timer deadline.
+int qtest_clock_step(QtestParser *handle, uint64_t ns);
+
+// Intercepts the input IRQs of the device at the given QOM path.
+int qtest_irq_intercept_in(QtestParser *handle, const char *qom_path);
+
+// Intercepts the output IRQs of the device at the given QOM path.
+int qtest_irq_intercept_out(QtestParser *handle, const char *qom_path);
+
+// Sets the given input IRQ line of the device at the given QOM path to the given level.
+int qtest_set_irq_in(QtestParser *handle,
+                     const char *qom_path,
+                     const char *irq_name,
+                     uintptr_t line,
+                     int level);
+
+// Polls the next pending IRQ event without blocking.
+//
+// Returns 1 and fills `irq` if an event was pending, 0 if there were no pending events,
+// and -1 if the connection was closed.
+int qtest_irq_poll(QtestParser *handle, QtestIrq *irq);
+
+// Reads a value from the given guest address and stores it in `value`.
+int qtest_readb(QtestParser *handle, uint64_t addr, uint8_t *value);
+
+// Writes the given value to the given guest address.
+int qtest_writeb(QtestParser *handle, uint64_t addr, uint8_t value);
+
+// Reads a value from the given guest address and stores it in `value`.
+int qtest_readw(QtestParser *handle, uint64_t addr, uint16_t *value);
+
+// Writes the given value to the given guest address.
+int qtest_writew(QtestParser *handle, uint64_t addr, uint16_t value);
+
+// Reads a value from the given guest address and stores it in `value`.
+int qtest_readl(QtestParser *handle, uint64_t addr, uint32_t *value);
+
+// Writes the given value to the given guest address.
+int qtest_writel(QtestParser *handle, uint64_t addr, uint32_t value);
+
+// Reads a value from the given guest address and stores it in `value`.
+int qtest_readq(QtestParser *handle, uint64_t addr, uint64_t *value);
+
+// Writes the given value to the given guest address.
+int qtest_writeq(QtestParser *handle, uint64_t addr, uint64_t value);
+
+#endif /* QTEST_H */
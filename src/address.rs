@@ -0,0 +1,141 @@
+use std::{collections::HashMap, io};
+
+use crate::loader::Symbol;
+
+/// Guest memory address, either absolute or relative to a symbol of the loaded firmware.
+///
+/// Every memory API of [`crate::parser::Parser`] accepts an `impl Into<Address>`, so plain integers and symbol names
+/// can be used interchangeably. Symbols are resolved with the symbol table of the last ELF file loaded with
+/// [`crate::loader::load_elf`], so tests keep working when the firmware is relinked.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{address::Address, loader::load_elf, parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+/// load_elf(&mut parser, "firmware.elf").await.unwrap();
+///
+/// let flags = parser.readl("driver_flags").await.unwrap();
+/// parser.writeb(Address::symbol("rx_buffer").offset(4), 0x55).await.unwrap();
+/// let id = parser.readl(0xe000_ed00).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// Absolute address
+    Abs(usize),
+    /// Address of the named symbol plus an offset in bytes
+    Symbol(String, usize),
+}
+
+impl Address {
+    /// Returns the address of the given symbol
+    pub fn symbol(name: &str) -> Self {
+        Self::Symbol(name.to_string(), 0)
+    }
+
+    /// Returns this address moved forward by the given number of bytes
+    pub fn offset(self, offset: usize) -> Self {
+        match self {
+            Self::Abs(addr) => Self::Abs(addr + offset),
+            Self::Symbol(name, base) => Self::Symbol(name, base + offset),
+        }
+    }
+}
+
+impl From<usize> for Address {
+    fn from(addr: usize) -> Self {
+        Self::Abs(addr)
+    }
+}
+
+impl From<&str> for Address {
+    fn from(name: &str) -> Self {
+        Self::symbol(name)
+    }
+}
+
+impl From<String> for Address {
+    fn from(name: String) -> Self {
+        Self::Symbol(name, 0)
+    }
+}
+
+/// Symbols of the loaded firmware, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    /// Creates a table with the given symbols. If a name is repeated, the first symbol is kept.
+    pub fn new(symbols: impl IntoIterator<Item = Symbol>) -> Self {
+        let mut table = HashMap::new();
+        for symbol in symbols {
+            table.entry(symbol.name.clone()).or_insert(symbol);
+        }
+        Self { symbols: table }
+    }
+
+    /// Returns the symbol with the given name, if present
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// Returns the number of symbols of the table
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Returns true if the table has no symbols
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Resolves the given address to an absolute address.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the symbol is not in the table.
+    pub fn resolve(&self, addr: &Address) -> io::Result<usize> {
+        match addr {
+            Address::Abs(addr) => Ok(*addr),
+            Address::Symbol(name, offset) => self
+                .get(name)
+                .map(|symbol| symbol.addr as usize + offset)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("Unknown symbol {name}"))
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let symbols = SymbolTable::new([
+            Symbol {
+                name: "rx_buffer".to_string(),
+                addr: 0x2000_0100,
+                size: 64,
+            },
+            Symbol {
+                name: "rx_buffer".to_string(),
+                addr: 0x2000_0200,
+                size: 64,
+            },
+        ]);
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols.resolve(&0x1000.into()).unwrap(), 0x1000);
+        assert_eq!(symbols.resolve(&"rx_buffer".into()).unwrap(), 0x2000_0100);
+        let addr = Address::symbol("rx_buffer").offset(4).offset(2);
+        assert_eq!(symbols.resolve(&addr).unwrap(), 0x2000_0106);
+        assert_eq!(Address::Abs(0x10).offset(1), Address::Abs(0x11));
+        let err = symbols.resolve(&"tx_buffer".into()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,25 @@
+use qtest::{codegen, transcript::Transcript};
+use std::{env, process};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 4 {
+        eprintln!("Usage: {} <transcript> [test_name] [url]", args[0]);
+        process::exit(1);
+    }
+
+    let transcript = match Transcript::load(&args[1]) {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            eprintln!("Could not load transcript {}: {}", args[1], e);
+            process::exit(1);
+        }
+    };
+    let test_name = args
+        .get(2)
+        .map(String::as_str)
+        .unwrap_or("recorded_session");
+    let url = args.get(3).map(String::as_str).unwrap_or("localhost:3000");
+
+    print!("{}", codegen::generate_test(&transcript, test_name, url));
+}
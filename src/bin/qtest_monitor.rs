@@ -0,0 +1,47 @@
+use futures_util::StreamExt;
+use qtest::{event::Event, monitor};
+use std::{env, process};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Capacity of the channel between the WebSocket client and the monitor
+const EVENT_CAPACITY: usize = 1024;
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 2 {
+        eprintln!("Usage: {} [event feed URL]", args[0]);
+        process::exit(1);
+    }
+    let url = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or("ws://localhost:3001");
+
+    let (ws_stream, _) = match tokio_tungstenite::connect_async(url).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("Could not connect to event feed {url}: {e}");
+            process::exit(1);
+        }
+    };
+
+    let (tx, rx) = broadcast::channel(EVENT_CAPACITY);
+    tokio::spawn(async move {
+        let (_, mut ws_rx) = ws_stream.split();
+        while let Some(Ok(msg)) = ws_rx.next().await {
+            if let Message::Text(text) = msg {
+                // Unknown messages are not events of the feed
+                if let Ok(event) = text.as_str().parse::<Event>() {
+                    let _ = tx.send(event);
+                }
+            }
+        }
+    });
+
+    if let Err(e) = monitor::run(rx).await {
+        eprintln!("Monitor error: {e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+use tokio::{runtime::Runtime, sync::mpsc};
+
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::hexdump::HexDump;
+use crate::parser::{LeakReport, Parser, ParserBuilder};
+use crate::socket::Socket;
+use crate::{Endianness, Irq, IrqState, Response};
+
+/// Blocking qtest parser, for test setups that do not use tokio.
+///
+/// It owns a [`Parser`] and an internal runtime that drives it, and exposes its commands as blocking methods.
+/// The IRQ receiver of the parser is kept inside, see [`BlockingParser::recv_irq`].
+/// It must not be used from within an asynchronous context, where the [`Parser`] should be used instead.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # fn example() {
+/// # use qtest::{blocking::BlockingParser, socket::tcp::SocketTcp};
+/// # use std::time::Duration;
+/// let mut parser = BlockingParser::<SocketTcp>::new("localhost:3000").unwrap();
+/// parser.attach_connection().unwrap();
+///
+/// parser.writel(0x4000_0000, 1).unwrap();
+/// parser.clock_step_ns(Some(1_000)).unwrap();
+/// if let Some(irq) = parser.recv_irq(Duration::from_millis(100)).unwrap() {
+///     println!("IRQ: {irq:?}");
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct BlockingParser<T: Socket> {
+    runtime: Runtime,
+    parser: Parser<T>,
+    irq_rx: mpsc::Receiver<Irq>,
+}
+
+impl<T: Socket> BlockingParser<T> {
+    /// Creates a new parser with the given URL, as [`Parser::new`]
+    pub fn new(url: &str) -> Result<Self, QtestError> {
+        Self::with_builder(ParserBuilder::new(url))
+    }
+
+    /// Creates a new parser with the settings of the given builder
+    pub fn with_builder(builder: ParserBuilder<T>) -> Result<Self, QtestError> {
+        let runtime = Runtime::new()?;
+        let (parser, irq_rx) = runtime.block_on(builder.build())?;
+        Ok(Self {
+            runtime,
+            parser,
+            irq_rx,
+        })
+    }
+
+    /// Returns the underlying parser, for its non-blocking methods (e.g. [`Parser::irq_level_watch`])
+    pub fn parser(&mut self) -> &mut Parser<T> {
+        &mut self.parser
+    }
+
+    /// Waits up to the given timeout for the next IRQ event.
+    ///
+    /// Returns `None` if no IRQ event arrived in time, and [`QtestError::ChannelClosed`] if the parser stopped.
+    pub fn recv_irq(&mut self, timeout: Duration) -> Result<Option<Irq>, QtestError> {
+        let irq = self
+            .runtime
+            .block_on(async { tokio::time::timeout(timeout, self.irq_rx.recv()).await });
+        match irq {
+            Ok(Some(irq)) => Ok(Some(irq)),
+            Ok(None) => Err(QtestError::ChannelClosed("IRQ channel is closed".into())),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Returns the next IRQ event if one already arrived, without waiting
+    pub fn try_recv_irq(&mut self) -> Option<Irq> {
+        self.irq_rx.try_recv().ok()
+    }
+
+    /// Closes the session, as [`Parser::close`]
+    pub fn close(self) -> Result<LeakReport, QtestError> {
+        self.runtime.block_on(self.parser.close())
+    }
+}
+
+/// Generates blocking versions of the given parser methods
+macro_rules! impl_blocking {
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        impl<T: Socket> BlockingParser<T> {
+            $(
+                #[doc = concat!("Blocking version of [`Parser::", stringify!($name), "`]")]
+                pub fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, QtestError> {
+                    self.runtime.block_on(self.parser.$name($($arg),*))
+                }
+            )*
+        }
+    };
+}
+
+impl_blocking! {
+    fn attach_connection() -> ();
+    fn reattach_connection() -> ();
+    fn probe() -> ();
+    fn wait_for_irq(line: usize, state: IrqState, timeout: Duration) -> Irq;
+    fn clock_step_ns(ns: Option<usize>) -> usize;
+    fn clock_set(ns: usize) -> usize;
+    fn irq_intercept_in(qom_path: &str) -> Response;
+    fn irq_intercept_out(qom_path: &str) -> Response;
+    fn set_irq_in(qom_path: &str, irq_name: &str, line: usize, level: isize) -> Response;
+    fn inb(addr: impl Into<Address>) -> u8;
+    fn inw(addr: impl Into<Address>) -> u16;
+    fn inl(addr: impl Into<Address>) -> u32;
+    fn outb(addr: impl Into<Address>, val: u8) -> Response;
+    fn outw(addr: impl Into<Address>, val: u16) -> Response;
+    fn outl(addr: impl Into<Address>, val: u32) -> Response;
+    fn readb(addr: impl Into<Address>) -> u8;
+    fn readw(addr: impl Into<Address>) -> u16;
+    fn readl(addr: impl Into<Address>) -> u32;
+    fn readq(addr: impl Into<Address>) -> u64;
+    fn writeb(addr: impl Into<Address>, val: u8) -> Response;
+    fn writew(addr: impl Into<Address>, val: u16) -> Response;
+    fn writel(addr: impl Into<Address>, val: u32) -> Response;
+    fn writeq(addr: impl Into<Address>, val: u64) -> Response;
+    fn poll_until(
+        addr: impl Into<Address>,
+        mask: u32,
+        expected: u32,
+        interval: Duration,
+        clock_step: Option<usize>,
+        timeout: Duration
+    ) -> u32;
+    fn endianness() -> Endianness;
+    fn guest_endianness() -> Endianness;
+    fn read(addr: impl Into<Address>, size: usize) -> String;
+    fn write(addr: impl Into<Address>, data: &str, data_len: Option<usize>) -> Response;
+    fn read_bytes(addr: impl Into<Address>, len: usize) -> Vec<u8>;
+    fn dump(addr: impl Into<Address>, len: usize) -> HexDump;
+    fn write_bytes(addr: impl Into<Address>, data: &[u8]) -> ();
+    fn memset(addr: impl Into<Address>, size: usize, pattern_byte: u8) -> ();
+    fn read_uint(addr: impl Into<Address>, bytes: usize, endianness: Endianness) -> u128;
+    fn write_uint(addr: impl Into<Address>, bytes: usize, val: u128, endianness: Endianness) -> ();
+}
+
+#[cfg(feature = "base64")]
+impl_blocking! {
+    fn b64read(addr: impl Into<Address>, len: usize) -> Vec<u8>;
+    fn b64write(addr: impl Into<Address>, data: &str) -> Response;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::dry_run::SocketDryRun;
+
+    #[test]
+    fn test_blocking_parser() {
+        let mut parser = BlockingParser::<SocketDryRun>::new("dry-run").unwrap();
+        parser.attach_connection().unwrap();
+        assert_eq!(parser.writel(0x0, 1).unwrap(), Response::Ok);
+        assert_eq!(parser.readl(0x0).unwrap(), 0);
+        assert_eq!(parser.read_bytes(0x0, 2).unwrap(), [0, 0]);
+        assert_eq!(parser.recv_irq(Duration::from_millis(1)).unwrap(), None);
+        assert_eq!(parser.try_recv_irq(), None);
+        parser.close().unwrap();
+    }
+}
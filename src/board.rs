@@ -0,0 +1,161 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::region::MemRegion;
+
+/// Description of a board: the peripherals of the emulated machine and where they live.
+///
+/// Test code refers to peripherals by name instead of hard-coding their addresses, so one suite
+/// can run against several board variants by loading a different description file. For example:
+///
+/// ```toml
+/// name = "netduino2"
+///
+/// [peripherals.uart0]
+/// base = 0x4001_1000
+/// size = 0x400
+/// irqs = [37]
+/// intercept_out = "/machine/soc/usart[0]"
+///
+/// [peripherals.gpioa]
+/// base = 0x4002_0000
+/// intercept_in = "/machine/soc/gpio[a]"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Board {
+    /// Name of the board, if any
+    pub name: Option<String>,
+    /// Peripherals of the board, by name
+    pub peripherals: BTreeMap<String, Peripheral>,
+}
+
+impl Board {
+    /// Reads the board description from the given TOML file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Returns the peripheral with the given name, if any
+    pub fn peripheral(&self, name: &str) -> Option<&Peripheral> {
+        self.peripherals.get(name)
+    }
+
+    /// Returns the QOM paths whose input IRQs are intercepted, in peripheral name order
+    pub fn intercepts_in(&self) -> impl Iterator<Item = &str> {
+        self.peripherals
+            .values()
+            .filter_map(|p| p.intercept_in.as_deref())
+    }
+
+    /// Returns the QOM paths whose output IRQs are intercepted, in peripheral name order
+    pub fn intercepts_out(&self) -> impl Iterator<Item = &str> {
+        self.peripherals
+            .values()
+            .filter_map(|p| p.intercept_out.as_deref())
+    }
+}
+
+impl std::str::FromStr for Board {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Peripheral of a board
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Peripheral {
+    /// Base address of the registers of the peripheral
+    pub base: usize,
+    /// Size in bytes of the register block, if known
+    #[serde(default)]
+    pub size: Option<usize>,
+    /// IRQ lines of the peripheral, as numbered by QEMU in its IRQ events
+    #[serde(default)]
+    pub irqs: Vec<usize>,
+    /// QOM path whose input IRQs are intercepted, if any
+    #[serde(default)]
+    pub intercept_in: Option<String>,
+    /// QOM path whose output IRQs are intercepted, if any
+    #[serde(default)]
+    pub intercept_out: Option<String>,
+}
+
+impl Peripheral {
+    /// Returns the address of the register at the given offset from the base address.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the offset is out of the register block.
+    pub fn addr(&self, offset: usize) -> io::Result<usize> {
+        match self.size {
+            Some(size) if offset >= size => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Offset {offset:#x} is out of the {size:#x} bytes register block"),
+            )),
+            _ => Ok(self.base + offset),
+        }
+    }
+
+    /// Returns the register block of the peripheral, if its size is known
+    pub fn region(&self) -> Option<MemRegion> {
+        self.size.map(|size| MemRegion::new(self.base, size))
+    }
+
+    /// Returns the first IRQ line of the peripheral, if any
+    pub fn irq(&self) -> Option<usize> {
+        self.irqs.first().copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_board_from_str() {
+        let board: Board = r#"
+            name = "netduino2"
+
+            [peripherals.uart0]
+            base = 0x4001_1000
+            size = 0x400
+            irqs = [37]
+            intercept_out = "/machine/soc/usart[0]"
+
+            [peripherals.gpioa]
+            base = 0x4002_0000
+            intercept_in = "/machine/soc/gpio[a]"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(board.name.as_deref(), Some("netduino2"));
+        let uart = board.peripheral("uart0").unwrap();
+        assert_eq!(uart.base, 0x4001_1000);
+        assert_eq!(uart.addr(0x4).unwrap(), 0x4001_1004);
+        assert_eq!(
+            uart.addr(0x400).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(uart.irq(), Some(37));
+        assert_eq!(uart.region(), Some(MemRegion::new(0x4001_1000, 0x400)));
+        let gpio = board.peripheral("gpioa").unwrap();
+        assert_eq!(gpio.addr(0x1000).unwrap(), 0x4002_1000);
+        assert_eq!(gpio.irq(), None);
+        assert_eq!(gpio.region(), None);
+        assert!(board.peripheral("spi0").is_none());
+
+        assert_eq!(
+            board.intercepts_in().collect::<Vec<_>>(),
+            ["/machine/soc/gpio[a]"]
+        );
+        assert_eq!(
+            board.intercepts_out().collect::<Vec<_>>(),
+            ["/machine/soc/usart[0]"]
+        );
+
+        assert!("[peripherals.uart0]\nsize = 4".parse::<Board>().is_err());
+    }
+}
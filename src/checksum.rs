@@ -0,0 +1,248 @@
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::socket::Socket;
+use crate::transfer::{read_chunked, CHUNK_SIZE};
+
+/// Incremental CRC-32 (IEEE 802.3) hasher
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    /// Creates a new hasher
+    pub fn new() -> Self {
+        Self { crc: 0xffff_ffff }
+    }
+
+    /// Feeds the given bytes to the hasher
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.crc & 1).wrapping_neg();
+                self.crc = (self.crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+
+    /// Returns the CRC-32 of the bytes fed so far
+    pub fn finalize(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SHA-256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Incremental SHA-256 hasher
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    /// Creates a new hasher
+    pub fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Feeds the given bytes to the hasher
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Returns the SHA-256 digest of the bytes fed so far
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Processes the current block
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the given region of guest memory in chunks, feeding each chunk to `update`
+async fn digest_region<T: Socket, F: FnMut(&[u8])>(
+    parser: &mut Parser<T>,
+    addr: usize,
+    len: usize,
+    mut update: F,
+) -> Result<(), QtestError> {
+    let mut done = 0;
+    while done < len {
+        let chunk_len = CHUNK_SIZE.min(len - done);
+        let chunk = read_chunked(parser, addr + done, chunk_len, |_, _| {}).await?;
+        update(&chunk);
+        done += chunk_len;
+    }
+    Ok(())
+}
+
+/// Computes the CRC-32 of the given region of guest memory, reading it back in chunks
+pub async fn crc32<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+) -> Result<u32, QtestError> {
+    let addr = parser.resolve(addr)?;
+    let mut hasher = Crc32::new();
+    digest_region(parser, addr, len, |chunk| hasher.update(chunk)).await?;
+    Ok(hasher.finalize())
+}
+
+/// Returns whether the CRC-32 of the given region of guest memory matches the expected value
+pub async fn verify_crc32<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+    expected: u32,
+) -> Result<bool, QtestError> {
+    Ok(crc32(parser, addr, len).await? == expected)
+}
+
+/// Computes the SHA-256 digest of the given region of guest memory, reading it back in chunks
+pub async fn sha256<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+) -> Result<[u8; 32], QtestError> {
+    let addr = parser.resolve(addr)?;
+    let mut hasher = Sha256::new();
+    digest_region(parser, addr, len, |chunk| hasher.update(chunk)).await?;
+    Ok(hasher.finalize())
+}
+
+/// Returns whether the SHA-256 digest of the given region of guest memory matches the expected value
+pub async fn verify_sha256<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+    expected: &[u8; 32],
+) -> Result<bool, QtestError> {
+    Ok(&sha256(parser, addr, len).await? == expected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        let mut hasher = Crc32::new();
+        hasher.update(b"12345");
+        hasher.update(b"6789");
+        assert_eq!(hasher.finalize(), 0xcbf4_3926);
+        assert_eq!(Crc32::new().finalize(), 0);
+    }
+
+    #[test]
+    fn test_sha256() {
+        let hex =
+            |digest: [u8; 32]| -> String { digest.iter().map(|b| format!("{b:02x}")).collect() };
+
+        assert_eq!(
+            hex(Sha256::new().finalize()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"abcdbcdecdefdefgefghfghighijhijkijkl");
+        hasher.update(b"jklmklmnlmnomnopnopq");
+        assert_eq!(
+            hex(hasher.finalize()),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
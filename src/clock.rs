@@ -0,0 +1,225 @@
+use std::{future::Future, io, ops::Add, pin::Pin, time::Duration};
+
+use crate::error::QtestError;
+use crate::icount::Icount;
+use crate::parser::Parser;
+use crate::socket::Socket;
+
+mod driver;
+
+pub use driver::ClockDriver;
+
+/// Point in the virtual time of the guest, measured from the start of the virtual clock.
+///
+/// Unlike [`std::time::Instant`], it is only advanced by the clock operations of [`ClockController`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualInstant(Duration);
+
+impl VirtualInstant {
+    /// Start of the virtual clock
+    pub const ZERO: Self = Self(Duration::ZERO);
+
+    /// Returns the instant at the given virtual time in nanoseconds
+    pub fn from_nanos(ns: usize) -> Self {
+        Self(Duration::from_nanos(ns as u64))
+    }
+
+    /// Returns the virtual time of the instant in nanoseconds
+    pub fn as_nanos(&self) -> usize {
+        self.0.as_nanos() as usize
+    }
+
+    /// Returns the virtual time elapsed since the start of the virtual clock
+    pub fn since_start(&self) -> Duration {
+        self.0
+    }
+
+    /// Returns the virtual time elapsed since the given instant, or zero if it is later
+    pub fn duration_since(&self, earlier: VirtualInstant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<Duration> for VirtualInstant {
+    type Output = Self;
+
+    fn add(self, duration: Duration) -> Self {
+        Self(self.0 + duration)
+    }
+}
+
+/// Future returned by the condition checked by [`ClockController::run_for`] after every step
+pub type PollFuture<'p> = Pin<Box<dyn Future<Output = Result<bool, QtestError>> + 'p>>;
+
+/// Converts the given duration to nanoseconds of virtual time
+fn duration_to_ns(duration: Duration) -> Result<usize, QtestError> {
+    usize::try_from(duration.as_nanos()).map_err(|_| {
+        QtestError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Duration too long for the virtual clock: {duration:?}"),
+        ))
+    })
+}
+
+/// Controller of the virtual clock of the guest, returned by [`Parser::clock`].
+///
+/// All virtual-time operations should go through the controller, which keeps the last value
+/// reported by QEMU and checks that virtual time never goes backwards.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let mut clock = parser.clock();
+/// clock.advance(1_000_000).await.unwrap();
+/// let now = clock.advance_to(5_000_000).await.unwrap();
+/// assert_eq!(clock.now().await.unwrap(), now);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ClockController<'a, T: Socket> {
+    parser: &'a mut Parser<T>,
+}
+
+impl<'a, T: Socket> ClockController<'a, T> {
+    pub(crate) fn new(parser: &'a mut Parser<T>) -> Self {
+        Self { parser }
+    }
+
+    /// Returns the instruction counting settings QEMU runs with, if known
+    pub fn icount(&self) -> Option<Icount> {
+        self.parser.icount()
+    }
+
+    /// Returns true if QEMU runs with deterministic instruction counting (fixed shift, `sleep=off`, `align=off`),
+    /// so the same sequence of clock operations produces the same virtual timeline on every run
+    pub fn is_deterministic(&self) -> bool {
+        self.icount()
+            .is_some_and(|icount| icount.is_deterministic())
+    }
+
+    /// Returns the virtual time that the given number of guest instructions take, if the icount shift is fixed
+    pub fn insns_to_ns(&self, insns: usize) -> Option<usize> {
+        let ns_per_insn = self.icount()?.ns_per_insn()? as usize;
+        Some(insns * ns_per_insn)
+    }
+
+    /// Returns the current virtual time in nanoseconds.
+    ///
+    /// The value is only queried to QEMU if no clock command has been sent yet.
+    pub async fn now(&mut self) -> Result<usize, QtestError> {
+        match self.parser.cached_clock() {
+            Some(ns) => Ok(ns),
+            None => self.advance(0).await,
+        }
+    }
+
+    /// Advances the virtual clock by the given number of nanoseconds, returns the new virtual time
+    pub async fn advance(&mut self, ns: usize) -> Result<usize, QtestError> {
+        let before = self.parser.cached_clock();
+        let now = self.parser.clock_step_ns(Some(ns)).await?;
+        check_monotonic(before, now)?;
+        Ok(now)
+    }
+
+    /// Advances the virtual clock up to the given time in nanoseconds, returns the new virtual time.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the given time is in the past.
+    pub async fn advance_to(&mut self, ns: usize) -> Result<usize, QtestError> {
+        let before = self.now().await?;
+        if ns < before {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot move the clock back from {before} ns to {ns} ns"),
+            )));
+        }
+        let now = self.parser.clock_set(ns).await?;
+        check_monotonic(Some(before), now)?;
+        Ok(now)
+    }
+
+    /// Returns the current virtual time, as [`ClockController::now`]
+    pub async fn instant(&mut self) -> Result<VirtualInstant, QtestError> {
+        self.now().await.map(VirtualInstant::from_nanos)
+    }
+
+    /// Advances the virtual clock by the given duration, returns the new virtual time
+    pub async fn advance_by(&mut self, duration: Duration) -> Result<VirtualInstant, QtestError> {
+        let ns = duration_to_ns(duration)?;
+        self.advance(ns).await.map(VirtualInstant::from_nanos)
+    }
+
+    /// Advances the virtual clock up to the given instant, returns the new virtual time, as [`ClockController::advance_to`]
+    pub async fn advance_until(
+        &mut self,
+        instant: VirtualInstant,
+    ) -> Result<VirtualInstant, QtestError> {
+        let ns = duration_to_ns(instant.since_start())?;
+        self.advance_to(ns).await.map(VirtualInstant::from_nanos)
+    }
+
+    /// Runs the guest for the given virtual duration in steps of `step`, until `poll` returns true.
+    ///
+    /// `poll` is called after every step with the parser, e.g. to check a status register, and the virtual time.
+    /// The last step is shortened so the clock does not go past the end. Returns the virtual time when it stopped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "tcp")]
+    /// # async fn example() {
+    /// # use qtest::{parser::Parser, socket::tcp::SocketTcp};
+    /// # use std::time::Duration;
+    /// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+    /// parser.attach_connection().await.unwrap();
+    ///
+    /// let mut clock = parser.clock();
+    /// let ready = clock
+    ///     .run_for(Duration::from_millis(10), Duration::from_micros(100), |parser, _| {
+    ///         Box::pin(async move { Ok(parser.readl(0x4000_0000).await? & 1 != 0) })
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn run_for<F>(
+        &mut self,
+        duration: Duration,
+        step: Duration,
+        mut poll: F,
+    ) -> Result<VirtualInstant, QtestError>
+    where
+        F: for<'p> FnMut(&'p mut Parser<T>, VirtualInstant) -> PollFuture<'p>,
+    {
+        if step.is_zero() {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The step must not be zero",
+            )));
+        }
+        let end = self.instant().await? + duration;
+        let mut now = self.instant().await?;
+        while now < end {
+            now = self.advance_by(step.min(end.duration_since(now))).await?;
+            if poll(self.parser, now).await? {
+                break;
+            }
+        }
+        Ok(now)
+    }
+}
+
+/// Checks that the virtual time reported by QEMU did not go backwards
+fn check_monotonic(before: Option<usize>, now: usize) -> Result<(), QtestError> {
+    match before {
+        Some(before) if now < before => Err(QtestError::Protocol(format!(
+            "Virtual clock went backwards from {before} ns to {now} ns"
+        ))),
+        _ => Ok(()),
+    }
+}
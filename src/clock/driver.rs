@@ -0,0 +1,379 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{watch, Mutex, Notify},
+    task::JoinHandle,
+    time::{Interval, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::QtestError;
+use crate::logging::log_error;
+use crate::parser::Parser;
+use crate::socket::Socket;
+
+/// How a [`ClockDriver`] advances the virtual clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Advance by `quantum_ns` every `period` of host time
+    FreeRunning { quantum_ns: usize, period: Duration },
+    /// Advance every `period` to keep virtual time in lockstep with host time, scaled by `scale`
+    WallClock { scale: f64, period: Duration },
+    /// Advance only to wake up sleeping tasks
+    OnDemand,
+}
+
+/// Background task that advances the virtual clock of the guest.
+///
+/// In free-running mode ([`ClockDriver::spawn`]), the clock advances by a fixed quantum at a fixed host rate,
+/// so interactive sessions behave like a live machine instead of a frozen one. In on-demand mode
+/// ([`ClockDriver::spawn_on_demand`]), the clock only advances to wake up the tasks sleeping on virtual time.
+/// In wall-clock mode ([`ClockDriver::spawn_wall_clock`]), virtual time is kept in lockstep with host time,
+/// which is useful for demos and for tests that interact with real external systems.
+///
+/// Tasks can wait for virtual time with [`ClockDriver::sleep`] and [`ClockDriver::sleep_until`].
+/// With several sleeping tasks, the driver steps to the earliest deadline first, so every task wakes up
+/// at its exact deadline. The parser is shared with the task, so other commands can still be sent by locking it.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{clock::ClockDriver, parser::Parser, socket::tcp::SocketTcp};
+/// # use std::{sync::Arc, time::Duration};
+/// # use tokio::sync::Mutex;
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+/// let parser = Arc::new(Mutex::new(parser));
+///
+/// // 1 ms of virtual time every 10 ms of host time
+/// let driver = ClockDriver::spawn(parser.clone(), 1_000_000, Duration::from_millis(10));
+/// let val = parser.lock().await.readl(0x4000_0000).await.unwrap();
+/// driver.pause();
+/// driver.stop().await.unwrap();
+///
+/// // Virtual time only advances while tasks are sleeping
+/// let driver = ClockDriver::spawn_on_demand(parser.clone());
+/// tokio::join!(driver.sleep(1_000), driver.sleep(5_000));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ClockDriver {
+    paused: watch::Sender<bool>,
+    cancel: CancellationToken,
+    timers: Arc<Timers>,
+    now: watch::Receiver<Option<usize>>,
+    task: JoinHandle<Result<(), QtestError>>,
+}
+
+impl ClockDriver {
+    /// Spawns a task that advances the virtual clock by `quantum_ns` nanoseconds every `period` of host time
+    pub fn spawn<T: Socket + Send + 'static>(
+        parser: Arc<Mutex<Parser<T>>>,
+        quantum_ns: usize,
+        period: Duration,
+    ) -> Self {
+        Self::spawn_mode(parser, Mode::FreeRunning { quantum_ns, period })
+    }
+
+    /// Spawns a task that keeps virtual time in lockstep with host time, checking every `period` of host time.
+    ///
+    /// Every nanosecond of host time advances the virtual clock by `scale` nanoseconds,
+    /// e.g. `0.5` runs the guest at half speed. The time spent paused is not caught up on resume.
+    pub fn spawn_wall_clock<T: Socket + Send + 'static>(
+        parser: Arc<Mutex<Parser<T>>>,
+        scale: f64,
+        period: Duration,
+    ) -> Self {
+        Self::spawn_mode(parser, Mode::WallClock { scale, period })
+    }
+
+    /// Spawns a task that only advances the virtual clock to the earliest deadline of the sleeping tasks
+    pub fn spawn_on_demand<T: Socket + Send + 'static>(parser: Arc<Mutex<Parser<T>>>) -> Self {
+        Self::spawn_mode(parser, Mode::OnDemand)
+    }
+
+    fn spawn_mode<T: Socket + Send + 'static>(parser: Arc<Mutex<Parser<T>>>, mode: Mode) -> Self {
+        let (paused, paused_rx) = watch::channel(false);
+        let (now_tx, now) = watch::channel(None);
+        let cancel = CancellationToken::new();
+        let timers = Arc::new(Timers::default());
+        let task = tokio::spawn(run(
+            parser,
+            mode,
+            timers.clone(),
+            now_tx,
+            paused_rx,
+            cancel.clone(),
+        ));
+        Self {
+            paused,
+            cancel,
+            timers,
+            now,
+            task,
+        }
+    }
+
+    /// Pauses the driver. The virtual clock is frozen until [`ClockDriver::resume`] is called.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Resumes a paused driver
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Returns true if the driver is paused
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns the virtual time in nanoseconds after the last step of the driver.
+    ///
+    /// Returns `None` until the driver has read the virtual time for the first time.
+    pub fn now(&self) -> Option<usize> {
+        *self.now.borrow()
+    }
+
+    /// Waits until the driver has advanced the virtual clock by the given number of nanoseconds
+    pub async fn sleep(&self, ns: usize) -> Result<(), QtestError> {
+        let now = *self
+            .now
+            .clone()
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| stopped())?;
+        self.sleep_until(now.unwrap_or_default() + ns).await
+    }
+
+    /// Waits until the driver has advanced the virtual clock to the given time in nanoseconds.
+    ///
+    /// Returns an error if the driver stops before reaching the deadline.
+    pub async fn sleep_until(&self, ns: usize) -> Result<(), QtestError> {
+        let _deadline = self.timers.register(ns);
+        self.now
+            .clone()
+            .wait_for(|now| now.is_some_and(|now| now >= ns))
+            .await
+            .map(|_| ())
+            .map_err(|_| stopped())
+    }
+
+    /// Stops the driver. Returns the error that stopped it earlier, if any.
+    pub async fn stop(self) -> Result<(), QtestError> {
+        self.cancel.cancel();
+        self.task
+            .await
+            .map_err(|e| QtestError::Io(io::Error::other(e)))?
+    }
+}
+
+/// Error returned to sleeping tasks when the driver stops
+fn stopped() -> QtestError {
+    QtestError::ChannelClosed("Clock driver stopped before reaching the deadline".into())
+}
+
+/// Deadlines of the tasks sleeping on virtual time
+#[derive(Debug, Default)]
+struct Timers {
+    /// Number of sleeping tasks per deadline
+    deadlines: StdMutex<BTreeMap<usize, usize>>,
+    /// Wakes up the driver when a new deadline is registered
+    registered: Notify,
+}
+
+impl Timers {
+    /// Registers a deadline until the returned guard is dropped
+    fn register(&self, ns: usize) -> Deadline<'_> {
+        *self.deadlines.lock().unwrap().entry(ns).or_default() += 1;
+        self.registered.notify_one();
+        Deadline { timers: self, ns }
+    }
+
+    /// Returns the earliest deadline after the given time
+    fn next_after(&self, now: usize) -> Option<usize> {
+        let deadlines = self.deadlines.lock().unwrap();
+        deadlines.range(now + 1..).next().map(|(ns, _)| *ns)
+    }
+}
+
+/// Deadline of a sleeping task, unregistered on drop
+struct Deadline<'a> {
+    timers: &'a Timers,
+    ns: usize,
+}
+
+impl Drop for Deadline<'_> {
+    fn drop(&mut self) {
+        let mut deadlines = self.timers.deadlines.lock().unwrap();
+        if let Some(count) = deadlines.get_mut(&self.ns) {
+            *count -= 1;
+            if *count == 0 {
+                deadlines.remove(&self.ns);
+            }
+        }
+    }
+}
+
+/// Host and virtual time at which the wall-clock mode started tracking host time
+#[derive(Debug, Clone, Copy)]
+struct Anchor {
+    host: Instant,
+    virtual_ns: usize,
+}
+
+impl Anchor {
+    fn new(virtual_ns: usize) -> Self {
+        Self {
+            host: Instant::now(),
+            virtual_ns,
+        }
+    }
+
+    /// Returns the virtual time corresponding to the current host time
+    fn target(&self, scale: f64) -> usize {
+        let elapsed = self.host.elapsed().as_nanos() as f64 * scale;
+        self.virtual_ns + elapsed as usize
+    }
+}
+
+/// Body of the driver task
+async fn run<T: Socket>(
+    parser: Arc<Mutex<Parser<T>>>,
+    mode: Mode,
+    timers: Arc<Timers>,
+    now: watch::Sender<Option<usize>>,
+    mut paused: watch::Receiver<bool>,
+    cancel: CancellationToken,
+) -> Result<(), QtestError> {
+    let current = parser.lock().await.clock().now().await?;
+    now.send_replace(Some(current));
+    let mut anchor = Anchor::new(current);
+
+    let mut interval = match mode {
+        Mode::FreeRunning { period, .. } | Mode::WallClock { period, .. } => {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Some(interval)
+        }
+        Mode::OnDemand => None,
+    };
+    loop {
+        let was_paused = *paused.borrow();
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            // The driver was dropped without being stopped
+            res = paused.wait_for(|paused| !paused) => if res.is_err() {
+                return Ok(());
+            }
+        }
+        if was_paused {
+            // The time spent paused is not caught up
+            anchor = Anchor::new(now.borrow().unwrap_or_default());
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = next_step(&mut interval, &timers, &now) => {}
+        }
+        if *paused.borrow() {
+            continue;
+        }
+        if let Err(e) = step(&parser, mode, &anchor, &timers, &now).await {
+            log_error!(target: "qtest::clock", "clock driver stopped: {e}");
+            return Err(e);
+        }
+    }
+}
+
+/// Waits for the next tick in periodic modes, or for a future deadline in on-demand mode
+async fn next_step(
+    interval: &mut Option<Interval>,
+    timers: &Timers,
+    now: &watch::Sender<Option<usize>>,
+) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => {
+            while timers
+                .next_after(now.borrow().unwrap_or_default())
+                .is_none()
+            {
+                timers.registered.notified().await;
+            }
+        }
+    }
+}
+
+/// Advances the virtual clock to the target of the mode, stopping at every deadline on the way
+async fn step<T: Socket>(
+    parser: &Mutex<Parser<T>>,
+    mode: Mode,
+    anchor: &Anchor,
+    timers: &Timers,
+    now: &watch::Sender<Option<usize>>,
+) -> Result<(), QtestError> {
+    let mut parser = parser.lock().await;
+    let mut clock = parser.clock();
+    let mut current = clock.now().await?;
+    let target = match mode {
+        Mode::FreeRunning { quantum_ns, .. } => current + quantum_ns,
+        Mode::WallClock { scale, .. } => anchor.target(scale),
+        Mode::OnDemand => timers.next_after(current).unwrap_or(current),
+    };
+    loop {
+        let next = timers
+            .next_after(current)
+            .filter(|deadline| *deadline < target)
+            .unwrap_or(target);
+        if next > current {
+            current = clock.advance(next - current).await?;
+        }
+        now.send_replace(Some(current));
+        if current >= target {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_anchor_target() {
+        let anchor = Anchor::new(1_000);
+        assert_eq!(anchor.target(0.0), 1_000);
+
+        std::thread::sleep(Duration::from_millis(10));
+        let half = anchor.target(0.5);
+        let elapsed = anchor.host.elapsed().as_nanos() as usize;
+        assert!(half >= 1_000 + 5_000_000);
+        assert!(half <= 1_000 + elapsed / 2 + 1);
+    }
+
+    #[test]
+    fn test_timers() {
+        let timers = Timers::default();
+        let late = timers.register(300);
+        let early = timers.register(100);
+        let again = timers.register(100);
+        assert_eq!(timers.next_after(0), Some(100));
+        assert_eq!(timers.next_after(100), Some(300));
+
+        drop(early);
+        assert_eq!(timers.next_after(0), Some(100));
+        drop(again);
+        assert_eq!(timers.next_after(0), Some(300));
+        drop(late);
+        assert_eq!(timers.next_after(0), None);
+    }
+}
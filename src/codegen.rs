@@ -0,0 +1,227 @@
+#[cfg(feature = "base64")]
+use base64::Engine;
+use std::fmt::Write;
+
+use crate::event::EventKind;
+use crate::transcript::Transcript;
+use crate::{IrqState, Response};
+
+/// Generates the source code of a Rust regression test that reproduces the given transcript.
+///
+/// Every recorded command is translated to the corresponding [`crate::parser::Parser`] call, with an
+/// assertion on the recorded response. A command is paired with the next recorded response, even when IRQs or
+/// notifications were recorded in between. Every recorded IRQ is translated to an assertion on the IRQ channel.
+/// Commands that have no equivalent in the parser API are kept as comments.
+///
+/// The generated test listens on `url` and waits for QEMU to connect before replaying the session.
+pub fn generate_test(transcript: &Transcript, test_name: &str, url: &str) -> String {
+    let mut body = String::new();
+    let events = &transcript.events;
+    let mut paired = vec![false; events.len()];
+
+    for (i, event) in events.iter().enumerate() {
+        if paired[i] {
+            continue;
+        }
+        match &event.kind {
+            EventKind::Command(cmd) => {
+                // The reply may come after IRQs and notifications, but never after the next command
+                let response = events[i + 1..]
+                    .iter()
+                    .enumerate()
+                    .take_while(|(_, e)| !matches!(e.kind, EventKind::Command(_)))
+                    .find_map(|(j, e)| match &e.kind {
+                        EventKind::Response(response) => Some((i + 1 + j, response)),
+                        _ => None,
+                    });
+                if let Some((j, _)) = response {
+                    paired[j] = true;
+                }
+                body.push_str(&command_statement(cmd, response.map(|(_, r)| r)));
+            }
+            EventKind::Irq(irq) => {
+                let state = match irq.state {
+                    IrqState::Raise => "IrqState::Raise",
+                    IrqState::Lower => "IrqState::Lower",
+                };
+                let _ = writeln!(
+                    body,
+                    "    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new({}, {state}));",
+                    irq.line
+                );
+            }
+            EventKind::Response(response) => {
+                let _ = writeln!(body, "    // unexpected response: {response}");
+            }
+            EventKind::Connected => {
+                body.push_str("    parser.attach_connection().await.unwrap();\n");
+            }
+            EventKind::Notification(notification) => {
+                let _ = writeln!(body, "    // notification: {notification}");
+            }
+        }
+    }
+
+    format!(
+        r#"use qtest::{{parser::Parser, socket::tcp::SocketTcp, Irq, IrqState, Response}};
+
+#[tokio::test]
+#[allow(unused_mut, unused_variables, unused_imports)]
+async fn {test_name}() {{
+    let (mut parser, mut irq_rx) = Parser::<SocketTcp>::new("{url}").await.unwrap();
+    parser.attach_connection().await.unwrap();
+
+{body}}}
+"#
+    )
+}
+
+/// Translates a recorded command and its response into a statement of the generated test
+fn command_statement(cmd: &str, response: Option<&Response>) -> String {
+    let args: Vec<&str> = cmd.split_whitespace().collect();
+    let Some((verb, args)) = args.split_first() else {
+        return String::new();
+    };
+
+    let call = match (*verb, args) {
+        ("irq_intercept_in" | "irq_intercept_out", [path]) => {
+            format!("parser.{verb}({path:?})")
+        }
+        ("set_irq_in", [path, name, line, level]) => {
+            format!("parser.set_irq_in({path:?}, {name:?}, {line}, {level})")
+        }
+        ("outb" | "outw" | "outl" | "writeb" | "writew" | "writel" | "writeq", [addr, val]) => {
+            format!("parser.{verb}({addr}, {val})")
+        }
+        ("write", [addr, size, data]) => format!("parser.write({addr}, {data:?}, Some({size}))"),
+        ("memset", [addr, size, val]) => {
+            return match response {
+                Some(Response::Ok) => {
+                    format!("    parser.memset({addr}, {size}, {val}).await.unwrap();\n")
+                }
+                _ => format!("    assert!(parser.memset({addr}, {size}, {val}).await.is_err());\n"),
+            };
+        }
+        #[cfg(feature = "base64")]
+        ("b64write", [addr, _, data]) => {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok());
+            match decoded {
+                Some(decoded) => format!("parser.b64write({addr}, {decoded:?})"),
+                None => return format!("    // unsupported command: {cmd}\n"),
+            }
+        }
+        // Commands returning typed values
+        ("clock_step", []) => return value_statement("parser.clock_step_ns(None)", response),
+        ("clock_step", [ns]) => {
+            return value_statement(&format!("parser.clock_step_ns(Some({ns}))"), response)
+        }
+        ("clock_set", [ns]) => {
+            return value_statement(&format!("parser.clock_set({ns})"), response)
+        }
+        ("inb" | "inw" | "inl" | "readb" | "readw" | "readl" | "readq", [addr]) => {
+            return value_statement(&format!("parser.{verb}({addr})"), response)
+        }
+        ("read", [addr, size]) => {
+            let call = format!("parser.read({addr}, {size})");
+            return match response {
+                Some(Response::OkVal(val)) => {
+                    format!("    assert_eq!({call}.await.unwrap(), {val:?});\n")
+                }
+                _ => format!("    assert!({call}.await.is_err());\n"),
+            };
+        }
+        #[cfg(feature = "base64")]
+        ("b64read", [addr, size]) => {
+            let call = format!("parser.b64read({addr}, {size})");
+            let decoded = match response {
+                Some(Response::OkVal(val)) => {
+                    base64::engine::general_purpose::STANDARD.decode(val).ok()
+                }
+                _ => None,
+            };
+            return match decoded {
+                Some(bytes) => format!("    assert_eq!({call}.await.unwrap(), {bytes:?});\n"),
+                None => format!("    assert!({call}.await.is_err());\n"),
+            };
+        }
+        _ => return format!("    // unsupported command: {cmd}\n"),
+    };
+
+    match response {
+        Some(Response::Ok) => format!("    assert_eq!({call}.await.unwrap(), Response::Ok);\n"),
+        Some(Response::OkVal(val)) => format!(
+            "    assert_eq!({call}.await.unwrap(), Response::OkVal({val:?}.to_string()));\n"
+        ),
+        Some(Response::Err(_)) => {
+            format!("    assert!(matches!({call}.await.unwrap(), Response::Err(_)));\n")
+        }
+        None => format!("    {call}.await.unwrap();\n"),
+    }
+}
+
+/// Translates a call returning a typed value into an assertion on the recorded value
+fn value_statement(call: &str, response: Option<&Response>) -> String {
+    match response {
+        Some(Response::OkVal(val)) => format!("    assert_eq!({call}.await.unwrap(), {val});\n"),
+        _ => format!("    assert!({call}.await.is_err());\n"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate_test() {
+        let transcript: Transcript = "0 > writel 0x40000000 0x1\n\
+                                      1 < OK\n\
+                                      2 > readl 0x40000004\n\
+                                      3 < OK 0x12\n\
+                                      4 ! IRQ raise 3\n\
+                                      5 > qom-get /machine\n\
+                                      6 < FAIL Unknown command\n\
+                                      7 > clock_step 100\n\
+                                      8 < OK 100\n"
+            .parse()
+            .unwrap();
+
+        let code = generate_test(&transcript, "replay", "localhost:3000");
+
+        assert!(code.contains("async fn replay()"));
+        assert!(code.contains("Parser::<SocketTcp>::new(\"localhost:3000\")"));
+        assert!(code.contains(
+            "    assert_eq!(parser.writel(0x40000000, 0x1).await.unwrap(), Response::Ok);\n"
+        ));
+        assert!(code.contains("    assert_eq!(parser.readl(0x40000004).await.unwrap(), 0x12);\n"));
+        assert!(code.contains(
+            "    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(3, IrqState::Raise));\n"
+        ));
+        assert!(code.contains("    // unsupported command: qom-get /machine\n"));
+        assert!(
+            code.contains("    assert_eq!(parser.clock_step_ns(Some(100)).await.unwrap(), 100);\n")
+        );
+    }
+
+    #[test]
+    fn test_generate_test_irq_before_reply() {
+        let transcript: Transcript = "0 > writel 0x40000000 0x1\n\
+                                      1 ! IRQ raise 3\n\
+                                      2 < OK\n\
+                                      3 > readl 0x40000004\n\
+                                      4 < OK 0x12\n"
+            .parse()
+            .unwrap();
+
+        let code = generate_test(&transcript, "replay", "localhost:3000");
+
+        assert!(code.contains(
+            "    assert_eq!(parser.writel(0x40000000, 0x1).await.unwrap(), Response::Ok);\n\
+             \x20   assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(3, IrqState::Raise));\n\
+             \x20   assert_eq!(parser.readl(0x40000004).await.unwrap(), 0x12);\n"
+        ));
+        assert!(!code.contains("unexpected response"));
+    }
+}
@@ -0,0 +1,270 @@
+use std::{collections::HashSet, fmt, io};
+use tokio::sync::broadcast;
+
+use crate::event::{Event, EventKind};
+use crate::logging::log_warn;
+use crate::machine::Machine;
+use crate::protocol::Command;
+use crate::socket::Socket;
+use crate::transcript::Transcript;
+use crate::{Irq, Response};
+
+/// Difference in the behavior of the two machines for a command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DivergenceKind {
+    /// The machines answered with different responses
+    Response {
+        /// Response of the first machine
+        a: Response,
+        /// Response of the second machine
+        b: Response,
+    },
+    /// The machines propagated different IRQ events while running the command
+    Irqs {
+        /// IRQ events of the first machine, in order
+        a: Vec<Irq>,
+        /// IRQ events of the second machine, in order
+        b: Vec<Irq>,
+    },
+}
+
+/// Divergence of the two machines of a [`Comparison`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Divergence {
+    /// Position of the command in the sequence
+    pub index: usize,
+    /// Command run on both machines
+    pub command: Command,
+    /// How the machines differ
+    pub kind: DivergenceKind,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}: ", self.index, self.command)?;
+        match &self.kind {
+            DivergenceKind::Response { a, b } => write!(f, "response {a} != {b}"),
+            DivergenceKind::Irqs { a, b } => write!(f, "IRQs {a:?} != {b:?}"),
+        }
+    }
+}
+
+/// Result of a [`Comparison`], displayed with one divergence per line
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ComparisonReport {
+    /// Number of commands run on both machines
+    pub commands: usize,
+    /// The divergences, in order
+    pub divergences: Vec<Divergence>,
+    /// Number of events lost because the comparison fell behind, per machine
+    pub lost: (u64, u64),
+}
+
+impl ComparisonReport {
+    /// Returns true if both machines behaved the same
+    pub fn is_equivalent(&self) -> bool {
+        self.divergences.is_empty() && self.lost == (0, 0)
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} commands, {} divergences",
+            self.commands,
+            self.divergences.len()
+        )?;
+        for divergence in &self.divergences {
+            writeln!(f, "{divergence}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Harness running the same command sequence on two machines in lockstep, e.g. on two QEMU releases,
+/// and reporting where their responses or IRQ streams diverge.
+///
+/// Every command is sent to both machines at the same time, and the next one is only sent once both
+/// answered. The IRQ events that each machine propagates while running a command are compared too.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{compare::Comparison, machine::Machine, parser::Parser, socket::tcp::SocketTcp};
+/// # use qtest::transcript::Transcript;
+/// let (mut old, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// let (mut new, _irq_rx) = Parser::<SocketTcp>::new("localhost:3001").await.unwrap();
+/// old.attach_connection().await.unwrap();
+/// new.attach_connection().await.unwrap();
+///
+/// let transcript = Transcript::load("boot.qtest").unwrap();
+/// let report = Comparison::from_transcript(&transcript)
+///     .unwrap()
+///     .ignore_verb("clock_step")
+///     .run(&mut Machine::new(old), &mut Machine::new(new))
+///     .await
+///     .unwrap();
+/// print!("{report}");
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    commands: Vec<Command>,
+    ignored: HashSet<String>,
+}
+
+impl Comparison {
+    /// Creates a comparison of the given command sequence
+    pub fn new(commands: Vec<Command>) -> Self {
+        Self {
+            commands,
+            ignored: HashSet::new(),
+        }
+    }
+
+    /// Creates a comparison of the commands of the given transcript.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if a recorded command is not a valid qtest command.
+    pub fn from_transcript(transcript: &Transcript) -> io::Result<Self> {
+        let commands = transcript
+            .events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                EventKind::Command(line) => Some(line.parse().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid recorded command {line:?}: {e}"),
+                    )
+                })),
+                _ => None,
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self::new(commands))
+    }
+
+    /// Does not compare the responses to the commands with the given name (e.g. `clock_step`),
+    /// for values that are expected to differ between the machines. Their IRQ events are still compared.
+    pub fn ignore_verb(mut self, verb: &str) -> Self {
+        self.ignored.insert(verb.to_string());
+        self
+    }
+
+    /// Runs the command sequence on both attached machines and compares their behavior.
+    ///
+    /// Returns Err if the connection with either machine fails.
+    pub async fn run<A: Socket, B: Socket>(
+        &self,
+        a: &mut Machine<A>,
+        b: &mut Machine<B>,
+    ) -> io::Result<ComparisonReport> {
+        let mut report = ComparisonReport::default();
+        let mut events_a = a.parser().events();
+        let mut events_b = b.parser().events();
+
+        for (index, command) in self.commands.iter().enumerate() {
+            let (response_a, response_b) = tokio::join!(
+                a.parser().execute(command.clone()),
+                b.parser().execute(command.clone())
+            );
+            let (response_a, response_b) = (response_a?, response_b?);
+            report.commands += 1;
+
+            // QEMU sends the IRQ events caused by a command before its response
+            let irqs_a = drain_irqs(&mut events_a, &mut report.lost.0);
+            let irqs_b = drain_irqs(&mut events_b, &mut report.lost.1);
+
+            if response_a != response_b && !self.ignored.contains(command.verb()) {
+                report.divergences.push(Divergence {
+                    index,
+                    command: command.clone(),
+                    kind: DivergenceKind::Response {
+                        a: response_a,
+                        b: response_b,
+                    },
+                });
+            }
+            if irqs_a != irqs_b {
+                report.divergences.push(Divergence {
+                    index,
+                    command: command.clone(),
+                    kind: DivergenceKind::Irqs {
+                        a: irqs_a,
+                        b: irqs_b,
+                    },
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Takes the IRQ events already published in the given event stream
+fn drain_irqs(rx: &mut broadcast::Receiver<Event>, lost: &mut u64) -> Vec<Irq> {
+    let mut irqs = Vec::new();
+    loop {
+        match rx.try_recv() {
+            Ok(Event {
+                kind: EventKind::Irq(irq),
+                ..
+            }) => irqs.push(irq),
+            Ok(_) => {}
+            Err(broadcast::error::TryRecvError::Lagged(n)) => {
+                log_warn!(target: "qtest::compare", "{n} compared events lost");
+                *lost += n;
+            }
+            Err(_) => return irqs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::socket::dry_run::SocketDryRun;
+
+    async fn machine() -> Machine<SocketDryRun> {
+        let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+        parser.attach_connection().await.unwrap();
+        Machine::new(parser)
+    }
+
+    #[tokio::test]
+    async fn test_comparison() {
+        let (mut a, mut b) = (machine().await, machine().await);
+        // The clock of the second machine is ahead
+        b.clock_step(100).await.unwrap();
+
+        let transcript: Transcript = "0 > writel 0x0 0x1\n1 < OK\n2 > clock_step 10\n3 < OK 10\n"
+            .parse()
+            .unwrap();
+        let comparison = Comparison::from_transcript(&transcript).unwrap();
+        let report = comparison.run(&mut a, &mut b).await.unwrap();
+        assert_eq!(report.commands, 2);
+        assert_eq!(
+            report.divergences,
+            [Divergence {
+                index: 1,
+                command: Command::ClockStep(Some(10)),
+                kind: DivergenceKind::Response {
+                    a: Response::OkVal("10".to_string()),
+                    b: Response::OkVal("110".to_string()),
+                },
+            }]
+        );
+        assert_eq!(
+            report.to_string(),
+            "2 commands, 1 divergences\n#1 clock_step 10: response OK 10 != OK 110\n"
+        );
+
+        let report = comparison
+            .ignore_verb("clock_step")
+            .run(&mut a, &mut b)
+            .await
+            .unwrap();
+        assert!(report.is_equivalent());
+    }
+}
@@ -0,0 +1,83 @@
+//! Transparent zstd compression of the files written by the crate (snapshots and transcripts).
+//!
+//! Files are compressed and decompressed as they are written and read, without a copy of the whole contents.
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Magic number at the beginning of zstd frames
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Returns true if the given bytes are a zstd frame
+pub(crate) fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ZSTD_MAGIC)
+}
+
+/// Opens the given file for reading, decompressing it as it is read if it is a zstd frame
+pub(crate) fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if !is_compressed(file.fill_buf()?) {
+        return Ok(Box::new(file));
+    }
+    #[cfg(feature = "zstd")]
+    {
+        Ok(Box::new(zstd::stream::Decoder::with_buffer(file)?))
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "File is zstd-compressed, but the zstd feature is disabled",
+        ))
+    }
+}
+
+/// Creates the given file and writes it with the given function
+pub(crate) fn create<P: AsRef<Path>>(
+    path: P,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(&mut file)?;
+    file.flush()
+}
+
+/// Creates the given file and writes it with the given function, compressed with zstd at the given level
+/// (1-22, 0 for the default level)
+#[cfg(feature = "zstd")]
+pub(crate) fn create_compressed<P: AsRef<Path>>(
+    path: P,
+    level: i32,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut encoder = zstd::stream::Encoder::new(BufWriter::new(File::create(path)?), level)?;
+    write(&mut encoder)?;
+    encoder.finish()?.flush()
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let data = b"120 > writel 0x40000000 0x1\n245 < OK\n".repeat(100);
+        let path = std::env::temp_dir().join(format!("qtest-zstd-{}.qtest", std::process::id()));
+
+        create_compressed(&path, 0, |w| w.write_all(&data)).unwrap();
+        let compressed = std::fs::read(&path).unwrap();
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < data.len());
+        let mut read = Vec::new();
+        open(&path).unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+
+        create(&path, |w| w.write_all(&data)).unwrap();
+        let mut read = Vec::new();
+        open(&path).unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        std::fs::remove_file(path).unwrap();
+    }
+}
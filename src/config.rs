@@ -0,0 +1,227 @@
+use serde::Deserialize;
+use std::{fs, io, path::Path, time::Duration};
+
+use crate::icount::Icount;
+use crate::socket::Mode;
+
+/// Configuration of a qtest session, usually loaded from a `qtest.toml` file.
+///
+/// Every section and field is optional. For example:
+///
+/// ```toml
+/// [endpoint]
+/// scheme = "tcp"
+/// mode = "listen"
+/// url = "localhost:3000"
+///
+/// [timeouts]
+/// response_ms = 5000
+/// connect_ms = 30000
+///
+/// [channels]
+/// capacity = 64
+///
+/// [qemu]
+/// binary = "qemu-system-arm"
+/// version = ">=8.0, <10"
+/// args = ["-M", "netduino2", "-nographic"]
+///
+/// [qemu.icount]
+/// shift = 3
+/// sleep = false
+/// align = false
+///
+/// [intercepts]
+/// input = ["/machine/soc"]
+/// output = []
+///
+/// [rate_limit]
+/// commands_per_sec = 1000
+/// bytes_per_sec = 65536
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Socket endpoint of the session
+    pub endpoint: EndpointConfig,
+    /// Timeouts of the session
+    pub timeouts: TimeoutConfig,
+    /// Internal channels of the parser
+    pub channels: ChannelConfig,
+    /// QEMU binary and arguments of the session
+    pub qemu: QemuConfig,
+    /// QOM paths whose IRQs are intercepted when QEMU connects
+    pub intercepts: InterceptConfig,
+    /// Limits of the commands sent to QEMU
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Config {
+    /// Reads the configuration from the given TOML file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Kind of socket used to communicate with QEMU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    /// TCP socket ([`crate::socket::tcp::SocketTcp`])
+    #[default]
+    Tcp,
+    /// UNIX socket ([`crate::socket::unix::SocketUnix`])
+    Unix,
+    /// Remote UNIX socket reached over SSH ([`crate::socket::ssh::SocketSsh`]), with an `ssh://` URL
+    #[cfg(all(feature = "ssh", unix))]
+    Ssh,
+}
+
+/// Socket endpoint of the session
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointConfig {
+    /// Kind of socket
+    pub scheme: Scheme,
+    /// Whether this crate listens at the URL or connects to QEMU
+    pub mode: Mode,
+    /// Address (TCP), path (UNIX) or `ssh://` URL (SSH) of the socket
+    pub url: String,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            scheme: Scheme::Tcp,
+            mode: Mode::Listen,
+            url: "localhost:3000".to_string(),
+        }
+    }
+}
+
+/// Timeouts of the session, in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Maximum time to wait for the response to a command
+    pub response_ms: Option<u64>,
+    /// Maximum time to wait for QEMU to connect
+    pub connect_ms: Option<u64>,
+}
+
+impl TimeoutConfig {
+    /// Returns the response timeout, if set
+    pub fn response(&self) -> Option<Duration> {
+        self.response_ms.map(Duration::from_millis)
+    }
+
+    /// Returns the connection timeout, if set
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect_ms.map(Duration::from_millis)
+    }
+}
+
+/// Internal channels of the parser
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// Capacity of the raw socket data, response and IRQ channels
+    pub capacity: usize,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self { capacity: 32 }
+    }
+}
+
+/// QEMU binary and arguments of the session
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QemuConfig {
+    /// Path or name of the QEMU binary (e.g. `qemu-system-arm`)
+    pub binary: Option<String>,
+    /// Range of accepted QEMU versions (e.g. `>=8.0, <10`), checked by [`crate::qemu::QemuResolver`]
+    pub version: Option<String>,
+    /// Arguments passed to QEMU, besides the `-qtest` ones
+    pub args: Vec<String>,
+    /// Instruction counting settings, if enabled. A missing shift means `shift=auto`.
+    pub icount: Option<Icount>,
+}
+
+/// QOM paths whose IRQs are intercepted when QEMU connects
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InterceptConfig {
+    /// QOM paths whose input IRQs are intercepted
+    pub input: Vec<String>,
+    /// QOM paths whose output IRQs are intercepted
+    pub output: Vec<String>,
+}
+
+/// Limits of the commands sent to QEMU
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Maximum number of commands per second
+    pub commands_per_sec: Option<u32>,
+    /// Maximum number of bytes per second
+    pub bytes_per_sec: Option<u32>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_from_str() {
+        let config: Config = r#"
+            [endpoint]
+            scheme = "unix"
+            mode = "connect"
+            url = "/tmp/qtest.sock"
+
+            [timeouts]
+            response_ms = 5000
+
+            [qemu]
+            binary = "qemu-system-arm"
+            args = ["-M", "netduino2"]
+
+            [qemu.icount]
+            shift = 2
+
+            [intercepts]
+            input = ["/machine/soc"]
+
+            [rate_limit]
+            commands_per_sec = 100
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.endpoint.scheme, Scheme::Unix);
+        assert_eq!(config.endpoint.mode, Mode::Connect);
+        assert_eq!(config.endpoint.url, "/tmp/qtest.sock");
+        assert_eq!(config.timeouts.response(), Some(Duration::from_secs(5)));
+        assert_eq!(config.timeouts.connect(), None);
+        assert_eq!(config.channels.capacity, 32);
+        assert_eq!(config.qemu.binary.as_deref(), Some("qemu-system-arm"));
+        assert_eq!(config.qemu.args, vec!["-M", "netduino2"]);
+        assert_eq!(config.qemu.icount, Some(crate::icount::Icount::fixed(2)));
+        assert_eq!(config.intercepts.input, vec!["/machine/soc"]);
+        assert_eq!(config.rate_limit.commands_per_sec, Some(100));
+        assert_eq!(config.rate_limit.bytes_per_sec, None);
+
+        assert_eq!("".parse::<Config>().unwrap(), Config::default());
+        assert!("[endpoint]\nport = 3000".parse::<Config>().is_err());
+    }
+}
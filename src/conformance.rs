@@ -0,0 +1,402 @@
+use std::{fmt, io};
+
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::protocol::{Command, Width};
+use crate::socket::Socket;
+use crate::Response;
+
+/// Bytes written at the scratch address by the memory checks
+const SCRATCH: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+/// Support of a qtest command by the target
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Support {
+    /// The command succeeded with a well-formed response
+    Supported,
+    /// The command failed, with the given `FAIL` message
+    Unsupported(String),
+    /// The command succeeded, but the response was not the expected one
+    Malformed(String),
+    /// The command was not run, for the given reason
+    Skipped(&'static str),
+}
+
+impl fmt::Display for Support {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Supported => write!(f, "supported"),
+            Self::Unsupported(msg) => write!(f, "unsupported ({msg})"),
+            Self::Malformed(msg) => write!(f, "malformed ({msg})"),
+            Self::Skipped(reason) => write!(f, "skipped ({reason})"),
+        }
+    }
+}
+
+/// Result of the check of a qtest command
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VerbCheck {
+    /// Name of the command, e.g. `readl`
+    pub verb: &'static str,
+    /// Support of the command
+    pub support: Support,
+}
+
+/// Result of a conformance run, displayed as a table with one command per line
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ConformanceReport {
+    /// Checks of the commands, in the order they were run
+    pub checks: Vec<VerbCheck>,
+}
+
+impl ConformanceReport {
+    /// Returns the support of the given command, if it was checked
+    pub fn get(&self, verb: &str) -> Option<&Support> {
+        self.checks
+            .iter()
+            .find(|c| c.verb == verb)
+            .map(|c| &c.support)
+    }
+
+    /// Returns the commands supported by the target
+    pub fn supported(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.checks
+            .iter()
+            .filter(|c| c.support == Support::Supported)
+            .map(|c| c.verb)
+    }
+
+    /// Returns true if no command was unsupported or malformed
+    pub fn conformant(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|c| matches!(c.support, Support::Supported | Support::Skipped(_)))
+    }
+
+    fn push(&mut self, verb: &'static str, support: Support) {
+        self.checks.push(VerbCheck { verb, support });
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{:<18} {}", check.verb, check.support)?;
+        }
+        Ok(())
+    }
+}
+
+/// Suite of checks of the qtest commands supported by a target, used to build compatibility matrices
+/// of QEMU versions and forks.
+///
+/// Every command of the protocol is sent at least once and its response is checked: values read back must
+/// match the values written, and clock values must be consistent. Memory commands use 8 bytes of RAM at the
+/// scratch address, which are overwritten. Port I/O and IRQ commands are skipped unless their targets are set.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{conformance::Conformance, parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let report = Conformance::new(0x2000_0000)
+///     .irq_path("/machine/soc")
+///     .run(&mut parser)
+///     .await
+///     .unwrap();
+/// println!("{report}");
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conformance {
+    scratch: Address,
+    port: Option<usize>,
+    irq_path: Option<String>,
+    set_irq: Option<(String, String, usize)>,
+}
+
+impl Conformance {
+    /// Creates a suite using 8 bytes of RAM at the given scratch address
+    pub fn new(scratch: impl Into<Address>) -> Self {
+        Self {
+            scratch: scratch.into(),
+            port: None,
+            irq_path: None,
+            set_irq: None,
+        }
+    }
+
+    /// Sets the I/O port used by the port commands (`in*` and `out*`), e.g. a scratch register of an x86 machine
+    pub fn port(mut self, port: usize) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Sets the QOM path whose IRQs are intercepted by `irq_intercept_in` and `irq_intercept_out`
+    pub fn irq_path(mut self, qom_path: &str) -> Self {
+        self.irq_path = Some(qom_path.to_string());
+        self
+    }
+
+    /// Sets the GPIO input raised and lowered by `set_irq_in`
+    pub fn set_irq_in(mut self, qom_path: &str, irq_name: &str, line: usize) -> Self {
+        self.set_irq = Some((qom_path.to_string(), irq_name.to_string(), line));
+        self
+    }
+
+    /// Runs the checks through the given attached parser.
+    ///
+    /// Returns Err if the connection fails, e.g. if the target does not answer a command.
+    pub async fn run<T: Socket>(&self, parser: &mut Parser<T>) -> io::Result<ConformanceReport> {
+        let mut report = ConformanceReport::default();
+        let scratch = parser.resolve(self.scratch.clone())?;
+
+        // Clock
+        let now = match parser.execute(Command::ClockStep(None)).await? {
+            Response::OkVal(val) => match val.parse::<usize>() {
+                Ok(ns) => {
+                    report.push("clock_step", Support::Supported);
+                    Some(ns)
+                }
+                Err(_) => {
+                    report.push("clock_step", malformed(&val));
+                    None
+                }
+            },
+            response => {
+                report.push("clock_step", unexpected(response));
+                None
+            }
+        };
+        let target = now.unwrap_or(0) + 1_000;
+        let response = parser.execute(Command::ClockSet(target)).await?;
+        let support = check(response, |val| val.parse() == Ok(target));
+        report.push("clock_set", support);
+
+        // Memory values
+        for width in [Width::Byte, Width::Word, Width::Long, Width::Quad] {
+            let mut bytes = [0; 8];
+            bytes[..width.bytes()].copy_from_slice(&SCRATCH[..width.bytes()]);
+            let val = u64::from_le_bytes(bytes);
+            let write = Command::WriteValue {
+                width,
+                addr: scratch,
+                val,
+            };
+            let verb = write.verb();
+            let response = parser.execute(write).await?;
+            report.push(verb, check_ok(response));
+
+            let read = Command::ReadValue {
+                width,
+                addr: scratch,
+            };
+            let verb = read.verb();
+            let response = parser.execute(read).await?;
+            report.push(verb, check(response, |v| parse_hex(v) == Some(val)));
+        }
+
+        // Memory blocks
+        let data: String = SCRATCH.iter().map(|b| format!("{b:02x}")).collect();
+        let write = Command::Write {
+            addr: scratch,
+            len: SCRATCH.len(),
+            data: data.clone(),
+        };
+        report.push("write", check_ok(parser.execute(write).await?));
+        let read = Command::Read {
+            addr: scratch,
+            len: SCRATCH.len(),
+        };
+        let response = parser.execute(read).await?;
+        report.push("read", check(response, |v| v == format!("0x{data}")));
+        #[cfg(feature = "base64")]
+        {
+            use base64::Engine;
+
+            let b64write = Command::B64Write {
+                addr: scratch,
+                len: SCRATCH.len(),
+                data: base64::engine::general_purpose::STANDARD.encode(SCRATCH),
+            };
+            let support = match check_ok(parser.execute(b64write).await?) {
+                Support::Supported => {
+                    let read = Command::ReadValue {
+                        width: Width::Quad,
+                        addr: scratch,
+                    };
+                    let expected = u64::from_le_bytes(SCRATCH);
+                    let response = parser.execute(read).await?;
+                    match check(response, |v| parse_hex(v) == Some(expected)) {
+                        Support::Supported => Support::Supported,
+                        _ => Support::Malformed("data read back differs".to_string()),
+                    }
+                }
+                support => support,
+            };
+            report.push("b64write", support);
+        }
+        #[cfg(not(feature = "base64"))]
+        report.push("b64write", Support::Skipped("base64 feature disabled"));
+
+        // Port I/O, without quad accesses
+        for width in [Width::Byte, Width::Word, Width::Long] {
+            let (out_verb, in_verb) = match width {
+                Width::Byte => ("outb", "inb"),
+                Width::Word => ("outw", "inw"),
+                _ => ("outl", "inl"),
+            };
+            let Some(port) = self.port else {
+                report.push(out_verb, Support::Skipped("no port set"));
+                report.push(in_verb, Support::Skipped("no port set"));
+                continue;
+            };
+            let out = Command::Out {
+                width,
+                addr: port,
+                val: 0x1,
+            };
+            report.push(out_verb, check_ok(parser.execute(out).await?));
+            let read = Command::In { width, addr: port };
+            report.push(in_verb, check(parser.execute(read).await?, is_hex));
+        }
+
+        // IRQs
+        match &self.irq_path {
+            Some(qom_path) => {
+                let support = intercept(parser.irq_intercept_in(qom_path).await);
+                report.push("irq_intercept_in", support);
+                let support = intercept(parser.irq_intercept_out(qom_path).await);
+                report.push("irq_intercept_out", support);
+            }
+            None => {
+                report.push("irq_intercept_in", Support::Skipped("no QOM path set"));
+                report.push("irq_intercept_out", Support::Skipped("no QOM path set"));
+            }
+        }
+        match &self.set_irq {
+            Some((qom_path, irq_name, line)) => {
+                let mut support = Support::Supported;
+                for level in [1, 0] {
+                    let set = Command::SetIrqIn {
+                        qom_path: qom_path.clone(),
+                        irq_name: irq_name.clone(),
+                        line: *line,
+                        level,
+                    };
+                    if support == Support::Supported {
+                        support = check_ok(parser.execute(set).await?);
+                    }
+                }
+                report.push("set_irq_in", support);
+            }
+            None => report.push("set_irq_in", Support::Skipped("no GPIO input set")),
+        }
+
+        Ok(report)
+    }
+}
+
+/// Runs the [`Conformance`] suite with the given scratch address, skipping the port I/O and IRQ commands
+pub async fn run<T: Socket>(
+    parser: &mut Parser<T>,
+    scratch: impl Into<Address>,
+) -> io::Result<ConformanceReport> {
+    Conformance::new(scratch).run(parser).await
+}
+
+/// Checks that the response is a value accepted by the given function
+fn check(response: Response, valid: impl FnOnce(&str) -> bool) -> Support {
+    match response {
+        Response::OkVal(val) if valid(&val) => Support::Supported,
+        Response::OkVal(val) => malformed(&val),
+        response => unexpected(response),
+    }
+}
+
+/// Checks that the response is a plain `OK`
+fn check_ok(response: Response) -> Support {
+    match response {
+        Response::Ok => Support::Supported,
+        Response::OkVal(val) => malformed(&val),
+        response => unexpected(response),
+    }
+}
+
+/// Classifies the result of an interception, which the parser refuses to repeat
+fn intercept(result: Result<Response, QtestError>) -> Support {
+    match result {
+        Ok(response) => check_ok(response),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            Support::Skipped("already intercepted")
+        }
+        Err(e) => Support::Unsupported(e.to_string()),
+    }
+}
+
+fn malformed(val: &str) -> Support {
+    Support::Malformed(format!("unexpected value {val}"))
+}
+
+fn unexpected(response: Response) -> Support {
+    match response {
+        Response::Err(msg) => Support::Unsupported(msg),
+        Response::Ok => Support::Malformed("missing value".to_string()),
+        Response::OkVal(val) => malformed(&val),
+    }
+}
+
+fn parse_hex(val: &str) -> Option<u64> {
+    u64::from_str_radix(val.strip_prefix("0x")?, 16).ok()
+}
+
+fn is_hex(val: &str) -> bool {
+    parse_hex(val).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::dry_run::SocketDryRun;
+
+    #[test]
+    fn test_classification() {
+        assert_eq!(check_ok(Response::Ok), Support::Supported);
+        assert_eq!(
+            check_ok(Response::Err("Unknown command".to_string())),
+            Support::Unsupported("Unknown command".to_string())
+        );
+        assert_eq!(
+            check(Response::OkVal("0x2a".to_string()), is_hex),
+            Support::Supported
+        );
+        assert!(matches!(
+            check(Response::OkVal("2a".to_string()), is_hex),
+            Support::Malformed(_)
+        ));
+        assert!(matches!(check(Response::Ok, is_hex), Support::Malformed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+        parser.attach_connection().await.unwrap();
+        let report = run(&mut parser, 0x2000_0000).await.unwrap();
+
+        assert_eq!(report.get("clock_step"), Some(&Support::Supported));
+        assert_eq!(report.get("clock_set"), Some(&Support::Supported));
+        assert_eq!(report.get("writel"), Some(&Support::Supported));
+        // The dry run reads back zeroes instead of the written values
+        assert!(matches!(report.get("readl"), Some(Support::Malformed(_))));
+        assert!(matches!(report.get("inb"), Some(Support::Skipped(_))));
+        assert!(!report.conformant());
+        assert!(report
+            .to_string()
+            .starts_with("clock_step         supported\n"));
+    }
+}
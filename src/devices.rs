@@ -0,0 +1,4 @@
+/// GPIO module, used to drive and observe the pins of a GPIO controller.
+pub mod gpio;
+/// UART module, used to interact with the console of the firmware through a memory-mapped UART.
+pub mod uart;
@@ -0,0 +1,299 @@
+use std::{io, ops::Range};
+
+use tokio::sync::watch;
+
+use crate::error::QtestError;
+use crate::parser::{InterceptDirection, Parser};
+use crate::region::{MemRegion, RegValue};
+use crate::socket::Socket;
+use crate::{IrqState, Response};
+
+/// Default name of the unnamed GPIO inputs of a QEMU device
+const DEFAULT_INPUT: &str = "unnamed-gpio-in";
+/// Default name of the unnamed GPIO outputs of a QEMU device
+const DEFAULT_OUTPUT: &str = "unnamed-gpio-out";
+/// Default number of pins of a controller
+const DEFAULT_PINS: usize = 16;
+
+/// Logic level of a GPIO pin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Level {
+    /// The pin is low
+    #[default]
+    Low,
+    /// The pin is high
+    High,
+}
+
+impl Level {
+    /// Returns true if the level is high
+    pub fn is_high(&self) -> bool {
+        *self == Level::High
+    }
+}
+
+impl From<bool> for Level {
+    fn from(high: bool) -> Self {
+        if high {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+}
+
+impl From<IrqState> for Level {
+    fn from(state: IrqState) -> Self {
+        match state {
+            IrqState::Raise => Level::High,
+            IrqState::Lower => Level::Low,
+        }
+    }
+}
+
+impl From<Level> for IrqState {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::High => IrqState::Raise,
+            Level::Low => IrqState::Lower,
+        }
+    }
+}
+
+/// GPIO controller of the guest, identified by its QOM path.
+///
+/// Input pins are driven with `set_irq_in` on the GPIO inputs of the controller. Output pins are observed
+/// through the IRQ events of its intercepted GPIO outputs: once [`Gpio::intercept`] is called, QEMU reports
+/// output pin `n` as IRQ line `n`, so the output IRQs of other devices must not be intercepted on the same lines.
+/// The registers of the controller, if set with [`Gpio::registers`], are accessed by offset as a [`MemRegion`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{devices::gpio::{Gpio, Level}, parser::Parser, region::MemRegion, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let gpio = Gpio::new("/machine/soc/gpio[2]")
+///     .input("input-in")
+///     .registers(MemRegion::new(0x4002_0800, 0x400));
+/// gpio.intercept(&mut parser).await.unwrap();
+///
+/// let mut led = gpio.watch_pin(&parser, 5);
+/// gpio.set_pin(&mut parser, 13, Level::High).await.unwrap();
+/// led.wait_for(Level::High).await.unwrap();
+/// let idr = gpio.read_reg::<u32>(&mut parser, 0x10).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Gpio {
+    qom_path: String,
+    input: String,
+    output: String,
+    pins: usize,
+    registers: Option<MemRegion>,
+}
+
+impl Gpio {
+    /// Creates a controller at the given QOM path, with 16 unnamed input and output pins
+    pub fn new(qom_path: &str) -> Self {
+        Self {
+            qom_path: qom_path.to_string(),
+            input: DEFAULT_INPUT.to_string(),
+            output: DEFAULT_OUTPUT.to_string(),
+            pins: DEFAULT_PINS,
+            registers: None,
+        }
+    }
+
+    /// Sets the name of the GPIO inputs driving the pins
+    pub fn input(mut self, name: &str) -> Self {
+        self.input = name.to_string();
+        self
+    }
+
+    /// Sets the name of the GPIO outputs reporting the pins
+    pub fn output(mut self, name: &str) -> Self {
+        self.output = name.to_string();
+        self
+    }
+
+    /// Sets the number of pins of the controller
+    pub fn pins(mut self, pins: usize) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    /// Sets the registers of the controller
+    pub fn registers(mut self, registers: MemRegion) -> Self {
+        self.registers = Some(registers);
+        self
+    }
+
+    /// Returns the QOM path of the controller
+    pub fn qom_path(&self) -> &str {
+        &self.qom_path
+    }
+
+    /// Returns the IRQ lines reporting the output pins
+    pub fn lines(&self) -> Range<usize> {
+        0..self.pins
+    }
+
+    /// Intercepts the GPIO outputs of the controller, so that the levels of its pins are reported.
+    ///
+    /// The lines of the pins are mapped to the output name, see [`Parser::map_irq_lines`].
+    pub async fn intercept(&self, parser: &mut Parser<impl Socket>) -> Result<(), QtestError> {
+        match parser.irq_intercept_out(&self.qom_path).await? {
+            Response::Ok => {}
+            Response::Err(e) => {
+                return Err(QtestError::Fail(format!(
+                    "Could not intercept {}: {e}",
+                    self.qom_path
+                )))
+            }
+            response => {
+                return Err(QtestError::Protocol(format!(
+                    "Invalid response: {response}"
+                )))
+            }
+        }
+        parser.map_irq_lines(
+            &self.qom_path,
+            InterceptDirection::Out,
+            &self.output,
+            self.lines(),
+        )?;
+        Ok(())
+    }
+
+    /// Drives the given input pin to the given level
+    pub async fn set_pin(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        pin: usize,
+        level: Level,
+    ) -> Result<(), QtestError> {
+        self.check_pin(pin)?;
+        let response = parser
+            .set_irq_in(&self.qom_path, &self.input, pin, level.is_high() as isize)
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(QtestError::Fail(e)),
+            response => Err(QtestError::Protocol(format!(
+                "Invalid response: {response}"
+            ))),
+        }
+    }
+
+    /// Returns the last level reported by the controller on the given output pin.
+    ///
+    /// Pins without any IRQ event yet are considered low.
+    pub fn pull(&self, parser: &Parser<impl Socket>, pin: usize) -> Level {
+        (*parser.irq_level_watch(pin).borrow()).into()
+    }
+
+    /// Returns a watch of the level of the given output pin, built on its IRQ events
+    pub fn watch_pin(&self, parser: &Parser<impl Socket>, pin: usize) -> PinWatch {
+        PinWatch {
+            pin,
+            level: parser.irq_level_watch(pin),
+        }
+    }
+
+    /// Reads the register at the given offset with a single access of its width, as [`MemRegion::read_at`]
+    pub async fn read_reg<V: RegValue>(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+    ) -> Result<V, QtestError> {
+        self.regs()?.read_at(parser, offset).await
+    }
+
+    /// Writes the register at the given offset with a single access of its width, as [`MemRegion::write_at`]
+    pub async fn write_reg<V: RegValue>(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+        val: V,
+    ) -> Result<(), QtestError> {
+        self.regs()?.write_at(parser, offset, val).await
+    }
+
+    /// Returns the registers of the controller, or an error if they were not set
+    fn regs(&self) -> Result<MemRegion, QtestError> {
+        self.registers.ok_or_else(|| {
+            QtestError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("The registers of {} are not set", self.qom_path),
+            ))
+        })
+    }
+
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the controller does not have the given pin
+    fn check_pin(&self, pin: usize) -> Result<(), QtestError> {
+        if pin < self.pins {
+            Ok(())
+        } else {
+            Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no pin {pin}", self.qom_path),
+            )))
+        }
+    }
+}
+
+/// Level of an output pin of a [`Gpio`], created with [`Gpio::watch_pin`]
+#[derive(Debug, Clone)]
+pub struct PinWatch {
+    pin: usize,
+    level: watch::Receiver<IrqState>,
+}
+
+impl PinWatch {
+    /// Returns the pin being watched
+    pub fn pin(&self) -> usize {
+        self.pin
+    }
+
+    /// Returns the last level reported on the pin
+    pub fn level(&self) -> Level {
+        (*self.level.borrow()).into()
+    }
+
+    /// Waits until the level of the pin changes, returns the new level
+    pub async fn changed(&mut self) -> Result<Level, QtestError> {
+        self.level.changed().await.map_err(|_| closed())?;
+        Ok(self.level())
+    }
+
+    /// Waits until the pin is at the given level, returning at once if it already is
+    pub async fn wait_for(&mut self, level: Level) -> Result<(), QtestError> {
+        self.level
+            .wait_for(|state| Level::from(*state) == level)
+            .await
+            .map_err(|_| closed())?;
+        Ok(())
+    }
+}
+
+/// Returns the error of a watch whose parser is closed
+fn closed() -> QtestError {
+    QtestError::ChannelClosed("The IRQ levels are closed".into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Level::from(IrqState::Raise), Level::High);
+        assert_eq!(IrqState::from(Level::Low), IrqState::Lower);
+        assert!(Level::from(true).is_high());
+        assert_eq!(Level::default(), Level::Low);
+    }
+}
@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use futures_util::stream::Stream;
+
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::protocol::Width;
+use crate::region::{read_value, write_value};
+use crate::socket::Socket;
+
+/// Bit of a status register telling whether the UART is ready
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusFlag {
+    /// Mask of the bit in the status register
+    pub mask: u64,
+    /// True if the UART is ready when the bit is set, false if it is ready when the bit is clear
+    pub ready_when_set: bool,
+}
+
+impl StatusFlag {
+    /// Returns true if the given value of the status register tells that the UART is ready
+    pub fn is_ready(&self, status: u64) -> bool {
+        (status & self.mask != 0) == self.ready_when_set
+    }
+}
+
+/// Register layout of a memory-mapped UART
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UartLayout {
+    /// Offset of the data register, read to receive and written to transmit a byte
+    pub data: usize,
+    /// Offset of the status register
+    pub status: usize,
+    /// Width of the accesses to the registers
+    pub width: Width,
+    /// Flag telling that a received byte can be read
+    pub rx_ready: StatusFlag,
+    /// Flag telling that a byte can be transmitted
+    pub tx_ready: StatusFlag,
+}
+
+impl UartLayout {
+    /// ARM PL011: `UARTDR` at 0x00 and `UARTFR` at 0x18, ready when `RXFE` and `TXFF` are clear
+    pub const PL011: Self = Self {
+        data: 0x00,
+        status: 0x18,
+        width: Width::Long,
+        rx_ready: StatusFlag {
+            mask: 1 << 4,
+            ready_when_set: false,
+        },
+        tx_ready: StatusFlag {
+            mask: 1 << 5,
+            ready_when_set: false,
+        },
+    };
+
+    /// NS16550 with byte-spaced registers: `RBR`/`THR` at 0x0 and `LSR` at 0x5, ready when `DR` and `THRE` are set
+    pub const NS16550: Self = Self {
+        data: 0x0,
+        status: 0x5,
+        width: Width::Byte,
+        rx_ready: StatusFlag {
+            mask: 1 << 0,
+            ready_when_set: true,
+        },
+        tx_ready: StatusFlag {
+            mask: 1 << 5,
+            ready_when_set: true,
+        },
+    };
+
+    /// NS16550 with registers spaced `1 << reg_shift` bytes apart and accessed with the given width,
+    /// as in the device tree bindings
+    pub fn ns16550(reg_shift: u32, width: Width) -> Self {
+        Self {
+            data: Self::NS16550.data << reg_shift,
+            status: Self::NS16550.status << reg_shift,
+            width,
+            ..Self::NS16550
+        }
+    }
+}
+
+/// Memory-mapped UART of the guest, driven through qtest reads and writes.
+///
+/// The UART is driven as the firmware would: the status register is polled before every access
+/// to the data register. While the UART is not ready, the virtual clock is advanced by the poll interval,
+/// so that the guest and its device models can make progress, up to the timeout in virtual time.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{devices::uart::{Uart, UartLayout}, parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let uart = Uart::new(0x0900_0000, UartLayout::PL011);
+/// uart.write_str(&mut parser, "help\n").await.unwrap();
+/// let line = uart.read_line(&mut parser).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Uart {
+    base: usize,
+    layout: UartLayout,
+    poll_interval: Duration,
+    timeout: Duration,
+}
+
+impl Uart {
+    /// Creates a UART with the given layout at the given base address.
+    ///
+    /// It is polled every 10 us of virtual time, with a timeout of 100 ms of virtual time.
+    pub fn new(base: usize, layout: UartLayout) -> Self {
+        Self {
+            base,
+            layout,
+            poll_interval: Duration::from_micros(10),
+            timeout: Duration::from_millis(100),
+        }
+    }
+
+    /// Sets the virtual time the clock is advanced by while the UART is not ready
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Sets the virtual time to wait for the UART to be ready before failing
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the base address of the UART
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Returns the register layout of the UART
+    pub fn layout(&self) -> &UartLayout {
+        &self.layout
+    }
+
+    /// Transmits the given byte, once the UART is ready
+    pub async fn write_byte(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        byte: u8,
+    ) -> Result<(), QtestError> {
+        self.wait_ready(parser, self.layout.tx_ready).await?;
+        let addr = self.base + self.layout.data;
+        write_value(parser, self.layout.width, addr, byte.into()).await
+    }
+
+    /// Transmits the given bytes, in order
+    pub async fn write_bytes(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        data: &[u8],
+    ) -> Result<(), QtestError> {
+        for byte in data {
+            self.write_byte(parser, *byte).await?;
+        }
+        Ok(())
+    }
+
+    /// Transmits the given string
+    pub async fn write_str(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        s: &str,
+    ) -> Result<(), QtestError> {
+        self.write_bytes(parser, s.as_bytes()).await
+    }
+
+    /// Receives a byte if one is ready, without advancing the virtual clock
+    pub async fn try_read_byte(
+        &self,
+        parser: &mut Parser<impl Socket>,
+    ) -> Result<Option<u8>, QtestError> {
+        let status = self.read_reg(parser, self.layout.status).await?;
+        if !self.layout.rx_ready.is_ready(status) {
+            return Ok(None);
+        }
+        let data = self.read_reg(parser, self.layout.data).await?;
+        Ok(Some(data as u8))
+    }
+
+    /// Receives a byte, once one is ready
+    pub async fn read_byte(&self, parser: &mut Parser<impl Socket>) -> Result<u8, QtestError> {
+        self.wait_ready(parser, self.layout.rx_ready).await?;
+        let data = self.read_reg(parser, self.layout.data).await?;
+        Ok(data as u8)
+    }
+
+    /// Receives a line, returned without its `\n` or `\r\n` terminator.
+    ///
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    pub async fn read_line(&self, parser: &mut Parser<impl Socket>) -> Result<String, QtestError> {
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte(parser).await? {
+                b'\n' => break,
+                byte => line.push(byte),
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Returns a stream of the received bytes.
+    ///
+    /// Every byte is received as with [`Uart::read_byte`]; the stream ends after the first error,
+    /// e.g. when no byte is received before the timeout.
+    pub fn bytes<'a, T: Socket>(
+        &self,
+        parser: &'a mut Parser<T>,
+    ) -> impl Stream<Item = Result<u8, QtestError>> + 'a {
+        let uart = *self;
+        futures_util::stream::unfold(Some(parser), move |parser| async move {
+            let parser = parser?;
+            match uart.read_byte(parser).await {
+                Ok(byte) => Some((Ok(byte), Some(parser))),
+                // Stop after the error
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    /// Polls the status register until the given flag is ready, advancing the virtual clock between polls
+    async fn wait_ready(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        flag: StatusFlag,
+    ) -> Result<(), QtestError> {
+        let step = usize::try_from(self.poll_interval.as_nanos()).unwrap_or(usize::MAX);
+        let mut waited = Duration::ZERO;
+        loop {
+            let status = self.read_reg(parser, self.layout.status).await?;
+            if flag.is_ready(status) {
+                return Ok(());
+            }
+            if waited >= self.timeout || step == 0 {
+                return Err(QtestError::Timeout(format!(
+                    "The UART at {:#x} was not ready after {waited:?} of virtual time",
+                    self.base
+                )));
+            }
+            parser.clock_step_ns(Some(step)).await?;
+            waited += self.poll_interval;
+        }
+    }
+
+    /// Reads the register at the given offset
+    async fn read_reg(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+    ) -> Result<u64, QtestError> {
+        read_value(parser, self.layout.width, self.base + offset).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_layouts() {
+        let pl011 = UartLayout::PL011;
+        assert!(pl011.rx_ready.is_ready(0x80));
+        assert!(!pl011.rx_ready.is_ready(0x90));
+        assert!(!pl011.tx_ready.is_ready(0x20));
+
+        let ns16550 = UartLayout::ns16550(2, Width::Long);
+        assert_eq!(ns16550.status, 0x14);
+        assert!(ns16550.rx_ready.is_ready(0x61));
+        assert!(!ns16550.tx_ready.is_ready(0x01));
+    }
+}
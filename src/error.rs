@@ -0,0 +1,125 @@
+use std::{fmt, io};
+
+/// Error of a qtest session, returned by the [`crate::parser::Parser`] methods and by the helpers that drive
+/// the parser, such as the transfers, the register maps, the clock controller and the device models.
+///
+/// The variants tell the failure modes apart, so they can be matched on instead of parsing messages.
+/// It converts from and into [`io::Error`], so code using [`io::Result`] can keep propagating it with `?`:
+/// converting a `QtestError` into an [`io::Error`] and back recovers the original variant.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{error::QtestError, parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// match parser.readl(0x4000_0000).await {
+///     Ok(value) => println!("{value:#x}"),
+///     Err(QtestError::Fail(reason)) => println!("QEMU refused the read: {reason}"),
+///     Err(QtestError::Timeout(_)) => println!("QEMU is not answering"),
+///     Err(e) => println!("{e}"),
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub enum QtestError {
+    /// QEMU sent a response that does not fit the command, e.g. a value to a write
+    Protocol(String),
+    /// A value sent by QEMU could not be parsed
+    Parse(String),
+    /// The socket with QEMU failed
+    Socket(io::Error),
+    /// QEMU did not connect or answer in time
+    Timeout(String),
+    /// An internal channel of the parser was closed, e.g. because its reader task stopped
+    ChannelClosed(String),
+    /// QEMU answered with a `FAIL` response, with the reason it gave
+    Fail(String),
+    /// Any other error, e.g. an invalid argument, with its [`io::ErrorKind`]
+    Io(io::Error),
+}
+
+impl QtestError {
+    /// Returns the [`io::ErrorKind`] of the error, the one of the [`io::Error`] it converts into
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::Protocol(_) | Self::Parse(_) => io::ErrorKind::InvalidData,
+            Self::Socket(e) | Self::Io(e) => e.kind(),
+            Self::Timeout(_) => io::ErrorKind::TimedOut,
+            Self::ChannelClosed(_) => io::ErrorKind::BrokenPipe,
+            Self::Fail(_) => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for QtestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Protocol(msg)
+            | Self::Parse(msg)
+            | Self::Timeout(msg)
+            | Self::ChannelClosed(msg) => write!(f, "{msg}"),
+            Self::Socket(e) => write!(f, "Socket error: {e}"),
+            Self::Fail(reason) => write!(f, "QEMU failed: {reason}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for QtestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Socket(e) | Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for QtestError {
+    /// Recovers the `QtestError` wrapped in the given error, if any, or wraps it as [`QtestError::Io`]
+    fn from(e: io::Error) -> Self {
+        match e.get_ref().is_some_and(|inner| inner.is::<QtestError>()) {
+            true => *e.into_inner().unwrap().downcast::<QtestError>().unwrap(),
+            false => Self::Io(e),
+        }
+    }
+}
+
+impl From<QtestError> for io::Error {
+    fn from(e: QtestError) -> Self {
+        match e {
+            QtestError::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_io_round_trip() {
+        let e = io::Error::from(QtestError::Timeout("Timed out waiting for response".into()));
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(e.to_string(), "Timed out waiting for response");
+        assert!(matches!(QtestError::from(e), QtestError::Timeout(_)));
+
+        let socket = QtestError::Socket(io::Error::from(io::ErrorKind::ConnectionReset));
+        let e = QtestError::from(io::Error::from(socket));
+        assert!(matches!(e, QtestError::Socket(_)));
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+
+        let e = QtestError::from(io::Error::new(io::ErrorKind::InvalidInput, "bad"));
+        assert!(matches!(e, QtestError::Io(_)));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "bad");
+        assert_eq!(
+            QtestError::Fail("FAIL".into()).to_string(),
+            "QEMU failed: FAIL"
+        );
+    }
+}
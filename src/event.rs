@@ -0,0 +1,481 @@
+#[cfg(feature = "websocket")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "websocket")]
+use std::io;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+
+#[cfg(feature = "websocket")]
+use crate::IrqState;
+use crate::{Irq, Response};
+
+/// Capacity of the event broadcast channel. Slow subscribers lag behind and lose old events.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Default number of events kept in the [`EventHistory`] of a session
+pub const DEFAULT_HISTORY_CAPACITY: usize = 4096;
+
+/// Value of the shared virtual clock while unknown
+const UNKNOWN_CLOCK: usize = usize::MAX;
+
+/// Kind of event observed in a qtest session
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// Command sent to QEMU, without the trailing newline
+    Command(String),
+    /// Response received from QEMU
+    Response(Response),
+    /// IRQ event propagated by QEMU
+    Irq(Irq),
+    /// QEMU connected to the parser
+    Connected,
+    /// Line recognized by a [`crate::parser::LineHook`], e.g. a vendor-specific notification of a QEMU fork
+    Notification(Notification),
+}
+
+/// Line sent by QEMU that is neither a response nor an IRQ event, recognized by a [`crate::parser::LineHook`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Notification {
+    /// Name of the notification, without whitespace, e.g. `TRACE`
+    pub name: String,
+    /// Rest of the line, possibly empty
+    pub payload: String,
+}
+
+impl Notification {
+    /// Creates a notification with the given name and payload
+    pub fn new(name: &str, payload: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            payload: payload.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.payload.is_empty() {
+            true => write!(f, "{}", self.name),
+            false => write!(f, "{} {}", self.name, self.payload),
+        }
+    }
+}
+
+/// Event of the unified qtest event stream.
+///
+/// Every command sent, response received and IRQ propagated by QEMU, as well as every connection,
+/// is published as an event, together with the time elapsed since the session was created.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Event {
+    /// Host time elapsed since the start of the session
+    pub timestamp: Duration,
+    /// Virtual clock of the guest, in nanoseconds, as last reported by QEMU when the event happened.
+    /// `None` until the clock is first queried or stepped on the current connection. The response
+    /// that reports a new clock value is still dated with the previous one.
+    pub virtual_ns: Option<usize>,
+    /// The kind of event
+    pub kind: EventKind,
+}
+
+#[cfg(feature = "websocket")]
+impl Event {
+    /// Serializes the event as a single-line JSON object.
+    ///
+    /// The resulting object has a `timestamp_us` field, a `virtual_ns` field if the virtual clock is known,
+    /// and a `type` field (`command`, `response`, `irq`, `connected` or `notification`), plus the fields specific to each kind of event.
+    pub fn to_json(&self) -> String {
+        let kind = match &self.kind {
+            EventKind::Command(cmd) => JsonKind::Command {
+                command: cmd.clone(),
+            },
+            EventKind::Response(response) => {
+                let (status, value, error) = match response {
+                    Response::Ok => (JsonStatus::Ok, None, None),
+                    Response::OkVal(val) => (JsonStatus::Ok, Some(val.clone()), None),
+                    Response::Err(err) => (JsonStatus::Error, None, Some(err.clone())),
+                };
+                JsonKind::Response {
+                    status,
+                    value,
+                    error,
+                }
+            }
+            EventKind::Irq(irq) => JsonKind::Irq {
+                line: irq.line,
+                state: match irq.state {
+                    IrqState::Raise => JsonIrqState::Raise,
+                    IrqState::Lower => JsonIrqState::Lower,
+                },
+            },
+            EventKind::Connected => JsonKind::Connected,
+            EventKind::Notification(notification) => JsonKind::Notification {
+                name: notification.name.clone(),
+                payload: notification.payload.clone(),
+            },
+        };
+        let event = JsonEvent {
+            timestamp_us: self.timestamp.as_micros() as u64,
+            virtual_ns: self.virtual_ns,
+            kind,
+        };
+        serde_json::to_string(&event).expect("events always serialize to JSON")
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl std::str::FromStr for Event {
+    type Err = io::Error;
+
+    /// Parses an event serialized with [`Event::to_json`], e.g. received from a [`crate::websocket::EventFeed`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid event: {s}"));
+        let event: JsonEvent = serde_json::from_str(s).map_err(|_| invalid())?;
+        let kind = match event.kind {
+            JsonKind::Command { command } => EventKind::Command(command),
+            JsonKind::Response {
+                status,
+                value,
+                error,
+            } => match (status, value, error) {
+                (JsonStatus::Ok, None, None) => EventKind::Response(Response::Ok),
+                (JsonStatus::Ok, Some(val), None) => EventKind::Response(Response::OkVal(val)),
+                (JsonStatus::Error, None, Some(err)) => EventKind::Response(Response::Err(err)),
+                _ => return Err(invalid()),
+            },
+            JsonKind::Irq { line, state } => {
+                let state = match state {
+                    JsonIrqState::Raise => IrqState::Raise,
+                    JsonIrqState::Lower => IrqState::Lower,
+                };
+                EventKind::Irq(Irq::new(line, state))
+            }
+            JsonKind::Connected => EventKind::Connected,
+            JsonKind::Notification { name, payload } => {
+                EventKind::Notification(Notification { name, payload })
+            }
+        };
+        Ok(Self {
+            timestamp: Duration::from_micros(event.timestamp_us),
+            virtual_ns: event.virtual_ns,
+            kind,
+        })
+    }
+}
+
+/// JSON representation of an [`Event`], as sent by a [`crate::websocket::EventFeed`]
+#[cfg(feature = "websocket")]
+#[derive(Serialize, Deserialize)]
+struct JsonEvent {
+    timestamp_us: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    virtual_ns: Option<usize>,
+    #[serde(flatten)]
+    kind: JsonKind,
+}
+
+#[cfg(feature = "websocket")]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonKind {
+    Command {
+        command: String,
+    },
+    Response {
+        status: JsonStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Irq {
+        line: usize,
+        state: JsonIrqState,
+    },
+    Connected,
+    Notification {
+        name: String,
+        payload: String,
+    },
+}
+
+#[cfg(feature = "websocket")]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JsonStatus {
+    Ok,
+    Error,
+}
+
+#[cfg(feature = "websocket")]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JsonIrqState {
+    Raise,
+    Lower,
+}
+
+/// Bounded history of the most recent events of a session, shared with the parser.
+///
+/// Once full, the oldest events are discarded. Obtained with [`crate::parser::Parser::event_history`],
+/// it allows assertions over the recent events without subscribing to the event stream beforehand.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+/// let history = parser.event_history();
+///
+/// let start = parser.clock().now().await.unwrap();
+/// parser.clock_step(Some(5_000_000)).await.unwrap();
+///
+/// // Number of IRQ events of line 3 in the last 5 ms of virtual time
+/// let irqs = history
+///     .irqs_on_line(3)
+///     .iter()
+///     .filter(|event| event.virtual_ns >= Some(start))
+///     .count();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventHistory {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<Event>>>,
+}
+
+impl EventHistory {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    fn push(&self, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Returns the maximum number of events kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns every event kept, oldest first
+    pub fn events(&self) -> Vec<Event> {
+        self.filter(|_| true)
+    }
+
+    /// Returns the events that happened at or after the given virtual time, in nanoseconds.
+    ///
+    /// Events are dated with the virtual clock last reported by QEMU, so the clock must be queried
+    /// or stepped (e.g. with [`crate::clock::ClockController::now`]) for the events to be dated.
+    /// Events with an unknown virtual time are not returned.
+    pub fn events_since(&self, virtual_ns: usize) -> Vec<Event> {
+        self.filter(|event| event.virtual_ns.is_some_and(|ns| ns >= virtual_ns))
+    }
+
+    /// Returns the IRQ events of the given line
+    pub fn irqs_on_line(&self, line: usize) -> Vec<Event> {
+        self.filter(|event| matches!(&event.kind, EventKind::Irq(irq) if irq.line == line))
+    }
+
+    /// Returns the events matching the given predicate, oldest first
+    pub fn filter<F: FnMut(&Event) -> bool>(&self, mut predicate: F) -> Vec<Event> {
+        let events = self.events.lock().unwrap();
+        events.iter().filter(|e| predicate(e)).cloned().collect()
+    }
+
+    /// Discards every event kept
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+/// Publisher side of the unified event stream, shared by the parser and its reader
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus {
+    start: Instant,
+    tx: broadcast::Sender<Event>,
+    history: EventHistory,
+    /// Last known virtual clock, or [`UNKNOWN_CLOCK`]
+    virtual_ns: Arc<AtomicUsize>,
+}
+
+impl EventBus {
+    /// Creates a new event bus keeping up to the given number of events in its history.
+    /// Timestamps are relative to the moment of creation.
+    pub(crate) fn new(history_capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            start: Instant::now(),
+            tx,
+            history: EventHistory::new(history_capacity),
+            virtual_ns: Arc::new(AtomicUsize::new(UNKNOWN_CLOCK)),
+        }
+    }
+
+    /// Publishes a new event, recording it in the history.
+    /// Events are silently discarded if there are no subscribers.
+    pub(crate) fn publish(&self, kind: EventKind) {
+        let event = Event {
+            timestamp: self.start.elapsed(),
+            virtual_ns: self.virtual_ns(),
+            kind,
+        };
+        self.history.push(event.clone());
+        let _ = self.tx.send(event);
+    }
+
+    /// Returns the last known virtual clock, used to date the next events
+    pub(crate) fn virtual_ns(&self) -> Option<usize> {
+        match self.virtual_ns.load(Ordering::Relaxed) {
+            UNKNOWN_CLOCK => None,
+            ns => Some(ns),
+        }
+    }
+
+    /// Sets the virtual clock used to date the next events
+    pub(crate) fn set_virtual_ns(&self, virtual_ns: Option<usize>) {
+        let ns = virtual_ns.unwrap_or(UNKNOWN_CLOCK);
+        self.virtual_ns.store(ns, Ordering::Relaxed);
+    }
+
+    /// Returns the history of the events
+    pub(crate) fn history(&self) -> EventHistory {
+        self.history.clone()
+    }
+
+    /// Returns a new receiver of the event stream
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IrqState;
+
+    #[test]
+    #[cfg(feature = "websocket")]
+    fn test_event_to_json() {
+        let event = Event {
+            timestamp: Duration::from_micros(42),
+            virtual_ns: None,
+            kind: EventKind::Command("readl 0x\"10\"".to_string()),
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"timestamp_us":42,"type":"command","command":"readl 0x\"10\""}"#
+        );
+
+        let event = Event {
+            timestamp: Duration::from_micros(1),
+            virtual_ns: None,
+            kind: EventKind::Response(Response::OkVal("0x10".to_string())),
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"timestamp_us":1,"type":"response","status":"ok","value":"0x10"}"#
+        );
+
+        let event = Event {
+            timestamp: Duration::from_micros(7),
+            virtual_ns: Some(1000),
+            kind: EventKind::Irq(Irq::new(3, IrqState::Raise)),
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"timestamp_us":7,"virtual_ns":1000,"type":"irq","line":3,"state":"raise"}"#
+        );
+
+        let event = Event {
+            timestamp: Duration::from_micros(8),
+            virtual_ns: None,
+            kind: EventKind::Notification(Notification::new("TRACE", "dma \"start\"")),
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"timestamp_us":8,"type":"notification","name":"TRACE","payload":"dma \"start\""}"#
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "websocket")]
+    fn test_event_from_json() {
+        let kinds = [
+            EventKind::Command("readl 0x\"10\"\n".to_string()),
+            EventKind::Response(Response::Ok),
+            EventKind::Response(Response::OkVal("0x10".to_string())),
+            EventKind::Response(Response::Err("FAIL \u{1}".to_string())),
+            EventKind::Irq(Irq::new(3, IrqState::Lower)),
+            EventKind::Connected,
+            EventKind::Notification(Notification::new("TRACE", "")),
+        ];
+        for (i, kind) in kinds.into_iter().enumerate() {
+            let event = Event {
+                timestamp: Duration::from_micros(i as u64),
+                virtual_ns: (i % 2 == 0).then_some(1000 * i),
+                kind,
+            };
+            assert_eq!(event.to_json().parse::<Event>().unwrap(), event);
+        }
+
+        let err = r#"{"timestamp_us":1,"type":"irq","line":3}"#.parse::<Event>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!("not json".parse::<Event>().is_err());
+        assert!(r#"{"timestamp_us":1 "type":"connected"}"#.parse::<Event>().is_err());
+    }
+
+    #[test]
+    fn test_history() {
+        let bus = EventBus::new(3);
+        let history = bus.history();
+        bus.publish(EventKind::Connected);
+        bus.publish(EventKind::Irq(Irq::new(1, IrqState::Raise)));
+        bus.set_virtual_ns(Some(100));
+        bus.publish(EventKind::Irq(Irq::new(2, IrqState::Raise)));
+        bus.set_virtual_ns(Some(200));
+        bus.publish(EventKind::Irq(Irq::new(1, IrqState::Lower)));
+
+        let events = history.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].kind, EventKind::Irq(Irq::new(1, IrqState::Raise)));
+
+        let since: Vec<_> = history
+            .events_since(150)
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        assert_eq!(since, [EventKind::Irq(Irq::new(1, IrqState::Lower))]);
+        assert_eq!(history.events_since(0).len(), 2);
+
+        let line: Vec<_> = history
+            .irqs_on_line(1)
+            .into_iter()
+            .map(|e| e.virtual_ns)
+            .collect();
+        assert_eq!(line, [None, Some(200)]);
+
+        history.clear();
+        assert!(history.events().is_empty());
+        EventBus::new(0).publish(EventKind::Connected);
+    }
+}
@@ -0,0 +1,330 @@
+//! C-compatible API of the crate.
+//!
+//! The functions of this module are exported with C linkage so C test harnesses can use the crate as
+//! their qtest backend. The corresponding header is `include/qtest.h`, which can be regenerated with
+//! `cbindgen --config cbindgen.toml --output include/qtest.h`.
+//!
+//! All functions returning `int` follow the same convention: `0` on success and `-1` on error.
+use std::{
+    ffi::{c_char, c_int, CStr},
+    io, ptr,
+};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+use crate::{
+    parser::Parser,
+    socket::{tcp::SocketTcp, unix::SocketUnix, Socket},
+    Irq, IrqState, Response,
+};
+
+/// Opaque handle to a qtest parser and the runtime that drives it
+pub struct QtestParser {
+    runtime: Runtime,
+    backend: Backend,
+    irq_rx: mpsc::Receiver<Irq>,
+}
+
+/// Socket implementation used by a [`QtestParser`]
+enum Backend {
+    Tcp(Parser<SocketTcp>),
+    Unix(Parser<SocketUnix>),
+}
+
+/// IRQ event reported by [`qtest_irq_poll`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QtestIrq {
+    /// The line of the IRQ event
+    pub line: usize,
+    /// 1 if the line was raised, 0 if it was lowered
+    pub raised: c_int,
+}
+
+/// Runs an async parser method on the runtime of the handle, regardless of its socket implementation
+macro_rules! block_on {
+    ($handle:expr, $parser:ident => $body:expr) => {{
+        let handle = &mut *$handle;
+        match &mut handle.backend {
+            Backend::Tcp($parser) => handle.runtime.block_on($body).map_err(io::Error::from),
+            Backend::Unix($parser) => handle.runtime.block_on($body).map_err(io::Error::from),
+        }
+    }};
+}
+
+/// Converts a result into the return convention of the C API
+fn status<V>(result: io::Result<V>) -> c_int {
+    match result {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Converts a qtest response into the return convention of the C API
+fn response_status(result: io::Result<Response>) -> c_int {
+    match result {
+        Ok(Response::Ok) | Ok(Response::OkVal(_)) => 0,
+        _ => -1,
+    }
+}
+
+/// Converts a C string into a string slice, returns `None` if it is NULL or not valid UTF-8
+unsafe fn c_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Creates a new handle with the given socket implementation
+unsafe fn new_handle<T: Socket>(
+    url: *const c_char,
+    backend: fn(Parser<T>) -> Backend,
+) -> *mut QtestParser {
+    let Some(url) = c_str(url) else {
+        return ptr::null_mut();
+    };
+    let Ok(runtime) = Runtime::new() else {
+        return ptr::null_mut();
+    };
+    match runtime.block_on(Parser::<T>::new(url)) {
+        Ok((parser, irq_rx)) => Box::into_raw(Box::new(QtestParser {
+            runtime,
+            backend: backend(parser),
+            irq_rx,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Creates a new parser listening on the given TCP address (e.g. `"localhost:3000"`).
+///
+/// Returns NULL on error. The handle must be released with [`qtest_parser_free`].
+///
+/// # Safety
+///
+/// `url` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_parser_new_tcp(url: *const c_char) -> *mut QtestParser {
+    new_handle::<SocketTcp>(url, Backend::Tcp)
+}
+
+/// Creates a new parser listening on the given UNIX socket path.
+///
+/// Returns NULL on error. The handle must be released with [`qtest_parser_free`].
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_parser_new_unix(path: *const c_char) -> *mut QtestParser {
+    new_handle::<SocketUnix>(path, Backend::Unix)
+}
+
+/// Waits until QEMU connects to the parser.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle returned by one of the `qtest_parser_new_*` functions.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_parser_attach(handle: *mut QtestParser) -> c_int {
+    status(block_on!(handle, parser => parser.attach_connection()))
+}
+
+/// Releases a parser handle. Passing NULL is a no-op.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a valid handle that has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_parser_free(handle: *mut QtestParser) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Steps the virtual clock by the given number of nanoseconds.
+/// If `ns` is 0, the clock is stepped to the next timer deadline.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle returned by one of the `qtest_parser_new_*` functions.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_clock_step(handle: *mut QtestParser, ns: u64) -> c_int {
+    let ns = (ns != 0).then_some(ns as usize);
+    status(block_on!(handle, parser => parser.clock_step_ns(ns)))
+}
+
+/// Intercepts the input IRQs of the device at the given QOM path.
+/// Returns -1 if `qom_path` is NULL.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle and `qom_path` NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_irq_intercept_in(
+    handle: *mut QtestParser,
+    qom_path: *const c_char,
+) -> c_int {
+    let Some(qom_path) = c_str(qom_path) else {
+        return -1;
+    };
+    response_status(block_on!(handle, parser => parser.irq_intercept_in(qom_path)))
+}
+
+/// Intercepts the output IRQs of the device at the given QOM path.
+/// Returns -1 if `qom_path` is NULL.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle and `qom_path` NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_irq_intercept_out(
+    handle: *mut QtestParser,
+    qom_path: *const c_char,
+) -> c_int {
+    let Some(qom_path) = c_str(qom_path) else {
+        return -1;
+    };
+    response_status(block_on!(handle, parser => parser.irq_intercept_out(qom_path)))
+}
+
+/// Sets the given input IRQ line of the device at the given QOM path to the given level.
+/// Returns -1 if `qom_path` or `irq_name` is NULL.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle, and `qom_path` and `irq_name` NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn qtest_set_irq_in(
+    handle: *mut QtestParser,
+    qom_path: *const c_char,
+    irq_name: *const c_char,
+    line: usize,
+    level: c_int,
+) -> c_int {
+    let (Some(qom_path), Some(irq_name)) = (c_str(qom_path), c_str(irq_name)) else {
+        return -1;
+    };
+    response_status(
+        block_on!(handle, parser => parser.set_irq_in(qom_path, irq_name, line, level as isize)),
+    )
+}
+
+/// Polls the next pending IRQ event without blocking.
+///
+/// Returns 1 and fills `irq` if an event was pending, 0 if there were no pending events,
+/// and -1 if the connection was closed.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle and `irq` a valid pointer to a [`QtestIrq`].
+#[no_mangle]
+pub unsafe extern "C" fn qtest_irq_poll(handle: *mut QtestParser, irq: *mut QtestIrq) -> c_int {
+    let handle = &mut *handle;
+    match handle.irq_rx.try_recv() {
+        Ok(event) => {
+            *irq = QtestIrq {
+                line: event.line,
+                raised: (event.state == IrqState::Raise) as c_int,
+            };
+            1
+        }
+        Err(mpsc::error::TryRecvError::Empty) => 0,
+        Err(mpsc::error::TryRecvError::Disconnected) => -1,
+    }
+}
+
+/// *Read & write functions*
+macro_rules! ffi_read_write {
+    ($c_read:ident, $c_write:ident, $read:ident, $write:ident, $ty:ty) => {
+        /// Reads a value from the given guest address and stores it in `value`.
+        ///
+        /// # Safety
+        ///
+        /// `handle` must be a valid handle and `value` a valid pointer.
+        #[no_mangle]
+        pub unsafe extern "C" fn $c_read(
+            handle: *mut QtestParser,
+            addr: u64,
+            value: *mut $ty,
+        ) -> c_int {
+            match block_on!(handle, parser => parser.$read(addr as usize)) {
+                Ok(val) => {
+                    *value = val;
+                    0
+                }
+                Err(_) => -1,
+            }
+        }
+
+        /// Writes the given value to the given guest address.
+        ///
+        /// # Safety
+        ///
+        /// `handle` must be a valid handle.
+        #[no_mangle]
+        pub unsafe extern "C" fn $c_write(handle: *mut QtestParser, addr: u64, value: $ty) -> c_int {
+            response_status(block_on!(handle, parser => parser.$write(addr as usize, value)))
+        }
+    };
+}
+
+ffi_read_write!(qtest_readb, qtest_writeb, readb, writeb, u8);
+ffi_read_write!(qtest_readw, qtest_writew, readw, writew, u16);
+ffi_read_write!(qtest_readl, qtest_writel, readl, writel, u32);
+ffi_read_write!(qtest_readq, qtest_writeq, readq, writeq, u64);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockQtestDevice;
+
+    #[test]
+    fn test_c_api() {
+        assert!(unsafe { qtest_parser_new_tcp(ptr::null()) }.is_null());
+
+        let device = MockQtestDevice::new();
+        device.on_write(0x4000_0000, |_| vec![Irq::new(3, IrqState::Raise)]);
+        let handle = unsafe { qtest_parser_new_tcp(c"127.0.0.1:0".as_ptr()) };
+        assert!(!handle.is_null());
+        let parser = unsafe { &*handle };
+        let Backend::Tcp(tcp) = &parser.backend else {
+            unreachable!()
+        };
+        parser
+            .runtime
+            .block_on(device.connect_tcp(&tcp.socket().address()))
+            .unwrap();
+
+        unsafe {
+            assert_eq!(qtest_parser_attach(handle), 0);
+
+            assert_eq!(qtest_writel(handle, 0x2000_0000, 0x1234_5678), 0);
+            let mut value = 0;
+            assert_eq!(qtest_readl(handle, 0x2000_0000, &mut value), 0);
+            assert_eq!(value, 0x1234_5678);
+            let mut value = 0;
+            assert_eq!(qtest_readb(handle, 0x2000_0003, &mut value), 0);
+            assert_eq!(value, 0x12);
+
+            let mut irq = QtestIrq::default();
+            assert_eq!(qtest_irq_poll(handle, &mut irq), 0);
+            assert_eq!(qtest_writeb(handle, 0x4000_0000, 1), 0);
+            assert_eq!(qtest_irq_poll(handle, &mut irq), 1);
+            assert_eq!((irq.line, irq.raised), (3, 1));
+
+            assert_eq!(qtest_irq_intercept_in(handle, c"/machine/soc".as_ptr()), 0);
+            assert_eq!(qtest_irq_intercept_out(handle, ptr::null()), -1);
+            let (soc, irq_name) = (c"/machine/soc".as_ptr(), c"irq".as_ptr());
+            assert_eq!(qtest_set_irq_in(handle, soc, irq_name, 2, 1), 0);
+            assert_eq!(qtest_set_irq_in(handle, soc, ptr::null(), 2, 0), -1);
+            assert_eq!(device.irq_in_level("/machine/soc", "irq", 2), Some(1));
+
+            assert_eq!(qtest_clock_step(handle, 100), 0);
+            assert_eq!(device.clock_ns(), 100);
+
+            qtest_parser_free(handle);
+            qtest_parser_free(ptr::null_mut());
+        }
+    }
+}
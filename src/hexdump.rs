@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// Number of bytes displayed per line
+const BYTES_PER_LINE: usize = 16;
+
+/// Bytes read from the guest, displayed in canonical hexdump format.
+///
+/// Each line shows the guest address of its first byte, 16 bytes in hexadecimal and their ASCII representation:
+///
+/// ```text
+/// 20000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|
+/// 20000010  de ad be ef                                       |....|
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct HexDump {
+    base: usize,
+    data: Vec<u8>,
+}
+
+impl HexDump {
+    /// Creates a new hexdump of the given bytes, located at the given guest address
+    pub fn new(base: usize, data: Vec<u8>) -> Self {
+        Self { base, data }
+    }
+
+    /// Returns the guest address of the first byte
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Returns the dumped bytes
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes the hexdump, returning the dumped bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl fmt::Display for HexDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.data.chunks(BYTES_PER_LINE).enumerate() {
+            write!(f, "{:08x} ", self.base + i * BYTES_PER_LINE)?;
+            for j in 0..BYTES_PER_LINE {
+                if j == BYTES_PER_LINE / 2 {
+                    write!(f, " ")?;
+                }
+                match line.get(j) {
+                    Some(byte) => write!(f, " {byte:02x}")?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            let ascii: String = line
+                .iter()
+                .map(|&b| match b.is_ascii_graphic() || b == b' ' {
+                    true => b as char,
+                    false => '.',
+                })
+                .collect();
+            writeln!(f, "  |{ascii}|")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hexdump_display() {
+        let mut data = b"Hello, world!\n\0\0".to_vec();
+        data.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        let dump = HexDump::new(0x2000_0000, data);
+
+        assert_eq!(
+            dump.to_string(),
+            "20000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|\n\
+             20000010  de ad be ef                                       |....|\n"
+        );
+        assert_eq!(HexDump::new(0, Vec::new()).to_string(), "");
+    }
+}
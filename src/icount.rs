@@ -0,0 +1,116 @@
+use std::io;
+
+/// Instruction counting (`-icount`) settings of QEMU.
+///
+/// With a fixed shift and `sleep=off`, every guest instruction advances the virtual clock by exactly
+/// `2^shift` nanoseconds and QEMU never waits for the host clock, so every run of a test produces the
+/// same virtual timeline. Auto shift, `sleep=on` and `align=on` tie virtual time to host time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(deny_unknown_fields))]
+pub struct Icount {
+    /// Each instruction takes `2^shift` ns of virtual time. `None` lets QEMU adjust it (`shift=auto`).
+    #[cfg_attr(feature = "config", serde(default))]
+    pub shift: Option<u8>,
+    /// Whether the virtual CPU sleeps when idle, waiting for the host clock to catch up
+    #[cfg_attr(feature = "config", serde(default))]
+    pub sleep: bool,
+    /// Whether QEMU delays execution to keep the virtual clock aligned with the host clock
+    #[cfg_attr(feature = "config", serde(default))]
+    pub align: bool,
+}
+
+impl Icount {
+    /// Deterministic settings: each instruction takes `2^shift` ns, without sleeping nor aligning
+    pub fn fixed(shift: u8) -> Self {
+        Self {
+            shift: Some(shift),
+            sleep: false,
+            align: false,
+        }
+    }
+
+    /// Settings with the shift adjusted by QEMU (`shift=auto`), which are not deterministic
+    pub fn auto() -> Self {
+        Self {
+            shift: None,
+            sleep: true,
+            align: false,
+        }
+    }
+
+    /// Sets whether the virtual CPU sleeps when idle
+    pub fn sleep(mut self, sleep: bool) -> Self {
+        self.sleep = sleep;
+        self
+    }
+
+    /// Sets whether QEMU keeps the virtual clock aligned with the host clock
+    pub fn align(mut self, align: bool) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Returns true if the virtual timeline of a run does not depend on the host
+    pub fn is_deterministic(&self) -> bool {
+        self.shift.is_some() && !self.sleep && !self.align
+    }
+
+    /// Returns the virtual time taken by each instruction, if the shift is fixed
+    pub fn ns_per_insn(&self) -> Option<u64> {
+        self.shift.map(|shift| 1 << shift)
+    }
+
+    /// Returns the value of the `-icount` option.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] for the combinations rejected by QEMU.
+    pub fn to_arg(&self) -> io::Result<String> {
+        if self.shift.is_some_and(|shift| shift > 10) {
+            return Err(invalid("icount shift must be between 0 and 10"));
+        }
+        if self.align && (self.shift.is_none() || !self.sleep) {
+            return Err(invalid(
+                "icount align=on requires a fixed shift and sleep=on",
+            ));
+        }
+        let shift = match self.shift {
+            Some(shift) => shift.to_string(),
+            None => "auto".to_string(),
+        };
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        Ok(format!(
+            "shift={shift},sleep={},align={}",
+            on_off(self.sleep),
+            on_off(self.align)
+        ))
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_icount() {
+        let icount = Icount::fixed(3);
+        assert!(icount.is_deterministic());
+        assert_eq!(icount.ns_per_insn(), Some(8));
+        assert_eq!(icount.to_arg().unwrap(), "shift=3,sleep=off,align=off");
+
+        let icount = Icount::auto();
+        assert!(!icount.is_deterministic());
+        assert_eq!(icount.to_arg().unwrap(), "shift=auto,sleep=on,align=off");
+
+        let icount = Icount::fixed(2).sleep(true).align(true);
+        assert!(!icount.is_deterministic());
+        assert_eq!(icount.to_arg().unwrap(), "shift=2,sleep=on,align=on");
+
+        assert!(Icount::fixed(2).align(true).to_arg().is_err());
+        assert!(Icount::auto().align(true).to_arg().is_err());
+        assert!(Icount::fixed(11).to_arg().is_err());
+    }
+}
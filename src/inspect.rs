@@ -0,0 +1,358 @@
+use std::{io, ops::Range};
+
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::hexdump::HexDump;
+use crate::parser::Parser;
+use crate::socket::Socket;
+use crate::transfer::read_chunked;
+use crate::Endianness;
+
+/// Layout of the stack of the firmware, growing downwards from `end`.
+///
+/// The bounds are usually the symbols defined by the linker script, such as `_sstack` and `_estack`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StackLayout {
+    /// Lowest address of the stack
+    pub start: Address,
+    /// Address right after the highest address of the stack
+    pub end: Address,
+    /// Word the stack is painted with at boot, used to find its high-water mark
+    pub paint: Option<u32>,
+    /// Word placed at the lowest address of the stack, overwritten on stack overflow
+    pub canary: Option<u32>,
+    /// Byte order of the words of the stack
+    pub endianness: Endianness,
+}
+
+impl StackLayout {
+    /// Creates the layout of an unpainted stack without canary, in little endian
+    pub fn new(start: impl Into<Address>, end: impl Into<Address>) -> Self {
+        Self {
+            start: start.into(),
+            end: end.into(),
+            paint: None,
+            canary: None,
+            endianness: Endianness::Little,
+        }
+    }
+
+    /// Sets the word the stack is painted with
+    pub fn paint(mut self, word: u32) -> Self {
+        self.paint = Some(word);
+        self
+    }
+
+    /// Sets the canary word at the lowest address of the stack
+    pub fn canary(mut self, word: u32) -> Self {
+        self.canary = Some(word);
+        self
+    }
+
+    /// Sets the byte order of the words of the stack
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Analyzes the contents of the stack, located at the given address
+    fn analyze(&self, base: usize, data: Vec<u8>) -> StackReport {
+        let words: Vec<u32> = data
+            .chunks_exact(4)
+            .map(|w| word(w, self.endianness))
+            .collect();
+        let canary_intact = self.canary.map(|canary| words.first() == Some(&canary));
+        let used = self.paint.map(|paint| {
+            let skip = self.canary.is_some() as usize;
+            let painted = words.iter().skip(skip).take_while(|&&w| w == paint).count();
+            data.len() - 4 * (skip + painted)
+        });
+        StackReport {
+            range: base..base + data.len(),
+            used,
+            canary_intact,
+            dump: HexDump::new(base, data),
+        }
+    }
+}
+
+/// Result of inspecting the stack of the firmware
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StackReport {
+    /// Address range of the stack
+    pub range: Range<usize>,
+    /// Number of bytes used at the high-water mark, if the stack is painted
+    pub used: Option<usize>,
+    /// Whether the canary holds its value, if the stack has a canary
+    pub canary_intact: Option<bool>,
+    /// Contents of the whole stack
+    pub dump: HexDump,
+}
+
+impl StackReport {
+    /// Returns true if the canary was overwritten, or if the painted stack was fully used
+    pub fn corrupted(&self) -> bool {
+        self.canary_intact == Some(false) || self.used == Some(self.range.len())
+    }
+
+    /// Returns the contents of the stack from the high-water mark to its top, or the whole stack if it is not painted
+    pub fn used_dump(&self) -> HexDump {
+        let used = self.used.unwrap_or(self.range.len());
+        let offset = self.range.len() - used;
+        HexDump::new(
+            self.range.start + offset,
+            self.dump.bytes()[offset..].to_vec(),
+        )
+    }
+}
+
+/// Reads the stack described by the given layout and reports its usage and canary.
+///
+/// Symbolic bounds are resolved with the symbol table of the parser.
+pub async fn inspect_stack<T: Socket>(
+    parser: &mut Parser<T>,
+    layout: &StackLayout,
+) -> Result<StackReport, QtestError> {
+    let range = resolve_range(parser, &layout.start, &layout.end)?;
+    let data = read_chunked(parser, range.start, range.len(), |_, _| {}).await?;
+    Ok(layout.analyze(range.start, data))
+}
+
+/// Layout of the block headers of a heap allocator.
+///
+/// Every block starts with a header holding its size in a 32-bit word, and a flag marking allocated blocks.
+/// The presets cover common allocators, and the fields can be adjusted for others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapLayout {
+    /// Size of the header of a block, in bytes
+    pub header_size: usize,
+    /// Offset of the size word in the header
+    pub size_offset: usize,
+    /// Bits of the size word holding the size of the block
+    pub size_mask: u32,
+    /// Whether the size of a block includes its header
+    pub size_includes_header: bool,
+    /// Bits of the size word set for allocated blocks
+    pub used_mask: u32,
+    /// Alignment of the blocks, in bytes
+    pub alignment: usize,
+    /// Offset in the header and value of the canary word, if any
+    pub canary: Option<(usize, u32)>,
+    /// Byte order of the words of the header
+    pub endianness: Endianness,
+}
+
+impl HeapLayout {
+    /// Layout of the FreeRTOS `heap_4` and `heap_5` allocators on 32-bit targets:
+    /// a next-free pointer and a size with the most significant bit set for allocated blocks
+    pub fn freertos_heap4() -> Self {
+        Self {
+            header_size: 8,
+            size_offset: 4,
+            size_mask: 0x7fff_ffff,
+            size_includes_header: true,
+            used_mask: 0x8000_0000,
+            alignment: 8,
+            canary: None,
+            endianness: Endianness::Little,
+        }
+    }
+
+    /// Sets the canary word of the headers
+    pub fn canary(mut self, offset: usize, word: u32) -> Self {
+        self.canary = Some((offset, word));
+        self
+    }
+
+    /// Walks the blocks of the heap, located at the given address
+    fn walk(&self, base: usize, data: &[u8]) -> HeapReport {
+        let mut blocks = Vec::new();
+        let mut corruption = None;
+        let mut offset = 0;
+        while offset + self.header_size <= data.len() {
+            let header = &data[offset..offset + self.header_size];
+            let size_word = word(&header[self.size_offset..], self.endianness);
+            let mut size = (size_word & self.size_mask) as usize;
+            if size == 0 {
+                break;
+            }
+            if !self.size_includes_header {
+                size += self.header_size;
+            }
+            let addr = base + offset;
+            if size < self.header_size
+                || !size.is_multiple_of(self.alignment)
+                || offset + size > data.len()
+            {
+                corruption = Some(HeapCorruption { addr, size_word });
+                break;
+            }
+            blocks.push(HeapBlock {
+                addr,
+                size,
+                used: size_word & self.used_mask != 0,
+                canary_intact: self
+                    .canary
+                    .map(|(at, canary)| word(&header[at..], self.endianness) == canary),
+            });
+            offset += size;
+        }
+        HeapReport {
+            range: base..base + data.len(),
+            blocks,
+            corruption,
+        }
+    }
+}
+
+/// Block of the heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapBlock {
+    /// Address of the header of the block
+    pub addr: usize,
+    /// Size of the block, including its header
+    pub size: usize,
+    /// Whether the block is allocated
+    pub used: bool,
+    /// Whether the canary of the header holds its value, if the layout has a canary
+    pub canary_intact: Option<bool>,
+}
+
+/// Header with an invalid size, which stopped the walk of the heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapCorruption {
+    /// Address of the header
+    pub addr: usize,
+    /// Raw size word of the header
+    pub size_word: u32,
+}
+
+/// Result of walking the heap of the firmware
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HeapReport {
+    /// Address range of the heap
+    pub range: Range<usize>,
+    /// Blocks of the heap, in address order, up to the end of the heap, a zero size or a corrupted header
+    pub blocks: Vec<HeapBlock>,
+    /// Header with an invalid size, if any
+    pub corruption: Option<HeapCorruption>,
+}
+
+impl HeapReport {
+    /// Returns true if a header has an invalid size or an overwritten canary
+    pub fn corrupted(&self) -> bool {
+        self.corruption.is_some() || self.blocks.iter().any(|b| b.canary_intact == Some(false))
+    }
+
+    /// Returns the number of bytes of the allocated blocks, including their headers
+    pub fn used_bytes(&self) -> usize {
+        self.blocks.iter().filter(|b| b.used).map(|b| b.size).sum()
+    }
+
+    /// Returns the number of bytes of the free blocks, including their headers
+    pub fn free_bytes(&self) -> usize {
+        self.blocks.iter().filter(|b| !b.used).map(|b| b.size).sum()
+    }
+}
+
+/// Reads the heap between the given addresses and walks its blocks with the given allocator layout.
+///
+/// Symbolic bounds are resolved with the symbol table of the parser.
+pub async fn inspect_heap<T: Socket>(
+    parser: &mut Parser<T>,
+    start: impl Into<Address>,
+    end: impl Into<Address>,
+    layout: &HeapLayout,
+) -> Result<HeapReport, QtestError> {
+    let range = resolve_range(parser, &start.into(), &end.into())?;
+    let data = read_chunked(parser, range.start, range.len(), |_, _| {}).await?;
+    Ok(layout.walk(range.start, &data))
+}
+
+/// Resolves the bounds of a memory region
+fn resolve_range<T: Socket>(
+    parser: &Parser<T>,
+    start: &Address,
+    end: &Address,
+) -> Result<Range<usize>, QtestError> {
+    let (start, end) = (parser.resolve(start.clone())?, parser.resolve(end.clone())?);
+    if end < start {
+        return Err(QtestError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Region ends at {end:#x}, before its start at {start:#x}"),
+        )));
+    }
+    Ok(start..end)
+}
+
+/// Converts the first four bytes to a word with the given byte order
+fn word(bytes: &[u8], endianness: Endianness) -> u32 {
+    let bytes = bytes[..4].try_into().unwrap();
+    match endianness {
+        Endianness::Little => u32::from_le_bytes(bytes),
+        Endianness::Big => u32::from_be_bytes(bytes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_stack() {
+        let layout = StackLayout::new(0x2000_0000, 0x2000_0014)
+            .paint(0xcccc_cccc)
+            .canary(0xdead_beef);
+        let data = words(&[0xdead_beef, 0xcccc_cccc, 0xcccc_cccc, 1, 2]);
+        let report = layout.analyze(0x2000_0000, data);
+        assert_eq!(report.used, Some(8));
+        assert_eq!(report.canary_intact, Some(true));
+        assert!(!report.corrupted());
+        assert_eq!(report.used_dump().base(), 0x2000_000c);
+        assert_eq!(report.used_dump().bytes(), &words(&[1, 2])[..]);
+
+        let data = words(&[0, 3, 4, 1, 2]);
+        let report = layout.analyze(0x2000_0000, data);
+        assert_eq!(report.used, Some(16));
+        assert_eq!(report.canary_intact, Some(false));
+        assert!(report.corrupted());
+    }
+
+    #[test]
+    fn test_heap() {
+        let layout = HeapLayout::freertos_heap4();
+        let data = words(&[
+            0,
+            0x8000_0010,
+            0xaa,
+            0xaa, // allocated, 16 bytes
+            0,
+            0x0000_0008, // free, 8 bytes
+            0,
+            0, // end marker
+        ]);
+        let report = layout.walk(0x2000_0000, &data);
+        assert_eq!(report.blocks.len(), 2);
+        assert_eq!(report.blocks[1].addr, 0x2000_0010);
+        assert!(report.blocks[0].used && !report.blocks[1].used);
+        assert_eq!((report.used_bytes(), report.free_bytes()), (16, 8));
+        assert!(!report.corrupted());
+
+        let data = words(&[0, 0x8000_0010, 0, 0, 0, 0x0000_0007, 0, 0]);
+        let report = layout.walk(0x2000_0000, &data);
+        assert_eq!(report.blocks.len(), 1);
+        let corruption = report.corruption.unwrap();
+        assert_eq!((corruption.addr, corruption.size_word), (0x2000_0010, 7));
+
+        let layout = layout.canary(0, 0x5a5a_5a5a);
+        let data = words(&[0x5a5a_5a5a, 0x8000_0008, 0, 0x8000_0008]);
+        let report = layout.walk(0x2000_0000, &data);
+        assert_eq!(report.blocks[0].canary_intact, Some(true));
+        assert_eq!(report.blocks[1].canary_intact, Some(false));
+        assert!(report.corrupted());
+    }
+}
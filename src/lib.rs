@@ -1,128 +1,90 @@
+extern crate alloc;
+
+/// Address module, used to refer to guest memory by firmware symbol.
+pub mod address;
+/// Blocking module, synchronous parser API for test setups without tokio.
+#[cfg(feature = "sync")]
+pub mod blocking;
+/// Board module, used to load descriptions of the peripherals of a board from TOML files.
+#[cfg(feature = "config")]
+pub mod board;
+/// Checksum module, used to validate guest memory contents without holding them in memory.
+pub mod checksum;
+/// Clock module, used to control the virtual clock of the guest.
+pub mod clock;
+/// Codegen module, converts recorded transcripts into Rust regression tests.
+pub mod codegen;
+/// Compare module, used to diff the behavior of two QEMU builds running the same commands.
+#[cfg(feature = "qemu-launcher")]
+pub mod compare;
+mod compression;
+/// Config module, used to load session settings from TOML files.
+#[cfg(feature = "config")]
+pub mod config;
+/// Conformance module, used to check which qtest commands a QEMU build supports.
+pub mod conformance;
+/// Devices module, helpers to drive common peripherals of the guest.
+pub mod devices;
+/// Error module, typed errors of the qtest sessions.
+pub mod error;
+/// Event module, unified stream of everything that happens in a qtest session.
+pub mod event;
+/// C FFI module, C-compatible API for C test harnesses.
+#[cfg(feature = "ffi")]
+pub mod ffi;
+/// Hexdump module, used to display guest memory contents.
+pub mod hexdump;
+/// Icount module, instruction counting settings that make the virtual timeline of QEMU deterministic.
+pub mod icount;
+/// Inspect module, used to examine the stack and heap of the firmware in crash triage.
+pub mod inspect;
+/// Loader module, used to load firmware images into guest memory.
+pub mod loader;
+mod logging;
+/// Machine module, high-level facade of an emulated machine.
+pub mod machine;
+/// Memory test module, used to smoke test RAM/ROM device models.
+pub mod memtest;
+/// Mock module, fake QEMU used to test harnesses without launching QEMU.
+pub mod mock;
+/// Monitor module, used to display the IRQ lines, clock and commands of a session live in a terminal.
+#[cfg(feature = "monitor")]
+pub mod monitor;
+/// OpenTelemetry module, used to export sessions as traces to an OTLP endpoint.
+#[cfg(feature = "otel")]
+pub mod otel;
 /// Parser module, interface to interact with qtest
 pub mod parser;
+/// Pool module, used to lease machines exclusively to concurrent tests.
+#[cfg(feature = "qemu-launcher")]
+pub mod pool;
+/// Protocol module, transport-free encoding and decoding of qtest messages.
+pub mod protocol;
+/// QEMU module, used to locate, configure and run QEMU.
+#[cfg(feature = "qemu-launcher")]
+pub mod qemu;
+/// QMP module, client of the QMP monitor used to control QEMU alongside qtest.
+#[cfg(feature = "qmp")]
+pub mod qmp;
+/// Region module, used to access blocks of guest memory by bounds-checked offsets.
+pub mod region;
+/// Register map module, used to access the registers of peripherals by name.
+pub mod regmap;
+/// Snapshot module, used to capture and compare regions of guest memory.
+pub mod snapshot;
 /// Socket module, used to serve and manage qtest socket connections.
 pub mod socket;
-
-/// QTest Response enum
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Response {
-    /// Successfull response, without any additional data
-    Ok,
-    /// Successfull response, with additional data
-    OkVal(String),
-    /// Error in processing the request
-    Err(String),
-}
-
-// Converts a qtest response string to a Response enum
-impl From<&str> for Response {
-    fn from(s: &str) -> Self {
-        let mut s_parts = s.split_whitespace();
-        if s_parts.next() != Some("OK") {
-            return Self::Err(s.to_string());
-        }
-        match s_parts.next() {
-            Some(val) => {
-                let msg = std::iter::once(val)
-                    .chain(s_parts)
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                Self::OkVal(msg)
-            }
-            None => Self::Ok,
-        }
-    }
-}
-
-/// Struct for defining IRQ events propagated by QEMU.
-///
-/// The line and state depends on the machine that emits the event.
-/// Refer to QEMU documentation for your desired machine.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct Irq {
-    /// The line of the IRQ event
-    pub line: usize,
-    /// The state of the IRQ event
-    pub state: IrqState,
-}
-
-impl Irq {
-    /// Creates a new IRQ instance
-    pub fn new(line: usize, state: IrqState) -> Self {
-        Irq { line, state }
-    }
-}
-
-/// Enum for defining the state of an IRQ event
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum IrqState {
-    /// The IRQ event is raised
-    Raise,
-    /// The IRQ event is lowered
-    Lower,
-}
-
-impl TryFrom<&str> for Irq {
-    type Error = &'static str;
-
-    fn try_from(s: &str) -> Result<Self, Self::Error> {
-        let mut s_parts = s.split_whitespace();
-
-        if s_parts.next() != Some("IRQ") {
-            return Err("Invalid IRQ string");
-        }
-        let ty = match s_parts.next() {
-            Some("raise") => IrqState::Raise,
-            Some("lower") => IrqState::Lower,
-            _ => return Err("Invalid IRQ type"),
-        };
-        let line = s_parts
-            .next()
-            .ok_or("Invalid IRQ line")?
-            .parse()
-            .map_err(|_| "Invalid IRQ line")?;
-
-        match s_parts.next() {
-            Some(_) => Err("Invalid IRQ string"),
-            None => Ok(Irq::new(line, ty)),
-        }
-    }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_response_from() {
-        let response = Response::from("OK");
-        assert_eq!(response, Response::Ok);
-
-        let response = Response::from("OK val");
-        assert_eq!(response, Response::OkVal("val".to_string()));
-
-        let response = Response::from("ERR error");
-        assert_eq!(response, Response::Err("ERR error".to_string()));
-    }
-
-    #[test]
-    fn test_irq_try_from() {
-        let irq = Irq::try_from("invalid");
-        assert_eq!(irq, Err("Invalid IRQ string"));
-
-        let irq = Irq::try_from("IRQ invalid");
-        assert_eq!(irq, Err("Invalid IRQ type"));
-
-        let irq = Irq::try_from("IRQ raise -1");
-        assert_eq!(irq, Err("Invalid IRQ line"));
-
-        let irq = Irq::try_from("IRQ raise 1 invalid");
-        assert_eq!(irq, Err("Invalid IRQ string"));
-
-        let irq = Irq::try_from("IRQ raise 1");
-        assert_eq!(irq, Ok(Irq::new(1, IrqState::Raise)));
-
-        let irq = Irq::try_from("IRQ lower 2");
-        assert_eq!(irq, Ok(Irq::new(2, IrqState::Lower)));
-    }
-}
+/// Strings module, used to read strings from guest memory.
+pub mod strings;
+/// Target module, used to select named test targets from a registry file.
+#[cfg(feature = "config")]
+pub mod target;
+/// Transcript module, used to record sessions and store them in files.
+pub mod transcript;
+/// Transfer module, used to move large blocks of data in and out of guest memory.
+pub mod transfer;
+/// WebSocket module, used to push the event stream to live monitors.
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use protocol::{Endianness, Irq, IrqState, Response};
@@ -0,0 +1,220 @@
+use std::{fs, io, ops::Range, path::Path};
+
+use crate::parser::Parser;
+use crate::socket::Socket;
+#[cfg(feature = "base64")]
+use crate::transfer::b64write_chunked;
+use crate::transfer::read_chunked;
+#[cfg(not(feature = "base64"))]
+use crate::transfer::write_chunked;
+
+pub mod dwarf;
+pub mod elf;
+pub mod ihex;
+
+pub use dwarf::{DebugInfo, VarLocation, VarValue};
+pub use elf::{ElfFile, Segment, Symbol};
+pub use ihex::IhexFile;
+
+/// Segment of an ELF file loaded into guest memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoadedSegment {
+    /// Address where the segment was loaded
+    pub addr: usize,
+    /// Number of bytes written from the file
+    pub file_size: usize,
+    /// Size of the segment in memory, including the zeroed bytes (BSS)
+    pub mem_size: usize,
+}
+
+impl LoadedSegment {
+    /// Returns the range of addresses of the segment in memory
+    pub fn range(&self) -> Range<usize> {
+        self.addr..self.addr + self.mem_size
+    }
+}
+
+/// Result of loading an ELF file into guest memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedElf {
+    /// Entry point of the program
+    pub entry: u64,
+    /// Segments loaded, in the order of the program headers
+    pub segments: Vec<LoadedSegment>,
+    /// Named symbols of the symbol table of the program
+    pub symbols: Vec<Symbol>,
+}
+
+impl LoadedElf {
+    /// Returns the symbol with the given name, if present
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+}
+
+/// Loads the ELF file at the given path into guest memory.
+///
+/// All the `PT_LOAD` segments are written to their physical addresses, and the bytes of each segment
+/// that are not present in the file (BSS) are zeroed with `memset`. Returns the entry point, the loaded segments
+/// and the symbol table of the program.
+///
+/// The symbol table also becomes the symbol table of the parser, so the memory functions accept symbol names.
+pub async fn load_elf<T: Socket, P: AsRef<Path>>(
+    parser: &mut Parser<T>,
+    path: P,
+) -> io::Result<LoadedElf> {
+    let elf = ElfFile::parse(&fs::read(path)?)?;
+
+    let mut segments = Vec::with_capacity(elf.segments.len());
+    for segment in &elf.segments {
+        let addr = segment.paddr as usize;
+        write_image(parser, addr, &segment.data, |_, _| {}).await?;
+
+        let mem_size = segment.mem_size as usize;
+        let bss_size = mem_size - segment.data.len();
+        if bss_size > 0 {
+            let bss_addr = addr + segment.data.len();
+            parser.memset(bss_addr, bss_size, 0).await?;
+        }
+        segments.push(LoadedSegment {
+            addr,
+            file_size: segment.data.len(),
+            mem_size,
+        });
+    }
+
+    parser.set_symbols(elf.symbols.clone());
+    Ok(LoadedElf {
+        entry: elf.entry,
+        segments,
+        symbols: elf.symbols,
+    })
+}
+
+/// Loads the Intel HEX file at the given path into guest memory.
+///
+/// Each contiguous block of data is written to its address, leaving the gaps between blocks untouched.
+/// If `verify` is true, the written data is read back and compared with the file contents.
+/// Returns the parsed file, including its start address if present.
+pub async fn load_ihex<T: Socket, P: AsRef<Path>>(
+    parser: &mut Parser<T>,
+    path: P,
+    verify: bool,
+) -> io::Result<IhexFile> {
+    let ihex = IhexFile::parse(&fs::read_to_string(path)?)?;
+
+    for segment in &ihex.segments {
+        write_image(parser, segment.paddr as usize, &segment.data, |_, _| {}).await?;
+    }
+
+    if verify {
+        for segment in &ihex.segments {
+            let addr = segment.paddr as usize;
+            let data = read_chunked(parser, addr, segment.data.len(), |_, _| {}).await?;
+            if let Some(offset) = data.iter().zip(&segment.data).position(|(a, b)| a != b) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Verification failed at {:#x}", addr + offset),
+                ));
+            }
+        }
+    }
+
+    Ok(ihex)
+}
+
+/// Loads the raw binary file at the given path into guest memory, starting at the given address.
+///
+/// After each chunk, `progress` is called with the number of bytes written so far and the total number of bytes.
+/// Returns the number of bytes loaded.
+pub async fn load_bin<T: Socket, P: AsRef<Path>, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: usize,
+    path: P,
+    progress: F,
+) -> io::Result<usize> {
+    let data = fs::read(path)?;
+    write_image(parser, addr, &data, progress).await?;
+    Ok(data.len())
+}
+
+/// Dumps the given number of bytes of guest memory, starting at the given address, to a raw binary file.
+///
+/// After each chunk, `progress` is called with the number of bytes read so far and the total number of bytes.
+pub async fn dump_bin<T: Socket, P: AsRef<Path>, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: usize,
+    len: usize,
+    path: P,
+    progress: F,
+) -> io::Result<()> {
+    let data = read_chunked(parser, addr, len, progress).await?;
+    fs::write(path, data)
+}
+
+/// Writes an image to guest memory in chunks, with `b64write` if the `base64` feature is enabled
+async fn write_image<T: Socket, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: usize,
+    data: &[u8],
+    progress: F,
+) -> io::Result<()> {
+    #[cfg(feature = "base64")]
+    b64write_chunked(parser, addr, data, progress).await?;
+    #[cfg(not(feature = "base64"))]
+    write_chunked(parser, addr, data, progress).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockQtestDevice;
+    use crate::protocol::Command;
+    use crate::socket::mem::{self, SocketPair};
+
+    #[tokio::test]
+    async fn test_load_images() {
+        let device = MockQtestDevice::new();
+        device.write_mem(0x2000_0004, &[0xff; 4]);
+        let (mut parser, _irq_rx) = Parser::<SocketPair>::new("loader").await.unwrap();
+        device.serve(mem::connect("loader").await.unwrap());
+        parser.attach_connection().await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("qtest-loader-{}.elf", std::process::id()));
+        fs::write(&path, elf::test::elf32()).unwrap();
+        let loaded = load_elf(&mut parser, &path).await.unwrap();
+        assert_eq!(loaded.entry, 0x2000_0001);
+        assert_eq!(
+            loaded.segments,
+            [LoadedSegment {
+                addr: 0x2000_0000,
+                file_size: 4,
+                mem_size: 8,
+            }]
+        );
+        assert_eq!(loaded.segments[0].range(), 0x2000_0000..0x2000_0008);
+        // The BSS is zeroed
+        assert_eq!(device.read_mem(0x2000_0000, 8), [1, 2, 3, 4, 0, 0, 0, 0]);
+
+        let data: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        fs::write(&path, &data).unwrap();
+        let mut chunks = 0;
+        let len = load_bin(&mut parser, 0x3000_0000, &path, |_, _| chunks += 1)
+            .await
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((len, chunks), (5000, 2));
+        assert_eq!(device.read_mem(0x3000_0000, 5000), data);
+
+        #[cfg(feature = "base64")]
+        assert!(device
+            .commands()
+            .iter()
+            .any(|command| matches!(command, Command::B64Write { .. })));
+        assert!(device
+            .commands()
+            .iter()
+            .any(|command| matches!(command, Command::Memset { val: 0, .. })));
+    }
+}
@@ -0,0 +1,952 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use super::elf::ElfFile;
+use crate::Endianness;
+
+/// DWARF tags of the entries kept by [`DebugInfo`]
+const TAG_ARRAY_TYPE: u64 = 0x01;
+const TAG_CLASS_TYPE: u64 = 0x02;
+const TAG_ENUMERATION_TYPE: u64 = 0x04;
+const TAG_MEMBER: u64 = 0x0d;
+const TAG_POINTER_TYPE: u64 = 0x0f;
+const TAG_COMPILE_UNIT: u64 = 0x11;
+const TAG_STRUCTURE_TYPE: u64 = 0x13;
+const TAG_TYPEDEF: u64 = 0x16;
+const TAG_UNION_TYPE: u64 = 0x17;
+const TAG_SUBRANGE_TYPE: u64 = 0x21;
+const TAG_BASE_TYPE: u64 = 0x24;
+const TAG_CONST_TYPE: u64 = 0x26;
+const TAG_VARIABLE: u64 = 0x34;
+const TAG_VOLATILE_TYPE: u64 = 0x35;
+const TAG_RESTRICT_TYPE: u64 = 0x37;
+const TAG_PARTIAL_UNIT: u64 = 0x3c;
+const TAG_ATOMIC_TYPE: u64 = 0x47;
+
+/// DWARF attributes read by [`DebugInfo`]
+const AT_LOCATION: u64 = 0x02;
+const AT_NAME: u64 = 0x03;
+const AT_BYTE_SIZE: u64 = 0x0b;
+const AT_UPPER_BOUND: u64 = 0x2f;
+const AT_COUNT: u64 = 0x37;
+const AT_DATA_MEMBER_LOCATION: u64 = 0x38;
+const AT_SPECIFICATION: u64 = 0x47;
+const AT_TYPE: u64 = 0x49;
+const AT_STR_OFFSETS_BASE: u64 = 0x72;
+const AT_ADDR_BASE: u64 = 0x73;
+
+/// DWARF expression operations of static variable locations
+const OP_ADDR: u8 = 0x03;
+const OP_PLUS_UCONST: u8 = 0x23;
+const OP_ADDRX: u8 = 0xa1;
+const OP_GNU_ADDR_INDEX: u8 = 0xfb;
+
+/// Location of a (part of a) variable in guest memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VarLocation {
+    /// Address of the variable
+    pub addr: u64,
+    /// Size of the variable, in bytes
+    pub size: u64,
+}
+
+/// Debugging information of a program, parsed from its DWARF sections (versions 2 to 5).
+///
+/// Only the information required to locate static variables is kept: their addresses and the layout of their types.
+/// Variables are looked up with C-like paths, such as `driver_state.flags` or `rx_ring.slots[3].len`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugInfo {
+    endianness: Endianness,
+    entries: HashMap<u64, Entry>,
+    variables: HashMap<String, (u64, u64)>,
+}
+
+impl DebugInfo {
+    /// Reads the debugging information of the ELF file at the given path
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&ElfFile::parse(&fs::read(path)?)?)
+    }
+
+    /// Parses the debugging information of the given ELF file.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the file has no (uncompressed) `.debug_info` section.
+    pub fn parse(elf: &ElfFile) -> io::Result<Self> {
+        let section = |name: &str| elf.debug_sections.get(name).map_or(&[][..], Vec::as_slice);
+        let info = elf
+            .debug_sections
+            .get(".debug_info")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No .debug_info section"))?;
+        Sections {
+            info,
+            abbrev: section(".debug_abbrev"),
+            str: section(".debug_str"),
+            line_str: section(".debug_line_str"),
+            str_offsets: section(".debug_str_offsets"),
+            addr: section(".debug_addr"),
+            is_le: elf.endianness == Endianness::Little,
+        }
+        .parse()
+    }
+
+    /// Returns the byte order of the program
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Returns the location of the given variable path.
+    ///
+    /// The path starts with the name of a static variable, followed by any number of `.member` and `[index]` accesses.
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the variable or a member does not exist,
+    /// and of kind [`io::ErrorKind::InvalidInput`] if the path is malformed or does not match the types.
+    pub fn resolve(&self, path: &str) -> io::Result<VarLocation> {
+        let (name, steps) = parse_path(path)?;
+        let &(mut addr, mut ty) = self
+            .variables
+            .get(name)
+            .ok_or_else(|| not_found(format!("Unknown variable {name}")))?;
+        // Number of dimensions of the array `ty` already indexed
+        let mut indexed = 0;
+
+        for step in steps {
+            match step {
+                Step::Member(member) => {
+                    let entry = self.strip(ty)?;
+                    if indexed > 0 || !entry.is_aggregate() {
+                        return Err(invalid_input(format!("{path}: {member} is not a member")));
+                    }
+                    let (offset, member_ty) = self
+                        .member(entry, member)
+                        .ok_or_else(|| not_found(format!("{path}: unknown member {member}")))?;
+                    addr += offset;
+                    ty = member_ty;
+                }
+                Step::Index(index) => {
+                    let array_ty = if indexed == 0 {
+                        self.strip_offset(ty)?
+                    } else {
+                        ty
+                    };
+                    let array = &self.entries[&array_ty];
+                    if array.tag != TAG_ARRAY_TYPE {
+                        return Err(invalid_input(format!("{path}: indexing a non-array")));
+                    }
+                    let dims = self.dims(array);
+                    let elem_ty = array.ty.ok_or_else(|| invalid_data("array without type"))?;
+                    if dims.get(indexed).is_some_and(|&dim| index >= dim) {
+                        return Err(invalid_input(format!(
+                            "{path}: index {index} out of bounds"
+                        )));
+                    }
+                    let stride =
+                        self.size_of(elem_ty)? * dims[indexed + 1..].iter().product::<u64>();
+                    addr += index * stride;
+                    indexed += 1;
+                    (ty, indexed) = match indexed == dims.len() {
+                        true => (elem_ty, 0),
+                        false => (array_ty, indexed),
+                    };
+                }
+            }
+        }
+
+        let size = match indexed {
+            0 => self.size_of(ty)?,
+            _ => {
+                let array = &self.entries[&ty];
+                let elem_ty = array.ty.ok_or_else(|| invalid_data("array without type"))?;
+                self.size_of(elem_ty)? * self.dims(array)[indexed..].iter().product::<u64>()
+            }
+        };
+        Ok(VarLocation { addr, size })
+    }
+
+    /// Returns the offset of the type entry, skipping typedefs and qualifiers
+    fn strip_offset(&self, mut ty: u64) -> io::Result<u64> {
+        loop {
+            let entry = self.entry(ty)?;
+            match entry.tag {
+                TAG_TYPEDEF | TAG_CONST_TYPE | TAG_VOLATILE_TYPE | TAG_RESTRICT_TYPE
+                | TAG_ATOMIC_TYPE => {
+                    ty = entry.ty.ok_or_else(|| invalid_data("void variable type"))?;
+                }
+                _ => return Ok(ty),
+            }
+        }
+    }
+
+    /// Returns the type entry, skipping typedefs and qualifiers
+    fn strip(&self, ty: u64) -> io::Result<&Entry> {
+        self.entry(self.strip_offset(ty)?)
+    }
+
+    fn entry(&self, offset: u64) -> io::Result<&Entry> {
+        self.entries
+            .get(&offset)
+            .ok_or_else(|| invalid_data(format!("unsupported type entry at {offset:#x}")))
+    }
+
+    /// Returns the size in bytes of the given type
+    fn size_of(&self, ty: u64) -> io::Result<u64> {
+        let entry = self.strip(ty)?;
+        match entry.tag {
+            TAG_ARRAY_TYPE => {
+                let elem_ty = entry.ty.ok_or_else(|| invalid_data("array without type"))?;
+                Ok(self.size_of(elem_ty)? * self.dims(entry).iter().product::<u64>())
+            }
+            _ => entry
+                .byte_size
+                .ok_or_else(|| invalid_data(format!("type {:?} without size", entry.name))),
+        }
+    }
+
+    /// Returns the number of elements of each dimension of the given array type
+    fn dims(&self, array: &Entry) -> Vec<u64> {
+        array
+            .children
+            .iter()
+            .filter_map(|child| self.entries.get(child))
+            .filter(|child| child.tag == TAG_SUBRANGE_TYPE)
+            .map(|child| child.count.unwrap_or(0))
+            .collect()
+    }
+
+    /// Returns the offset and type of the given member, looking into anonymous members too
+    fn member(&self, aggregate: &Entry, name: &str) -> Option<(u64, u64)> {
+        for child in aggregate
+            .children
+            .iter()
+            .filter_map(|c| self.entries.get(c))
+        {
+            if child.tag != TAG_MEMBER {
+                continue;
+            }
+            let (offset, ty) = (child.member_offset.unwrap_or(0), child.ty?);
+            match child.name.as_deref() {
+                Some(member) if member == name => return Some((offset, ty)),
+                None => {
+                    let inner = self.strip(ty).ok().filter(|e| e.is_aggregate())?;
+                    if let Some((inner_offset, ty)) = self.member(inner, name) {
+                        return Some((offset + inner_offset, ty));
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Value of a variable in guest memory, convertible from and to its bytes
+pub trait VarValue: Sized {
+    /// Size of the value, in bytes
+    const SIZE: usize;
+
+    /// Converts the bytes of the value, of length [`VarValue::SIZE`], with the given byte order
+    fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self;
+
+    /// Converts the value to its bytes with the given byte order
+    fn to_bytes(&self, endianness: Endianness) -> Vec<u8>;
+}
+
+macro_rules! impl_var_value {
+    ($($ty:ty),*) => {
+        $(
+            impl VarValue for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+
+                fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self {
+                    let bytes = bytes.try_into().unwrap();
+                    match endianness {
+                        Endianness::Little => <$ty>::from_le_bytes(bytes),
+                        Endianness::Big => <$ty>::from_be_bytes(bytes),
+                    }
+                }
+
+                fn to_bytes(&self, endianness: Endianness) -> Vec<u8> {
+                    match endianness {
+                        Endianness::Little => self.to_le_bytes().to_vec(),
+                        Endianness::Big => self.to_be_bytes().to_vec(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_var_value!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Debugging information entry of a type, member or variable
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Entry {
+    tag: u64,
+    name: Option<String>,
+    ty: Option<u64>,
+    byte_size: Option<u64>,
+    member_offset: Option<u64>,
+    count: Option<u64>,
+    addr: Option<u64>,
+    specification: Option<u64>,
+    children: Vec<u64>,
+}
+
+impl Entry {
+    fn is_aggregate(&self) -> bool {
+        matches!(
+            self.tag,
+            TAG_STRUCTURE_TYPE | TAG_UNION_TYPE | TAG_CLASS_TYPE
+        )
+    }
+}
+
+/// Step of a variable path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step<'a> {
+    Member(&'a str),
+    Index(u64),
+}
+
+/// Splits a variable path in the variable name and the following steps
+fn parse_path(path: &str) -> io::Result<(&str, Vec<Step<'_>>)> {
+    let malformed = || invalid_input(format!("Malformed variable path {path}"));
+    let ident_end = |s: &str| {
+        s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len())
+    };
+
+    let end = ident_end(path);
+    let (name, mut rest) = path.split_at(end);
+    if name.is_empty() {
+        return Err(malformed());
+    }
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('.') {
+            let end = ident_end(r);
+            if end == 0 {
+                return Err(malformed());
+            }
+            steps.push(Step::Member(&r[..end]));
+            rest = &r[end..];
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']').ok_or_else(malformed)?;
+            let index = r[..end].trim().parse().map_err(|_| malformed())?;
+            steps.push(Step::Index(index));
+            rest = &r[end + 1..];
+        } else {
+            return Err(malformed());
+        }
+    }
+    Ok((name, steps))
+}
+
+/// DWARF sections of a program
+struct Sections<'a> {
+    info: &'a [u8],
+    abbrev: &'a [u8],
+    str: &'a [u8],
+    line_str: &'a [u8],
+    str_offsets: &'a [u8],
+    addr: &'a [u8],
+    is_le: bool,
+}
+
+/// Header fields of a unit required to decode its entries
+#[derive(Debug, Clone, Copy)]
+struct Unit {
+    offset: u64,
+    version: u16,
+    offset_size: u8,
+    address_size: u8,
+    str_offsets_base: Option<u64>,
+    addr_base: Option<u64>,
+}
+
+/// Abbreviation of an entry: its tag, whether it has children, and its attributes with their forms
+struct Abbrev {
+    tag: u64,
+    children: bool,
+    attrs: Vec<(u64, u64, i64)>,
+}
+
+/// Raw value of an attribute
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    Unsigned(u64),
+    Signed(i64),
+    Ref(u64),
+    Str(&'a [u8]),
+    StrOffset(u64),
+    LineStrOffset(u64),
+    StrIndex(u64),
+    Block(&'a [u8]),
+    Other,
+}
+
+impl Value<'_> {
+    fn unsigned(self) -> Option<u64> {
+        match self {
+            Value::Unsigned(v) => Some(v),
+            Value::Signed(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Entry of a unit, with its raw attributes
+struct RawEntry<'a> {
+    offset: u64,
+    tag: u64,
+    parent: Option<u64>,
+    attrs: Vec<(u64, Value<'a>)>,
+}
+
+impl<'a> Sections<'a> {
+    fn parse(&self) -> io::Result<DebugInfo> {
+        let mut entries = HashMap::new();
+        let mut variables = Vec::new();
+
+        let mut unit_offset = 0;
+        while unit_offset < self.info.len() as u64 {
+            let mut c = self.cursor(self.info, unit_offset);
+            let (len, offset_size) = match c.u32()? {
+                0xffff_ffff => (c.u64()?, 8),
+                len => (len as u64, 4),
+            };
+            let end = c.pos + len;
+            let version = c.u16()?;
+            let (abbrev_offset, address_size) = match version {
+                2..=4 => (c.offset(offset_size)?, c.u8()?),
+                5 => {
+                    let unit_type = c.u8()?;
+                    let address_size = c.u8()?;
+                    let abbrev_offset = c.offset(offset_size)?;
+                    match unit_type {
+                        0x02 | 0x06 => c.skip(8 + offset_size as u64)?,
+                        0x04 | 0x05 => c.skip(8)?,
+                        _ => {}
+                    }
+                    (abbrev_offset, address_size)
+                }
+                _ => return Err(invalid_data(format!("unsupported DWARF version {version}"))),
+            };
+            let abbrevs = self.abbrevs(abbrev_offset)?;
+            let mut unit = Unit {
+                offset: unit_offset,
+                version,
+                offset_size,
+                address_size,
+                str_offsets_base: None,
+                addr_base: None,
+            };
+
+            let mut raw = Vec::new();
+            let mut parents = Vec::new();
+            while c.pos < end {
+                let offset = c.pos;
+                let code = c.uleb()?;
+                if code == 0 {
+                    parents.pop();
+                    continue;
+                }
+                let abbrev = abbrevs
+                    .get(&code)
+                    .ok_or_else(|| invalid_data(format!("unknown abbreviation {code}")))?;
+                let mut attrs = Vec::new();
+                for &(attr, form, implicit) in &abbrev.attrs {
+                    attrs.push((attr, c.value(form, implicit, &unit)?));
+                }
+                if matches!(abbrev.tag, TAG_COMPILE_UNIT | TAG_PARTIAL_UNIT) {
+                    for &(attr, value) in &attrs {
+                        match attr {
+                            AT_STR_OFFSETS_BASE => unit.str_offsets_base = value.unsigned(),
+                            AT_ADDR_BASE => unit.addr_base = value.unsigned(),
+                            _ => {}
+                        }
+                    }
+                }
+                raw.push(RawEntry {
+                    offset,
+                    tag: abbrev.tag,
+                    parent: parents.last().copied(),
+                    attrs,
+                });
+                if abbrev.children {
+                    parents.push(offset);
+                }
+            }
+
+            for raw in raw {
+                if !is_kept(raw.tag) {
+                    continue;
+                }
+                let entry = self.entry(&raw, &unit)?;
+                if entry.tag == TAG_VARIABLE && entry.addr.is_some() {
+                    variables.push(raw.offset);
+                }
+                if let Some(parent) = raw.parent.and_then(|p| entries.get_mut(&p)) {
+                    let parent: &mut Entry = parent;
+                    parent.children.push(raw.offset);
+                }
+                entries.insert(raw.offset, entry);
+            }
+            unit_offset = end;
+        }
+
+        let mut by_name = HashMap::new();
+        for offset in variables {
+            let var = &entries[&offset];
+            let spec = var.specification.and_then(|s| entries.get(&s));
+            let name = var.name.as_ref().or(spec.and_then(|s| s.name.as_ref()));
+            let ty = var.ty.or(spec.and_then(|s| s.ty));
+            if let (Some(name), Some(addr), Some(ty)) = (name, var.addr, ty) {
+                by_name.entry(name.clone()).or_insert((addr, ty));
+            }
+        }
+
+        Ok(DebugInfo {
+            endianness: match self.is_le {
+                true => Endianness::Little,
+                false => Endianness::Big,
+            },
+            entries,
+            variables: by_name,
+        })
+    }
+
+    /// Decodes the attributes of the given raw entry
+    fn entry(&self, raw: &RawEntry, unit: &Unit) -> io::Result<Entry> {
+        let mut entry = Entry {
+            tag: raw.tag,
+            ..Default::default()
+        };
+        for &(attr, value) in &raw.attrs {
+            match (attr, value) {
+                (AT_NAME, _) => entry.name = self.string(value, unit)?,
+                (AT_TYPE, Value::Ref(r)) => entry.ty = Some(r),
+                (AT_SPECIFICATION, Value::Ref(r)) => entry.specification = Some(r),
+                (AT_BYTE_SIZE, _) => entry.byte_size = value.unsigned(),
+                (AT_COUNT, _) => entry.count = value.unsigned(),
+                (AT_UPPER_BOUND, _) if entry.count.is_none() => {
+                    entry.count = value.unsigned().map(|bound| bound + 1)
+                }
+                (AT_DATA_MEMBER_LOCATION, Value::Block(expr)) => {
+                    let mut c = self.cursor(expr, 0);
+                    if c.u8()? == OP_PLUS_UCONST {
+                        entry.member_offset = Some(c.uleb()?);
+                    }
+                }
+                (AT_DATA_MEMBER_LOCATION, _) => entry.member_offset = value.unsigned(),
+                (AT_LOCATION, Value::Block(expr)) => entry.addr = self.static_addr(expr, unit)?,
+                _ => {}
+            }
+        }
+        if entry.tag == TAG_POINTER_TYPE && entry.byte_size.is_none() {
+            entry.byte_size = Some(unit.address_size as u64);
+        }
+        Ok(entry)
+    }
+
+    /// Returns the address of a location expression, if it is a static address
+    fn static_addr(&self, expr: &[u8], unit: &Unit) -> io::Result<Option<u64>> {
+        let mut c = self.cursor(expr, 0);
+        match expr.first() {
+            Some(&OP_ADDR) => {
+                c.skip(1)?;
+                c.address(unit.address_size).map(Some)
+            }
+            Some(&OP_ADDRX) | Some(&OP_GNU_ADDR_INDEX) => {
+                c.skip(1)?;
+                let index = c.uleb()?;
+                let base = unit.addr_base.unwrap_or(8);
+                let mut c = self.cursor(self.addr, base + index * unit.address_size as u64);
+                c.address(unit.address_size).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the string of an attribute value
+    fn string(&self, value: Value, unit: &Unit) -> io::Result<Option<String>> {
+        let bytes = match value {
+            Value::Str(bytes) => bytes,
+            Value::StrOffset(offset) => self.cursor(self.str, offset).cstr()?,
+            Value::LineStrOffset(offset) => self.cursor(self.line_str, offset).cstr()?,
+            Value::StrIndex(index) => {
+                let base = unit.str_offsets_base.unwrap_or(8);
+                let size = unit.offset_size as u64;
+                let offset = self
+                    .cursor(self.str_offsets, base + index * size)
+                    .offset(unit.offset_size)?;
+                self.cursor(self.str, offset).cstr()?
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    /// Parses the abbreviation table at the given offset
+    fn abbrevs(&self, offset: u64) -> io::Result<HashMap<u64, Abbrev>> {
+        let mut abbrevs = HashMap::new();
+        let mut c = self.cursor(self.abbrev, offset);
+        loop {
+            let code = c.uleb()?;
+            if code == 0 {
+                return Ok(abbrevs);
+            }
+            let tag = c.uleb()?;
+            let children = c.u8()? != 0;
+            let mut attrs = Vec::new();
+            loop {
+                let (attr, form) = (c.uleb()?, c.uleb()?);
+                if attr == 0 && form == 0 {
+                    break;
+                }
+                let implicit = if form == 0x21 { c.sleb()? } else { 0 };
+                attrs.push((attr, form, implicit));
+            }
+            abbrevs.insert(
+                code,
+                Abbrev {
+                    tag,
+                    children,
+                    attrs,
+                },
+            );
+        }
+    }
+
+    fn cursor(&self, bytes: &'a [u8], pos: u64) -> Cursor<'a> {
+        Cursor {
+            bytes,
+            pos,
+            is_le: self.is_le,
+        }
+    }
+}
+
+/// Returns true if the entries with the given tag are kept by [`DebugInfo`]
+fn is_kept(tag: u64) -> bool {
+    matches!(
+        tag,
+        TAG_ARRAY_TYPE
+            | TAG_CLASS_TYPE
+            | TAG_ENUMERATION_TYPE
+            | TAG_MEMBER
+            | TAG_POINTER_TYPE
+            | TAG_STRUCTURE_TYPE
+            | TAG_TYPEDEF
+            | TAG_UNION_TYPE
+            | TAG_SUBRANGE_TYPE
+            | TAG_BASE_TYPE
+            | TAG_CONST_TYPE
+            | TAG_VARIABLE
+            | TAG_VOLATILE_TYPE
+            | TAG_RESTRICT_TYPE
+            | TAG_ATOMIC_TYPE
+    )
+}
+
+/// Endianness-aware reader of the fields of a DWARF section
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: u64,
+    is_le: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: u64) -> io::Result<&'a [u8]> {
+        let bytes = usize::try_from(self.pos)
+            .ok()
+            .zip(usize::try_from(self.pos + len).ok())
+            .and_then(|(start, end)| self.bytes.get(start..end))
+            .ok_or_else(|| invalid_data("truncated DWARF section"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn uint(&mut self, len: u8) -> io::Result<u64> {
+        let bytes = self.take(len as u64)?;
+        let fold = |acc: u64, &b: &u8| (acc << 8) | b as u64;
+        Ok(match self.is_le {
+            true => bytes.iter().rev().fold(0, fold),
+            false => bytes.iter().fold(0, fold),
+        })
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        self.uint(1).map(|v| v as u8)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.uint(2).map(|v| v as u16)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.uint(4).map(|v| v as u32)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.uint(8)
+    }
+
+    fn offset(&mut self, offset_size: u8) -> io::Result<u64> {
+        self.uint(offset_size)
+    }
+
+    fn address(&mut self, address_size: u8) -> io::Result<u64> {
+        self.uint(address_size)
+    }
+
+    fn uleb(&mut self) -> io::Result<u64> {
+        let (mut value, mut shift) = (0u64, 0);
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> io::Result<i64> {
+        let (mut value, mut shift) = (0i64, 0);
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> io::Result<&'a [u8]> {
+        let start = usize::try_from(self.pos).map_err(|_| invalid_data("offset out of range"))?;
+        let rest = self
+            .bytes
+            .get(start..)
+            .ok_or_else(|| invalid_data("truncated DWARF section"))?;
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_data("unterminated DWARF string"))?;
+        self.pos += len as u64 + 1;
+        Ok(&rest[..len])
+    }
+
+    fn block(&mut self, len: u64) -> io::Result<Value<'a>> {
+        self.take(len).map(Value::Block)
+    }
+
+    /// Reads an attribute value of the given form
+    fn value(&mut self, form: u64, implicit: i64, unit: &Unit) -> io::Result<Value<'a>> {
+        let offset_size = unit.offset_size;
+        Ok(match form {
+            0x01 => Value::Unsigned(self.address(unit.address_size)?),
+            0x03 => {
+                let len = self.u16()? as u64;
+                self.block(len)?
+            }
+            0x04 => {
+                let len = self.u32()? as u64;
+                self.block(len)?
+            }
+            0x05 => Value::Unsigned(self.u16()? as u64),
+            0x06 => Value::Unsigned(self.u32()? as u64),
+            0x07 => Value::Unsigned(self.u64()?),
+            0x08 => Value::Str(self.cstr()?),
+            0x09 | 0x18 => {
+                let len = self.uleb()?;
+                self.block(len)?
+            }
+            0x0a => {
+                let len = self.u8()? as u64;
+                self.block(len)?
+            }
+            0x0b => Value::Unsigned(self.u8()? as u64),
+            0x0c => Value::Unsigned(self.u8()? as u64),
+            0x0d => Value::Signed(self.sleb()?),
+            0x0e => Value::StrOffset(self.offset(offset_size)?),
+            0x0f => Value::Unsigned(self.uleb()?),
+            0x10 => match unit.version {
+                2 => Value::Ref(self.address(unit.address_size)?),
+                _ => Value::Ref(self.offset(offset_size)?),
+            },
+            0x11 => Value::Ref(unit.offset + self.u8()? as u64),
+            0x12 => Value::Ref(unit.offset + self.u16()? as u64),
+            0x13 => Value::Ref(unit.offset + self.u32()? as u64),
+            0x14 => Value::Ref(unit.offset + self.u64()?),
+            0x15 => Value::Ref(unit.offset + self.uleb()?),
+            0x16 => {
+                let form = self.uleb()?;
+                self.value(form, implicit, unit)?
+            }
+            0x17 => Value::Unsigned(self.offset(offset_size)?),
+            0x19 => Value::Other,
+            0x1a | 0x1f02 => Value::StrIndex(self.uleb()?),
+            0x1b | 0x22 | 0x23 | 0x1f01 => {
+                self.uleb()?;
+                Value::Other
+            }
+            0x1c => {
+                self.skip(4)?;
+                Value::Other
+            }
+            0x1d | 0x1f20 | 0x1f21 => {
+                self.skip(offset_size as u64)?;
+                Value::Other
+            }
+            0x1e => {
+                self.skip(16)?;
+                Value::Other
+            }
+            0x1f => Value::LineStrOffset(self.offset(offset_size)?),
+            0x20 | 0x24 => {
+                self.skip(8)?;
+                Value::Other
+            }
+            0x21 => Value::Signed(implicit),
+            0x25 => Value::StrIndex(self.uint(1)?),
+            0x26 => Value::StrIndex(self.uint(2)?),
+            0x27 => Value::StrIndex(self.uint(3)?),
+            0x28 => Value::StrIndex(self.uint(4)?),
+            0x29 => self.uint(1).map(|_| Value::Other)?,
+            0x2a => self.uint(2).map(|_| Value::Other)?,
+            0x2b => self.uint(3).map(|_| Value::Other)?,
+            0x2c => self.uint(4).map(|_| Value::Other)?,
+            _ => return Err(invalid_data(format!("unsupported DWARF form {form:#x}"))),
+        })
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn not_found(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a DWARF 4 unit (32-bit little endian) equivalent to:
+    ///
+    /// ```c
+    /// struct driver_state { uint8_t mode; uint32_t flags; };
+    /// struct driver_state driver_state;   // at 0x2000_0000
+    /// uint32_t slots[2][3];               // at 0x2000_0100
+    /// ```
+    fn sections() -> (Vec<u8>, Vec<u8>) {
+        #[rustfmt::skip]
+        let abbrev = vec![
+            1, TAG_COMPILE_UNIT as u8, 1, 0, 0,
+            // base type: name (string), byte_size (data1)
+            2, TAG_BASE_TYPE as u8, 0, 0x03, 0x08, 0x0b, 0x0b, 0, 0,
+            // structure: name (string), byte_size (data1)
+            3, TAG_STRUCTURE_TYPE as u8, 1, 0x03, 0x08, 0x0b, 0x0b, 0, 0,
+            // member: name (string), type (ref4), data_member_location (data1)
+            4, TAG_MEMBER as u8, 0, 0x03, 0x08, 0x49, 0x13, 0x38, 0x0b, 0, 0,
+            // variable: name (string), type (ref4), location (exprloc)
+            5, TAG_VARIABLE as u8, 0, 0x03, 0x08, 0x49, 0x13, 0x02, 0x18, 0, 0,
+            // array: type (ref4)
+            6, TAG_ARRAY_TYPE as u8, 1, 0x49, 0x13, 0, 0,
+            // subrange: upper_bound (data1)
+            7, TAG_SUBRANGE_TYPE as u8, 0, 0x2f, 0x0b, 0, 0,
+            0,
+        ];
+
+        let mut info = vec![0; 4];
+        info.extend_from_slice(&4u16.to_le_bytes()); // version
+        info.extend_from_slice(&0u32.to_le_bytes()); // abbrev offset
+        info.push(4); // address size
+        info.push(1); // 0x0b: compile unit
+                      // 0x0c: uint8_t
+        info.extend_from_slice(&[2, b'u', b'8', 0, 1]);
+        // 0x11: uint32_t
+        info.extend_from_slice(&[2, b'u', b'3', b'2', 0, 4]);
+        // 0x17: struct driver_state
+        info.extend_from_slice(&[3, b'd', b's', 0, 8]);
+        info.extend_from_slice(&[4, b'm', b'o', b'd', b'e', 0, 0x0c, 0, 0, 0, 0]);
+        info.extend_from_slice(&[4, b'f', b'l', b'a', b'g', b's', 0, 0x11, 0, 0, 0, 4]);
+        info.push(0);
+        // driver_state variable
+        info.extend_from_slice(b"\x05driver_state\0\x17\0\0\0\x05\x03");
+        info.extend_from_slice(&0x2000_0000u32.to_le_bytes());
+        // uint32_t[2][3]
+        let array = info.len() as u8;
+        info.extend_from_slice(&[6, 0x11, 0, 0, 0, 7, 1, 7, 2, 0]);
+        // slots variable
+        info.extend_from_slice(b"\x05slots\0");
+        info.extend_from_slice(&[array, 0, 0, 0, 5, OP_ADDR]);
+        info.extend_from_slice(&0x2000_0100u32.to_le_bytes());
+        info.push(0);
+        let len = (info.len() - 4) as u32;
+        info[..4].copy_from_slice(&len.to_le_bytes());
+        (info, abbrev)
+    }
+
+    fn debug_info() -> DebugInfo {
+        let (info, abbrev) = sections();
+        Sections {
+            info: &info,
+            abbrev: &abbrev,
+            str: &[],
+            line_str: &[],
+            str_offsets: &[],
+            addr: &[],
+            is_le: true,
+        }
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let debug = debug_info();
+        let loc = |addr, size| VarLocation { addr, size };
+        assert_eq!(debug.resolve("driver_state").unwrap(), loc(0x2000_0000, 8));
+        assert_eq!(
+            debug.resolve("driver_state.mode").unwrap(),
+            loc(0x2000_0000, 1)
+        );
+        assert_eq!(
+            debug.resolve("driver_state.flags").unwrap(),
+            loc(0x2000_0004, 4)
+        );
+        assert_eq!(debug.resolve("slots").unwrap(), loc(0x2000_0100, 24));
+        assert_eq!(debug.resolve("slots[1]").unwrap(), loc(0x2000_010c, 12));
+        assert_eq!(debug.resolve("slots[1][2]").unwrap(), loc(0x2000_0114, 4));
+
+        let kind = |path| debug.resolve(path).unwrap_err().kind();
+        assert_eq!(kind("rx_buffer"), io::ErrorKind::NotFound);
+        assert_eq!(kind("driver_state.speed"), io::ErrorKind::NotFound);
+        assert_eq!(kind("slots[2]"), io::ErrorKind::InvalidInput);
+        assert_eq!(kind("driver_state[0]"), io::ErrorKind::InvalidInput);
+        assert_eq!(kind("slots.mode"), io::ErrorKind::InvalidInput);
+        assert_eq!(kind("driver_state..mode"), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_var_value() {
+        assert_eq!(u32::from_bytes(&[1, 0, 0, 0], Endianness::Little), 1);
+        assert_eq!(u16::from_bytes(&[1, 0], Endianness::Big), 0x100);
+        assert_eq!((-2i16).to_bytes(Endianness::Big), vec![0xff, 0xfe]);
+    }
+}
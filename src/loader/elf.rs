@@ -0,0 +1,320 @@
+use std::{collections::BTreeMap, io};
+
+use crate::Endianness;
+
+/// `PT_LOAD` program header type
+const PT_LOAD: u32 = 1;
+/// `SHT_SYMTAB` section header type
+const SHT_SYMTAB: u32 = 2;
+/// `SHF_COMPRESSED` section header flag
+const SHF_COMPRESSED: u64 = 0x800;
+
+/// Loadable segment of an ELF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Physical address where the segment must be loaded
+    pub paddr: u64,
+    /// Contents of the segment in the file
+    pub data: Vec<u8>,
+    /// Size of the segment in memory. Bytes beyond `data.len()` must be zeroed (BSS).
+    pub mem_size: u64,
+}
+
+/// Symbol of the symbol table of an ELF file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    /// Name of the symbol
+    pub name: String,
+    /// Address of the symbol
+    pub addr: u64,
+    /// Size of the symbol, in bytes
+    pub size: u64,
+}
+
+/// Parsed ELF file, with the information required to load it into guest memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfFile {
+    /// Entry point of the program
+    pub entry: u64,
+    /// Loadable segments of the program
+    pub segments: Vec<Segment>,
+    /// Named symbols of the symbol table, if present
+    pub symbols: Vec<Symbol>,
+    /// Byte order of the program
+    pub endianness: Endianness,
+    /// Contents of the uncompressed `.debug_*` sections, by name
+    pub debug_sections: BTreeMap<String, Vec<u8>>,
+}
+
+impl ElfFile {
+    /// Parses an ELF file (32 or 64 bits, little or big endian) from the given bytes
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 0x34 || &bytes[..4] != b"\x7fELF" {
+            return Err(invalid("not an ELF file"));
+        }
+        let r = Reader {
+            bytes,
+            is_64: match bytes[4] {
+                1 => false,
+                2 => true,
+                _ => return Err(invalid("invalid ELF class")),
+            },
+            is_le: match bytes[5] {
+                1 => true,
+                2 => false,
+                _ => return Err(invalid("invalid ELF data encoding")),
+            },
+        };
+
+        let (entry, phoff, shoff) = match r.is_64 {
+            true => (r.u64(0x18)?, r.u64(0x20)?, r.u64(0x28)?),
+            false => (
+                r.u32(0x18)? as u64,
+                r.u32(0x1c)? as u64,
+                r.u32(0x20)? as u64,
+            ),
+        };
+        let base = if r.is_64 { 0x34 } else { 0x28 };
+        let phentsize = r.u16(base + 2)? as u64;
+        let phnum = r.u16(base + 4)? as u64;
+        let shentsize = r.u16(base + 6)? as u64;
+        let shnum = r.u16(base + 8)? as u64;
+        let shstrndx = r.u16(base + 10)? as u64;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            if r.u32(ph)? != PT_LOAD {
+                continue;
+            }
+            let (offset, paddr, file_size, mem_size) = match r.is_64 {
+                true => (
+                    r.u64(ph + 8)?,
+                    r.u64(ph + 24)?,
+                    r.u64(ph + 32)?,
+                    r.u64(ph + 40)?,
+                ),
+                false => (
+                    r.u32(ph + 4)? as u64,
+                    r.u32(ph + 12)? as u64,
+                    r.u32(ph + 16)? as u64,
+                    r.u32(ph + 20)? as u64,
+                ),
+            };
+            if mem_size == 0 {
+                continue;
+            }
+            segments.push(Segment {
+                paddr,
+                data: r.slice(offset, file_size)?.to_vec(),
+                mem_size: mem_size.max(file_size),
+            });
+        }
+
+        let mut symbols = Vec::new();
+        let section = |i: u64| -> io::Result<(u32, u64, u64, u64, u64)> {
+            let sh = shoff + i * shentsize;
+            match r.is_64 {
+                true => Ok((
+                    r.u32(sh + 4)?,
+                    r.u64(sh + 24)?,
+                    r.u64(sh + 32)?,
+                    r.u32(sh + 40)? as u64,
+                    r.u64(sh + 56)?,
+                )),
+                false => Ok((
+                    r.u32(sh + 4)?,
+                    r.u32(sh + 16)? as u64,
+                    r.u32(sh + 20)? as u64,
+                    r.u32(sh + 24)? as u64,
+                    r.u32(sh + 36)? as u64,
+                )),
+            }
+        };
+        for i in 0..shnum {
+            let (sh_type, offset, size, link, entsize) = section(i)?;
+            if sh_type != SHT_SYMTAB || entsize == 0 {
+                continue;
+            }
+            let (_, str_offset, str_size, _, _) = section(link)?;
+            let strtab = r.slice(str_offset, str_size)?;
+
+            for j in 0..size / entsize {
+                let sym = offset + j * entsize;
+                let (name, addr, size) = match r.is_64 {
+                    true => (r.u32(sym)?, r.u64(sym + 8)?, r.u64(sym + 16)?),
+                    false => (r.u32(sym)?, r.u32(sym + 4)? as u64, r.u32(sym + 8)? as u64),
+                };
+                let name = strtab
+                    .get(name as usize..)
+                    .and_then(|s| s.split(|&b| b == 0).next())
+                    .map(|s| String::from_utf8_lossy(s).into_owned())
+                    .unwrap_or_default();
+                if !name.is_empty() {
+                    symbols.push(Symbol { name, addr, size });
+                }
+            }
+        }
+
+        let mut debug_sections = BTreeMap::new();
+        if shstrndx != 0 && shstrndx < shnum {
+            let (_, str_offset, str_size, _, _) = section(shstrndx)?;
+            let shstrtab = r.slice(str_offset, str_size)?;
+            for i in 0..shnum {
+                let sh = shoff + i * shentsize;
+                let (name, flags) = match r.is_64 {
+                    true => (r.u32(sh)?, r.u64(sh + 8)?),
+                    false => (r.u32(sh)?, r.u32(sh + 8)? as u64),
+                };
+                let name = shstrtab
+                    .get(name as usize..)
+                    .and_then(|s| s.split(|&b| b == 0).next())
+                    .map(|s| String::from_utf8_lossy(s).into_owned())
+                    .unwrap_or_default();
+                if !name.starts_with(".debug_") || flags & SHF_COMPRESSED != 0 {
+                    continue;
+                }
+                let (_, offset, size, _, _) = section(i)?;
+                debug_sections.insert(name, r.slice(offset, size)?.to_vec());
+            }
+        }
+
+        Ok(Self {
+            entry,
+            segments,
+            symbols,
+            endianness: match r.is_le {
+                true => Endianness::Little,
+                false => Endianness::Big,
+            },
+            debug_sections,
+        })
+    }
+}
+
+/// Endianness-aware reader of the fields of an ELF file
+struct Reader<'a> {
+    bytes: &'a [u8],
+    is_64: bool,
+    is_le: bool,
+}
+
+impl Reader<'_> {
+    fn slice(&self, offset: u64, len: u64) -> io::Result<&[u8]> {
+        let start = usize::try_from(offset).map_err(|_| invalid("offset out of range"))?;
+        let end = offset
+            .checked_add(len)
+            .and_then(|end| usize::try_from(end).ok())
+            .ok_or_else(|| invalid("offset out of range"))?;
+        self.bytes
+            .get(start..end)
+            .ok_or_else(|| invalid("truncated ELF file"))
+    }
+
+    fn u16(&self, offset: u64) -> io::Result<u16> {
+        let b = self.slice(offset, 2)?.try_into().unwrap();
+        Ok(if self.is_le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: u64) -> io::Result<u32> {
+        let b = self.slice(offset, 4)?.try_into().unwrap();
+        Ok(if self.is_le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn u64(&self, offset: u64) -> io::Result<u64> {
+        let b = self.slice(offset, 8)?.try_into().unwrap();
+        Ok(if self.is_le {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use super::*;
+
+    /// Builds a minimal 32-bit little endian ELF file with one PT_LOAD segment and one symbol
+    pub(crate) fn elf32() -> Vec<u8> {
+        let mut elf = vec![0u8; 0x100];
+        elf[..7].copy_from_slice(b"\x7fELF\x01\x01\x01");
+        elf[0x18..0x1c].copy_from_slice(&0x2000_0001u32.to_le_bytes()); // e_entry
+        elf[0x1c..0x20].copy_from_slice(&0x34u32.to_le_bytes()); // e_phoff
+        elf[0x20..0x24].copy_from_slice(&0x54u32.to_le_bytes()); // e_shoff
+        elf[0x2a..0x2c].copy_from_slice(&0x20u16.to_le_bytes()); // e_phentsize
+        elf[0x2c..0x2e].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+        elf[0x2e..0x30].copy_from_slice(&0x28u16.to_le_bytes()); // e_shentsize
+        elf[0x30..0x32].copy_from_slice(&2u16.to_le_bytes()); // e_shnum
+
+        // Program header: PT_LOAD, offset 0xe0, paddr 0x2000_0000, filesz 4, memsz 8
+        let ph = 0x34;
+        elf[ph..ph + 4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        elf[ph + 4..ph + 8].copy_from_slice(&0xe0u32.to_le_bytes());
+        elf[ph + 12..ph + 16].copy_from_slice(&0x2000_0000u32.to_le_bytes());
+        elf[ph + 16..ph + 20].copy_from_slice(&4u32.to_le_bytes());
+        elf[ph + 20..ph + 24].copy_from_slice(&8u32.to_le_bytes());
+        elf[0xe0..0xe4].copy_from_slice(&[1, 2, 3, 4]);
+
+        // Section 0: symbol table at 0xb0 with 2 entries, linked to section 1
+        let sh = 0x54;
+        elf[sh + 4..sh + 8].copy_from_slice(&SHT_SYMTAB.to_le_bytes());
+        elf[sh + 16..sh + 20].copy_from_slice(&0xb0u32.to_le_bytes());
+        elf[sh + 20..sh + 24].copy_from_slice(&0x20u32.to_le_bytes());
+        elf[sh + 24..sh + 28].copy_from_slice(&1u32.to_le_bytes());
+        elf[sh + 36..sh + 40].copy_from_slice(&0x10u32.to_le_bytes());
+        // Section 1: string table at 0xf0
+        let sh = 0x54 + 0x28;
+        elf[sh + 4..sh + 8].copy_from_slice(&3u32.to_le_bytes());
+        elf[sh + 16..sh + 20].copy_from_slice(&0xf0u32.to_le_bytes());
+        elf[sh + 20..sh + 24].copy_from_slice(&0x10u32.to_le_bytes());
+        elf[0xf0..0xf8].copy_from_slice(b"\0main\0\0\0");
+
+        // Symbol 1: "main" at 0x2000_0001, size 4 (symbol 0 is the null symbol)
+        let sym = 0xc0;
+        elf[sym..sym + 4].copy_from_slice(&1u32.to_le_bytes());
+        elf[sym + 4..sym + 8].copy_from_slice(&0x2000_0001u32.to_le_bytes());
+        elf[sym + 8..sym + 12].copy_from_slice(&4u32.to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn test_parse_elf32() {
+        let elf = ElfFile::parse(&elf32()).unwrap();
+        assert_eq!(elf.entry, 0x2000_0001);
+        assert_eq!(
+            elf.segments,
+            vec![Segment {
+                paddr: 0x2000_0000,
+                data: vec![1, 2, 3, 4],
+                mem_size: 8,
+            }]
+        );
+        assert_eq!(
+            elf.symbols,
+            vec![Symbol {
+                name: "main".to_string(),
+                addr: 0x2000_0001,
+                size: 4,
+            }]
+        );
+
+        assert_eq!(elf.endianness, Endianness::Little);
+        assert!(elf.debug_sections.is_empty());
+
+        assert!(ElfFile::parse(b"not an elf").is_err());
+        assert!(ElfFile::parse(&elf32()[..0x40]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+
+use super::Segment;
+use crate::transfer::decode_hex;
+
+/// Parsed Intel HEX file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IhexFile {
+    /// Start address of the program (start linear or start segment address record), if present
+    pub start_address: Option<u64>,
+    /// Contiguous blocks of data of the file, in order of appearance.
+    /// Gaps between records start a new segment.
+    pub segments: Vec<Segment>,
+}
+
+impl IhexFile {
+    /// Parses an Intel HEX file, supporting extended segment and extended linear addressing
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let mut file = IhexFile::default();
+        let mut base = 0u64;
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |msg: &str| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {msg}", n + 1))
+            };
+
+            let record = line
+                .strip_prefix(':')
+                .ok_or_else(|| invalid("missing start code"))?;
+            let bytes = decode_hex(record).map_err(|_| invalid("invalid hex digits"))?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(invalid("invalid record length"));
+            }
+            if bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0 {
+                return Err(invalid("invalid checksum"));
+            }
+
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u64;
+            let data = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                // Data
+                0x00 => {
+                    let addr = base + offset;
+                    match file.segments.last_mut() {
+                        Some(last) if last.paddr + last.mem_size == addr => {
+                            last.data.extend_from_slice(data);
+                            last.mem_size += data.len() as u64;
+                        }
+                        _ => file.segments.push(Segment {
+                            paddr: addr,
+                            data: data.to_vec(),
+                            mem_size: data.len() as u64,
+                        }),
+                    }
+                }
+                // End of file
+                0x01 => break,
+                // Extended segment address
+                0x02 if data.len() == 2 => {
+                    base = (u16::from_be_bytes([data[0], data[1]]) as u64) << 4;
+                }
+                // Start segment address (CS:IP)
+                0x03 if data.len() == 4 => {
+                    let cs = u16::from_be_bytes([data[0], data[1]]) as u64;
+                    let ip = u16::from_be_bytes([data[2], data[3]]) as u64;
+                    file.start_address = Some((cs << 4) + ip);
+                }
+                // Extended linear address
+                0x04 if data.len() == 2 => {
+                    base = (u16::from_be_bytes([data[0], data[1]]) as u64) << 16;
+                }
+                // Start linear address
+                0x05 if data.len() == 4 => {
+                    file.start_address =
+                        Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64);
+                }
+                _ => return Err(invalid("invalid record")),
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_ihex() {
+        let hex = ":020000040800F2\n\
+                   :0400000001020304F2\n\
+                   :0400040005060708DE\n\
+                   :02010000AABB98\n\
+                   :0400000508000101ED\n\
+                   :00000001FF\n";
+        let file = IhexFile::parse(hex).unwrap();
+
+        assert_eq!(file.start_address, Some(0x0800_0101));
+        assert_eq!(
+            file.segments,
+            vec![
+                Segment {
+                    paddr: 0x0800_0000,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                    mem_size: 8,
+                },
+                Segment {
+                    paddr: 0x0800_0100,
+                    data: vec![0xaa, 0xbb],
+                    mem_size: 2,
+                },
+            ]
+        );
+
+        assert!(IhexFile::parse("0400000001020304F2").is_err());
+        assert!(IhexFile::parse(":0400000001020304F3").is_err());
+        assert!(IhexFile::parse(":04000000010203F2").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Logging macros that forward to the backend selected with the `log` or `tracing` features.
+//!
+//! If both features are enabled, `tracing` is used. If none is enabled, messages are discarded.
+//! Every message is emitted with a target of the form `qtest::<module>`.
+//!
+//! The protocol is logged at trace level on `qtest::parser`: every line sent (`> `) and received (`< `),
+//! and every IRQ dispatched. Lines that cannot be parsed and failed commands are logged as warnings.
+//!
+//! With the `tracing` feature, commands, batches, transfer chunks and IRQ dispatches also run inside
+//! `qtest.command`, `qtest.batch`, `qtest.chunk` and `qtest.irq` spans, with a `verb` field and, when
+//! relevant, `addr` and `len` (or `line`) fields.
+
+macro_rules! emit {
+    ($level:ident, target: $target:expr, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!(target: $target, $($arg)+);
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        log::$level!(target: $target, $($arg)+);
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        {
+            let _ = $target;
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+#[allow(unused_macros)]
+macro_rules! log_error {
+    ($($arg:tt)+) => { $crate::logging::emit!(error, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_warn {
+    ($($arg:tt)+) => { $crate::logging::emit!(warn, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_info {
+    ($($arg:tt)+) => { $crate::logging::emit!(info, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_trace {
+    ($($arg:tt)+) => { $crate::logging::emit!(trace, $($arg)+) };
+}
+
+/// Runs the given future inside a debug-level span with the given target, name and fields
+#[cfg(feature = "tracing")]
+macro_rules! in_span {
+    (target: $target:expr, $name:literal $(, $field:ident = $value:expr)* ; $future:expr) => {
+        {
+            let span = tracing::debug_span!(target: $target, $name $(, $field = $value)*);
+            tracing::Instrument::instrument($future, span)
+        }
+    };
+}
+
+/// Without the `tracing` feature, the future runs as is and the fields are not evaluated
+#[cfg(not(feature = "tracing"))]
+macro_rules! in_span {
+    (target: $target:expr, $name:literal $(, $field:ident = $value:expr)* ; $future:expr) => {
+        $future
+    };
+}
+
+// Not every macro is used with every combination of transport features
+#[allow(unused_imports)]
+pub(crate) use {emit, in_span, log_error, log_info, log_trace, log_warn};
@@ -0,0 +1,289 @@
+use std::io;
+use tokio::sync::watch;
+
+#[cfg(feature = "config")]
+use crate::board::{Board, Peripheral};
+use crate::logging::log_info;
+use crate::parser::Parser;
+#[cfg(feature = "qmp")]
+use crate::parser::PausePolicy;
+#[cfg(feature = "qmp")]
+use crate::qmp::QmpClient;
+use crate::socket::Socket;
+#[cfg(feature = "config")]
+use crate::Irq;
+
+/// Run state of the emulated machine, as set from QMP (`stop` and `cont` commands)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RunState {
+    /// The VM is running
+    #[default]
+    Running,
+    /// The VM is stopped, e.g. with QMP `stop`
+    Stopped,
+}
+
+/// What happens to the clock operations requested while the VM is stopped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StoppedClockPolicy {
+    /// The operations fail with an error of kind [`io::ErrorKind::WouldBlock`]
+    #[default]
+    Reject,
+    /// The operations are merged and applied when the VM is resumed
+    Queue,
+}
+
+/// Clock operation queued while the VM is stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueuedClock {
+    /// Advance the clock by the given number of nanoseconds
+    Step(usize),
+    /// Set the clock to the given virtual time
+    Set(usize),
+}
+
+impl QueuedClock {
+    /// Merges a step into the queued operation
+    fn step(queued: Option<Self>, ns: usize) -> Self {
+        match queued {
+            None => Self::Step(ns),
+            Some(Self::Step(total)) => Self::Step(total + ns),
+            Some(Self::Set(target)) => Self::Set(target + ns),
+        }
+    }
+}
+
+/// High-level facade of an emulated machine, on top of its qtest [`Parser`].
+///
+/// QEMU handles qtest clock commands differently while the VM is stopped from QMP, so the machine
+/// tracks the run state and applies the [`StoppedClockPolicy`] to the clock operations requested while stopped.
+/// This keeps the qtest and QMP views of time consistent. Other commands (e.g. memory inspection)
+/// are sent through [`Machine::parser`] regardless of the run state.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{machine::{Machine, StoppedClockPolicy}, parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+/// let mut machine = Machine::new(parser).stopped_clock_policy(StoppedClockPolicy::Queue);
+///
+/// // After QMP `stop`
+/// machine.vm_stopped();
+/// assert_eq!(machine.clock_step(1_000).await.unwrap(), None);
+/// let flags = machine.parser().readl(0x2000_0000).await.unwrap();
+///
+/// // After QMP `cont`, the queued step is applied
+/// let now = machine.vm_resumed().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Machine<T: Socket> {
+    parser: Parser<T>,
+    run_state: watch::Sender<RunState>,
+    policy: StoppedClockPolicy,
+    queued: Option<QueuedClock>,
+    #[cfg(feature = "config")]
+    board: Board,
+    #[cfg(feature = "qmp")]
+    qmp: Option<QmpClient>,
+}
+
+impl<T: Socket> Machine<T> {
+    /// Creates a running machine controlled through the given parser
+    pub fn new(parser: Parser<T>) -> Self {
+        Self {
+            parser,
+            run_state: watch::Sender::new(RunState::Running),
+            policy: StoppedClockPolicy::default(),
+            queued: None,
+            #[cfg(feature = "config")]
+            board: Board::default(),
+            #[cfg(feature = "qmp")]
+            qmp: None,
+        }
+    }
+
+    /// Connects to the target with the given name in the registry of [`crate::target::TargetRegistry::load`],
+    /// and returns its machine together with the receiver of the intercepted IRQs.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the registry has no such target.
+    #[cfg(feature = "config")]
+    pub async fn open(name: &str) -> io::Result<(Self, tokio::sync::mpsc::Receiver<Irq>)> {
+        crate::target::TargetRegistry::load()?.open(name).await
+    }
+
+    /// Sets the policy for the clock operations requested while the VM is stopped
+    pub fn stopped_clock_policy(mut self, policy: StoppedClockPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the board description used to look up peripherals by name
+    #[cfg(feature = "config")]
+    pub fn board(mut self, board: Board) -> Self {
+        self.board = board;
+        self
+    }
+
+    /// Returns the peripheral of the board with the given name.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the board description has no such peripheral.
+    #[cfg(feature = "config")]
+    pub fn peripheral(&self, name: &str) -> io::Result<&Peripheral> {
+        self.board.peripheral(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("The board has no peripheral named {name}"),
+            )
+        })
+    }
+
+    /// Sets the client of the QMP monitor of the machine, used by [`Machine::stop`], [`Machine::cont`]
+    /// and [`Machine::quit`]
+    #[cfg(feature = "qmp")]
+    pub fn with_qmp(mut self, qmp: QmpClient) -> Self {
+        self.qmp = Some(qmp);
+        self
+    }
+
+    /// Returns the client of the QMP monitor of the machine.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotConnected`] if the machine has no QMP client.
+    #[cfg(feature = "qmp")]
+    pub fn qmp(&mut self) -> io::Result<&mut QmpClient> {
+        self.qmp.as_mut().ok_or_else(no_qmp)
+    }
+
+    /// Stops the VM with QMP `stop`, see [`Machine::vm_stopped`].
+    ///
+    /// The qtest session is paused while the VM stops, so no command is in flight when it does.
+    #[cfg(feature = "qmp")]
+    pub async fn stop(&mut self) -> io::Result<()> {
+        let qmp = self.qmp.as_mut().ok_or_else(no_qmp)?;
+        self.parser.pause(PausePolicy::Buffer).await;
+        let stopped = qmp.stop().await;
+        self.parser.resume();
+        stopped?;
+        self.vm_stopped();
+        Ok(())
+    }
+
+    /// Resumes the VM with QMP `cont`, see [`Machine::vm_resumed`].
+    ///
+    /// The qtest session is paused while the VM resumes, so no command is in flight when it does.
+    #[cfg(feature = "qmp")]
+    pub async fn cont(&mut self) -> io::Result<Option<usize>> {
+        let qmp = self.qmp.as_mut().ok_or_else(no_qmp)?;
+        self.parser.pause(PausePolicy::Buffer).await;
+        let resumed = qmp.cont().await;
+        self.parser.resume();
+        resumed?;
+        self.vm_resumed().await
+    }
+
+    /// Terminates QEMU with QMP `quit`
+    #[cfg(feature = "qmp")]
+    pub async fn quit(&mut self) -> io::Result<()> {
+        self.qmp()?.quit().await
+    }
+
+    /// Returns the parser of the machine
+    pub fn parser(&mut self) -> &mut Parser<T> {
+        &mut self.parser
+    }
+
+    /// Returns the parser of the machine, dropping the queued clock operations, if any
+    pub fn into_parser(self) -> Parser<T> {
+        self.parser
+    }
+
+    /// Returns the current run state of the VM
+    pub fn run_state(&self) -> RunState {
+        *self.run_state.borrow()
+    }
+
+    /// Returns a receiver of the run state of the VM
+    pub fn run_state_watch(&self) -> watch::Receiver<RunState> {
+        self.run_state.subscribe()
+    }
+
+    /// Records that the VM was stopped (QMP `stop`, or a `STOP` event)
+    pub fn vm_stopped(&mut self) {
+        self.run_state.send_replace(RunState::Stopped);
+    }
+
+    /// Records that the VM was resumed (QMP `cont`, or a `RESUME` event) and applies the queued clock operation.
+    ///
+    /// Returns the virtual time after the queued operation, or `None` if no operation was queued.
+    pub async fn vm_resumed(&mut self) -> io::Result<Option<usize>> {
+        self.run_state.send_replace(RunState::Running);
+        let Some(queued) = self.queued.take() else {
+            return Ok(None);
+        };
+        log_info!(target: "qtest::machine", "applying queued clock operation {queued:?}");
+        let mut clock = self.parser.clock();
+        let now = match queued {
+            QueuedClock::Step(ns) => clock.advance(ns).await?,
+            QueuedClock::Set(ns) => clock.advance_to(ns).await?,
+        };
+        Ok(Some(now))
+    }
+
+    /// Advances the virtual clock by the given number of nanoseconds.
+    ///
+    /// Returns the new virtual time, or `None` if the VM is stopped and the step was queued.
+    pub async fn clock_step(&mut self, ns: usize) -> io::Result<Option<usize>> {
+        if self.check_stopped()? {
+            self.queued = Some(QueuedClock::step(self.queued, ns));
+            return Ok(None);
+        }
+        Ok(Some(self.parser.clock().advance(ns).await?))
+    }
+
+    /// Sets the virtual clock to the given time in nanoseconds, which cannot be in the past.
+    ///
+    /// Returns the new virtual time, or `None` if the VM is stopped and the operation was queued.
+    /// A queued operation replaces the operations queued before.
+    pub async fn clock_set(&mut self, ns: usize) -> io::Result<Option<usize>> {
+        if self.check_stopped()? {
+            self.queued = Some(QueuedClock::Set(ns));
+            return Ok(None);
+        }
+        Ok(Some(self.parser.clock().advance_to(ns).await?))
+    }
+
+    /// Returns true if clock operations must be queued, or an error if they must be rejected
+    fn check_stopped(&self) -> io::Result<bool> {
+        match (self.run_state(), self.policy) {
+            (RunState::Running, _) => Ok(false),
+            (RunState::Stopped, StoppedClockPolicy::Queue) => Ok(true),
+            (RunState::Stopped, StoppedClockPolicy::Reject) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "The VM is stopped: clock operations are rejected until it is resumed",
+            )),
+        }
+    }
+}
+
+/// Error returned when a QMP command is requested from a machine without QMP client
+#[cfg(feature = "qmp")]
+fn no_qmp() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "The machine has no QMP client")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_queued_clock() {
+        let queued = QueuedClock::step(None, 10);
+        assert_eq!(queued, QueuedClock::Step(10));
+        assert_eq!(QueuedClock::step(Some(queued), 5), QueuedClock::Step(15));
+        let set = QueuedClock::Set(100);
+        assert_eq!(QueuedClock::step(Some(set), 5), QueuedClock::Set(105));
+    }
+}
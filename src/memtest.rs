@@ -0,0 +1,120 @@
+use std::ops::Range;
+
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::socket::Socket;
+use crate::transfer::{read_chunked, write_chunked};
+
+/// Test pattern written to guest memory by [`mem_test`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    /// Every byte is set to the given value
+    Fill(u8),
+    /// A single bit set, moving one position on every byte (`0x01`, `0x02`, ..., `0x80`, `0x01`, ...)
+    WalkingOnes,
+    /// A single bit cleared, moving one position on every byte (`0xfe`, `0xfd`, ..., `0x7f`, `0xfe`, ...)
+    WalkingZeros,
+    /// Every 32-bit word holds its own address, in little endian
+    AddressInAddress,
+}
+
+impl Pattern {
+    /// Returns the contents of the given address range filled with the pattern
+    pub fn bytes(&self, range: &Range<usize>) -> Vec<u8> {
+        match self {
+            Self::Fill(byte) => vec![*byte; range.len()],
+            Self::WalkingOnes => range.clone().map(|addr| 1 << (addr % 8)).collect(),
+            Self::WalkingZeros => range.clone().map(|addr| !(1 << (addr % 8))).collect(),
+            Self::AddressInAddress => range
+                .clone()
+                .map(|addr| {
+                    let word = (addr & !0b11) as u32;
+                    word.to_le_bytes()[addr % 4]
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Byte that did not hold the expected value after writing a test pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemTestFailure {
+    /// Address of the byte
+    pub addr: usize,
+    /// Value written to the byte
+    pub expected: u8,
+    /// Value read back from the byte
+    pub actual: u8,
+}
+
+/// Result of a memory test
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemTestReport {
+    /// The tested address range
+    pub range: Range<usize>,
+    /// The pattern used
+    pub pattern: Pattern,
+    /// The bytes that did not hold the expected value
+    pub failures: Vec<MemTestFailure>,
+}
+
+impl MemTestReport {
+    /// Returns true if every byte held the expected value
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Writes the given pattern to the given range of guest memory, reads it back and reports the bytes that differ.
+///
+/// This is the standard smoke test for new RAM/ROM device models. Note that the previous contents
+/// of the range are overwritten.
+pub async fn mem_test<T: Socket>(
+    parser: &mut Parser<T>,
+    range: Range<usize>,
+    pattern: Pattern,
+) -> Result<MemTestReport, QtestError> {
+    let expected = pattern.bytes(&range);
+    write_chunked(parser, range.start, &expected, |_, _| {}).await?;
+    let actual = read_chunked(parser, range.start, range.len(), |_, _| {}).await?;
+
+    let failures = expected
+        .iter()
+        .zip(&actual)
+        .enumerate()
+        .filter(|(_, (e, a))| e != a)
+        .map(|(offset, (&expected, &actual))| MemTestFailure {
+            addr: range.start + offset,
+            expected,
+            actual,
+        })
+        .collect();
+
+    Ok(MemTestReport {
+        range,
+        pattern,
+        failures,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pattern_bytes() {
+        assert_eq!(Pattern::Fill(0xa5).bytes(&(0..3)), vec![0xa5; 3]);
+        assert_eq!(
+            Pattern::WalkingOnes.bytes(&(6..10)),
+            vec![0x40, 0x80, 0x01, 0x02]
+        );
+        assert_eq!(
+            Pattern::WalkingZeros.bytes(&(6..10)),
+            vec![0xbf, 0x7f, 0xfe, 0xfd]
+        );
+        assert_eq!(
+            Pattern::AddressInAddress.bytes(&(0x1002..0x1008)),
+            vec![0x00, 0x00, 0x04, 0x10, 0x00, 0x00]
+        );
+    }
+}
@@ -0,0 +1,397 @@
+#[cfg(feature = "base64")]
+use base64::{
+    alphabet,
+    engine::{Engine, GeneralPurpose, GeneralPurposeConfig},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::Notify,
+    task::JoinHandle,
+};
+
+use crate::protocol::{Command, Width};
+use crate::transfer::{decode_hex, encode_hex};
+use crate::{Endianness, Irq, IrqState};
+
+#[cfg(feature = "base64")]
+const ENGINE: GeneralPurpose =
+    GeneralPurpose::new(&alphabet::STANDARD, GeneralPurposeConfig::new());
+
+/// Callback registered with [`MockQtestDevice::on_write`]
+type WriteHook = Arc<dyn Fn(u64) -> Vec<Irq> + Send + Sync>;
+
+/// Fake QEMU serving a qtest connection from an in-memory address space, for testing harnesses without QEMU.
+///
+/// It answers the qtest commands like QEMU does:
+///
+/// - memory and I/O accesses read and write sparse in-memory maps, where unwritten bytes read as zero,
+///   with the byte order set with [`MockQtestDevice::endianness`] (little endian by default),
+/// - clock commands advance a virtual clock, emitting the IRQs scheduled until the new time,
+///   and `clock_step` without a step advances to the next scheduled IRQ,
+/// - `irq_intercept_in`, `irq_intercept_out` and `set_irq_in` are accepted, recording the input levels,
+/// - any other line is answered with `FAIL`.
+///
+/// IRQs are scripted with [`MockQtestDevice::set_irq`], [`MockQtestDevice::schedule_irq`] and
+/// [`MockQtestDevice::on_write`], and sent whether or not they were intercepted. The device can be cloned
+/// to script it and inspect its state while a connection is served.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() {
+/// # use qtest::{mock::MockQtestDevice, parser::Parser, socket::mem::{self, SocketPair}, Irq, IrqState};
+/// let device = MockQtestDevice::new();
+/// device.on_write(0x4000_0000, |val| match val {
+///     0 => vec![Irq::new(3, IrqState::Lower)],
+///     _ => vec![Irq::new(3, IrqState::Raise)],
+/// });
+///
+/// let (mut parser, mut irq_rx) = Parser::<SocketPair>::new("mock").await.unwrap();
+/// device.serve(mem::connect("mock").await.unwrap());
+/// parser.attach_connection().await.unwrap();
+///
+/// parser.writel(0x4000_0000, 1).await.unwrap();
+/// assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(3, IrqState::Raise));
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct MockQtestDevice {
+    state: Arc<Mutex<State>>,
+    irqs_ready: Arc<Notify>,
+}
+
+impl fmt::Debug for MockQtestDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockQtestDevice")
+            .field("endianness", &state.endianness)
+            .field("clock_ns", &state.clock_ns)
+            .field("commands", &state.commands.len())
+            .finish()
+    }
+}
+
+impl MockQtestDevice {
+    /// Creates a device with empty address spaces and the virtual clock at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the byte order of the guest, as reported by the `endianness` command
+    pub fn endianness(self, endianness: Endianness) -> Self {
+        self.state.lock().unwrap().endianness = endianness;
+        self
+    }
+
+    /// Writes the given bytes to memory, without going through the qtest connection
+    pub fn write_mem(&self, addr: usize, data: &[u8]) {
+        self.state.lock().unwrap().memory.write(addr, data);
+    }
+
+    /// Reads bytes from memory, without going through the qtest connection
+    pub fn read_mem(&self, addr: usize, len: usize) -> Vec<u8> {
+        self.state.lock().unwrap().memory.read(addr, len)
+    }
+
+    /// Returns the virtual clock, in nanoseconds
+    pub fn clock_ns(&self) -> usize {
+        self.state.lock().unwrap().clock_ns
+    }
+
+    /// Returns the commands received so far, in order
+    pub fn commands(&self) -> Vec<Command> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Returns the last level set with `set_irq_in` on the given GPIO input, if any
+    pub fn irq_in_level(&self, qom_path: &str, irq_name: &str, line: usize) -> Option<isize> {
+        let key = (qom_path.to_string(), irq_name.to_string(), line);
+        self.state.lock().unwrap().irq_in.get(&key).copied()
+    }
+
+    /// Sends an IRQ event on the given line as soon as possible
+    pub fn set_irq(&self, line: usize, state: IrqState) {
+        self.state
+            .lock()
+            .unwrap()
+            .irqs
+            .push_back(Irq::new(line, state));
+        self.irqs_ready.notify_one();
+    }
+
+    /// Schedules an IRQ event when the virtual clock reaches the given time, in nanoseconds
+    pub fn schedule_irq(&self, at_ns: usize, irq: Irq) {
+        let mut state = self.state.lock().unwrap();
+        let index = state.scheduled.partition_point(|(ns, _)| *ns <= at_ns);
+        state.scheduled.insert(index, (at_ns, irq));
+    }
+
+    /// Registers a callback run for every write to the given memory or I/O address, with the value written.
+    ///
+    /// The IRQ events it returns are sent before the response to the write, as QEMU does for the IRQs
+    /// raised synchronously by a device model. It runs with the device locked, so it must not use it.
+    pub fn on_write<F>(&self, addr: usize, callback: F)
+    where
+        F: Fn(u64) -> Vec<Irq> + Send + Sync + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.write_hooks.insert(addr, Arc::new(callback));
+    }
+
+    /// Spawns the task serving a qtest connection on the given stream, until the parser closes it
+    pub fn serve<S>(&self, stream: S) -> JoinHandle<io::Result<()>>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let device = self.clone();
+        tokio::spawn(async move {
+            let (read_half, mut write_half) = tokio::io::split(stream);
+            let mut lines = BufReader::new(read_half).lines();
+            loop {
+                let out = tokio::select! {
+                    line = lines.next_line() => match line? {
+                        Some(line) => device.state.lock().unwrap().respond(&line),
+                        None => return Ok(()),
+                    },
+                    _ = device.irqs_ready.notified() => device.state.lock().unwrap().take_irqs(),
+                };
+                write_half.write_all(out.as_bytes()).await?;
+            }
+        })
+    }
+
+    /// Connects to a parser listening at the given TCP address and serves the connection
+    #[cfg(feature = "tcp")]
+    pub async fn connect_tcp(&self, addr: &str) -> io::Result<JoinHandle<io::Result<()>>> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        Ok(self.serve(stream))
+    }
+
+    /// Connects to a parser listening at the given UNIX socket path and serves the connection
+    #[cfg(feature = "unix")]
+    pub async fn connect_unix(&self, path: &str) -> io::Result<JoinHandle<io::Result<()>>> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Ok(self.serve(stream))
+    }
+}
+
+/// Sparse byte-addressed space, where unwritten bytes read as zero
+#[derive(Debug, Default)]
+struct Space(HashMap<usize, u8>);
+
+impl Space {
+    fn read(&self, addr: usize, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.0.get(&(addr + i)).copied().unwrap_or_default())
+            .collect()
+    }
+
+    fn write(&mut self, addr: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.0.insert(addr + i, *byte);
+        }
+    }
+}
+
+struct State {
+    memory: Space,
+    io: Space,
+    endianness: Endianness,
+    clock_ns: usize,
+    commands: Vec<Command>,
+    irq_in: HashMap<(String, String, usize), isize>,
+    /// IRQ events to send as soon as possible
+    irqs: VecDeque<Irq>,
+    /// IRQ events to send when the virtual clock reaches their time, sorted by time
+    scheduled: Vec<(usize, Irq)>,
+    write_hooks: HashMap<usize, WriteHook>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            memory: Space::default(),
+            io: Space::default(),
+            endianness: Endianness::Little,
+            clock_ns: 0,
+            commands: Vec::new(),
+            irq_in: HashMap::new(),
+            irqs: VecDeque::new(),
+            scheduled: Vec::new(),
+            write_hooks: HashMap::new(),
+        }
+    }
+}
+
+impl State {
+    /// Returns the lines answering the given command line, preceded by the pending IRQ events
+    fn respond(&mut self, line: &str) -> String {
+        let mut out = self.take_irqs();
+        let response = match line.parse::<Command>() {
+            Ok(command) => {
+                self.commands.push(command.clone());
+                self.execute(command, &mut out)
+            }
+            Err(e) => format!("FAIL {e}"),
+        };
+        out.push_str(&response);
+        out.push('\n');
+        out
+    }
+
+    /// Returns the lines of the pending IRQ events
+    fn take_irqs(&mut self) -> String {
+        self.irqs.drain(..).map(|irq| format!("{irq}\n")).collect()
+    }
+
+    /// Executes the given command, appending the IRQ events it triggers to `out`, and returns the response
+    fn execute(&mut self, command: Command, out: &mut String) -> String {
+        match command {
+            Command::Endianness => match self.endianness {
+                Endianness::Little => "OK little".to_string(),
+                Endianness::Big => "OK big".to_string(),
+            },
+            Command::ClockStep(ns) => {
+                let target = match ns {
+                    Some(ns) => self.clock_ns + ns,
+                    None => self.scheduled.first().map_or(self.clock_ns, |(at, _)| *at),
+                };
+                self.advance_clock(target, out);
+                format!("OK {}", self.clock_ns)
+            }
+            Command::ClockSet(ns) => {
+                self.advance_clock(ns, out);
+                format!("OK {}", self.clock_ns)
+            }
+            Command::IrqInterceptIn(_) | Command::IrqInterceptOut(_) => "OK".to_string(),
+            Command::SetIrqIn {
+                qom_path,
+                irq_name,
+                line,
+                level,
+            } => {
+                self.irq_in.insert((qom_path, irq_name, line), level);
+                "OK".to_string()
+            }
+            Command::In { width, addr } => self.read_value(width, addr, true),
+            Command::ReadValue { width, addr } => self.read_value(width, addr, false),
+            Command::Out { width, addr, val } => self.write_value(width, addr, val, true, out),
+            Command::WriteValue { width, addr, val } => {
+                self.write_value(width, addr, val, false, out)
+            }
+            Command::Read { addr, len } => {
+                format!("OK 0x{}", encode_hex(&self.memory.read(addr, len)))
+            }
+            Command::Write { addr, len, data } => match decode_hex(&data) {
+                Ok(bytes) if bytes.len() == len => {
+                    self.memory.write(addr, &bytes);
+                    "OK".to_string()
+                }
+                _ => format!("FAIL invalid data: {data}"),
+            },
+            Command::Memset { addr, len, val } => {
+                self.memory.write(addr, &vec![val; len]);
+                "OK".to_string()
+            }
+            #[cfg(feature = "base64")]
+            Command::B64Read { addr, len } => {
+                format!("OK {}", ENGINE.encode(self.memory.read(addr, len)))
+            }
+            #[cfg(feature = "base64")]
+            Command::B64Write { addr, len, data } => match ENGINE.decode(&data) {
+                Ok(bytes) if bytes.len() == len => {
+                    self.memory.write(addr, &bytes);
+                    "OK".to_string()
+                }
+                _ => format!("FAIL invalid data: {data}"),
+            },
+            #[cfg(not(feature = "base64"))]
+            Command::B64Read { .. } | Command::B64Write { .. } => {
+                "FAIL base64 is not supported".to_string()
+            }
+        }
+    }
+
+    /// Advances the virtual clock to the given time, if later, appending the IRQ events scheduled until then
+    fn advance_clock(&mut self, ns: usize, out: &mut String) {
+        self.clock_ns = self.clock_ns.max(ns);
+        let due = self
+            .scheduled
+            .partition_point(|(at, _)| *at <= self.clock_ns);
+        for (_, irq) in self.scheduled.drain(..due) {
+            out.push_str(&format!("{irq}\n"));
+        }
+    }
+
+    fn read_value(&self, width: Width, addr: usize, io: bool) -> String {
+        let space = if io { &self.io } else { &self.memory };
+        let mut bytes = space.read(addr, width.bytes());
+        if self.endianness == Endianness::Little {
+            bytes.reverse();
+        }
+        let val = bytes
+            .iter()
+            .fold(0u64, |val, byte| (val << 8) | u64::from(*byte));
+        format!("OK 0x{val:0digits$x}", digits = width.bytes() * 2)
+    }
+
+    fn write_value(
+        &mut self,
+        width: Width,
+        addr: usize,
+        val: u64,
+        io: bool,
+        out: &mut String,
+    ) -> String {
+        let mut bytes = val.to_be_bytes()[8 - width.bytes()..].to_vec();
+        if self.endianness == Endianness::Little {
+            bytes.reverse();
+        }
+        let space = if io { &mut self.io } else { &mut self.memory };
+        space.write(addr, &bytes);
+        if let Some(hook) = self.write_hooks.get(&addr) {
+            for irq in hook(val) {
+                out.push_str(&format!("{irq}\n"));
+            }
+        }
+        "OK".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        let device = MockQtestDevice::new();
+        device.schedule_irq(150, Irq::new(2, IrqState::Raise));
+        device.on_write(0x2000, |val| match val {
+            0 => vec![Irq::new(5, IrqState::Lower)],
+            _ => vec![Irq::new(5, IrqState::Raise)],
+        });
+        let mut state = device.state.lock().unwrap();
+
+        assert_eq!(state.respond("writel 0x1000 0x12345678"), "OK\n");
+        assert_eq!(state.respond("readl 0x1000"), "OK 0x12345678\n");
+        assert_eq!(state.respond("readb 0x1000"), "OK 0x78\n");
+        assert_eq!(state.respond("read 0x1000 2"), "OK 0x7856\n");
+        assert_eq!(state.respond("outw 0x60 0x1"), "OK\n");
+        assert_eq!(state.respond("inw 0x60"), "OK 0x0001\n");
+        assert_eq!(state.respond("readw 0x60"), "OK 0x0000\n");
+        assert_eq!(state.respond("memset 0x3000 2 0xff"), "OK\n");
+        assert_eq!(state.respond("readw 0x3000"), "OK 0xffff\n");
+
+        assert_eq!(state.respond("clock_step 100"), "OK 100\n");
+        assert_eq!(state.respond("clock_step"), "IRQ raise 2\nOK 150\n");
+        assert_eq!(state.respond("writeb 0x2000 0x1"), "IRQ raise 5\nOK\n");
+        assert_eq!(state.respond("endianness"), "OK little\n");
+        assert!(state.respond("bogus").starts_with("FAIL"));
+        assert_eq!(state.commands.len(), 13);
+    }
+}
@@ -0,0 +1,273 @@
+use ratatui::{
+    crossterm::event::{self as term, KeyCode},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    widgets::{Block, List, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    time::Duration,
+};
+use tokio::sync::broadcast;
+
+use crate::event::{Event, EventKind};
+use crate::IrqState;
+
+/// Default number of entries kept in the command log of the monitor
+pub const DEFAULT_LOG_CAPACITY: usize = 1024;
+
+/// Period between redraws of the terminal
+const REFRESH_PERIOD: Duration = Duration::from_millis(100);
+
+/// Activity of an IRQ line seen by the monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineActivity {
+    /// Current level of the line
+    pub level: IrqState,
+    /// Number of raise events
+    pub raises: usize,
+    /// Number of lower events
+    pub lowers: usize,
+    /// Virtual clock of the last event, if known
+    pub last_ns: Option<usize>,
+}
+
+/// State of a session as shown by the monitor, updated with every event of the unified event stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorState {
+    lines: BTreeMap<usize, LineActivity>,
+    virtual_ns: Option<usize>,
+    log: VecDeque<String>,
+    log_capacity: usize,
+    events: usize,
+    lost: u64,
+    ended: bool,
+}
+
+impl MonitorState {
+    /// Creates an empty state keeping up to the given number of entries in its command log
+    pub fn new(log_capacity: usize) -> Self {
+        Self {
+            lines: BTreeMap::new(),
+            virtual_ns: None,
+            log: VecDeque::new(),
+            log_capacity,
+            events: 0,
+            lost: 0,
+            ended: false,
+        }
+    }
+
+    /// Updates the state with the given event
+    pub fn apply(&mut self, event: &Event) {
+        self.events += 1;
+        if event.virtual_ns.is_some() {
+            self.virtual_ns = event.virtual_ns;
+        }
+        let entry = match &event.kind {
+            EventKind::Irq(irq) => {
+                let line = self.lines.entry(irq.line).or_insert(LineActivity {
+                    level: IrqState::Lower,
+                    raises: 0,
+                    lowers: 0,
+                    last_ns: None,
+                });
+                line.level = irq.state;
+                match irq.state {
+                    IrqState::Raise => line.raises += 1,
+                    IrqState::Lower => line.lowers += 1,
+                }
+                line.last_ns = event.virtual_ns;
+                return;
+            }
+            EventKind::Command(command) => format!("> {command}"),
+            EventKind::Response(response) => format!("< {response}"),
+            EventKind::Notification(notification) => format!("~ {notification}"),
+            EventKind::Connected => "connected".to_string(),
+        };
+        if self.log_capacity == 0 {
+            return;
+        }
+        if self.log.len() == self.log_capacity {
+            self.log.pop_front();
+        }
+        let timestamp = event.timestamp.as_secs_f64();
+        self.log.push_back(format!("{timestamp:>10.3}s {entry}"));
+    }
+
+    /// Returns the activity of every IRQ line seen so far, by line number
+    pub fn lines(&self) -> &BTreeMap<usize, LineActivity> {
+        &self.lines
+    }
+
+    /// Returns the last known virtual clock, in nanoseconds
+    pub fn virtual_ns(&self) -> Option<usize> {
+        self.virtual_ns
+    }
+
+    /// Returns the entries of the command log, oldest first
+    pub fn log(&self) -> impl Iterator<Item = &str> {
+        self.log.iter().map(String::as_str)
+    }
+
+    /// Draws the state on the given frame: a status bar, the IRQ lines and the tail of the command log
+    fn draw(&self, frame: &mut Frame<'_>) {
+        let [status, lines, log] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Percentage(40),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        let clock = match self.virtual_ns {
+            Some(ns) => format!("{ns} ns"),
+            None => "unknown".to_string(),
+        };
+        let session = if self.ended { "ended" } else { "live" };
+        let text = format!(
+            "virtual clock: {clock} | events: {} | lost: {} | session {session} | q: quit",
+            self.events, self.lost
+        );
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title("qtest-monitor")),
+            status,
+        );
+
+        let rows = self.lines.iter().map(|(line, activity)| {
+            let (level, style) = match activity.level {
+                IrqState::Raise => ("raised", Style::new().fg(Color::Red)),
+                IrqState::Lower => ("lowered", Style::new()),
+            };
+            let last = activity
+                .last_ns
+                .map(|ns| ns.to_string())
+                .unwrap_or_default();
+            Row::new([
+                line.to_string(),
+                level.to_string(),
+                activity.raises.to_string(),
+                activity.lowers.to_string(),
+                last,
+            ])
+            .style(style)
+        });
+        let widths = [
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Min(16),
+        ];
+        let header = Row::new(["line", "level", "raises", "lowers", "last event (ns)"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(header)
+                .block(Block::bordered().title("IRQ lines")),
+            lines,
+        );
+
+        // Only the most recent entries that fit, as a scrolling log
+        let visible = log.height.saturating_sub(2) as usize;
+        let entries = self.log.iter().skip(self.log.len().saturating_sub(visible));
+        frame.render_widget(
+            List::new(entries.map(String::as_str)).block(Block::bordered().title("Commands")),
+            log,
+        );
+    }
+}
+
+/// Shows the given event stream live in the terminal, until `q` or `Esc` is pressed.
+///
+/// The terminal is switched to the alternate screen while the monitor runs, and restored afterwards.
+/// The events can come from [`crate::parser::Parser::events`] in the same process,
+/// or from a [`crate::websocket::EventFeed`], as done by the `qtest-monitor` binary.
+///
+/// Returns Err if the terminal cannot be drawn.
+pub async fn run(events: broadcast::Receiver<Event>) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = show(&mut terminal, events).await;
+    ratatui::restore();
+    result
+}
+
+/// Body of [`run`], with the terminal already initialized
+async fn show(
+    terminal: &mut DefaultTerminal,
+    mut events: broadcast::Receiver<Event>,
+) -> io::Result<()> {
+    let mut state = MonitorState::new(DEFAULT_LOG_CAPACITY);
+    let mut refresh = tokio::time::interval(REFRESH_PERIOD);
+    loop {
+        tokio::select! {
+            event = events.recv(), if !state.ended => match event {
+                Ok(event) => state.apply(&event),
+                Err(broadcast::error::RecvError::Lagged(n)) => state.lost += n,
+                // Keep showing the last state until the user quits
+                Err(broadcast::error::RecvError::Closed) => state.ended = true,
+            },
+            _ = refresh.tick() => {
+                terminal.draw(|frame| state.draw(frame))?;
+                while term::poll(Duration::ZERO)? {
+                    if let term::Event::Key(key) = term::read()? {
+                        if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Irq, Response};
+
+    fn event(virtual_ns: Option<usize>, kind: EventKind) -> Event {
+        Event {
+            timestamp: Duration::from_millis(1500),
+            virtual_ns,
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_monitor_state() {
+        let mut state = MonitorState::new(2);
+        state.apply(&event(None, EventKind::Connected));
+        state.apply(&event(None, EventKind::Command("clock_step 10".into())));
+        state.apply(&event(
+            None,
+            EventKind::Response(Response::OkVal("10".into())),
+        ));
+        state.apply(&event(
+            Some(10),
+            EventKind::Irq(Irq::new(3, IrqState::Raise)),
+        ));
+        state.apply(&event(
+            Some(10),
+            EventKind::Irq(Irq::new(3, IrqState::Lower)),
+        ));
+        state.apply(&event(None, EventKind::Irq(Irq::new(4, IrqState::Raise))));
+
+        assert_eq!(state.virtual_ns(), Some(10));
+        assert_eq!(
+            state.lines()[&3],
+            LineActivity {
+                level: IrqState::Lower,
+                raises: 1,
+                lowers: 1,
+                last_ns: Some(10),
+            }
+        );
+        assert_eq!(state.lines()[&4].level, IrqState::Raise);
+        assert_eq!(
+            state.log().collect::<Vec<_>>(),
+            ["     1.500s > clock_step 10", "     1.500s < OK 10"]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use opentelemetry::{
+    trace::{Span, Status, TraceContextExt, Tracer, TracerProvider},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    trace::{SdkTracerProvider, Span as SdkSpan},
+    Resource,
+};
+use std::{collections::VecDeque, io};
+use tokio::sync::broadcast;
+
+use crate::event::{Event, EventKind};
+use crate::logging::log_warn;
+use crate::Response;
+
+/// Exporter of qtest sessions as OpenTelemetry traces.
+///
+/// Each session is exported as a trace whose root span covers the whole session. Every command is a child span
+/// (`qtest.command`) from the moment it is sent until its response arrives, and failed commands have an error status.
+/// Batched commands are in flight together, and QEMU answers them in order. IRQ events and notifications are added
+/// as span events to the oldest command in flight, or to the session span between commands. Connections and lost events are added as events of the session span.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{otel::SessionExporter, parser::Parser, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+///
+/// let exporter = SessionExporter::otlp("http://localhost:4318/v1/traces", "firmware-tests").unwrap();
+/// let events = parser.events();
+/// let export = tokio::spawn(async move {
+///     exporter.export("boot", events).await;
+///     exporter.shutdown()
+/// });
+///
+/// parser.attach_connection().await.unwrap();
+/// parser.readl(0x4000_0000).await.unwrap();
+/// // The session ends when the parser is dropped
+/// drop(parser);
+/// export.await.unwrap().unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SessionExporter {
+    provider: SdkTracerProvider,
+}
+
+impl SessionExporter {
+    /// Creates an exporter that sends the traces over OTLP/HTTP to the given endpoint,
+    /// e.g. `http://localhost:4318/v1/traces`, on behalf of the given service.
+    ///
+    /// Spans are sent in batches from a background thread.
+    pub fn otlp(endpoint: &str, service_name: &str) -> io::Result<Self> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(io::Error::other)?;
+        let resource = Resource::builder()
+            .with_service_name(service_name.to_string())
+            .build();
+        let provider = SdkTracerProvider::builder()
+            .with_resource(resource)
+            .with_batch_exporter(exporter)
+            .build();
+        Ok(Self::with_provider(provider))
+    }
+
+    /// Creates an exporter that records the traces with the given tracer provider, e.g. one already set up
+    /// for the rest of the test pipeline
+    pub fn with_provider(provider: SdkTracerProvider) -> Self {
+        Self { provider }
+    }
+
+    /// Exports the events of the given stream as a trace with a root span of the given name.
+    ///
+    /// Returns when the event stream is closed, i.e. when the parser is dropped, ending the open spans.
+    pub async fn export(&self, session: &str, mut events: broadcast::Receiver<Event>) {
+        let tracer = self.provider.tracer("qtest");
+        let cx = Context::current_with_span(tracer.start(session.to_string()));
+        let mut in_flight: VecDeque<SdkSpan> = VecDeque::new();
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log_warn!(target: "qtest::otel", "{n} exported events lost");
+                    let lost = KeyValue::new("qtest.lost", n as i64);
+                    cx.span().add_event("events lost", vec![lost]);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let mut attributes = Vec::new();
+            if let Some(ns) = event.virtual_ns {
+                attributes.push(KeyValue::new("qtest.virtual_ns", ns as i64));
+            }
+            match event.kind {
+                EventKind::Command(line) => {
+                    let mut span = tracer.start_with_context("qtest.command", &cx);
+                    let verb = line.split_whitespace().next().unwrap_or_default();
+                    span.set_attribute(KeyValue::new("qtest.verb", verb.to_string()));
+                    span.set_attribute(KeyValue::new("qtest.command", line));
+                    span.add_event("sent", attributes);
+                    in_flight.push_back(span);
+                }
+                EventKind::Response(response) => {
+                    let Some(mut span) = in_flight.pop_front() else {
+                        continue;
+                    };
+                    if let Response::Err(e) = &response {
+                        span.set_status(Status::error(e.clone()));
+                    }
+                    span.set_attribute(KeyValue::new("qtest.response", response.to_string()));
+                    span.add_event("answered", attributes);
+                    span.end();
+                }
+                EventKind::Irq(irq) => {
+                    attributes.push(KeyValue::new("qtest.line", irq.line as i64));
+                    attributes.push(KeyValue::new("qtest.state", irq.state.as_str()));
+                    match in_flight.front_mut() {
+                        Some(span) => span.add_event("irq", attributes),
+                        None => cx.span().add_event("irq", attributes),
+                    }
+                }
+                EventKind::Notification(notification) => {
+                    attributes.push(KeyValue::new("qtest.name", notification.name));
+                    attributes.push(KeyValue::new("qtest.payload", notification.payload));
+                    match in_flight.front_mut() {
+                        Some(span) => span.add_event("notification", attributes),
+                        None => cx.span().add_event("notification", attributes),
+                    }
+                }
+                EventKind::Connected => cx.span().add_event("connected", attributes),
+            }
+        }
+        for mut span in in_flight {
+            span.end();
+        }
+        cx.span().end();
+    }
+
+    /// Sends the pending spans and shuts the exporter down
+    pub fn shutdown(self) -> io::Result<()> {
+        self.provider.shutdown().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Irq, IrqState};
+    use opentelemetry_sdk::{
+        error::OTelSdkResult,
+        trace::{SpanData, SpanExporter},
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    /// Keeps the exported spans in memory
+    #[derive(Debug, Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Spans {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    fn event(kind: EventKind) -> Event {
+        Event {
+            timestamp: Duration::ZERO,
+            virtual_ns: Some(100),
+            kind,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let spans = Spans::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(spans.clone())
+            .build();
+        let exporter = SessionExporter::with_provider(provider);
+
+        let (tx, rx) = broadcast::channel(16);
+        for kind in [
+            EventKind::Connected,
+            EventKind::Command("clock_step 10".to_string()),
+            EventKind::Irq(Irq::new(3, IrqState::Raise)),
+            EventKind::Response(Response::OkVal("10".to_string())),
+            EventKind::Command("readl 0x1".to_string()),
+            EventKind::Response(Response::Err("FAIL".to_string())),
+            EventKind::Irq(Irq::new(3, IrqState::Lower)),
+        ] {
+            tx.send(event(kind)).unwrap();
+        }
+        drop(tx);
+        exporter.export("boot", rx).await;
+        exporter.shutdown().unwrap();
+
+        let spans = spans.0.lock().unwrap();
+        let [step, read, session] = spans.as_slice() else {
+            panic!("unexpected spans: {spans:?}");
+        };
+        assert_eq!(session.name, "boot");
+        assert_eq!(session.events.len(), 2);
+        for command in [step, read] {
+            assert_eq!(command.name, "qtest.command");
+            assert_eq!(command.parent_span_id, session.span_context.span_id());
+            assert_eq!(
+                command.span_context.trace_id(),
+                session.span_context.trace_id()
+            );
+        }
+        let events: Vec<_> = step.events.iter().map(|e| e.name.as_ref()).collect();
+        assert_eq!(events, ["sent", "irq", "answered"]);
+        assert_eq!(step.status, Status::Unset);
+        assert_eq!(read.status, Status::error("FAIL"));
+    }
+    #[tokio::test]
+    async fn test_export_batch() {
+        let spans = Spans::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(spans.clone())
+            .build();
+        let exporter = SessionExporter::with_provider(provider);
+
+        let (tx, rx) = broadcast::channel(16);
+        for kind in [
+            EventKind::Command("writel 0x0 0x1".to_string()),
+            EventKind::Command("readl 0x4".to_string()),
+            EventKind::Command("writel 0x8 0x2".to_string()),
+            EventKind::Response(Response::Ok),
+            EventKind::Irq(Irq::new(3, IrqState::Raise)),
+            EventKind::Response(Response::OkVal("0x5".to_string())),
+            EventKind::Response(Response::Err("FAIL".to_string())),
+        ] {
+            tx.send(event(kind)).unwrap();
+        }
+        drop(tx);
+        exporter.export("batch", rx).await;
+        exporter.shutdown().unwrap();
+
+        let spans = spans.0.lock().unwrap();
+        let [first, second, third, session] = spans.as_slice() else {
+            panic!("unexpected spans: {spans:?}");
+        };
+        assert_eq!(session.name, "batch");
+        assert!(session.events.is_empty());
+        let attribute = |span: &SpanData, key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+        for (span, command, response) in [
+            (first, "writel 0x0 0x1", "OK"),
+            (second, "readl 0x4", "OK 0x5"),
+            (third, "writel 0x8 0x2", "FAIL"),
+        ] {
+            assert_eq!(attribute(span, "qtest.command").as_deref(), Some(command));
+            assert_eq!(attribute(span, "qtest.response").as_deref(), Some(response));
+        }
+        let events: Vec<_> = second.events.iter().map(|e| e.name.as_ref()).collect();
+        assert_eq!(events, ["sent", "irq", "answered"]);
+        assert_eq!(third.status, Status::error("FAIL"));
+    }
+}
@@ -1,13 +1,55 @@
+#[cfg(feature = "base64")]
 use base64::{
     alphabet,
     engine::{Engine, GeneralPurpose, GeneralPurposeConfig},
 };
-use std::io;
-use tokio::sync::mpsc;
+use std::{collections::VecDeque, future::Future, io, ops::Range, time::Duration};
+use tokio::sync::{broadcast, mpsc, watch, OwnedRwLockReadGuard};
 
+use crate::address::{Address, SymbolTable};
+use crate::clock::ClockController;
+use crate::error::QtestError;
+use crate::event::{Event, EventBus, EventHistory, EventKind};
+use crate::hexdump::HexDump;
+use crate::icount::Icount;
+use crate::loader::{DebugInfo, Symbol, VarLocation, VarValue};
+use crate::logging::{in_span, log_trace, log_warn};
+use crate::protocol::{Command, Width};
 use crate::socket::Socket;
-use crate::{Irq, Response};
+use crate::transfer::{decode_hex, encode_hex, read_chunked, write_chunked};
+use crate::{Endianness, Irq, IrqState, Response};
 
+mod batch;
+mod builder;
+mod extension;
+mod handle;
+mod heartbeat;
+mod history;
+mod intercept;
+mod irq;
+mod leak;
+mod rate;
+mod reader;
+mod router;
+mod session;
+
+pub use batch::Batch;
+pub use builder::ParserBuilder;
+pub use extension::{Ext, Extension, ExtensionCommand};
+pub use handle::{ParserHandle, Priority};
+pub use heartbeat::{ConnectionState, Heartbeat};
+pub use history::{CommandOutcome, CommandRecord};
+pub use intercept::{Intercept, InterceptDirection, InterceptId, IrqEvent, IrqLines};
+pub use irq::IrqStorm;
+pub use leak::LeakReport;
+pub use reader::{LineHook, ReaderConfig, UnknownLines};
+pub use router::IrqRouter;
+pub use session::PausePolicy;
+
+/// Time given to QEMU to close the connection when the parser is closed, see [`Parser::close`]
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "base64")]
 const ENGINE: GeneralPurpose =
     GeneralPurpose::new(&alphabet::STANDARD, GeneralPurposeConfig::new());
 
@@ -16,6 +58,30 @@ const ENGINE: GeneralPurpose =
 pub struct Parser<T: Socket> {
     socket: T,
     response_queue: mpsc::Receiver<Response>,
+    raw_lines: Option<mpsc::Receiver<String>>,
+    events: EventBus,
+    irq_levels: irq::IrqLevels,
+    irq_events: broadcast::Sender<IrqEvent>,
+    response_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    intercepts_in: Vec<String>,
+    intercepts_out: Vec<String>,
+    intercepts: intercept::InterceptRegistry,
+    clock_ns: Option<usize>,
+    rate_limiter: Option<rate::RateLimiter>,
+    session: session::SessionGate,
+    pending: VecDeque<String>,
+    awaited: usize,
+    commands: history::CommandHistory,
+    extensions: extension::ExtensionRegistry,
+    unobserved_irqs: Option<irq::UnobservedIrqs>,
+    irq_storms: Option<irq::IrqStorms>,
+    heartbeat: Option<Heartbeat>,
+    liveness: heartbeat::Liveness,
+    symbols: SymbolTable,
+    debug_info: Option<DebugInfo>,
+    icount: Option<Icount>,
+    guest_endianness: Option<Endianness>,
 }
 
 impl<T: Socket> Parser<T> {
@@ -28,8 +94,11 @@ impl<T: Socket> Parser<T> {
     ///
     /// # Example
     ///
-    /// ```
-    /// let (parser, irq_rx) = Parser::<TcpSocket>::new("localhost:3000").await.unwrap();
+    /// ```no_run
+    /// # #[cfg(feature = "tcp")]
+    /// # async fn example() {
+    /// # use qtest::{parser::Parser, socket::tcp::SocketTcp};
+    /// let (mut parser, mut irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
     ///
     /// parser.attach_connection().await.unwrap();
     ///
@@ -38,88 +107,656 @@ impl<T: Socket> Parser<T> {
     ///       println!("IRQ: {:?}", irq);
     ///   }
     /// });
+    /// # }
+    /// ```
+    pub async fn new(url: &str) -> Result<(Parser<T>, mpsc::Receiver<Irq>), QtestError> {
+        ParserBuilder::new(url).build().await
+    }
+
+    /// Attaches the parser to the QTest socket connection, waiting for QEMU to connect.
+    ///
+    /// Once connected, the IRQs of the QOM paths registered with [`ParserBuilder::intercept_in`]
+    /// and [`ParserBuilder::intercept_out`] are intercepted.
+    ///
+    /// It can be called again after QEMU disconnects (e.g. after a guest reboot or a re-launch
+    /// with a `reconnect=` chardev) to wait for the next connection. The pending responses and
+    /// the cached virtual clock of the previous connection are discarded, and the IRQ interceptions
+    /// of the previous connection are restored, keeping their identifiers and line mappings.
+    pub async fn attach_connection(&mut self) -> Result<(), QtestError> {
+        match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.socket.attach_connection())
+                .await
+                .map_err(|_| QtestError::Timeout("Timed out waiting for connection".into()))?
+                .map_err(QtestError::Socket)?,
+            None => self
+                .socket
+                .attach_connection()
+                .await
+                .map_err(QtestError::Socket)?,
+        }
+
+        self.clock_ns = None;
+        self.events.set_virtual_ns(None);
+        self.pending.clear();
+        self.awaited = 0;
+        while self.response_queue.try_recv().is_ok() {}
+        self.liveness.attached();
+        self.events.publish(EventKind::Connected);
+
+        for intercept in self.intercepts.list() {
+            let (qom_path, direction) = (intercept.qom_path, intercept.direction);
+            let command = intercept_command(&qom_path, direction);
+            if let Response::Err(e) = self.execute(command).await? {
+                return Err(QtestError::Fail(format!(
+                    "Could not restore the interception of {direction:?} IRQs of {qom_path}: {e}"
+                )));
+            }
+        }
+        for qom_path in self.intercepts_in.clone() {
+            if self
+                .intercepts
+                .find(&qom_path, InterceptDirection::In)
+                .is_some()
+            {
+                continue;
+            }
+            if let Response::Err(e) = self.irq_intercept_in(&qom_path).await? {
+                return Err(QtestError::Fail(format!(
+                    "Could not intercept input IRQs of {qom_path}: {e}"
+                )));
+            }
+        }
+        for qom_path in self.intercepts_out.clone() {
+            if self
+                .intercepts
+                .find(&qom_path, InterceptDirection::Out)
+                .is_some()
+            {
+                continue;
+            }
+            if let Response::Err(e) = self.irq_intercept_out(&qom_path).await? {
+                return Err(QtestError::Fail(format!(
+                    "Could not intercept output IRQs of {qom_path}: {e}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Attaches the next connection after QEMU closed the previous one, e.g. after a restart.
+    ///
+    /// It is [`Parser::attach_connection`], so the IRQ interceptions of the previous connection are
+    /// restored and the connection state goes back to [`ConnectionState::Alive`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "tcp")]
+    /// # async fn example() {
+    /// # use qtest::{parser::Parser, socket::tcp::SocketTcp};
+    /// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+    /// parser.attach_connection().await.unwrap();
+    ///
+    /// parser.on_disconnect().await;
+    /// // QEMU is restarted by the harness
+    /// parser.reattach_connection().await.unwrap();
+    /// # }
     /// ```
-    pub async fn new(url: &str) -> io::Result<(Parser<T>, mpsc::Receiver<Irq>)> {
-        let (tx_raw_sock_out, rx_raw_sock_out) = mpsc::channel(32);
-        let (tx_response, rx_response) = mpsc::channel(32);
-        let (tx_irq, rx_irq) = mpsc::channel(32);
+    pub async fn reattach_connection(&mut self) -> Result<(), QtestError> {
+        self.attach_connection().await
+    }
 
-        let qtest_socket = T::new(url, tx_raw_sock_out).await?;
+    /// Returns a future that completes when QEMU closes the connection, or when the parser is dropped.
+    ///
+    /// It completes immediately if the connection is already closed. It never completes for
+    /// sockets that do not track their connection, see [`Socket::link_state`].
+    pub fn on_disconnect(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut state = self.liveness.watch();
+        async move {
+            let _ = state
+                .wait_for(|state| *state == ConnectionState::Disconnected)
+                .await;
+        }
+    }
 
-        tokio::spawn(async move {
-            let mut reader = Reader::new(rx_raw_sock_out, tx_irq, tx_response);
-            reader.read().await.unwrap();
-        });
+    /// Returns the socket of the parser
+    pub fn socket(&self) -> &T {
+        &self.socket
+    }
 
-        Ok((
-            Parser {
-                socket: qtest_socket,
-                response_queue: rx_response,
-            },
-            rx_irq,
-        ))
+    /// Returns a receiver of the unified event stream of the session.
+    ///
+    /// The stream contains every command sent, response received and IRQ propagated by QEMU
+    /// from the moment this method is called. Receivers that fall behind lose the oldest events.
+    pub fn events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
     }
 
-    pub async fn attach_connection(&mut self) -> io::Result<()> {
-        self.socket.attach_connection().await
+    /// Returns a receiver reflecting the last known level of the given IRQ line.
+    ///
+    /// Level-triggered logic can wait for the next level change with [`watch::Receiver::changed`],
+    /// without replaying every edge. Lines without any IRQ event yet are considered lowered.
+    pub fn irq_level_watch(&self, line: usize) -> watch::Receiver<IrqState> {
+        self.irq_levels.watch(line)
     }
 
-    /// Clock step function, steps the clock by the given number of nanoseconds
-    pub async fn clock_step(&mut self, ns: Option<usize>) -> io::Result<Response> {
-        let data = match ns {
-            Some(ns) => format!("clock_step {ns}\n"),
-            None => "clock_step\n".to_string(),
-        };
-        self.socket.send(&data).await?;
-        self.response_queue
-            .recv()
+    /// Waits until the given IRQ line is at the given level, up to the given timeout.
+    ///
+    /// It follows the level of the line, as [`Parser::irq_level_watch`]: if the line is already at the level,
+    /// it returns at once, so an IRQ raised before the call (e.g. before the response to the write that caused it)
+    /// is not missed. Returns [`QtestError::Timeout`] if the line does not reach the level in time.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "tcp")]
+    /// # async fn example() {
+    /// # use qtest::{parser::Parser, socket::tcp::SocketTcp, IrqState};
+    /// # use std::time::Duration;
+    /// # let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+    /// parser.writel(0x4000_0000, 1).await.unwrap();
+    /// parser.wait_for_irq(5, IrqState::Raise, Duration::from_secs(1)).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn wait_for_irq(
+        &self,
+        line: usize,
+        state: IrqState,
+        timeout: Duration,
+    ) -> Result<Irq, QtestError> {
+        let mut level = self.irq_levels.watch(line);
+        tokio::time::timeout(timeout, level.wait_for(|level| *level == state))
             .await
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not receive response"))
+            .map_err(|_| {
+                QtestError::Timeout(format!("Timed out waiting for IRQ {line} to {state:?}"))
+            })?
+            .map_err(|_| QtestError::ChannelClosed("IRQ levels are closed".into()))?;
+        Ok(Irq::new(line, state))
+    }
+
+    /// Takes the receiver of the lines that QEMU sent but are neither responses nor IRQ events.
+    ///
+    /// Returns `None` if the parser was not built with [`UnknownLines::Raw`], or if the receiver was already taken.
+    pub fn take_raw_lines(&mut self) -> Option<mpsc::Receiver<String>> {
+        self.raw_lines.take()
+    }
+
+    /// Returns the bounded history of the most recent events of the session,
+    /// dated with the virtual clock last reported by QEMU
+    pub fn event_history(&self) -> EventHistory {
+        self.events.history()
+    }
+
+    /// Replaces the symbol table used to resolve the [`Address::Symbol`] addresses of the memory functions.
+    ///
+    /// [`crate::loader::load_elf`] sets the symbol table of the loaded program.
+    pub fn set_symbols(&mut self, symbols: impl IntoIterator<Item = Symbol>) {
+        self.symbols = SymbolTable::new(symbols);
+    }
+
+    /// Returns the symbol table used to resolve the addresses of the memory functions
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Resolves the given address to an absolute address with the symbol table of the parser
+    pub fn resolve(&self, addr: impl Into<Address>) -> Result<usize, QtestError> {
+        Ok(self.symbols.resolve(&addr.into())?)
+    }
+
+    /// Sets the debugging information used to locate the variables of [`Parser::read_var`] and [`Parser::write_var`]
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info = Some(debug_info);
+    }
+
+    /// Returns the debugging information of the parser, if set
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    /// Returns the location of the given variable path (e.g. `driver_state.flags`) and the byte order of the program.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the size of the variable is not the expected one.
+    fn locate_var(&self, path: &str, size: usize) -> Result<(VarLocation, Endianness), QtestError> {
+        let debug_info = self.debug_info.as_ref().ok_or_else(|| {
+            QtestError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No debugging information loaded",
+            ))
+        })?;
+        let location = debug_info.resolve(path)?;
+        if location.size != size as u64 {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{path} is {} bytes long, not {size}", location.size),
+            )));
+        }
+        Ok((location, debug_info.endianness()))
+    }
+
+    /// Reads the variable at the given path (e.g. `driver_state.flags`) with the byte order of the program.
+    ///
+    /// The variable is located with the debugging information set with [`Parser::set_debug_info`],
+    /// and its size must match the size of the type it is read as.
+    pub async fn read_var<V: VarValue>(&mut self, path: &str) -> Result<V, QtestError> {
+        let (location, endianness) = self.locate_var(path, V::SIZE)?;
+        let bytes = read_chunked(self, location.addr as usize, V::SIZE, |_, _| {}).await?;
+        Ok(V::from_bytes(&bytes, endianness))
+    }
+
+    /// Writes the variable at the given path with the byte order of the program, as [`Parser::read_var`] reads it
+    pub async fn write_var<V: VarValue>(&mut self, path: &str, value: V) -> Result<(), QtestError> {
+        let (location, endianness) = self.locate_var(path, V::SIZE)?;
+        let bytes = value.to_bytes(endianness);
+        write_chunked(self, location.addr as usize, &bytes, |_, _| {}).await
+    }
+
+    /// Returns a receiver of the health of the connection with QEMU.
+    ///
+    /// The state becomes [`ConnectionState::Disconnected`] when QEMU closes the connection, if the socket
+    /// tracks it (see [`Socket::link_state`]), and [`ConnectionState::Suspect`] or [`ConnectionState::Dead`]
+    /// only if the parser was built with [`ParserBuilder::heartbeat`].
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.liveness.watch()
+    }
+
+    /// Probes QEMU with `clock_step 0`, as the heartbeat of [`ParserHandle`] does when idle
+    pub async fn probe(&mut self) -> Result<(), QtestError> {
+        match self.execute(Command::ClockStep(Some(0))).await? {
+            Response::OkVal(_) => Ok(()),
+            response => Err(QtestError::Protocol(format!(
+                "Invalid response to heartbeat probe: {response}"
+            ))),
+        }
+    }
+
+    /// Pauses the session: waits for the command in flight, if any, and stops sending commands.
+    ///
+    /// The commands issued while paused are buffered or rejected according to the given policy.
+    /// Calling it on a paused session only changes the policy.
+    pub async fn pause(&self, policy: PausePolicy) {
+        self.session.pause(policy).await
+    }
+
+    /// Resumes a paused session, sending the buffered commands
+    pub fn resume(&self) {
+        self.session.resume()
+    }
+
+    /// Returns true if the session is paused
+    pub fn is_paused(&self) -> bool {
+        self.session.is_paused()
+    }
+
+    /// Returns an empty batch of commands, sent to QEMU in a single socket write when submitted
+    pub fn batch(&mut self) -> Batch<'_, T> {
+        Batch::new(self)
+    }
+
+    /// Sends a command to the socket and publishes it in the event stream.
+    /// The command is in flight until the returned guard is dropped, once its response is received.
+    async fn send_command(
+        &mut self,
+        command: Command,
+    ) -> Result<OwnedRwLockReadGuard<()>, QtestError> {
+        self.send_line(command.to_string()).await
+    }
+
+    /// Sends a command line, without the trailing newline, as [`Parser::send_command`]
+    async fn send_line(&mut self, line: String) -> Result<OwnedRwLockReadGuard<()>, QtestError> {
+        self.send_lines(vec![line]).await
+    }
+
+    /// Sends command lines, without their trailing newlines, in a single socket write.
+    ///
+    /// The commands are in flight until the returned guard is dropped, once the response to the last one
+    /// is received. Keeping the guard in the future that waits for the responses releases it on errors
+    /// and when the future is dropped, so [`Parser::pause`] never waits for a command given up.
+    async fn send_lines(
+        &mut self,
+        lines: Vec<String>,
+    ) -> Result<OwnedRwLockReadGuard<()>, QtestError> {
+        if self.liveness.is_disconnected() {
+            return Err(QtestError::Socket(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "QEMU closed the connection",
+            )));
+        }
+        let in_flight = self.session.enter().await?;
+        let mut data = String::new();
+        for line in &lines {
+            log_trace!(target: "qtest::parser", "> {line}");
+            if let Some(rate_limiter) = &mut self.rate_limiter {
+                rate_limiter.acquire(line.len() + 1).await;
+            }
+            data.push_str(line);
+            data.push('\n');
+        }
+        self.socket.send(&data).await.map_err(QtestError::Socket)?;
+        // The commands still pending were given up, so their responses are discarded when they arrive
+        self.awaited = lines.len();
+        for line in lines {
+            self.liveness.command_sent();
+            self.pending.push_back(line.clone());
+            self.events.publish(EventKind::Command(line));
+        }
+        Ok(in_flight)
+    }
+
+    /// Sends a command and waits for its response, keeping track of the virtual clock reported by clock commands
+    pub(crate) async fn execute(&mut self, command: Command) -> Result<Response, QtestError> {
+        let clock_command = matches!(command, Command::ClockStep(_) | Command::ClockSet(_));
+        let record = command.clone();
+        let response = in_span!(
+            target: "qtest::parser",
+            "qtest.command",
+            verb = command.verb(),
+            addr = command.addr(),
+            len = command.access_len();
+            async {
+                let _in_flight = self.send_command(command).await?;
+                self.recv_response().await
+            }
+        )
+        .await;
+        if let Err(e) = &response {
+            log_warn!(target: "qtest::parser", "{record} failed: {e}");
+        }
+        self.commands.push(record, &response);
+        let response = response?;
+        if clock_command {
+            self.track_clock(&response);
+        }
+        Ok(response)
+    }
+
+    /// Sends the given commands in a single socket write and waits for their responses, in order.
+    ///
+    /// Stops at the first command without a response, whose error is returned.
+    pub(crate) async fn execute_batch(
+        &mut self,
+        commands: Vec<Command>,
+    ) -> Result<Vec<Response>, QtestError> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let lines = commands.iter().map(ToString::to_string).collect();
+        let len = commands.len();
+        in_span!(
+            target: "qtest::parser",
+            "qtest.batch",
+            verb = "batch",
+            len = len;
+            async {
+                // The batch is in flight until its last response
+                let _in_flight = match self.send_lines(lines).await {
+                    Ok(in_flight) => in_flight,
+                    Err(e) => {
+                        log_warn!(target: "qtest::parser", "batch of {len} commands failed: {e}");
+                        return Err(e);
+                    }
+                };
+                let mut responses = Vec::with_capacity(len);
+                for command in commands {
+                    let clock_command =
+                        matches!(command, Command::ClockStep(_) | Command::ClockSet(_));
+                    let response = self.recv_response().await;
+                    if let Err(e) = &response {
+                        log_warn!(target: "qtest::parser", "{command} failed: {e}");
+                    }
+                    self.commands.push(command, &response);
+                    let response = response?;
+                    if clock_command {
+                        self.track_clock(&response);
+                    }
+                    responses.push(response);
+                }
+                Ok(responses)
+            }
+        )
+        .await
+    }
+
+    /// Updates the virtual clock with the response to a clock command
+    fn track_clock(&mut self, response: &Response) {
+        if let Response::OkVal(val) = response {
+            self.clock_ns = val.parse().ok().or(self.clock_ns);
+            self.events.set_virtual_ns(self.clock_ns);
+        }
+    }
+
+    /// Waits for the response to the next command in flight, up to the response timeout if set.
+    ///
+    /// The late responses to the commands given up before, e.g. after a timeout, are discarded first.
+    async fn recv_response(&mut self) -> Result<Response, QtestError> {
+        let deadline = self
+            .response_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        loop {
+            let response = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, self.response_queue.recv())
+                    .await
+                    .map_err(|_| QtestError::Timeout("Timed out waiting for response".into()))?,
+                None => self.response_queue.recv().await,
+            };
+            let response = response
+                .ok_or_else(|| QtestError::ChannelClosed("Could not receive response".into()))?;
+            let stale = self.pending.len() > self.awaited;
+            let command = self.pending.pop_front();
+            self.liveness.responded();
+            if stale {
+                let command = command.unwrap_or_default();
+                log_warn!(target: "qtest::parser", "discarding late response to {command}: {response}");
+                continue;
+            }
+            self.awaited = self.awaited.saturating_sub(1);
+            return Ok(response);
+        }
+    }
+
+    /// Sets the maximum time to wait for the response to each command from now on, or no limit with `None`.
+    ///
+    /// If the timeout expires, the command fails with [`QtestError::Timeout`], see [`ParserBuilder::response_timeout`].
+    pub fn set_response_timeout(&mut self, timeout: Option<Duration>) {
+        self.response_timeout = timeout;
+    }
+
+    /// Returns the maximum time to wait for the response to each command, if limited
+    pub fn response_timeout(&self) -> Option<Duration> {
+        self.response_timeout
+    }
+
+    /// Returns the last command sent to QEMU with its outcome, if any is kept in the command history
+    pub fn last_command(&self) -> Option<&CommandRecord> {
+        self.commands.last()
+    }
+
+    /// Returns up to the last `n` commands sent to QEMU with their outcomes, oldest first.
+    ///
+    /// Only the commands kept in the command history are returned, see [`ParserBuilder::command_history_capacity`].
+    pub fn history(&self, n: usize) -> Vec<CommandRecord> {
+        self.commands.recent(n)
+    }
+
+    /// Returns up to the last `n` commands sent to QEMU with their outcomes, as [`Parser::history`]
+    pub fn command_history(&self, n: usize) -> Vec<CommandRecord> {
+        self.history(n)
+    }
+
+    /// Registers an extension with vendor-specific commands, replacing any previous extension of the same type
+    pub fn register_extension<E: Extension>(&mut self, ext: E) {
+        self.extensions.register(ext);
+    }
+
+    /// Returns the access to the commands of the registered extension `E`.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the extension is not registered.
+    pub fn ext<E: Extension>(&mut self) -> Result<Ext<'_, T, E>, QtestError> {
+        let ext = self.extensions.get::<E>()?;
+        Ok(Ext::new(self, ext))
+    }
+
+    /// Returns the IRQ events delivered while nothing observed them, if built with [`ParserBuilder::strict_irqs`]
+    pub fn unobserved_irqs(&self) -> Vec<Irq> {
+        self.unobserved_irqs
+            .as_ref()
+            .map(|irqs| irqs.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the IRQ storms detected so far, if built with [`ParserBuilder::irq_storm_threshold`].
+    /// The counts of an ongoing storm keep growing until it ends.
+    pub fn irq_storms(&self) -> Vec<IrqStorm> {
+        self.irq_storms
+            .as_ref()
+            .map(|storms| storms.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Closes the socket and reports the responses that arrived but were never consumed,
+    /// and the commands that never got a response, e.g. because their future was dropped or timed out.
+    ///
+    /// Dropping the socket shuts the connection down, and QEMU closes its end once it has sent every line.
+    /// The lines received until then are read before building the report, waiting up to one second for QEMU.
+    ///
+    /// In strict IRQ mode, the report also lists the IRQ events that nothing observed.
+    /// The leftovers are also logged: unconsumed responses as warnings, and the others as errors.
+    pub async fn close(mut self) -> Result<LeakReport, QtestError> {
+        let closed = self.socket.close();
+        drop(self.socket);
+        // The reader delivers the lines left and drops the response sender once the connection is closed
+        let mut responses = Vec::new();
+        let deadline = tokio::time::Instant::now() + CLOSE_TIMEOUT;
+        while let Ok(Some(response)) =
+            tokio::time::timeout_at(deadline, self.response_queue.recv()).await
+        {
+            responses.push(response);
+        }
+        let unobserved_irqs = self
+            .unobserved_irqs
+            .map(|irqs| irqs.lock().unwrap().clone())
+            .unwrap_or_default();
+        let report = LeakReport::new(
+            std::mem::take(&mut self.pending),
+            responses,
+            unobserved_irqs,
+        );
+        report.log();
+        closed?;
+        Ok(report)
+    }
+
+    /// Moves the parser to a background task and returns a cloneable handle to it
+    pub fn into_handle(self) -> ParserHandle
+    where
+        T: Send + 'static,
+    {
+        ParserHandle::spawn(self)
+    }
+
+    /// Returns the controller of the virtual clock of the guest
+    pub fn clock(&mut self) -> ClockController<'_, T> {
+        ClockController::new(self)
+    }
+
+    /// Returns the instruction counting settings QEMU runs with, if set with [`ParserBuilder::icount`]
+    pub(crate) fn icount(&self) -> Option<Icount> {
+        self.icount
+    }
+
+    /// Returns the last virtual clock value reported by QEMU, in nanoseconds
+    pub(crate) fn cached_clock(&self) -> Option<usize> {
+        self.clock_ns
+    }
+
+    /// Clock step function, steps the clock by the given number of nanoseconds
+    #[deprecated(note = "use `Parser::clock_step_ns`, which returns the new virtual clock")]
+    pub async fn clock_step(&mut self, ns: Option<usize>) -> Result<Response, QtestError> {
+        self.execute(Command::ClockStep(ns)).await
+    }
+
+    /// Steps the clock by the given number of nanoseconds, or to the next timer deadline with `None`,
+    /// and returns the new virtual clock in nanoseconds
+    pub async fn clock_step_ns(&mut self, ns: Option<usize>) -> Result<usize, QtestError> {
+        let response = self.execute(Command::ClockStep(ns)).await?;
+        parse_clock(response)
     }
 
     /// Set the clock to the given number of nanoseconds
-    pub async fn clock_set(&mut self, ns: usize) -> io::Result<usize> {
-        let data = format!("clock_set {}\n", ns);
-        self.socket.send(&data).await?;
-        let response =
-            self.response_queue.recv().await.ok_or_else(|| {
-                io::Error::new(io::ErrorKind::Other, "Could not receive response")
-            })?;
+    pub async fn clock_set(&mut self, ns: usize) -> Result<usize, QtestError> {
+        let response = self.execute(Command::ClockSet(ns)).await?;
+        parse_clock(response)
+    }
 
-        match response {
-            Response::OkVal(val) => val.parse().map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Could not parse value: {}\n error {}", val, e),
-                )
-            }),
-            Response::Err(e) => Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("invalid response: {}", e),
-            )),
-            _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
-        }
-    }
-
-    /// IRQ intercept in function, intercepts the given IRQ in the given QOM path, this function can be only used once with one IRQ path,
-    /// QEMU will clash if called more than once.
-    pub async fn irq_intercept_in(&mut self, qom_path: &str) -> io::Result<Response> {
-        let data = format!("irq_intercept_in {}\n", qom_path);
-        self.socket.send(&data).await?;
-        self.response_queue
-            .recv()
-            .await
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not receive response"))
+    /// IRQ intercept in function, intercepts the input IRQs of the given QOM path.
+    ///
+    /// QEMU aborts if the same IRQs are intercepted more than once, so the parser keeps track of
+    /// the intercepted paths and returns an error of kind [`io::ErrorKind::AlreadyExists`] instead.
+    pub async fn irq_intercept_in(&mut self, qom_path: &str) -> Result<Response, QtestError> {
+        self.irq_intercept(qom_path, InterceptDirection::In).await
     }
 
-    /// IRQ intercept out function, intercepts the given IRQ in the given QOM path
-    pub async fn irq_intercept_out(&mut self, qom_path: &str) -> io::Result<Response> {
-        let data = format!("irq_intercept_out {}\n", qom_path);
-        self.socket.send(&data).await?;
-        self.response_queue
-            .recv()
-            .await
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not receive response"))
+    /// IRQ intercept out function, intercepts the output IRQs of the given QOM path.
+    ///
+    /// As with [`Parser::irq_intercept_in`], intercepting the same path twice returns an error
+    /// of kind [`io::ErrorKind::AlreadyExists`].
+    pub async fn irq_intercept_out(&mut self, qom_path: &str) -> Result<Response, QtestError> {
+        self.irq_intercept(qom_path, InterceptDirection::Out).await
+    }
+
+    /// Returns the IRQ interceptions accepted by QEMU, in registration order
+    pub fn intercepts(&self) -> Vec<Intercept> {
+        self.intercepts.list()
+    }
+
+    /// Maps a range of IRQ lines to a named GPIO list of an intercepted device.
+    ///
+    /// QEMU IRQ events only carry a line number, so the mapped ranges are used to attribute the events
+    /// delivered by [`Parser::irq_events`] to their device. Returns an error of kind [`io::ErrorKind::NotFound`]
+    /// if the IRQs of the given path and direction are not intercepted.
+    pub fn map_irq_lines(
+        &mut self,
+        qom_path: &str,
+        direction: InterceptDirection,
+        gpio: &str,
+        lines: Range<usize>,
+    ) -> Result<InterceptId, QtestError> {
+        let id = self.intercepts.find(qom_path, direction).ok_or_else(|| {
+            QtestError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{direction:?} IRQs of {qom_path} are not intercepted"),
+            ))
+        })?;
+        self.intercepts.map_lines(id, gpio, lines);
+        Ok(id)
+    }
+
+    /// Returns a receiver of the IRQ events attributed to the interception that produced them.
+    ///
+    /// Events of lines mapped with [`Parser::map_irq_lines`] are attributed to the first mapping
+    /// containing the line, the rest to the first interception.
+    pub fn irq_events(&self) -> broadcast::Receiver<IrqEvent> {
+        self.irq_events.subscribe()
+    }
+
+    /// Intercepts the IRQs of the given QOM path, unless they are already intercepted
+    async fn irq_intercept(
+        &mut self,
+        qom_path: &str,
+        direction: InterceptDirection,
+    ) -> Result<Response, QtestError> {
+        if self.intercepts.find(qom_path, direction).is_some() {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{direction:?} IRQs of {qom_path} are already intercepted"),
+            )));
+        }
+
+        let response = self.execute(intercept_command(qom_path, direction)).await?;
+        if response == Response::Ok {
+            self.intercepts.register(qom_path, direction);
+        }
+        Ok(response)
     }
 
     /// Set IRQ in function, sets the given IRQ in the given QOM path to the given level
@@ -129,201 +766,708 @@ impl<T: Socket> Parser<T> {
         irq_name: &str,
         line: usize,
         level: isize,
-    ) -> io::Result<Response> {
-        let data = format!("set_irq_in {} {} {} {}\n", qom_path, irq_name, line, level);
-        self.socket.send(&data).await?;
-        self.response_queue
-            .recv()
-            .await
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not receive response"))
+    ) -> Result<Response, QtestError> {
+        self.execute(Command::SetIrqIn {
+            qom_path: qom_path.to_string(),
+            irq_name: irq_name.to_string(),
+            line,
+            level,
+        })
+        .await
+    }
+}
+
+/// Returns the command intercepting the IRQs of the given QOM path and direction
+fn intercept_command(qom_path: &str, direction: InterceptDirection) -> Command {
+    match direction {
+        InterceptDirection::In => Command::IrqInterceptIn(qom_path.to_string()),
+        InterceptDirection::Out => Command::IrqInterceptOut(qom_path.to_string()),
+    }
+}
+
+/// Parses the virtual clock, in nanoseconds, reported by a clock command
+pub(crate) fn parse_clock(response: Response) -> Result<usize, QtestError> {
+    match response {
+        Response::OkVal(val) => val.parse().map_err(|e| {
+            QtestError::Parse(format!("Could not parse value: {}\n error {}", val, e))
+        }),
+        Response::Err(e) => Err(QtestError::Fail(e)),
+        _ => Err(QtestError::Protocol("Invalid response".into())),
     }
 }
 
 /// *In & out functions*
 macro_rules! impl_in_out {
-    ($in:ident, $out:ident, $ty:ty) => {
+    ($in:ident, $out:ident, $ty:ty, $width:expr) => {
         impl<T: Socket> Parser<T> {
-            pub async fn $in(&mut self, addr: usize) -> io::Result<$ty> {
-                let data = format!("{} {:#x}\n", stringify!($in), addr);
-                self.socket.send(&data).await?;
-                let response = self.response_queue.recv().await.ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::Other, "Could not receive response")
-                })?;
+            pub async fn $in(&mut self, addr: impl Into<Address>) -> Result<$ty, QtestError> {
+                let addr = self.resolve(addr)?;
+                let response = self
+                    .execute(Command::In {
+                        width: $width,
+                        addr,
+                    })
+                    .await?;
 
                 match response {
                     Response::OkVal(val) => <$ty>::from_str_radix(val.trim_start_matches("0x"), 16)
                         .map_err(|e| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("Could not parse value: {}\n error {}", val, e),
-                            )
+                            QtestError::Parse(format!(
+                                "Could not parse value: {}\n error {}",
+                                val, e
+                            ))
                         }),
-                    _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
+                    Response::Err(e) => Err(QtestError::Fail(e)),
+                    _ => Err(QtestError::Protocol("Invalid response".into())),
                 }
             }
 
-            pub async fn $out(&mut self, addr: usize, val: $ty) -> io::Result<Response> {
-                let data = format!("{} {:#x} {:#x}\n", stringify!($out), addr, val);
-                self.socket.send(&data).await?;
-                self.response_queue.recv().await.ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::Other, "Could not receive response")
+            pub async fn $out(
+                &mut self,
+                addr: impl Into<Address>,
+                val: $ty,
+            ) -> Result<Response, QtestError> {
+                let addr = self.resolve(addr)?;
+                self.execute(Command::Out {
+                    width: $width,
+                    addr,
+                    val: val.into(),
                 })
+                .await
             }
         }
     };
 }
 
-impl_in_out!(inb, outb, u8);
-impl_in_out!(inw, outw, u16);
-impl_in_out!(inl, outl, u32);
+impl_in_out!(inb, outb, u8, Width::Byte);
+impl_in_out!(inw, outw, u16, Width::Word);
+impl_in_out!(inl, outl, u32, Width::Long);
 
 /// *Write & Read functions*
 macro_rules! impl_write_read {
-    ($write:ident, $read:ident, $ty:ty) => {
+    ($write:ident, $read:ident, $compare_and_write:ident, $poll_until:ident, $ty:ty, $width:expr) => {
         impl<T: Socket> Parser<T> {
             /// Write a value to the given address, returns a Ok()
-            pub async fn $write(&mut self, addr: usize, val: $ty) -> io::Result<Response> {
-                let data = format!("{} {:#x} {:#x}", stringify!($write), addr, val);
-                self.socket.send(&data).await?;
-                self.response_queue.recv().await.ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::Other, "Could not receive response")
+            pub async fn $write(
+                &mut self,
+                addr: impl Into<Address>,
+                val: $ty,
+            ) -> Result<Response, QtestError> {
+                let addr = self.resolve(addr)?;
+                self.execute(Command::WriteValue {
+                    width: $width,
+                    addr,
+                    val: val.into(),
                 })
+                .await
             }
 
             /// Reads a value from the given address, returns a result with the value
-            pub async fn $read(&mut self, addr: usize) -> io::Result<$ty> {
-                let data = format!("{} {:#x}\n", stringify!($read), addr);
-                self.socket.send(&data).await?;
-                let response = self.response_queue.recv().await.ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::Other, "Could not receive response")
-                })?;
+            pub async fn $read(&mut self, addr: impl Into<Address>) -> Result<$ty, QtestError> {
+                let addr = self.resolve(addr)?;
+                let response = self
+                    .execute(Command::ReadValue {
+                        width: $width,
+                        addr,
+                    })
+                    .await?;
 
                 match response {
                     Response::OkVal(val) => <$ty>::from_str_radix(val.trim_start_matches("0x"), 16)
                         .map_err(|e| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("Could not parse value: {}\n error {}", val, e),
-                            )
+                            QtestError::Parse(format!(
+                                "Could not parse value: {}\n error {}",
+                                val, e
+                            ))
                         }),
-                    _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
+                    Response::Err(e) => Err(QtestError::Fail(e)),
+                    _ => Err(QtestError::Protocol("Invalid response".into())),
                 }
             }
+
+            /// Writes `new` to the given address if it holds `expected`, and reads it back to verify the write.
+            ///
+            /// Returns whether the value was swapped. As the guest does not run between commands unless the clock
+            /// is stepped, the swap is atomic with respect to the firmware.
+            /// Returns an error of kind [`io::ErrorKind::InvalidData`] if the write was ignored, i.e. the value
+            /// read back is not `new`.
+            pub async fn $compare_and_write(
+                &mut self,
+                addr: impl Into<Address>,
+                expected: $ty,
+                new: $ty,
+            ) -> Result<bool, QtestError> {
+                let addr = self.resolve(addr)?;
+                if self.$read(addr).await? != expected {
+                    return Ok(false);
+                }
+                match self.$write(addr, new).await? {
+                    Response::Ok => {}
+                    Response::Err(e) => return Err(QtestError::Fail(e)),
+                    response => {
+                        return Err(QtestError::Protocol(format!(
+                            "Invalid response: {response}"
+                        )))
+                    }
+                }
+                let actual = self.$read(addr).await?;
+                if actual != new {
+                    return Err(QtestError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Write of {new:#x} to {addr:#x} ignored, read back {actual:#x}"),
+                    )));
+                }
+                Ok(true)
+            }
+
+            #[doc = concat!("Reads the given address with [`Parser::", stringify!($read), "`] until its value, masked with `mask`, equals `expected`, and returns the last value read.")]
+            ///
+            /// The address is read every `interval` of host time. If `clock_step` is given, the virtual clock
+            /// is stepped by that number of nanoseconds before each read after the first, so the guest makes progress
+            /// even when the clock is stopped. Returns [`QtestError::Timeout`] if the value does not match within
+            /// `timeout` of host time; the timeout is checked between reads, so no command is left in flight.
+            pub async fn $poll_until(
+                &mut self,
+                addr: impl Into<Address>,
+                mask: $ty,
+                expected: $ty,
+                interval: Duration,
+                clock_step: Option<usize>,
+                timeout: Duration,
+            ) -> Result<$ty, QtestError> {
+                let addr = self.resolve(addr)?;
+                let deadline = tokio::time::Instant::now() + timeout;
+                loop {
+                    let val = self.$read(addr).await?;
+                    if val & mask == expected & mask {
+                        return Ok(val);
+                    }
+                    let now = tokio::time::Instant::now();
+                    if now >= deadline {
+                        return Err(QtestError::Timeout(format!(
+                            "Timed out polling {addr:#x} for {expected:#x} with mask {mask:#x}, last read {val:#x}"
+                        )));
+                    }
+                    tokio::time::sleep(interval.min(deadline - now)).await;
+                    if let Some(ns) = clock_step {
+                        self.clock_step_ns(Some(ns)).await?;
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_write_read!(
+    writeb,
+    readb,
+    compare_and_writeb,
+    poll_untilb,
+    u8,
+    Width::Byte
+);
+impl_write_read!(
+    writew,
+    readw,
+    compare_and_writew,
+    poll_untilw,
+    u16,
+    Width::Word
+);
+impl_write_read!(
+    writel,
+    readl,
+    compare_and_writel,
+    poll_until,
+    u32,
+    Width::Long
+);
+impl_write_read!(
+    writeq,
+    readq,
+    compare_and_writeq,
+    poll_untilq,
+    u64,
+    Width::Quad
+);
+
+/// *Explicit endianness functions*
+impl<T: Socket> Parser<T> {
+    /// Queries the byte order of the guest to QEMU with the `endianness` command.
+    ///
+    /// The result is stored on the parser, replacing the one set with [`ParserBuilder::guest_endianness`],
+    /// and used by the explicit endianness accesses such as [`Parser::readl_be`].
+    pub async fn endianness(&mut self) -> Result<Endianness, QtestError> {
+        let endianness = match self.execute(Command::Endianness).await? {
+            Response::OkVal(val) => val
+                .parse()
+                .map_err(|e| QtestError::Parse(format!("{e}: {val}")))?,
+            Response::Err(e) => return Err(QtestError::Fail(e)),
+            _ => return Err(QtestError::Protocol("Invalid response".into())),
+        };
+        self.guest_endianness = Some(endianness);
+        Ok(endianness)
+    }
+
+    /// Returns the byte order of the guest, as set with [`ParserBuilder::guest_endianness`].
+    ///
+    /// If not set, it is queried to QEMU with [`Parser::endianness`] and kept for the next calls.
+    pub async fn guest_endianness(&mut self) -> Result<Endianness, QtestError> {
+        match self.guest_endianness {
+            Some(endianness) => Ok(endianness),
+            None => self.endianness().await,
+        }
+    }
+}
+
+macro_rules! impl_endian {
+    ($read:ident, $write:ident, $read_le:ident, $read_be:ident, $write_le:ident, $write_be:ident, $ty:ty) => {
+        impl<T: Socket> Parser<T> {
+            /// Reads a little-endian value from the given address, swapping the bytes on big-endian guests
+            pub async fn $read_le(&mut self, addr: impl Into<Address>) -> Result<$ty, QtestError> {
+                let guest = self.guest_endianness().await?;
+                let val = self.$read(addr).await?;
+                Ok(match guest {
+                    Endianness::Little => val,
+                    Endianness::Big => val.swap_bytes(),
+                })
+            }
+
+            /// Reads a big-endian value from the given address, swapping the bytes on little-endian guests
+            pub async fn $read_be(&mut self, addr: impl Into<Address>) -> Result<$ty, QtestError> {
+                let guest = self.guest_endianness().await?;
+                let val = self.$read(addr).await?;
+                Ok(match guest {
+                    Endianness::Little => val.swap_bytes(),
+                    Endianness::Big => val,
+                })
+            }
+
+            /// Writes a little-endian value to the given address, swapping the bytes on big-endian guests
+            pub async fn $write_le(
+                &mut self,
+                addr: impl Into<Address>,
+                val: $ty,
+            ) -> Result<Response, QtestError> {
+                let val = match self.guest_endianness().await? {
+                    Endianness::Little => val,
+                    Endianness::Big => val.swap_bytes(),
+                };
+                self.$write(addr, val).await
+            }
+
+            /// Writes a big-endian value to the given address, swapping the bytes on little-endian guests
+            pub async fn $write_be(
+                &mut self,
+                addr: impl Into<Address>,
+                val: $ty,
+            ) -> Result<Response, QtestError> {
+                let val = match self.guest_endianness().await? {
+                    Endianness::Little => val.swap_bytes(),
+                    Endianness::Big => val,
+                };
+                self.$write(addr, val).await
+            }
+        }
+    };
+}
+
+impl_endian!(readw, writew, readw_le, readw_be, writew_le, writew_be, u16);
+impl_endian!(readl, writel, readl_le, readl_be, writel_le, writel_be, u32);
+impl_endian!(readq, writeq, readq_le, readq_be, writeq_le, writeq_be, u64);
+
+/// *Integer array functions*
+macro_rules! impl_slice {
+    ($read:ident, $write:ident, $ty:ty) => {
+        impl<T: Socket> Parser<T> {
+            /// Reads `count` consecutive integers from the given address with the given endianness.
+            ///
+            /// The whole array is read with a single bulk transfer and converted locally.
+            pub async fn $read(
+                &mut self,
+                addr: impl Into<Address>,
+                count: usize,
+                endianness: Endianness,
+            ) -> Result<Vec<$ty>, QtestError> {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                let bytes = read_chunked(self, addr, count * SIZE, |_, _| {}).await?;
+                Ok(bytes
+                    .chunks_exact(SIZE)
+                    .map(|b| {
+                        let b = b.try_into().unwrap();
+                        match endianness {
+                            Endianness::Little => <$ty>::from_le_bytes(b),
+                            Endianness::Big => <$ty>::from_be_bytes(b),
+                        }
+                    })
+                    .collect())
+            }
+
+            /// Writes the given integers consecutively from the given address with the given endianness.
+            ///
+            /// The values are converted locally and written with a single bulk transfer.
+            pub async fn $write(
+                &mut self,
+                addr: impl Into<Address>,
+                values: &[$ty],
+                endianness: Endianness,
+            ) -> Result<(), QtestError> {
+                let bytes: Vec<u8> = values
+                    .iter()
+                    .flat_map(|v| match endianness {
+                        Endianness::Little => v.to_le_bytes(),
+                        Endianness::Big => v.to_be_bytes(),
+                    })
+                    .collect();
+                write_chunked(self, addr, &bytes, |_, _| {}).await
+            }
         }
     };
 }
 
-impl_write_read!(writeb, readb, u8);
-impl_write_read!(writew, readw, u16);
-impl_write_read!(writel, readl, u32);
-impl_write_read!(writeq, readq, u64);
+impl_slice!(read_u16_slice, write_u16_slice, u16);
+impl_slice!(read_u32_slice, write_u32_slice, u32);
+impl_slice!(read_u64_slice, write_u64_slice, u64);
 
 /// *Other memory functions*
 impl<T: Socket> Parser<T> {
     /// Reads the given number of bytes from the given address, returns a string with the data.
-    pub async fn read(&mut self, addr: usize, size: usize) -> io::Result<String> {
-        let data = format!("read {:#x} {}\n", addr, size);
-        self.socket.send(&data).await?;
-        let response =
-            self.response_queue.recv().await.ok_or_else(|| {
-                io::Error::new(io::ErrorKind::Other, "Could not receive response")
-            })?;
+    pub async fn read(
+        &mut self,
+        addr: impl Into<Address>,
+        size: usize,
+    ) -> Result<String, QtestError> {
+        let addr = self.resolve(addr)?;
+        let response = self.execute(Command::Read { addr, len: size }).await?;
 
         match response {
             Response::OkVal(val) => Ok(val),
-            _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid response")),
+            Response::Err(e) => Err(QtestError::Fail(e)),
+            _ => Err(QtestError::Protocol("Invalid response".into())),
+        }
+    }
+
+    /// Reads the given number of bytes from the given address, decoding the hexadecimal data sent by QEMU.
+    ///
+    /// The bytes are read with a single `read` command, see [`read_chunked`] for large regions.
+    pub async fn read_bytes(
+        &mut self,
+        addr: impl Into<Address>,
+        len: usize,
+    ) -> Result<Vec<u8>, QtestError> {
+        let data = self.read(addr, len).await?;
+        let bytes = decode_hex(&data).map_err(|e| QtestError::Parse(format!("{e}: {data}")))?;
+        match bytes.len() == len {
+            true => Ok(bytes),
+            false => Err(QtestError::Protocol(format!(
+                "Expected {len} bytes, got {}",
+                bytes.len()
+            ))),
+        }
+    }
+
+    /// Reads the given number of bytes from the given address, for display in hexdump format.
+    ///
+    /// The bytes are read in chunks, as [`read_chunked`], and the dump is located at the resolved address.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "tcp")]
+    /// # async fn example() {
+    /// # use qtest::{parser::Parser, socket::tcp::SocketTcp};
+    /// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+    /// parser.attach_connection().await.unwrap();
+    ///
+    /// let dump = parser.dump(0x2000_0000, 64).await.unwrap();
+    /// println!("{dump}");
+    /// assert_eq!(dump.bytes().len(), 64);
+    /// # }
+    /// ```
+    pub async fn dump(
+        &mut self,
+        addr: impl Into<Address>,
+        len: usize,
+    ) -> Result<HexDump, QtestError> {
+        let addr = self.resolve(addr)?;
+        let data = read_chunked(self, addr, len, |_, _| {}).await?;
+        Ok(HexDump::new(addr, data))
+    }
+
+    /// Writes the given bytes to the given address, encoded in hexadecimal.
+    ///
+    /// The bytes are written with a single `write` command, see [`write_chunked`] for large regions.
+    pub async fn write_bytes(
+        &mut self,
+        addr: impl Into<Address>,
+        data: &[u8],
+    ) -> Result<(), QtestError> {
+        match self
+            .write(addr, &encode_hex(data), Some(data.len()))
+            .await?
+        {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(QtestError::Fail(e)),
+            _ => Err(QtestError::Protocol("Invalid response".into())),
         }
     }
 
     /// Writes the given data to the given address, returns a Ok() if the write was successful
     pub async fn write(
         &mut self,
-        addr: usize,
+        addr: impl Into<Address>,
         data: &str,
         data_len: Option<usize>,
-    ) -> io::Result<Response> {
+    ) -> Result<Response, QtestError> {
+        let addr = self.resolve(addr)?;
         let len = match data_len {
             Some(len) => len,
             None => data.len(),
         };
-        let data = format!(
-            "write {:#x} {} 0x{}\n",
+        self.execute(Command::Write {
             addr,
             len,
-            data.trim_start_matches("0x")
-        );
-        self.socket.send(&data).await?;
-        self.response_queue
-            .recv()
-            .await
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not receive response"))
+            data: data.trim_start_matches("0x").to_string(),
+        })
+        .await
+    }
+
+    /// Fills the given number of bytes from the given address with the given byte, in a single round trip
+    pub async fn memset(
+        &mut self,
+        addr: impl Into<Address>,
+        size: usize,
+        pattern_byte: u8,
+    ) -> Result<(), QtestError> {
+        let addr = self.resolve(addr)?;
+        let response = self
+            .execute(Command::Memset {
+                addr,
+                len: size,
+                val: pattern_byte,
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(QtestError::Fail(e)),
+            _ => Err(QtestError::Protocol("Invalid response".into())),
+        }
+    }
+
+    /// Reads the given number of bytes from the given address with `b64read`, decoding the base64 data sent by QEMU.
+    ///
+    /// Base64 is more compact than the hexadecimal data of [`Parser::read_bytes`], so it suits large regions.
+    #[cfg(feature = "base64")]
+    pub async fn b64read(
+        &mut self,
+        addr: impl Into<Address>,
+        len: usize,
+    ) -> Result<Vec<u8>, QtestError> {
+        let addr = self.resolve(addr)?;
+        let data = match self.execute(Command::B64Read { addr, len }).await? {
+            Response::OkVal(val) => val,
+            Response::Err(e) => return Err(QtestError::Fail(e)),
+            _ => return Err(QtestError::Protocol("Invalid response".into())),
+        };
+        let bytes = ENGINE
+            .decode(data.trim())
+            .map_err(|e| QtestError::Parse(format!("Invalid base64 data: {e}")))?;
+        match bytes.len() == len {
+            true => Ok(bytes),
+            false => Err(QtestError::Protocol(format!(
+                "Expected {len} bytes, got {}",
+                bytes.len()
+            ))),
+        }
     }
 
     /// Writes the given base64 data to the given address, returns a Ok() if the write was successful
-    pub async fn b64write(&mut self, addr: usize, data: &str) -> io::Result<Response> {
-        let enc_data = ENGINE.encode(data);
-        let data = format!("b64write {:#x} {} {}\n", addr, data.len(), enc_data);
-        self.socket.send(&data).await?;
-        self.response_queue
-            .recv()
-            .await
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not receive response"))
+    #[cfg(feature = "base64")]
+    pub async fn b64write(
+        &mut self,
+        addr: impl Into<Address>,
+        data: &str,
+    ) -> Result<Response, QtestError> {
+        let addr = self.resolve(addr)?;
+        self.execute(Command::B64Write {
+            addr,
+            len: data.len(),
+            data: ENGINE.encode(data),
+        })
+        .await
     }
-}
 
-/// Used to read data from the qtest socket, should not be used by the user
-struct Reader {
-    /// Receiver for the socket data
-    rx_socket: mpsc::Receiver<String>,
-    /// Sender for IRQ data
-    tx_irq: mpsc::Sender<Irq>,
-    /// Sender for Response data
-    tx_response: mpsc::Sender<Response>,
+    /// Writes the given bytes to the given address with `b64write`, encoding them as base64.
+    ///
+    /// Base64 is more compact than the hexadecimal data of [`Parser::write_bytes`], so it suits large regions.
+    #[cfg(feature = "base64")]
+    pub async fn b64write_bytes(
+        &mut self,
+        addr: impl Into<Address>,
+        data: &[u8],
+    ) -> Result<(), QtestError> {
+        let addr = self.resolve(addr)?;
+        let response = self
+            .execute(Command::B64Write {
+                addr,
+                len: data.len(),
+                data: ENGINE.encode(data),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(QtestError::Fail(e)),
+            _ => Err(QtestError::Protocol("Invalid response".into())),
+        }
+    }
 }
 
-impl Reader {
-    /// Create a new reader instance with the given receivers and senders
-    fn new(
-        rx_socket: mpsc::Receiver<String>,
-        tx_irq: mpsc::Sender<Irq>,
-        tx_response: mpsc::Sender<Response>,
-    ) -> Self {
-        Self {
-            rx_socket,
-            tx_irq,
-            tx_response,
+/// *Wide value functions*
+impl<T: Socket> Parser<T> {
+    /// Reads an unsigned integer of the given number of bytes (1 to 16) with the given endianness,
+    /// the byte order of the guest.
+    ///
+    /// The value is composed from the widest naturally aligned accesses that fit, e.g. a 128-bit value at an
+    /// 8-byte aligned address is read with two `readq`, so registers that only accept aligned accesses work.
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the number of bytes is out of range.
+    pub async fn read_uint(
+        &mut self,
+        addr: impl Into<Address>,
+        bytes: usize,
+        endianness: Endianness,
+    ) -> Result<u128, QtestError> {
+        check_uint_bytes(bytes)?;
+        let addr = self.resolve(addr)?;
+        let mut image = [0; 16];
+        for (offset, width) in aligned_accesses(addr, bytes) {
+            let response = self
+                .execute(Command::ReadValue {
+                    width,
+                    addr: addr + offset,
+                })
+                .await?;
+            let val = match response {
+                Response::OkVal(val) => u64::from_str_radix(val.trim_start_matches("0x"), 16)
+                    .map_err(|e| {
+                        QtestError::Parse(format!("Could not parse value: {}\n error {}", val, e))
+                    })?,
+                Response::Err(e) => return Err(QtestError::Fail(e)),
+                _ => return Err(QtestError::Protocol("Invalid response".into())),
+            };
+            let len = width.bytes();
+            let chunk = match endianness {
+                Endianness::Little => val.to_le_bytes()[..len].to_vec(),
+                Endianness::Big => val.to_be_bytes()[8 - len..].to_vec(),
+            };
+            image[offset..offset + len].copy_from_slice(&chunk);
         }
+        let image = &image[..bytes];
+        let fold = |acc: u128, b: &u8| acc << 8 | u128::from(*b);
+        Ok(match endianness {
+            Endianness::Little => image.iter().rev().fold(0, fold),
+            Endianness::Big => image.iter().fold(0, fold),
+        })
     }
 
-    /// Reads data from the socket and sends it to the IRQ or Response channels
-    async fn read(&mut self) -> io::Result<()> {
-        while let Some(raw_data) = self.rx_socket.recv().await {
-            let string_data = raw_data.trim_matches(char::from(0)).to_string();
-
-            let lines = string_data.lines();
-
-            for line in lines {
-                if line.is_empty() {
-                    continue;
+    /// Writes an unsigned integer of the given number of bytes (1 to 16) with the given endianness,
+    /// composed from aligned accesses as [`Parser::read_uint`].
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the number of bytes is out of range
+    /// or the value does not fit in them.
+    pub async fn write_uint(
+        &mut self,
+        addr: impl Into<Address>,
+        bytes: usize,
+        val: u128,
+        endianness: Endianness,
+    ) -> Result<(), QtestError> {
+        check_uint_bytes(bytes)?;
+        if bytes < 16 && val >> (bytes * 8) != 0 {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{val:#x} does not fit in {bytes} bytes"),
+            )));
+        }
+        let addr = self.resolve(addr)?;
+        let image = match endianness {
+            Endianness::Little => val.to_le_bytes()[..bytes].to_vec(),
+            Endianness::Big => val.to_be_bytes()[16 - bytes..].to_vec(),
+        };
+        for (offset, width) in aligned_accesses(addr, bytes) {
+            let mut chunk = [0; 8];
+            let len = width.bytes();
+            let val = match endianness {
+                Endianness::Little => {
+                    chunk[..len].copy_from_slice(&image[offset..offset + len]);
+                    u64::from_le_bytes(chunk)
+                }
+                Endianness::Big => {
+                    chunk[8 - len..].copy_from_slice(&image[offset..offset + len]);
+                    u64::from_be_bytes(chunk)
+                }
+            };
+            let response = self
+                .execute(Command::WriteValue {
+                    width,
+                    addr: addr + offset,
+                    val,
+                })
+                .await?;
+            match response {
+                Response::Ok => {}
+                Response::Err(e) => return Err(QtestError::Fail(e)),
+                response => {
+                    return Err(QtestError::Protocol(format!(
+                        "Invalid response: {response}"
+                    )))
                 }
-
-                match Irq::try_from(line) {
-                    Ok(irq) => self.tx_irq.send(irq).await.map_err(|e| {
-                        io::Error::new(io::ErrorKind::Other, format!("Could not send IRQ: {e}"))
-                    }),
-                    Err(_) => self
-                        .tx_response
-                        .send(Response::from(string_data.as_str()))
-                        .await
-                        .map_err(|e| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("Could not send response: {e}"),
-                            )
-                        }),
-                }?;
             }
         }
         Ok(())
     }
+
+    /// Reads a 128-bit value with the given endianness, see [`Parser::read_uint`]
+    pub async fn read_u128(
+        &mut self,
+        addr: impl Into<Address>,
+        endianness: Endianness,
+    ) -> Result<u128, QtestError> {
+        self.read_uint(addr, 16, endianness).await
+    }
+
+    /// Writes a 128-bit value with the given endianness, see [`Parser::write_uint`]
+    pub async fn write_u128(
+        &mut self,
+        addr: impl Into<Address>,
+        val: u128,
+        endianness: Endianness,
+    ) -> Result<(), QtestError> {
+        self.write_uint(addr, 16, val, endianness).await
+    }
+}
+
+/// Checks the number of bytes of the integers of [`Parser::read_uint`] and [`Parser::write_uint`]
+fn check_uint_bytes(bytes: usize) -> Result<(), QtestError> {
+    match bytes {
+        1..=16 => Ok(()),
+        _ => Err(QtestError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Integers must be 1 to 16 bytes long, not {bytes}"),
+        ))),
+    }
+}
+
+/// Splits the given memory range into the widest naturally aligned accesses, as offsets from its start
+fn aligned_accesses(addr: usize, len: usize) -> Vec<(usize, Width)> {
+    let mut accesses = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let width = [Width::Quad, Width::Long, Width::Word, Width::Byte]
+            .into_iter()
+            .find(|w| (addr + offset).is_multiple_of(w.bytes()) && len - offset >= w.bytes())
+            .unwrap_or(Width::Byte);
+        accesses.push((offset, width));
+        offset += width.bytes();
+    }
+    accesses
 }
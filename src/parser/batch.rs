@@ -0,0 +1,156 @@
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::protocol::{Command, Width};
+use crate::socket::Socket;
+use crate::Response;
+
+use super::Parser;
+
+/// Commands sent to QEMU in a single socket write, created with [`Parser::batch`].
+///
+/// QEMU processes the commands in order, so a batch costs a single round trip instead of one per command,
+/// e.g. for long register initialization sequences. The responses are returned in the order of the commands.
+/// Addresses are resolved as the commands are added; if any fails, the batch is not sent.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp, Response};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let responses = parser
+///     .batch()
+///     .writel(0x4000_0000, 1)
+///     .readl(0x4000_0004)
+///     .clock_step(Some(100))
+///     .submit()
+///     .await
+///     .unwrap();
+/// assert_eq!(responses[0], Response::Ok);
+/// # }
+/// ```
+#[derive(Debug)]
+#[must_use = "the commands are not sent until the batch is submitted"]
+pub struct Batch<'a, T: Socket> {
+    parser: &'a mut Parser<T>,
+    commands: Vec<Command>,
+    error: Option<QtestError>,
+}
+
+impl<'a, T: Socket> Batch<'a, T> {
+    pub(super) fn new(parser: &'a mut Parser<T>) -> Self {
+        Self {
+            parser,
+            commands: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Adds the given command
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Adds a `clock_step` command
+    pub fn clock_step(self, ns: Option<usize>) -> Self {
+        self.command(Command::ClockStep(ns))
+    }
+
+    /// Adds a `clock_set` command
+    pub fn clock_set(self, ns: usize) -> Self {
+        self.command(Command::ClockSet(ns))
+    }
+
+    /// Returns the number of commands in the batch
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns true if the batch has no commands
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Sends the commands and returns their responses, in order.
+    ///
+    /// Failed commands are reported as [`Response::Err`] without stopping the batch.
+    /// Returns Err if an address could not be resolved or a response could not be received.
+    pub async fn submit(self) -> Result<Vec<Response>, QtestError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.parser.execute_batch(self.commands).await
+    }
+
+    /// Adds the command built with the resolved address, or records the resolution error
+    fn access(mut self, addr: impl Into<Address>, command: impl FnOnce(usize) -> Command) -> Self {
+        match self.parser.resolve(addr) {
+            Ok(addr) => self.commands.push(command(addr)),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+}
+
+/// Generates the methods adding memory accesses of the given width
+macro_rules! impl_batch_write_read {
+    ($write:ident, $read:ident, $ty:ty, $width:expr) => {
+        impl<T: Socket> Batch<'_, T> {
+            #[doc = concat!("Adds a `", stringify!($write), "` command")]
+            pub fn $write(self, addr: impl Into<Address>, val: $ty) -> Self {
+                self.access(addr, |addr| Command::WriteValue {
+                    width: $width,
+                    addr,
+                    val: val.into(),
+                })
+            }
+
+            #[doc = concat!("Adds a `", stringify!($read), "` command")]
+            pub fn $read(self, addr: impl Into<Address>) -> Self {
+                self.access(addr, |addr| Command::ReadValue {
+                    width: $width,
+                    addr,
+                })
+            }
+        }
+    };
+}
+
+impl_batch_write_read!(writeb, readb, u8, Width::Byte);
+impl_batch_write_read!(writew, readw, u16, Width::Word);
+impl_batch_write_read!(writel, readl, u32, Width::Long);
+impl_batch_write_read!(writeq, readq, u64, Width::Quad);
+
+/// Generates the methods adding port accesses of the given width
+macro_rules! impl_batch_in_out {
+    ($in:ident, $out:ident, $ty:ty, $width:expr) => {
+        impl<T: Socket> Batch<'_, T> {
+            #[doc = concat!("Adds an `", stringify!($in), "` command")]
+            pub fn $in(self, addr: impl Into<Address>) -> Self {
+                self.access(addr, |addr| Command::In {
+                    width: $width,
+                    addr,
+                })
+            }
+
+            #[doc = concat!("Adds an `", stringify!($out), "` command")]
+            pub fn $out(self, addr: impl Into<Address>, val: $ty) -> Self {
+                self.access(addr, |addr| Command::Out {
+                    width: $width,
+                    addr,
+                    val: val.into(),
+                })
+            }
+        }
+    };
+}
+
+impl_batch_in_out!(inb, outb, u8, Width::Byte);
+impl_batch_in_out!(inw, outw, u16, Width::Word);
+impl_batch_in_out!(inl, outl, u32, Width::Long);
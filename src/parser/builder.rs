@@ -0,0 +1,333 @@
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+use super::heartbeat::{Heartbeat, Liveness};
+use super::history::{CommandHistory, DEFAULT_COMMAND_HISTORY_CAPACITY};
+use super::intercept::InterceptRegistry;
+use super::irq::{IrqDispatch, IrqLevels, StormGuard, IRQ_EVENT_CHANNEL_CAPACITY};
+use super::rate::RateLimiter;
+use super::reader::{LineHook, Reader, ReaderConfig, UnknownLines};
+use super::Parser;
+use crate::error::QtestError;
+use crate::event::{EventBus, Notification, DEFAULT_HISTORY_CAPACITY};
+use crate::icount::Icount;
+use crate::socket::{Mode, Socket};
+use crate::{Endianness, Irq};
+
+/// Default capacity of the internal channels of the parser
+const DEFAULT_CHANNEL_CAPACITY: usize = 32;
+
+/// Builder for [`Parser`] instances with non-default settings.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::ParserBuilder, socket::tcp::SocketTcp};
+/// # use std::time::Duration;
+/// let (mut parser, irq_rx) = ParserBuilder::<SocketTcp>::new("localhost:3000")
+///     .response_timeout(Duration::from_secs(5))
+///     .intercept_in("/machine/soc")
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// parser.attach_connection().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParserBuilder<T: Socket> {
+    url: String,
+    mode: Mode,
+    channel_capacity: usize,
+    response_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    intercepts_in: Vec<String>,
+    intercepts_out: Vec<String>,
+    reader: ReaderConfig,
+    line_hooks: Vec<LineHook>,
+    commands_per_sec: Option<u32>,
+    bytes_per_sec: Option<u32>,
+    heartbeat: Option<Heartbeat>,
+    history_capacity: usize,
+    command_history_capacity: usize,
+    icount: Option<Icount>,
+    guest_endianness: Option<Endianness>,
+    strict_irqs: bool,
+    irq_storm_threshold: Option<usize>,
+    _socket: std::marker::PhantomData<T>,
+}
+
+impl<T: Socket> ParserBuilder<T> {
+    /// Creates a new builder for a parser at the given URL, with the default settings
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            mode: Mode::default(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            response_timeout: None,
+            connect_timeout: None,
+            intercepts_in: Vec::new(),
+            intercepts_out: Vec::new(),
+            reader: ReaderConfig::default(),
+            line_hooks: Vec::new(),
+            commands_per_sec: None,
+            bytes_per_sec: None,
+            heartbeat: None,
+            history_capacity: DEFAULT_HISTORY_CAPACITY,
+            command_history_capacity: DEFAULT_COMMAND_HISTORY_CAPACITY,
+            icount: None,
+            guest_endianness: None,
+            strict_irqs: false,
+            irq_storm_threshold: None,
+            _socket: std::marker::PhantomData,
+        }
+    }
+
+    /// Creates a new builder with the settings of the given configuration file
+    #[cfg(feature = "config")]
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let mut builder =
+            Self::new(&config.endpoint.url).channel_capacity(config.channels.capacity);
+        builder.mode = config.endpoint.mode;
+        builder.response_timeout = config.timeouts.response();
+        builder.connect_timeout = config.timeouts.connect();
+        builder.intercepts_in = config.intercepts.input.clone();
+        builder.intercepts_out = config.intercepts.output.clone();
+        builder.commands_per_sec = config.rate_limit.commands_per_sec;
+        builder.bytes_per_sec = config.rate_limit.bytes_per_sec;
+        builder.icount = config.qemu.icount;
+        builder
+    }
+
+    /// Adds the QOM paths intercepted by the peripherals of the given board description
+    #[cfg(feature = "config")]
+    pub fn board(mut self, board: &crate::board::Board) -> Self {
+        self.intercepts_in
+            .extend(board.intercepts_in().map(str::to_string));
+        self.intercepts_out
+            .extend(board.intercepts_out().map(str::to_string));
+        self
+    }
+
+    /// Sets whether the parser listens at the URL for QEMU to connect (the default) or connects to QEMU
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the capacity of the internal channels (raw socket data, responses and IRQs)
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum time to wait for the response to a command.
+    /// If the timeout expires, the command fails with [`QtestError::Timeout`], of kind [`io::ErrorKind::TimedOut`].
+    /// It can be changed later with [`Parser::set_response_timeout`].
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time [`Parser::attach_connection`] waits for the connection with QEMU
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a QOM path whose input IRQs are intercepted as soon as QEMU connects
+    pub fn intercept_in(mut self, qom_path: &str) -> Self {
+        self.intercepts_in.push(qom_path.to_string());
+        self
+    }
+
+    /// Adds a QOM path whose output IRQs are intercepted as soon as QEMU connects
+    pub fn intercept_out(mut self, qom_path: &str) -> Self {
+        self.intercepts_out.push(qom_path.to_string());
+        self
+    }
+
+    /// Limits the number of commands sent to QEMU per second.
+    ///
+    /// Slow QEMU builds (TCI, sanitizers) stall when flooded with commands, causing spurious timeouts.
+    /// Bursts of up to one second worth of commands are sent without delay.
+    pub fn max_commands_per_sec(mut self, commands: u32) -> Self {
+        self.commands_per_sec = Some(commands);
+        self
+    }
+
+    /// Limits the number of bytes sent to QEMU per second, with the same burst behavior as
+    /// [`ParserBuilder::max_commands_per_sec`]
+    pub fn max_bytes_per_sec(mut self, bytes: u32) -> Self {
+        self.bytes_per_sec = Some(bytes);
+        self
+    }
+
+    /// Enables the idle-connection heartbeat and the staleness detection of [`Parser::connection_state`]
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Sets the maximum number of events kept in the [`crate::event::EventHistory`] of the session.
+    /// A capacity of 0 disables the history.
+    pub fn history_capacity(mut self, capacity: usize) -> Self {
+        self.history_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum number of commands kept with their outcomes for [`Parser::command_history`].
+    /// A capacity of 0 disables the command history.
+    pub fn command_history_capacity(mut self, capacity: usize) -> Self {
+        self.command_history_capacity = capacity;
+        self
+    }
+
+    /// Sets the instruction counting settings QEMU runs with, reported by [`crate::clock::ClockController::icount`]
+    pub fn icount(mut self, icount: Icount) -> Self {
+        self.icount = Some(icount);
+        self
+    }
+
+    /// Sets the byte order of the guest, used by the explicit endianness accesses such as [`Parser::readl_be`].
+    ///
+    /// If not set, it is queried to QEMU with the `endianness` command on the first of those accesses.
+    pub fn guest_endianness(mut self, endianness: Endianness) -> Self {
+        self.guest_endianness = Some(endianness);
+        self
+    }
+
+    /// Enables the strict mode for IRQs: the IRQ events delivered while nothing observes them are logged
+    /// as warnings and recorded, see [`Parser::unobserved_irqs`].
+    ///
+    /// An IRQ event is observed by the IRQ receiver returned with the parser, while it is not dropped,
+    /// by the subscribers of [`Parser::irq_events`] and by the watchers of the level of its line.
+    /// In strict mode, dropping the IRQ receiver does not stop the parser.
+    pub fn strict_irqs(mut self, strict: bool) -> Self {
+        self.strict_irqs = strict;
+        self
+    }
+
+    /// Enables the detection of IRQ storms: once a line exceeds the given number of IRQ events per
+    /// virtual millisecond, its further events in that millisecond are coalesced. They still update the
+    /// level of the line, but are neither delivered nor published, and the storm is logged as a warning
+    /// and recorded, see [`Parser::irq_storms`].
+    ///
+    /// The virtual millisecond of an event is the one last reported by QEMU, so storms are only told apart
+    /// in virtual time if the clock is queried or stepped. Until QEMU reports the clock, the rates are
+    /// measured in host milliseconds instead.
+    pub fn irq_storm_threshold(mut self, events_per_virtual_ms: usize) -> Self {
+        self.irq_storm_threshold = Some(events_per_virtual_ms);
+        self
+    }
+
+    /// Sets how the lines sent by QEMU are classified
+    pub fn reader(mut self, config: ReaderConfig) -> Self {
+        self.reader = config;
+        self
+    }
+
+    /// Adds a hook that recognizes vendor-specific lines as [`Notification`]s, published in the event stream.
+    ///
+    /// Hooks are tried in the order they were added on the lines that are neither a response nor an IRQ event,
+    /// before they are handled according to [`ReaderConfig::unknown_lines`]. See [`LineHook`].
+    pub fn line_hook(
+        mut self,
+        hook: impl Fn(&str) -> Option<Notification> + Send + Sync + 'static,
+    ) -> Self {
+        self.line_hooks.push(LineHook::new(hook));
+        self
+    }
+
+    /// Creates the parser and its socket.
+    ///
+    /// Returns a result with the parser instance and a receiver for IRQs, as [`Parser::new`].
+    pub async fn build(self) -> Result<(Parser<T>, mpsc::Receiver<Irq>), QtestError> {
+        let (tx_raw_sock_out, rx_raw_sock_out) = mpsc::channel(self.channel_capacity);
+        let (tx_response, rx_response) = mpsc::channel(self.channel_capacity);
+        let (tx_irq, rx_irq) = mpsc::channel(self.channel_capacity);
+        let (tx_raw, rx_raw) = match self.reader.unknown_lines {
+            UnknownLines::Raw => {
+                let (tx_raw, rx_raw) = mpsc::channel(self.channel_capacity);
+                (Some(tx_raw), Some(rx_raw))
+            }
+            _ => (None, None),
+        };
+
+        let events = EventBus::new(self.history_capacity);
+
+        let qtest_socket = T::with_mode(&self.url, tx_raw_sock_out, self.mode)
+            .await
+            .map_err(QtestError::Socket)?;
+
+        let irq_levels = IrqLevels::default();
+        let intercepts = InterceptRegistry::default();
+        let (tx_irq_events, _) = broadcast::channel(IRQ_EVENT_CHANNEL_CAPACITY);
+        let irqs = IrqDispatch {
+            tx_irq,
+            levels: irq_levels.clone(),
+            intercepts: intercepts.clone(),
+            tx_events: tx_irq_events.clone(),
+            unobserved: self.strict_irqs.then(Default::default),
+            storms: self.irq_storm_threshold.map(StormGuard::new),
+        };
+        let unobserved_irqs = irqs.unobserved.clone();
+        let irq_storms = irqs.storms.as_ref().map(|guard| guard.storms.clone());
+
+        let liveness = Liveness::default();
+        if let Some(heartbeat) = self.heartbeat {
+            liveness.spawn_monitor(heartbeat);
+        }
+        if let Some(link) = qtest_socket.link_state() {
+            liveness.spawn_link_monitor(link);
+        }
+
+        let reader_events = events.clone();
+        tokio::spawn(async move {
+            let mut reader = Reader::new(
+                rx_raw_sock_out,
+                tx_response,
+                tx_raw,
+                reader_events,
+                irqs,
+                self.reader,
+                self.line_hooks,
+            );
+            reader.read().await.unwrap();
+        });
+
+        Ok((
+            Parser {
+                socket: qtest_socket,
+                response_queue: rx_response,
+                raw_lines: rx_raw,
+                events,
+                irq_levels,
+                irq_events: tx_irq_events,
+                response_timeout: self.response_timeout,
+                connect_timeout: self.connect_timeout,
+                intercepts_in: self.intercepts_in,
+                intercepts_out: self.intercepts_out,
+                intercepts,
+                clock_ns: None,
+                rate_limiter: RateLimiter::new(self.commands_per_sec, self.bytes_per_sec),
+                session: Default::default(),
+                pending: Default::default(),
+                awaited: 0,
+                commands: CommandHistory::new(self.command_history_capacity),
+                extensions: Default::default(),
+                unobserved_irqs,
+                irq_storms,
+                heartbeat: self.heartbeat,
+                liveness,
+                symbols: Default::default(),
+                debug_info: None,
+                icount: self.icount,
+                guest_endianness: self.guest_endianness,
+            },
+            rx_irq,
+        ))
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt, io,
+    sync::Arc,
+};
+
+use super::Parser;
+use crate::error::QtestError;
+use crate::logging::in_span;
+use crate::socket::Socket;
+use crate::Response;
+
+/// Set of vendor-specific commands understood by a QEMU fork, registered with
+/// [`Parser::register_extension`] and used through [`Parser::ext`].
+///
+/// The commands of the extension implement [`ExtensionCommand`], which ties them to the extension,
+/// so they can only be sent through a parser the extension is registered with.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::{Extension, ExtensionCommand, Parser}, socket::tcp::SocketTcp, Response};
+/// # use std::io;
+/// struct Acme;
+///
+/// impl Extension for Acme {
+///     fn name(&self) -> &str {
+///         "acme"
+///     }
+/// }
+///
+/// /// `acme_dma_status <channel>`, answered with `OK <pending bytes>`
+/// struct DmaStatus(u8);
+///
+/// impl ExtensionCommand<Acme> for DmaStatus {
+///     type Output = usize;
+///
+///     fn line(&self, _ext: &Acme) -> String {
+///         format!("acme_dma_status {}", self.0)
+///     }
+///
+///     fn parse(&self, _ext: &Acme, response: Response) -> io::Result<usize> {
+///         match response {
+///             Response::OkVal(val) => val.parse().map_err(io::Error::other),
+///             response => Err(io::Error::other(format!("invalid response: {response}"))),
+///         }
+///     }
+/// }
+///
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.register_extension(Acme);
+/// parser.attach_connection().await.unwrap();
+///
+/// let pending = parser.ext::<Acme>().unwrap().call(DmaStatus(2)).await.unwrap();
+/// # }
+/// ```
+pub trait Extension: Send + Sync + 'static {
+    /// Returns the name of the extension, used in diagnostics
+    fn name(&self) -> &str;
+}
+
+/// Vendor-specific command of the extension `E`: its wire syntax and the parsing of its response
+pub trait ExtensionCommand<E: Extension> {
+    /// Value the response is parsed into
+    type Output;
+
+    /// Returns the line sent to QEMU, without the trailing newline
+    fn line(&self, ext: &E) -> String;
+
+    /// Parses the response of QEMU, which may be a `FAIL`
+    fn parse(&self, ext: &E, response: Response) -> io::Result<Self::Output>;
+}
+
+/// Extensions registered with a parser, by type
+#[derive(Default)]
+pub(super) struct ExtensionRegistry {
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    names: Vec<String>,
+}
+
+impl fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.names).finish()
+    }
+}
+
+impl ExtensionRegistry {
+    /// Registers the given extension, replacing any previous extension of the same type
+    pub(super) fn register<E: Extension>(&mut self, ext: E) {
+        let name = ext.name().to_string();
+        if self
+            .extensions
+            .insert(TypeId::of::<E>(), Arc::new(ext))
+            .is_none()
+        {
+            self.names.push(name);
+        }
+    }
+
+    /// Returns the extension of the given type.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if it is not registered.
+    pub(super) fn get<E: Extension>(&self) -> io::Result<Arc<E>> {
+        self.extensions
+            .get(&TypeId::of::<E>())
+            .cloned()
+            .and_then(|ext| ext.downcast().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Extension {} is not registered", std::any::type_name::<E>()),
+                )
+            })
+    }
+}
+
+/// Access to the commands of the extension `E` through a parser, returned by [`Parser::ext`]
+pub struct Ext<'a, T: Socket, E: Extension> {
+    parser: &'a mut Parser<T>,
+    ext: Arc<E>,
+}
+
+impl<T: Socket, E: Extension> fmt::Debug for Ext<'_, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ext")
+            .field("extension", &self.ext.name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, T: Socket, E: Extension> Ext<'a, T, E> {
+    pub(super) fn new(parser: &'a mut Parser<T>, ext: Arc<E>) -> Self {
+        Self { parser, ext }
+    }
+
+    /// Returns the extension
+    pub fn extension(&self) -> &E {
+        &self.ext
+    }
+
+    /// Sends the given command and parses its response.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the line of the command spans several lines.
+    /// Extension commands are published in the event stream, but not kept in [`Parser::command_history`].
+    pub async fn call<C: ExtensionCommand<E>>(
+        &mut self,
+        command: C,
+    ) -> Result<C::Output, QtestError> {
+        let line = command.line(&self.ext);
+        if line.contains('\n') {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Extension command {line:?} spans several lines"),
+            )));
+        }
+        let parser = &mut *self.parser;
+        let response = in_span!(
+            target: "qtest::parser",
+            "qtest.command",
+            verb = line.split_whitespace().next().unwrap_or_default(),
+            extension = self.ext.name();
+            async {
+                let _in_flight = parser.send_line(line.clone()).await?;
+                parser.recv_response().await
+            }
+        )
+        .await?;
+        Ok(command.parse(&self.ext, response)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Acme;
+
+    impl Extension for Acme {
+        fn name(&self) -> &str {
+            "acme"
+        }
+    }
+
+    #[test]
+    fn test_extension_registry() {
+        let mut registry = ExtensionRegistry::default();
+        let err = registry.get::<Acme>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        registry.register(Acme);
+        registry.register(Acme);
+        assert_eq!(registry.get::<Acme>().unwrap().name(), "acme");
+        assert_eq!(format!("{registry:?}"), r#"["acme"]"#);
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use super::heartbeat::{ConnectionState, Liveness};
+use super::session::{PausePolicy, SessionGate};
+use super::{parse_clock, Parser};
+use crate::error::QtestError;
+use crate::event::EventHistory;
+use crate::logging::in_span;
+use crate::protocol::{Command, Width};
+use crate::socket::Socket;
+use crate::transfer::{chunks, decode_chunk, encode_hex, CHUNK_SIZE};
+use crate::Response;
+
+/// Capacity of the request channel of the task owning the parser
+const REQUEST_CAPACITY: usize = 32;
+
+/// Priority lane of a command sent through a [`ParserHandle`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Latency-sensitive commands (IRQ acknowledgements, small reads).
+    /// They are sent before any queued bulk command.
+    #[default]
+    Interactive,
+    /// Bulk-transfer chunks, sent only when no interactive command is queued
+    Bulk,
+}
+
+/// Command sent through a handle, with the channel to send its response back
+#[derive(Debug)]
+struct Request {
+    client: usize,
+    priority: Priority,
+    command: Command,
+    reply: oneshot::Sender<Result<Response, QtestError>>,
+}
+
+/// Cloneable handle to a parser owned by a background task, created with [`Parser::into_handle`].
+///
+/// Commands are queued in two priority lanes. Every command is sent and answered as a whole,
+/// so interactive commands can jump ahead of queued bulk commands at command boundaries,
+/// e.g. between the chunks of a firmware load.
+///
+/// Every clone of a handle is an independent client of the parser: within a lane, the queued commands
+/// of the clients are served in round-robin, and every response is delivered only to its requester.
+/// Independent components (e.g. a UART helper and a DMA helper) can thus share one connection fairly.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::{Parser, Priority}, protocol::{Command, Width}, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+/// let handle = parser.into_handle();
+///
+/// let loader = handle.clone();
+/// tokio::spawn(async move { loader.write_chunked(0x2000_0000, &[0; 1 << 20]).await });
+///
+/// // Not delayed by the firmware load
+/// let command = Command::ReadValue { width: Width::Long, addr: 0x4000_0000 };
+/// let response = handle.send(command, Priority::Interactive).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ParserHandle {
+    requests: mpsc::Sender<Request>,
+    client: usize,
+    next_client: Arc<AtomicUsize>,
+    session: SessionGate,
+    liveness: Liveness,
+    history: EventHistory,
+}
+
+impl Clone for ParserHandle {
+    /// Returns a handle for a new client of the parser
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+            client: self.next_client.fetch_add(1, Ordering::Relaxed),
+            next_client: self.next_client.clone(),
+            session: self.session.clone(),
+            liveness: self.liveness.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl ParserHandle {
+    /// Spawns the task that owns the parser and serves the priority lanes
+    pub(super) fn spawn<T: Socket + Send + 'static>(parser: Parser<T>) -> Self {
+        let (requests, requests_rx) = mpsc::channel(REQUEST_CAPACITY);
+        let session = parser.session.clone();
+        let liveness = parser.liveness.clone();
+        let history = parser.event_history();
+        tokio::spawn(serve(parser, requests_rx));
+        Self {
+            requests,
+            client: 0,
+            next_client: Arc::new(AtomicUsize::new(1)),
+            session,
+            liveness,
+            history,
+        }
+    }
+
+    /// Returns the history of the most recent events of the session, as [`Parser::event_history`]
+    pub fn event_history(&self) -> EventHistory {
+        self.history.clone()
+    }
+
+    /// Returns a receiver of the health of the connection with QEMU, as [`Parser::connection_state`]
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.liveness.watch()
+    }
+
+    /// Pauses the session, as [`Parser::pause`].
+    ///
+    /// With [`PausePolicy::Buffer`], the commands of every client are queued until the session is resumed.
+    pub async fn pause(&self, policy: PausePolicy) {
+        self.session.pause(policy).await
+    }
+
+    /// Resumes a paused session, sending the buffered commands
+    pub fn resume(&self) {
+        self.session.resume()
+    }
+
+    /// Returns true if the session is paused
+    pub fn is_paused(&self) -> bool {
+        self.session.is_paused()
+    }
+
+    /// Sends the given command in the given priority lane and waits for its response
+    pub async fn send(&self, command: Command, priority: Priority) -> Result<Response, QtestError> {
+        let (reply, response) = oneshot::channel();
+        let request = Request {
+            client: self.client,
+            priority,
+            command,
+            reply,
+        };
+        self.requests.send(request).await.map_err(|_| closed())?;
+        response.await.map_err(|_| closed())?
+    }
+
+    /// Writes the given bytes to guest memory in chunks of [`CHUNK_SIZE`] bytes, sent in the bulk lane
+    pub async fn write_chunked(&self, addr: usize, data: &[u8]) -> Result<(), QtestError> {
+        for (chunk, (chunk_addr, chunk_len)) in
+            data.chunks(CHUNK_SIZE).zip(chunks(addr, data.len()))
+        {
+            let command = Command::Write {
+                addr: chunk_addr,
+                len: chunk_len,
+                data: encode_hex(chunk),
+            };
+            let response = in_span!(
+                target: "qtest::transfer",
+                "qtest.chunk",
+                verb = "write",
+                addr = chunk_addr,
+                len = chunk_len;
+                self.send(command, Priority::Bulk)
+            )
+            .await?;
+            if let Response::Err(e) = response {
+                return Err(QtestError::Fail(format!(
+                    "Could not write to {chunk_addr:#x}: {e}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the given number of bytes from guest memory in chunks of [`CHUNK_SIZE`] bytes, sent in the bulk lane
+    ///
+    /// Returns an error of kind [`std::io::ErrorKind::InvalidData`] if QEMU returns another number of bytes.
+    pub async fn read_chunked(&self, addr: usize, len: usize) -> Result<Vec<u8>, QtestError> {
+        let mut data = Vec::with_capacity(len);
+        for (chunk_addr, chunk_len) in chunks(addr, len) {
+            let command = Command::Read {
+                addr: chunk_addr,
+                len: chunk_len,
+            };
+            let response = in_span!(
+                target: "qtest::transfer",
+                "qtest.chunk",
+                verb = "read",
+                addr = chunk_addr,
+                len = chunk_len;
+                self.send(command, Priority::Bulk)
+            )
+            .await?;
+            match response {
+                Response::OkVal(val) => data.extend_from_slice(&decode_chunk(&val, chunk_len)?),
+                Response::Err(e) => return Err(QtestError::Fail(e)),
+                _ => return Err(QtestError::Protocol("Invalid response".into())),
+            }
+        }
+        Ok(data)
+    }
+
+    /// Returns a future that polls the 32-bit register at the given address until its value, masked with `mask`,
+    /// equals `expected`, and resolves with the virtual time in nanoseconds at which it matched.
+    ///
+    /// The register is read every `poll_interval` of host time, in the interactive lane. If `clock_step_per_poll`
+    /// is given, the virtual clock is stepped by that number of nanoseconds before each poll after the first,
+    /// so the guest makes progress even when the clock is stopped.
+    ///
+    /// The future is a new client of the parser and does not borrow the handle, so it can be spawned and run
+    /// concurrently with other test activity. It polls until the value matches; wrap it in
+    /// [`tokio::time::timeout`] to bound the wait.
+    pub fn wait_for_value(
+        &self,
+        addr: usize,
+        mask: u32,
+        expected: u32,
+        poll_interval: Duration,
+        clock_step_per_poll: Option<usize>,
+    ) -> impl Future<Output = Result<usize, QtestError>> + Send + 'static {
+        let handle = self.clone();
+        async move {
+            let mut first = true;
+            loop {
+                if !first {
+                    tokio::time::sleep(poll_interval).await;
+                    if let Some(ns) = clock_step_per_poll {
+                        handle.clock_step(Some(ns)).await?;
+                    }
+                }
+                first = false;
+                if handle.readl(addr).await? & mask == expected & mask {
+                    // Stepping by 0 reports the virtual time without advancing it
+                    return handle.clock_step(Some(0)).await;
+                }
+            }
+        }
+    }
+}
+
+/// *Typed commands, sent in the interactive lane*
+impl ParserHandle {
+    /// Advances the virtual clock by the given number of nanoseconds, or to the next timer deadline with `None`,
+    /// and returns the new virtual time
+    pub async fn clock_step(&self, ns: Option<usize>) -> Result<usize, QtestError> {
+        parse_clock(
+            self.send(Command::ClockStep(ns), Priority::Interactive)
+                .await?,
+        )
+    }
+}
+
+macro_rules! impl_write_read {
+    ($write:ident, $read:ident, $ty:ty, $width:expr) => {
+        impl ParserHandle {
+            #[doc = concat!("Writes a value to the given address, as [`Parser::", stringify!($write), "`]")]
+            pub async fn $write(&self, addr: usize, val: $ty) -> Result<(), QtestError> {
+                let command = Command::WriteValue {
+                    width: $width,
+                    addr,
+                    val: val.into(),
+                };
+                match self.send(command, Priority::Interactive).await? {
+                    Response::Ok => Ok(()),
+                    Response::Err(e) => Err(QtestError::Fail(e)),
+                    response => Err(QtestError::Protocol(format!(
+                        "Invalid response: {response}"
+                    ))),
+                }
+            }
+
+            #[doc = concat!("Reads a value from the given address, as [`Parser::", stringify!($read), "`]")]
+            pub async fn $read(&self, addr: usize) -> Result<$ty, QtestError> {
+                let command = Command::ReadValue {
+                    width: $width,
+                    addr,
+                };
+                match self.send(command, Priority::Interactive).await? {
+                    Response::OkVal(val) => <$ty>::from_str_radix(val.trim_start_matches("0x"), 16)
+                        .map_err(|e| {
+                            QtestError::Parse(format!("Could not parse value: {val}\n error {e}"))
+                        }),
+                    Response::Err(e) => Err(QtestError::Fail(e)),
+                    response => Err(QtestError::Protocol(format!(
+                        "Invalid response: {response}"
+                    ))),
+                }
+            }
+        }
+    };
+}
+
+impl_write_read!(writeb, readb, u8, Width::Byte);
+impl_write_read!(writew, readw, u16, Width::Word);
+impl_write_read!(writel, readl, u32, Width::Long);
+impl_write_read!(writeq, readq, u64, Width::Quad);
+
+/// Error returned when the task owning the parser is gone
+fn closed() -> QtestError {
+    QtestError::ChannelClosed("Parser task is closed".into())
+}
+
+/// Commands waiting to be sent, per client and priority lane
+#[derive(Debug, Default)]
+struct Queues {
+    /// Interactive and bulk lanes of every client with queued commands
+    clients: BTreeMap<usize, [VecDeque<Request>; 2]>,
+    /// Last client served
+    last: usize,
+}
+
+impl Queues {
+    fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    fn push(&mut self, request: Request) {
+        let lane = request.priority as usize;
+        self.clients.entry(request.client).or_default()[lane].push_back(request);
+    }
+
+    /// Pops the next request: interactive lanes first, and within a lane,
+    /// the first client with queued commands after the last client served
+    fn pop(&mut self) -> Option<Request> {
+        for lane in [Priority::Interactive as usize, Priority::Bulk as usize] {
+            let after = self.clients.range(self.last + 1..);
+            let client = after
+                .chain(self.clients.range(..=self.last))
+                .find(|(_, lanes)| !lanes[lane].is_empty())
+                .map(|(client, _)| *client);
+            if let Some(client) = client {
+                let lanes = self.clients.get_mut(&client)?;
+                let request = lanes[lane].pop_front();
+                if lanes.iter().all(VecDeque::is_empty) {
+                    self.clients.remove(&client);
+                }
+                self.last = client;
+                return request;
+            }
+        }
+        None
+    }
+}
+
+/// Waits for the next request, or returns `Ok(None)` if the heartbeat interval elapses first.
+/// Returns `Err(())` when every handle has been dropped.
+async fn next_request(
+    requests: &mut mpsc::Receiver<Request>,
+    interval: Option<Duration>,
+) -> Result<Option<Request>, ()> {
+    let request = match interval {
+        Some(interval) => match tokio::time::timeout(interval, requests.recv()).await {
+            Ok(request) => request,
+            Err(_) => return Ok(None),
+        },
+        None => requests.recv().await,
+    };
+    request.map(Some).ok_or(())
+}
+
+/// Body of the task owning the parser. Ends when every handle has been dropped.
+///
+/// When idle for the heartbeat interval, if any, QEMU is probed.
+async fn serve<T: Socket>(mut parser: Parser<T>, mut requests: mpsc::Receiver<Request>) {
+    let mut queues = Queues::default();
+    let interval = parser.heartbeat.map(|heartbeat| heartbeat.interval);
+    loop {
+        if queues.is_empty() {
+            match next_request(&mut requests, interval).await {
+                Ok(Some(request)) => queues.push(request),
+                Ok(None) => {
+                    // Staleness is tracked by the parser, so the result is irrelevant
+                    let _ = parser.probe().await;
+                    continue;
+                }
+                Err(()) => return,
+            }
+        }
+        while let Ok(request) = requests.try_recv() {
+            queues.push(request);
+        }
+        let Some(request) = queues.pop() else {
+            continue;
+        };
+        let response = parser.execute(request.command).await;
+        // The requester may have given up waiting
+        let _ = request.reply.send(response);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(client: usize, priority: Priority, ns: usize) -> Request {
+        Request {
+            client,
+            priority,
+            command: Command::ClockStep(Some(ns)),
+            reply: oneshot::channel().0,
+        }
+    }
+
+    #[test]
+    fn test_queues_round_robin() {
+        let mut queues = Queues::default();
+        queues.push(request(1, Priority::Bulk, 10));
+        queues.push(request(1, Priority::Bulk, 11));
+        queues.push(request(1, Priority::Bulk, 12));
+        queues.push(request(2, Priority::Bulk, 20));
+        queues.push(request(3, Priority::Bulk, 30));
+        queues.push(request(3, Priority::Interactive, 31));
+
+        let mut order = Vec::new();
+        while let Some(request) = queues.pop() {
+            if let Command::ClockStep(Some(ns)) = request.command {
+                order.push(ns);
+            }
+        }
+        assert_eq!(order, [31, 10, 20, 30, 11, 12]);
+        assert!(queues.is_empty());
+    }
+}
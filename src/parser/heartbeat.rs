@@ -0,0 +1,189 @@
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+use tokio::{sync::watch, time::Instant};
+
+use crate::logging::log_warn;
+use crate::socket::LinkState;
+
+/// Minimum period of the staleness checks
+const MIN_CHECK_PERIOD: Duration = Duration::from_millis(1);
+
+/// Health of the connection with QEMU, as seen from the responses to the commands sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// QEMU answered every command sent so far
+    #[default]
+    Alive,
+    /// A command has been waiting for its response longer than [`Heartbeat::suspect_after`]
+    Suspect,
+    /// A command has been waiting for its response longer than [`Heartbeat::dead_after`]
+    Dead,
+    /// QEMU closed the connection. Commands fail until a new connection is attached.
+    Disconnected,
+}
+
+/// Idle-connection heartbeat, set with [`super::ParserBuilder::heartbeat`].
+///
+/// When a [`super::ParserHandle`] has no command to send for `interval`, it probes QEMU with `clock_step 0`.
+/// The connection state flips to [`ConnectionState::Suspect`] and [`ConnectionState::Dead`] when any command,
+/// probe or not, waits for its response longer than the thresholds, and back to [`ConnectionState::Alive`]
+/// as soon as QEMU responds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Heartbeat {
+    /// Idle time before a probe is sent
+    pub interval: Duration,
+    /// Time waiting for a response before the connection is suspect
+    pub suspect_after: Duration,
+    /// Time waiting for a response before the connection is dead
+    pub dead_after: Duration,
+}
+
+impl Heartbeat {
+    /// Returns the state of a connection waiting for a response since the given instant, if any
+    fn evaluate(&self, pending_since: Option<Instant>, now: Instant) -> ConnectionState {
+        match pending_since.map(|since| now.saturating_duration_since(since)) {
+            Some(waiting) if waiting >= self.dead_after => ConnectionState::Dead,
+            Some(waiting) if waiting >= self.suspect_after => ConnectionState::Suspect,
+            _ => ConnectionState::Alive,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Instant the oldest unanswered command was sent
+    pending_since: Mutex<Option<Instant>>,
+    state: watch::Sender<ConnectionState>,
+}
+
+impl Inner {
+    fn set(&self, state: ConnectionState) {
+        self.state
+            .send_if_modified(|current| Self::transition(current, state));
+    }
+
+    /// Sets the state derived from the staleness checks, which do not override a closed connection
+    fn refresh(&self, state: ConnectionState) {
+        self.state.send_if_modified(|current| {
+            *current != ConnectionState::Disconnected && Self::transition(current, state)
+        });
+    }
+
+    fn transition(current: &mut ConnectionState, state: ConnectionState) -> bool {
+        if *current == state {
+            return false;
+        }
+        if state != ConnectionState::Alive {
+            log_warn!(target: "qtest::parser", "connection is {state:?}");
+        }
+        *current = state;
+        true
+    }
+}
+
+/// Tracks the commands waiting for a response to derive the [`ConnectionState`]
+#[derive(Debug, Clone)]
+pub(super) struct Liveness {
+    inner: Arc<Inner>,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                pending_since: Mutex::new(None),
+                state: watch::Sender::new(ConnectionState::Alive),
+            }),
+        }
+    }
+}
+
+impl Liveness {
+    /// Records that a command was sent
+    pub(super) fn command_sent(&self) {
+        self.inner
+            .pending_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    /// Records that QEMU responded
+    pub(super) fn responded(&self) {
+        self.inner.pending_since.lock().unwrap().take();
+        // The responses received before QEMU closed the connection may be read afterwards
+        self.inner.refresh(ConnectionState::Alive);
+    }
+
+    /// Records that a new connection was attached
+    pub(super) fn attached(&self) {
+        self.inner.pending_since.lock().unwrap().take();
+        self.inner.set(ConnectionState::Alive);
+    }
+
+    pub(super) fn watch(&self) -> watch::Receiver<ConnectionState> {
+        self.inner.state.subscribe()
+    }
+
+    /// Returns true if QEMU closed the connection
+    pub(super) fn is_disconnected(&self) -> bool {
+        *self.inner.state.borrow() == ConnectionState::Disconnected
+    }
+
+    /// Spawns the task reporting the connections closed by QEMU, which ends with the socket or the parser
+    pub(super) fn spawn_link_monitor(&self, link: watch::Receiver<LinkState>) {
+        tokio::spawn(link_monitor(Arc::downgrade(&self.inner), link));
+    }
+
+    /// Spawns the task checking the staleness of the connection, which ends with the parser
+    pub(super) fn spawn_monitor(&self, heartbeat: Heartbeat) {
+        let period = (heartbeat.suspect_after.min(heartbeat.dead_after) / 4).max(MIN_CHECK_PERIOD);
+        tokio::spawn(monitor(Arc::downgrade(&self.inner), heartbeat, period));
+    }
+}
+
+async fn monitor(inner: Weak<Inner>, heartbeat: Heartbeat, period: Duration) {
+    loop {
+        tokio::time::sleep(period).await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let pending_since = *inner.pending_since.lock().unwrap();
+        inner.refresh(heartbeat.evaluate(pending_since, Instant::now()));
+    }
+}
+
+async fn link_monitor(inner: Weak<Inner>, mut link: watch::Receiver<LinkState>) {
+    while link.changed().await.is_ok() {
+        let closed = *link.borrow_and_update() == LinkState::Closed;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        // A new connection is reported as alive when the parser attaches it
+        if closed {
+            inner.set(ConnectionState::Disconnected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(1),
+            suspect_after: Duration::from_secs(2),
+            dead_after: Duration::from_secs(5),
+        };
+        let now = Instant::now();
+        let since = |secs| Some(now - Duration::from_secs(secs));
+        assert_eq!(heartbeat.evaluate(None, now), ConnectionState::Alive);
+        assert_eq!(heartbeat.evaluate(since(1), now), ConnectionState::Alive);
+        assert_eq!(heartbeat.evaluate(since(2), now), ConnectionState::Suspect);
+        assert_eq!(heartbeat.evaluate(since(5), now), ConnectionState::Dead);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::VecDeque, io};
+
+use crate::error::QtestError;
+use crate::protocol::Command;
+use crate::Response;
+
+/// Default number of commands kept by the command history of the parser
+pub(super) const DEFAULT_COMMAND_HISTORY_CAPACITY: usize = 32;
+
+/// Outcome of a command sent by the parser
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CommandOutcome {
+    /// QEMU answered with the given response, which may be a `FAIL`
+    Response(Response),
+    /// The command failed without response, e.g. it timed out or the connection was lost
+    Error {
+        /// Kind of the error, see [`QtestError::kind`]
+        kind: io::ErrorKind,
+        /// Message of the error
+        message: String,
+    },
+}
+
+/// Command sent by the parser, with its outcome
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommandRecord {
+    /// Command sent to QEMU
+    pub command: Command,
+    /// Outcome of the command
+    pub outcome: CommandOutcome,
+}
+
+impl std::fmt::Display for CommandRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            CommandOutcome::Response(response) => write!(f, "{} -> {response}", self.command),
+            CommandOutcome::Error { message, .. } => {
+                write!(f, "{} -> error: {message}", self.command)
+            }
+        }
+    }
+}
+
+/// Bounded record of the most recent commands of the parser, oldest first
+#[derive(Debug, Clone)]
+pub(super) struct CommandHistory {
+    records: VecDeque<CommandRecord>,
+    capacity: usize,
+}
+
+impl CommandHistory {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records the outcome of a command, discarding the oldest record if full
+    pub(super) fn push(&mut self, command: Command, result: &Result<Response, QtestError>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        let outcome = match result {
+            Ok(response) => CommandOutcome::Response(response.clone()),
+            Err(e) => CommandOutcome::Error {
+                kind: e.kind(),
+                message: e.to_string(),
+            },
+        };
+        self.records.push_back(CommandRecord { command, outcome });
+    }
+
+    pub(super) fn last(&self) -> Option<&CommandRecord> {
+        self.records.back()
+    }
+
+    /// Returns the last `n` records, oldest first
+    pub(super) fn recent(&self, n: usize) -> Vec<CommandRecord> {
+        let skip = self.records.len().saturating_sub(n);
+        self.records.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_command_history() {
+        let mut history = CommandHistory::new(2);
+        history.push(
+            Command::ClockStep(None),
+            &Ok(Response::OkVal("10".to_string())),
+        );
+        history.push(
+            Command::ClockSet(20),
+            &Ok(Response::OkVal("20".to_string())),
+        );
+        let timeout = QtestError::Timeout("Timed out waiting for response".to_string());
+        history.push(Command::ClockStep(Some(5)), &Err(timeout));
+
+        let recent = history.recent(5);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].command, Command::ClockSet(20));
+        assert_eq!(history.recent(1), [history.last().unwrap().clone()]);
+        assert_eq!(
+            history.last().unwrap().to_string(),
+            "clock_step 5 -> error: Timed out waiting for response"
+        );
+
+        let mut disabled = CommandHistory::new(0);
+        disabled.push(Command::ClockStep(None), &Ok(Response::Ok));
+        assert!(disabled.last().is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use crate::{Irq, IrqState};
+
+/// Direction of the intercepted IRQs of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterceptDirection {
+    /// Input GPIOs, intercepted with `irq_intercept_in`
+    In,
+    /// Output GPIOs, intercepted with `irq_intercept_out`
+    Out,
+}
+
+/// Identifier of an IRQ interception, assigned in registration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InterceptId(pub usize);
+
+/// Range of IRQ lines that belong to a named GPIO list of an intercepted device
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IrqLines {
+    /// Name of the GPIO list
+    pub gpio: String,
+    /// Lines of the GPIO list, as numbered by QEMU in its IRQ events
+    pub lines: Range<usize>,
+}
+
+/// IRQ interception registered in a parser
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Intercept {
+    /// Identifier of the interception
+    pub id: InterceptId,
+    /// QOM path of the intercepted device
+    pub qom_path: String,
+    /// Direction of the intercepted IRQs
+    pub direction: InterceptDirection,
+    /// Lines mapped with [`super::Parser::map_irq_lines`], in registration order
+    pub lines: Vec<IrqLines>,
+}
+
+/// IRQ event attributed to the interception that produced it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IrqEvent {
+    /// Interception the event is attributed to, `None` if there are no interceptions
+    pub source: Option<InterceptId>,
+    /// Name of the GPIO list the line belongs to, if mapped
+    pub gpio: Option<String>,
+    /// The line of the IRQ event
+    pub line: usize,
+    /// The state of the IRQ event
+    pub state: IrqState,
+}
+
+/// Interceptions of a parser, shared with its reader task to attribute IRQ events
+#[derive(Debug, Clone, Default)]
+pub(super) struct InterceptRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// Interceptions, indexed by their identifier
+    intercepts: Vec<Intercept>,
+    /// Every line mapping, in registration order
+    mappings: Vec<(InterceptId, IrqLines)>,
+}
+
+impl InterceptRegistry {
+    /// Returns the interception of the given path and direction, if any
+    pub(super) fn find(
+        &self,
+        qom_path: &str,
+        direction: InterceptDirection,
+    ) -> Option<InterceptId> {
+        self.inner
+            .lock()
+            .unwrap()
+            .intercepts
+            .iter()
+            .find(|i| i.qom_path == qom_path && i.direction == direction)
+            .map(|i| i.id)
+    }
+
+    /// Registers a new interception and returns its identifier
+    pub(super) fn register(&self, qom_path: &str, direction: InterceptDirection) -> InterceptId {
+        let intercepts = &mut self.inner.lock().unwrap().intercepts;
+        let id = InterceptId(intercepts.len());
+        intercepts.push(Intercept {
+            id,
+            qom_path: qom_path.to_string(),
+            direction,
+            lines: Vec::new(),
+        });
+        id
+    }
+
+    /// Maps the given lines to a GPIO list of the given interception
+    pub(super) fn map_lines(&self, id: InterceptId, gpio: &str, lines: Range<usize>) {
+        let mut inner = self.inner.lock().unwrap();
+        let mapping = IrqLines {
+            gpio: gpio.to_string(),
+            lines,
+        };
+        inner.intercepts[id.0].lines.push(mapping.clone());
+        inner.mappings.push((id, mapping));
+    }
+
+    /// Returns a copy of the registered interceptions
+    pub(super) fn list(&self) -> Vec<Intercept> {
+        self.inner.lock().unwrap().intercepts.clone()
+    }
+
+    /// Attributes the given IRQ event to the first mapping containing its line, in registration order.
+    ///
+    /// Events of unmapped lines are attributed to the first interception.
+    pub(super) fn attribute(&self, irq: Irq) -> IrqEvent {
+        let inner = self.inner.lock().unwrap();
+        let mapped = inner
+            .mappings
+            .iter()
+            .find(|(_, mapping)| mapping.lines.contains(&irq.line));
+        let (source, gpio) = match mapped {
+            Some((id, mapping)) => (Some(*id), Some(mapping.gpio.clone())),
+            None => (inner.intercepts.first().map(|i| i.id), None),
+        };
+        IrqEvent {
+            source,
+            gpio,
+            line: irq.line,
+            state: irq.state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attribute() {
+        let registry = InterceptRegistry::default();
+        let irq = Irq::new(5, IrqState::Raise);
+        assert_eq!(registry.attribute(irq).source, None);
+
+        let soc = registry.register("/machine/soc", InterceptDirection::Out);
+        let gpio = registry.register("/machine/gpio", InterceptDirection::In);
+        assert_eq!(
+            registry.find("/machine/gpio", InterceptDirection::In),
+            Some(gpio)
+        );
+        assert_eq!(
+            registry.find("/machine/gpio", InterceptDirection::Out),
+            None
+        );
+
+        registry.map_lines(soc, "uart", 0..2);
+        registry.map_lines(gpio, "pins", 2..10);
+        registry.map_lines(soc, "timer", 4..6);
+
+        let event = registry.attribute(irq);
+        assert_eq!(event.source, Some(gpio));
+        assert_eq!(event.gpio.as_deref(), Some("pins"));
+
+        let event = registry.attribute(Irq::new(12, IrqState::Lower));
+        assert_eq!(event.source, Some(soc));
+        assert_eq!(event.gpio, None);
+        assert_eq!(event.state, IrqState::Lower);
+    }
+}
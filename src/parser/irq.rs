@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    time::Instant,
+};
+
+use super::intercept::{InterceptRegistry, IrqEvent};
+use crate::logging::{in_span, log_trace, log_warn};
+use crate::{Irq, IrqState};
+
+/// Capacity of the attributed IRQ event broadcast channel
+pub(super) const IRQ_EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Delivers the IRQ events received by the reader task to every IRQ consumer of the parser
+#[derive(Debug)]
+pub(super) struct IrqDispatch {
+    /// Sender of the IRQ channel returned when the parser is created
+    pub(super) tx_irq: mpsc::Sender<Irq>,
+    /// Last known level of every IRQ line
+    pub(super) levels: IrqLevels,
+    /// Interceptions used to attribute the IRQ events
+    pub(super) intercepts: InterceptRegistry,
+    /// Publisher of the attributed IRQ events
+    pub(super) tx_events: broadcast::Sender<IrqEvent>,
+    /// IRQ events delivered while nothing observed them, recorded in strict mode
+    pub(super) unobserved: Option<UnobservedIrqs>,
+    /// Detector of IRQ storms, if enabled
+    pub(super) storms: Option<StormGuard>,
+}
+
+/// IRQ events delivered in strict mode while nothing observed them, shared with the parser
+pub(super) type UnobservedIrqs = Arc<Mutex<Vec<Irq>>>;
+
+impl IrqDispatch {
+    /// Returns true if the given IRQ event is coalesced because its line is in a storm.
+    /// The level of the line is still updated, but the event is neither published nor delivered.
+    pub(super) fn coalesce(&mut self, irq: Irq, virtual_ns: Option<usize>) -> bool {
+        let coalesced = self
+            .storms
+            .as_mut()
+            .is_some_and(|storms| storms.coalesce(irq, virtual_ns));
+        if coalesced {
+            self.levels.update(irq);
+        }
+        coalesced
+    }
+
+    /// Delivers the given IRQ event
+    pub(super) async fn dispatch(&self, irq: Irq) -> io::Result<()> {
+        in_span!(
+            target: "qtest::parser",
+            "qtest.irq",
+            verb = irq.state.as_str(),
+            line = irq.line;
+            async {
+                self.levels.update(irq);
+                // There may be no subscribers for attributed events
+                let subscribers = self.tx_events.send(self.intercepts.attribute(irq)).unwrap_or(0);
+                let sent = self.tx_irq.send(irq).await;
+                log_trace!(
+                    target: "qtest::parser",
+                    "dispatched {irq} to {subscribers} event subscribers{}",
+                    if sent.is_ok() { " and the IRQ receiver" } else { "" }
+                );
+                match &self.unobserved {
+                    // In strict mode, a closed IRQ receiver is not an error, only one observer less
+                    Some(unobserved) => {
+                        if sent.is_err() && subscribers == 0 && !self.levels.is_watched(irq.line) {
+                            log_warn!(target: "qtest::parser", "unobserved IRQ: {irq}");
+                            unobserved.lock().unwrap().push(irq);
+                        }
+                        Ok(())
+                    }
+                    None => sent.map_err(|e| io::Error::other(format!("Could not send IRQ: {e}"))),
+                }
+            }
+        )
+        .await
+    }
+}
+
+/// Virtual nanoseconds of the windows in which IRQ rates are measured
+const STORM_WINDOW_NS: usize = 1_000_000;
+
+/// Host time of the windows in which IRQ rates are measured while the virtual clock is unknown
+const STORM_WINDOW: Duration = Duration::from_millis(1);
+
+/// IRQ storm detected by the parser, see [`super::ParserBuilder::irq_storm_threshold`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IrqStorm {
+    /// IRQ line of the storm
+    pub line: usize,
+    /// Virtual millisecond of the storm, `None` if the virtual clock was unknown
+    pub virtual_ms: Option<usize>,
+    /// Number of IRQ events of the line in that millisecond, the rate of the storm
+    pub events: usize,
+    /// Number of those events that were coalesced instead of delivered
+    pub coalesced: usize,
+}
+
+/// IRQ storms detected by the reader task, shared with the parser
+pub(super) type IrqStorms = Arc<Mutex<Vec<IrqStorm>>>;
+
+/// Window in which the IRQ events of a line are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Window {
+    /// Virtual millisecond, if the virtual clock is known
+    Virtual(usize),
+    /// Host millisecond starting at the given instant, if the virtual clock is unknown
+    Host(Instant),
+}
+
+impl Window {
+    /// Returns the window of an event at the given virtual clock and host instant,
+    /// continuing the given current window if the event falls in it
+    fn at(virtual_ns: Option<usize>, now: Instant, current: Option<Window>) -> Self {
+        match (virtual_ns, current) {
+            (Some(ns), _) => Self::Virtual(ns / STORM_WINDOW_NS),
+            (None, Some(Self::Host(start))) if now < start + STORM_WINDOW => Self::Host(start),
+            (None, _) => Self::Host(now),
+        }
+    }
+
+    /// Returns the virtual millisecond of the window, if measured in virtual time
+    fn virtual_ms(self) -> Option<usize> {
+        match self {
+            Self::Virtual(ms) => Some(ms),
+            Self::Host(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Virtual(ms) => write!(f, "virtual ms {ms}"),
+            Self::Host(_) => write!(f, "one host ms"),
+        }
+    }
+}
+
+/// Rate of the IRQ events of a line in the current window
+#[derive(Debug, Default)]
+struct LineRate {
+    window: Option<Window>,
+    events: usize,
+    /// Index of the ongoing storm of the line, if any
+    storm: Option<usize>,
+}
+
+/// Detects lines that exceed a number of IRQ events per virtual millisecond and coalesces
+/// their events until the virtual clock moves to the next millisecond.
+/// While the virtual clock is unknown, the rates are measured in host milliseconds instead.
+#[derive(Debug)]
+pub(super) struct StormGuard {
+    threshold: usize,
+    lines: HashMap<usize, LineRate>,
+    pub(super) storms: IrqStorms,
+}
+
+impl StormGuard {
+    pub(super) fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            lines: HashMap::new(),
+            storms: Default::default(),
+        }
+    }
+
+    /// Counts the given IRQ event in the window of the given virtual clock, returns true if it is coalesced
+    fn coalesce(&mut self, irq: Irq, virtual_ns: Option<usize>) -> bool {
+        self.coalesce_at(irq, virtual_ns, Instant::now())
+    }
+
+    /// Counts the given IRQ event, received at the given host instant, as [`StormGuard::coalesce`]
+    fn coalesce_at(&mut self, irq: Irq, virtual_ns: Option<usize>, now: Instant) -> bool {
+        let rate = self.lines.entry(irq.line).or_default();
+        let window = Window::at(virtual_ns, now, rate.window);
+        if rate.window != Some(window) || rate.events == 0 {
+            if let Some(index) = rate.storm.take() {
+                let storm = self.storms.lock().unwrap()[index];
+                log_warn!(
+                    target: "qtest::parser",
+                    "IRQ storm on line {} ended: {} events coalesced", storm.line, storm.coalesced
+                );
+            }
+            *rate = LineRate {
+                window: Some(window),
+                ..Default::default()
+            };
+        }
+        rate.events += 1;
+        let mut storms = self.storms.lock().unwrap();
+        match rate.storm {
+            Some(index) => {
+                let storm = &mut storms[index];
+                storm.events = rate.events;
+                storm.coalesced += 1;
+                true
+            }
+            None if rate.events > self.threshold => {
+                log_warn!(
+                    target: "qtest::parser",
+                    "IRQ storm on line {}: {} events in {}, over the threshold of {}, coalescing",
+                    irq.line, rate.events, window, self.threshold
+                );
+                rate.storm = Some(storms.len());
+                storms.push(IrqStorm {
+                    line: irq.line,
+                    virtual_ms: window.virtual_ms(),
+                    events: rate.events,
+                    coalesced: 1,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Last known level of every IRQ line, shared between the parser and its reader task
+#[derive(Debug, Clone, Default)]
+pub(super) struct IrqLevels {
+    lines: Arc<Mutex<HashMap<usize, watch::Sender<IrqState>>>>,
+}
+
+impl IrqLevels {
+    /// Records the level of the line of the given IRQ event, notifying its watchers
+    pub(super) fn update(&self, irq: Irq) {
+        self.lines
+            .lock()
+            .unwrap()
+            .entry(irq.line)
+            .or_insert_with(|| watch::channel(IrqState::Lower).0)
+            .send_replace(irq.state);
+    }
+
+    /// Returns true if the level of the given line has watchers
+    fn is_watched(&self, line: usize) -> bool {
+        self.lines
+            .lock()
+            .unwrap()
+            .get(&line)
+            .is_some_and(|tx| tx.receiver_count() > 0)
+    }
+
+    /// Returns a receiver of the level of the given line.
+    /// Lines without any event yet are considered lowered.
+    pub(super) fn watch(&self, line: usize) -> watch::Receiver<IrqState> {
+        self.lines
+            .lock()
+            .unwrap()
+            .entry(line)
+            .or_insert_with(|| watch::channel(IrqState::Lower).0)
+            .subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_irq_levels() {
+        let levels = IrqLevels::default();
+        let mut rx = levels.watch(3);
+        assert_eq!(*rx.borrow(), IrqState::Lower);
+
+        levels.update(Irq::new(3, IrqState::Raise));
+        levels.update(Irq::new(4, IrqState::Raise));
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(*rx.borrow_and_update(), IrqState::Raise);
+        assert_eq!(*levels.watch(4).borrow(), IrqState::Raise);
+
+        levels.update(Irq::new(3, IrqState::Lower));
+        assert_eq!(*rx.borrow_and_update(), IrqState::Lower);
+    }
+
+    #[test]
+    fn test_storm_guard() {
+        let mut guard = StormGuard::new(2);
+        let raise = Irq::new(5, IrqState::Raise);
+        assert!(!guard.coalesce(raise, Some(0)));
+        assert!(!guard.coalesce(raise, Some(10)));
+        assert!(guard.coalesce(raise, Some(20)));
+        assert!(guard.coalesce(raise, Some(30)));
+        // Other lines are not affected
+        assert!(!guard.coalesce(Irq::new(6, IrqState::Raise), Some(30)));
+        assert_eq!(
+            guard.storms.lock().unwrap().clone(),
+            [IrqStorm {
+                line: 5,
+                virtual_ms: Some(0),
+                events: 4,
+                coalesced: 2,
+            }]
+        );
+
+        // The storm ends with the virtual millisecond
+        assert!(!guard.coalesce(raise, Some(STORM_WINDOW_NS)));
+        assert_eq!(guard.storms.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_storm_guard_host_time() {
+        let mut guard = StormGuard::new(2);
+        let raise = Irq::new(5, IrqState::Raise);
+        let start = Instant::now();
+        let at = |us| start + Duration::from_micros(us);
+        assert!(!guard.coalesce_at(raise, None, at(0)));
+        assert!(!guard.coalesce_at(raise, None, at(100)));
+        assert!(guard.coalesce_at(raise, None, at(200)));
+        assert_eq!(
+            guard.storms.lock().unwrap().clone(),
+            [IrqStorm {
+                line: 5,
+                virtual_ms: None,
+                events: 3,
+                coalesced: 1,
+            }]
+        );
+
+        // Without a virtual clock, the storm ends with the host millisecond
+        assert!(!guard.coalesce_at(raise, None, at(1000)));
+        assert!(!guard.coalesce_at(raise, None, at(1100)));
+        assert!(guard.coalesce_at(raise, None, at(1200)));
+        assert_eq!(guard.storms.lock().unwrap().len(), 2);
+
+        // Once the virtual clock is known, it takes over
+        assert!(!guard.coalesce_at(raise, Some(0), at(1300)));
+        assert!(!guard.coalesce_at(raise, Some(10), at(5000)));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+
+use crate::logging::{log_error, log_warn};
+use crate::{Irq, Response};
+
+/// Traffic left over when a parser is closed with [`super::Parser::close`].
+///
+/// Responses are matched to commands in order, so a response left in the queue belongs to the oldest
+/// command without response. Leftovers are a sign of test code firing commands without awaiting them,
+/// or of a protocol desync between the parser and QEMU.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LeakReport {
+    /// Commands whose response arrived but was never consumed, with their response
+    pub unconsumed: Vec<(String, Response)>,
+    /// Commands that never got a response
+    pub unanswered: Vec<String>,
+    /// Responses that arrived without any command waiting for them
+    pub unexpected: Vec<Response>,
+    /// IRQ events that nothing observed, in strict IRQ mode
+    pub unobserved_irqs: Vec<Irq>,
+}
+
+impl LeakReport {
+    /// Matches the commands without response to the responses left in the queue
+    pub(super) fn new(
+        mut pending: VecDeque<String>,
+        responses: Vec<Response>,
+        unobserved_irqs: Vec<Irq>,
+    ) -> Self {
+        let mut report = Self {
+            unobserved_irqs,
+            ..Default::default()
+        };
+        for response in responses {
+            match pending.pop_front() {
+                Some(command) => report.unconsumed.push((command, response)),
+                None => report.unexpected.push(response),
+            }
+        }
+        report.unanswered = pending.into();
+        report
+    }
+
+    /// Returns true if every command was answered, every response consumed and every IRQ event observed
+    pub fn is_clean(&self) -> bool {
+        self.unconsumed.is_empty()
+            && self.unanswered.is_empty()
+            && self.unexpected.is_empty()
+            && self.unobserved_irqs.is_empty()
+    }
+
+    /// Logs the leftovers: unconsumed responses as warnings, and the desync symptoms as errors
+    pub(super) fn log(&self) {
+        for (command, response) in &self.unconsumed {
+            log_warn!(target: "qtest::parser", "response to {command:?} never consumed: {response}");
+        }
+        for command in &self.unanswered {
+            log_error!(target: "qtest::parser", "{command:?} never got a response");
+        }
+        for response in &self.unexpected {
+            log_error!(target: "qtest::parser", "unexpected response: {response}");
+        }
+        if !self.unobserved_irqs.is_empty() {
+            let count = self.unobserved_irqs.len();
+            log_warn!(target: "qtest::parser", "{count} IRQ events were never observed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_leak_report() {
+        let pending = VecDeque::from(["readl 0x0".to_string(), "writel 0x0 0x1".to_string()]);
+        let report = LeakReport::new(pending, vec![Response::OkVal("0x1".to_string())], vec![]);
+        assert_eq!(
+            report.unconsumed,
+            [("readl 0x0".to_string(), Response::OkVal("0x1".to_string()))]
+        );
+        assert_eq!(report.unanswered, ["writel 0x0 0x1"]);
+        assert!(!report.is_clean());
+
+        let report = LeakReport::new(VecDeque::new(), vec![Response::Ok], vec![]);
+        assert_eq!(report.unexpected, [Response::Ok]);
+        assert!(LeakReport::new(VecDeque::new(), vec![], vec![]).is_clean());
+
+        let irq = Irq::new(3, crate::IrqState::Raise);
+        assert!(!LeakReport::new(VecDeque::new(), vec![], vec![irq]).is_clean());
+    }
+}
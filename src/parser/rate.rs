@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket refilled at a fixed rate, with a capacity of one second worth of tokens
+#[derive(Debug, Clone)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        let rate = f64::from(rate.max(1));
+        Self {
+            rate,
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    /// Takes the given number of tokens and returns how long to wait until they are available.
+    ///
+    /// The bucket may go into debt, so a single cost larger than the capacity is delayed but never rejected.
+    fn take(&mut self, cost: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        self.tokens -= cost;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Limits the rate of commands and bytes sent to QEMU
+#[derive(Debug, Clone)]
+pub(super) struct RateLimiter {
+    commands: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter, returns `None` if no limit is set
+    pub(super) fn new(commands_per_sec: Option<u32>, bytes_per_sec: Option<u32>) -> Option<Self> {
+        let now = Instant::now();
+        match (commands_per_sec, bytes_per_sec) {
+            (None, None) => None,
+            (commands, bytes) => Some(Self {
+                commands: commands.map(|rate| Bucket::new(rate, now)),
+                bytes: bytes.map(|rate| Bucket::new(rate, now)),
+            }),
+        }
+    }
+
+    /// Returns how long to wait before sending a command of the given size
+    fn delay(&mut self, bytes: usize, now: Instant) -> Duration {
+        let commands = self.commands.as_mut().map(|b| b.take(1.0, now));
+        let bytes = self.bytes.as_mut().map(|b| b.take(bytes as f64, now));
+        commands.max(bytes).unwrap_or_default()
+    }
+
+    /// Waits until a command of the given size can be sent
+    pub(super) async fn acquire(&mut self, bytes: usize) {
+        let delay = self.delay(bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        assert!(RateLimiter::new(None, None).is_none());
+
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(Some(2), Some(100)).unwrap();
+        limiter.commands.as_mut().unwrap().last = start;
+        limiter.bytes.as_mut().unwrap().last = start;
+
+        // Burst of one second worth of commands
+        assert_eq!(limiter.delay(10, start), Duration::ZERO);
+        assert_eq!(limiter.delay(10, start), Duration::ZERO);
+        assert_eq!(limiter.delay(10, start), Duration::from_millis(500));
+
+        // Refilled after one second, but a large command must wait for the bytes
+        let later = start + Duration::from_secs(2);
+        assert_eq!(limiter.delay(150, later), Duration::from_millis(500));
+    }
+}
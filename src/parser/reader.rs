@@ -0,0 +1,302 @@
+use std::{fmt, io, sync::Arc};
+use tokio::sync::mpsc;
+
+use super::irq::IrqDispatch;
+use crate::event::{EventBus, EventKind, Notification};
+use crate::logging::{log_trace, log_warn};
+use crate::protocol::LineDecoder;
+use crate::{Irq, Response};
+
+/// What the parser does with lines that are neither a response (`OK`, `FAIL`) nor an IRQ event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnknownLines {
+    /// The line is delivered as a [`Response::Err`] to the pending command
+    #[default]
+    Response,
+    /// The line is logged and dropped
+    Drop,
+    /// The line is delivered on the raw channel returned by [`super::Parser::take_raw_lines`]
+    Raw,
+}
+
+/// Hook that gets the first chance to recognize the lines that are neither a response nor an IRQ event,
+/// before they are handled according to [`UnknownLines`].
+///
+/// A hook returns a [`Notification`], published in the event stream of the parser, or `None` to pass
+/// the line through to the next hook. It runs in the task that reads the socket, so it should be quick.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{event::{EventKind, Notification}, parser::ParserBuilder, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = ParserBuilder::<SocketTcp>::new("localhost:3000")
+///     .line_hook(|line| {
+///         let payload = line.strip_prefix("TRACE ")?;
+///         Some(Notification::new("TRACE", payload))
+///     })
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let mut events = parser.events();
+/// parser.attach_connection().await.unwrap();
+/// while let Ok(event) = events.recv().await {
+///     if let EventKind::Notification(notification) = event.kind {
+///         println!("{}", notification.payload);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct LineHook(Arc<HookFn>);
+
+type HookFn = dyn Fn(&str) -> Option<Notification> + Send + Sync;
+
+impl LineHook {
+    /// Creates a hook from the given function
+    pub fn new(hook: impl Fn(&str) -> Option<Notification> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for LineHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LineHook").finish_non_exhaustive()
+    }
+}
+
+/// Settings of the task that reads and classifies the lines sent by QEMU.
+///
+/// Some forks of QEMU emit extra diagnostic lines that would otherwise be taken as responses,
+/// corrupting the matching between commands and responses.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::{ParserBuilder, ReaderConfig, UnknownLines}, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = ParserBuilder::<SocketTcp>::new("localhost:3000")
+///     .reader(ReaderConfig::new().unknown_lines(UnknownLines::Raw))
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let mut raw_rx = parser.take_raw_lines().unwrap();
+/// tokio::spawn(async move {
+///     while let Some(line) = raw_rx.recv().await {
+///         println!("QEMU: {line}");
+///     }
+/// });
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReaderConfig {
+    pub(super) unknown_lines: UnknownLines,
+    irqs: bool,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self {
+            unknown_lines: UnknownLines::Response,
+            irqs: true,
+        }
+    }
+}
+
+impl ReaderConfig {
+    /// Creates a new reader configuration with the default settings:
+    /// unknown lines are delivered as responses and IRQ events are parsed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what to do with unknown lines
+    pub fn unknown_lines(mut self, unknown_lines: UnknownLines) -> Self {
+        self.unknown_lines = unknown_lines;
+        self
+    }
+
+    /// Enables or disables IRQ parsing.
+    ///
+    /// When disabled, IRQ events are handled as unknown lines and nothing is sent on the IRQ channel.
+    pub fn irqs(mut self, enabled: bool) -> Self {
+        self.irqs = enabled;
+        self
+    }
+
+    /// Classifies the given line according to the configuration, trying the hooks on unknown lines first
+    fn classify(&self, line: &str, hooks: &[LineHook]) -> Line {
+        if self.irqs {
+            match Irq::try_from(line) {
+                Ok(irq) => return Line::Irq(irq),
+                Err(e) if line.starts_with("IRQ ") => {
+                    log_warn!(target: "qtest::parser", "could not parse IRQ line {line:?}: {e}");
+                }
+                Err(_) => {}
+            }
+        }
+        match line.split_whitespace().next() {
+            Some("OK" | "FAIL") => Line::Response(Response::from(line)),
+            _ => match hooks.iter().find_map(|hook| (hook.0)(line)) {
+                Some(notification) => Line::Notification(notification),
+                None => self.classify_unknown(line),
+            },
+        }
+    }
+
+    /// Classifies a line that no hook recognized
+    fn classify_unknown(&self, line: &str) -> Line {
+        match self.unknown_lines {
+            UnknownLines::Response => Line::Response(Response::from(line)),
+            UnknownLines::Drop => Line::Drop,
+            UnknownLines::Raw => Line::Raw,
+        }
+    }
+}
+
+/// Destination of a line received from QEMU
+#[derive(Debug, PartialEq)]
+enum Line {
+    Response(Response),
+    Irq(Irq),
+    Notification(Notification),
+    Raw,
+    Drop,
+}
+
+/// Used to read data from the qtest socket, should not be used by the user
+pub(super) struct Reader {
+    /// Receiver for the socket data
+    rx_socket: mpsc::Receiver<String>,
+    /// Sender for Response data
+    tx_response: mpsc::Sender<Response>,
+    /// Sender for unknown lines, if they are delivered raw
+    tx_raw: Option<mpsc::Sender<String>>,
+    /// Publisher of the unified event stream
+    events: EventBus,
+    /// Consumers of IRQ data
+    irqs: IrqDispatch,
+    /// Splits the socket data into lines
+    decoder: LineDecoder,
+    /// How lines are classified
+    config: ReaderConfig,
+    /// Hooks tried on unknown lines, in order
+    hooks: Vec<LineHook>,
+}
+
+impl Reader {
+    /// Create a new reader instance with the given receivers and senders
+    pub(super) fn new(
+        rx_socket: mpsc::Receiver<String>,
+        tx_response: mpsc::Sender<Response>,
+        tx_raw: Option<mpsc::Sender<String>>,
+        events: EventBus,
+        irqs: IrqDispatch,
+        config: ReaderConfig,
+        hooks: Vec<LineHook>,
+    ) -> Self {
+        Self {
+            rx_socket,
+            tx_response,
+            tx_raw,
+            events,
+            irqs,
+            decoder: LineDecoder::new(),
+            config,
+            hooks,
+        }
+    }
+
+    /// Reads data from the socket and sends it to the IRQ, Response or raw channels
+    pub(super) async fn read(&mut self) -> io::Result<()> {
+        while let Some(raw_data) = self.rx_socket.recv().await {
+            self.decoder.push(raw_data.as_bytes());
+
+            while let Some(line) = self.decoder.next_line() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                log_trace!(target: "qtest::parser", "< {line}");
+
+                match self.config.classify(&line, &self.hooks) {
+                    // IRQ storms are coalesced before reaching any consumer
+                    Line::Irq(irq) if self.irqs.coalesce(irq, self.events.virtual_ns()) => Ok(()),
+                    Line::Irq(irq) => {
+                        self.events.publish(EventKind::Irq(irq));
+                        self.irqs.dispatch(irq).await
+                    }
+                    Line::Response(response) => {
+                        self.events.publish(EventKind::Response(response.clone()));
+                        self.tx_response
+                            .send(response)
+                            .await
+                            .map_err(|e| io::Error::other(format!("Could not send response: {e}")))
+                    }
+                    Line::Notification(notification) => {
+                        self.events.publish(EventKind::Notification(notification));
+                        Ok(())
+                    }
+                    Line::Raw => match &self.tx_raw {
+                        Some(tx_raw) => tx_raw
+                            .send(line)
+                            .await
+                            .map_err(|e| io::Error::other(format!("Could not send raw line: {e}"))),
+                        None => Ok(()),
+                    },
+                    Line::Drop => {
+                        log_warn!(target: "qtest::parser", "dropped unknown line: {line}");
+                        Ok(())
+                    }
+                }?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IrqState;
+
+    #[test]
+    fn test_classify() {
+        let config = ReaderConfig::new();
+        assert_eq!(config.classify("OK", &[]), Line::Response(Response::Ok));
+        assert_eq!(
+            config.classify("IRQ raise 1", &[]),
+            Line::Irq(Irq::new(1, IrqState::Raise))
+        );
+        assert_eq!(
+            config.classify("debug: hello", &[]),
+            Line::Response(Response::Err("debug: hello".to_string()))
+        );
+
+        let config = config.unknown_lines(UnknownLines::Raw).irqs(false);
+        assert_eq!(
+            config.classify("FAIL Unknown command", &[]),
+            Line::Response(Response::Err("FAIL Unknown command".to_string()))
+        );
+        assert_eq!(config.classify("IRQ raise 1", &[]), Line::Raw);
+        assert_eq!(config.classify("debug: hello", &[]), Line::Raw);
+
+        let config = config.unknown_lines(UnknownLines::Drop);
+        assert_eq!(config.classify("debug: hello", &[]), Line::Drop);
+
+        let hooks = [
+            LineHook::new(|line| Some(Notification::new("VENDOR", line.strip_prefix("vendor: ")?))),
+            LineHook::new(|line| Some(Notification::new("ALL", line))),
+        ];
+        assert_eq!(
+            config.classify("vendor: ready", &hooks),
+            Line::Notification(Notification::new("VENDOR", "ready"))
+        );
+        assert_eq!(config.classify("debug: hello", &hooks[..1]), Line::Drop);
+        // Responses never reach the hooks
+        assert_eq!(config.classify("OK", &hooks), Line::Response(Response::Ok));
+    }
+}
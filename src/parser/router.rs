@@ -0,0 +1,188 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+
+use crate::Irq;
+
+/// Capacity of the channel of every subscription
+const SUBSCRIPTION_CAPACITY: usize = 32;
+
+/// Asynchronous callback registered with [`IrqRouter::on_irq`]
+type Callback = Arc<dyn Fn(Irq) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Destination of the IRQ events of a subscription
+#[derive(Clone)]
+enum Sink {
+    Channel(mpsc::Sender<Irq>),
+    Callback(Callback),
+}
+
+/// Subscription to the IRQ events of a line, or of every line
+#[derive(Clone)]
+struct Subscriber {
+    line: Option<usize>,
+    sink: Sink,
+}
+
+/// Router of the IRQ events of a parser to per-line subscribers.
+///
+/// It takes over the IRQ receiver returned with the parser, so device models in different tasks only
+/// see their own interrupt lines. Subscribers only receive the IRQ events routed after they subscribed.
+/// Events are routed in order, and a subscriber that does not keep up (a full channel or a slow callback)
+/// delays the others, as the IRQ receiver of the parser does. The router can be cloned to subscribe from
+/// other tasks; routing stops when the parser is dropped, closing the receivers of the subscriptions.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::{IrqRouter, Parser}, socket::tcp::SocketTcp};
+/// let (mut parser, irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// let router = IrqRouter::spawn(irq_rx);
+///
+/// let mut uart_irqs = router.subscribe(5);
+/// tokio::spawn(async move {
+///     while let Some(irq) = uart_irqs.recv().await {
+///         println!("UART IRQ: {:?}", irq.state);
+///     }
+/// });
+/// router.on_irq(Some(7), |irq| async move { println!("timer IRQ: {:?}", irq.state) });
+///
+/// parser.attach_connection().await.unwrap();
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct IrqRouter {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl fmt::Debug for IrqRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subscribers = self.subscribers.lock().unwrap().len();
+        f.debug_struct("IrqRouter")
+            .field("subscribers", &subscribers)
+            .finish()
+    }
+}
+
+impl IrqRouter {
+    /// Spawns the task routing the IRQ events of the given receiver, as returned with the parser
+    pub fn spawn(mut irq_rx: mpsc::Receiver<Irq>) -> Self {
+        let router = Self::default();
+        let subscribers = router.subscribers.clone();
+        tokio::spawn(async move {
+            while let Some(irq) = irq_rx.recv().await {
+                route(&subscribers, irq).await;
+            }
+            subscribers.lock().unwrap().clear();
+        });
+        router
+    }
+
+    /// Returns a receiver of the IRQ events of the given line
+    pub fn subscribe(&self, line: usize) -> mpsc::Receiver<Irq> {
+        self.add_channel(Some(line))
+    }
+
+    /// Returns a receiver of the IRQ events of every line
+    pub fn subscribe_all(&self) -> mpsc::Receiver<Irq> {
+        self.add_channel(None)
+    }
+
+    /// Registers an asynchronous callback run for every IRQ event of the given line, or of every line with `None`.
+    ///
+    /// The callback is awaited before the next event is routed, so it should hand long work over to another task.
+    pub fn on_irq<F, Fut>(&self, line: Option<usize>, callback: F)
+    where
+        F: Fn(Irq) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let callback: Callback = Arc::new(move |irq| Box::pin(callback(irq)));
+        self.subscribers.lock().unwrap().push(Subscriber {
+            line,
+            sink: Sink::Callback(callback),
+        });
+    }
+
+    /// Adds a channel subscription to the given line, or to every line with `None`
+    fn add_channel(&self, line: Option<usize>) -> mpsc::Receiver<Irq> {
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_CAPACITY);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            line,
+            sink: Sink::Channel(tx),
+        });
+        rx
+    }
+}
+
+/// Delivers the given IRQ event to its subscribers, dropping the subscriptions whose receiver was dropped
+async fn route(subscribers: &Mutex<Vec<Subscriber>>, irq: Irq) {
+    // The lock is not held while delivering, so subscribers can be added meanwhile
+    let targets: Vec<Subscriber> = subscribers
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|s| s.line.is_none_or(|line| line == irq.line))
+        .cloned()
+        .collect();
+    let mut closed = false;
+    for subscriber in targets {
+        match subscriber.sink {
+            Sink::Channel(tx) => closed |= tx.send(irq).await.is_err(),
+            Sink::Callback(callback) => callback(irq).await,
+        }
+    }
+    if closed {
+        subscribers.lock().unwrap().retain(|s| match &s.sink {
+            Sink::Channel(tx) => !tx.is_closed(),
+            Sink::Callback(_) => true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IrqState;
+
+    #[tokio::test]
+    async fn test_router() {
+        let (tx, rx) = mpsc::channel(8);
+        let router = IrqRouter::spawn(rx);
+        let mut uart = router.subscribe(5);
+        let mut all = router.subscribe_all();
+        let dropped = router.subscribe(5);
+        drop(dropped);
+        let (tx_timer, mut timer) = mpsc::unbounded_channel();
+        router.on_irq(Some(7), move |irq| {
+            let tx_timer = tx_timer.clone();
+            async move { tx_timer.send(irq).unwrap() }
+        });
+
+        let irqs = [
+            Irq::new(5, IrqState::Raise),
+            Irq::new(7, IrqState::Raise),
+            Irq::new(5, IrqState::Lower),
+        ];
+        for irq in irqs {
+            tx.send(irq).await.unwrap();
+        }
+        assert_eq!(uart.recv().await, Some(irqs[0]));
+        assert_eq!(uart.recv().await, Some(irqs[2]));
+        for irq in irqs {
+            assert_eq!(all.recv().await, Some(irq));
+        }
+        assert_eq!(timer.recv().await, Some(irqs[1]));
+        assert_eq!(format!("{router:?}"), "IrqRouter { subscribers: 3 }");
+
+        // Routing stops with the parser
+        drop(tx);
+        assert_eq!(uart.recv().await, None);
+        assert_eq!(all.recv().await, None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+/// What happens to the commands issued while the session is paused
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PausePolicy {
+    /// Commands wait until the session is resumed.
+    /// Only useful with a [`super::ParserHandle`], as a paused [`super::Parser`] cannot be resumed while waiting.
+    #[default]
+    Buffer,
+    /// Commands fail with an error of kind [`io::ErrorKind::WouldBlock`]
+    Reject,
+}
+
+/// Pause policy and write lock held while the session is paused
+type Paused = Option<(PausePolicy, OwnedRwLockWriteGuard<()>)>;
+
+/// Gate that every command goes through, closed while the session is paused.
+///
+/// Commands hold a read lock from the moment they are sent until their response is received,
+/// so pausing waits for the command in flight and never leaves half-sent traffic.
+#[derive(Debug, Clone, Default)]
+pub(super) struct SessionGate {
+    lock: Arc<RwLock<()>>,
+    paused: Arc<Mutex<Paused>>,
+    /// Held by a pause from the check of the current state until the write lock is stored,
+    /// so that concurrent pauses wait for the first one instead of waiting for each other's lock
+    pausing: Arc<AsyncMutex<()>>,
+}
+
+impl SessionGate {
+    /// Waits for the command in flight to complete and closes the gate
+    pub(super) async fn pause(&self, policy: PausePolicy) {
+        let _pausing = self.pausing.lock().await;
+        if let Some((current, _)) = self.paused.lock().unwrap().as_mut() {
+            *current = policy;
+            return;
+        }
+        let guard = self.lock.clone().write_owned().await;
+        *self.paused.lock().unwrap() = Some((policy, guard));
+    }
+
+    /// Opens the gate, releasing the buffered commands
+    pub(super) fn resume(&self) {
+        self.paused.lock().unwrap().take();
+    }
+
+    /// Returns true if the gate is closed
+    pub(super) fn is_paused(&self) -> bool {
+        self.paused.lock().unwrap().is_some()
+    }
+
+    /// Waits until a command can be sent, according to the pause policy.
+    /// The command is in flight until the returned guard is dropped.
+    pub(super) async fn enter(&self) -> io::Result<OwnedRwLockReadGuard<()>> {
+        if let Some((PausePolicy::Reject, _)) = self.paused.lock().unwrap().as_ref() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "The session is paused",
+            ));
+        }
+        Ok(self.lock.clone().read_owned().await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_pause() {
+        let gate = SessionGate::default();
+        let in_flight = gate.enter().await.unwrap();
+        let (first, second) = (gate.clone(), gate.clone());
+        let first = tokio::spawn(async move { first.pause(PausePolicy::Buffer).await });
+        let second = tokio::spawn(async move { second.pause(PausePolicy::Reject).await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!gate.is_paused());
+
+        drop(in_flight);
+        let limit = Duration::from_secs(1);
+        tokio::time::timeout(limit, first).await.unwrap().unwrap();
+        tokio::time::timeout(limit, second).await.unwrap().unwrap();
+        assert!(gate.is_paused());
+        assert!(gate.enter().await.is_err());
+
+        gate.resume();
+        assert!(gate.enter().await.is_ok());
+    }
+}
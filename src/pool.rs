@@ -0,0 +1,255 @@
+use std::{
+    future::Future,
+    io,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::logging::log_warn;
+use crate::machine::Machine;
+use crate::socket::Socket;
+
+type Factory<T> =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = io::Result<Machine<T>>> + Send>> + Send + Sync>;
+
+/// Pool of machines leased exclusively to concurrently running tests.
+///
+/// Machines are either pre-spawned ([`MachinePool::new`]) or created on demand by a factory, up to a maximum
+/// ([`MachinePool::on_demand`]). [`MachinePool::lease`] waits until a machine is free, so tests never share one.
+/// A lease returns its machine to the pool when dropped. If the test panics while holding the lease,
+/// the machine is in an unknown state and is discarded instead: its slot is reclaimed, and the factory,
+/// if any, creates a new machine for the next lease.
+///
+/// The sockets of the machines run on the Tokio runtime that created them, so with `cargo test`
+/// the pool and the tests should share a runtime, e.g. a static one, instead of one per `#[tokio::test]`.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{machine::Machine, parser::Parser, pool::MachinePool, socket::tcp::SocketTcp};
+/// let pool = MachinePool::on_demand(8, || async {
+///     // e.g. spawn QEMU with a free port, then connect to it
+///     let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:0").await?;
+///     parser.attach_connection().await?;
+///     Ok(Machine::new(parser))
+/// });
+///
+/// let mut machine = pool.lease().await.unwrap();
+/// machine.parser().writel(0x4000_0000, 0x1).await.unwrap();
+/// // The machine goes back to the pool when the lease is dropped
+/// # }
+/// ```
+pub struct MachinePool<T: Socket> {
+    shared: Arc<Shared<T>>,
+}
+
+/// State shared by a pool and its leases
+struct Shared<T: Socket> {
+    idle: Mutex<Vec<Machine<T>>>,
+    slots: Arc<Semaphore>,
+    capacity: Mutex<usize>,
+    factory: Option<Factory<T>>,
+}
+
+impl<T: Socket> Clone for MachinePool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Socket> std::fmt::Debug for MachinePool<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MachinePool")
+            .field("capacity", &self.capacity())
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+impl<T: Socket> MachinePool<T> {
+    /// Creates a pool of the given pre-spawned machines
+    pub fn new(machines: Vec<Machine<T>>) -> Self {
+        Self::with_parts(machines.len(), machines, None)
+    }
+
+    /// Creates a pool of at most `max` machines, created by the given factory when no idle machine is left
+    pub fn on_demand<F, Fut>(max: usize, factory: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<Machine<T>>> + Send + 'static,
+    {
+        let factory: Factory<T> = Box::new(move || Box::pin(factory()));
+        Self::with_parts(max, Vec::new(), Some(factory))
+    }
+
+    fn with_parts(capacity: usize, idle: Vec<Machine<T>>, factory: Option<Factory<T>>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                idle: Mutex::new(idle),
+                slots: Arc::new(Semaphore::new(capacity)),
+                capacity: Mutex::new(capacity),
+                factory,
+            }),
+        }
+    }
+
+    /// Returns the number of machines the pool can lease at the same time
+    pub fn capacity(&self) -> usize {
+        *self.shared.capacity.lock().unwrap()
+    }
+
+    /// Returns the number of machines that can be leased without waiting
+    pub fn available(&self) -> usize {
+        self.shared.slots.available_permits()
+    }
+
+    /// Waits for a free machine and leases it, creating it with the factory if no idle machine is left.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the pool has no machines left, i.e. all of its
+    /// pre-spawned machines were discarded. Errors of the factory are returned as is, and free the slot.
+    pub async fn lease(&self) -> io::Result<MachineLease<T>> {
+        let permit = self
+            .shared
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::NotFound, "The pool has no machines left")
+            })?;
+        let idle = self.shared.idle.lock().unwrap().pop();
+        let machine = match (idle, &self.shared.factory) {
+            (Some(machine), _) => machine,
+            (None, Some(factory)) => factory().await?,
+            (None, None) => unreachable!("every free slot of a fixed pool has an idle machine"),
+        };
+        Ok(MachineLease {
+            machine: Some(machine),
+            shared: self.shared.clone(),
+            permit: Some(permit),
+        })
+    }
+}
+
+/// Exclusive lease of a machine of a [`MachinePool`], dereferencing to the [`Machine`]
+pub struct MachineLease<T: Socket> {
+    machine: Option<Machine<T>>,
+    shared: Arc<Shared<T>>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T: Socket> std::fmt::Debug for MachineLease<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MachineLease").finish_non_exhaustive()
+    }
+}
+
+impl<T: Socket> MachineLease<T> {
+    /// Drops the machine instead of returning it to the pool, e.g. after QEMU crashed.
+    /// The factory of the pool, if any, creates a new machine for the next lease.
+    pub fn discard(mut self) {
+        self.machine = None;
+    }
+}
+
+impl<T: Socket> Deref for MachineLease<T> {
+    type Target = Machine<T>;
+
+    fn deref(&self) -> &Machine<T> {
+        self.machine.as_ref().unwrap()
+    }
+}
+
+impl<T: Socket> DerefMut for MachineLease<T> {
+    fn deref_mut(&mut self) -> &mut Machine<T> {
+        self.machine.as_mut().unwrap()
+    }
+}
+
+impl<T: Socket> Drop for MachineLease<T> {
+    fn drop(&mut self) {
+        match self.machine.take() {
+            Some(machine) if !std::thread::panicking() => {
+                self.shared.idle.lock().unwrap().push(machine);
+                return;
+            }
+            Some(_) => {
+                log_warn!(target: "qtest::pool", "discarding the machine of a panicked test");
+            }
+            None => {}
+        }
+        // Without a factory, the slot of a discarded machine cannot be refilled
+        if self.shared.factory.is_none() {
+            self.permit.take().unwrap().forget();
+            let mut capacity = self.shared.capacity.lock().unwrap();
+            *capacity -= 1;
+            if *capacity == 0 {
+                self.shared.slots.close();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::socket::dry_run::SocketDryRun;
+
+    async fn machine() -> io::Result<Machine<SocketDryRun>> {
+        let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await?;
+        parser.attach_connection().await?;
+        Ok(Machine::new(parser))
+    }
+
+    #[tokio::test]
+    async fn test_fixed_pool() {
+        let pool = MachinePool::new(vec![machine().await.unwrap(), machine().await.unwrap()]);
+        let first = pool.lease().await.unwrap();
+        let mut second = pool.lease().await.unwrap();
+        assert_eq!(pool.available(), 0);
+        assert_eq!(second.clock_step(10).await.unwrap(), Some(10));
+        drop(first);
+        assert_eq!(pool.available(), 1);
+
+        // A discarded machine shrinks a pool without factory
+        second.discard();
+        assert_eq!((pool.capacity(), pool.available()), (1, 1));
+        let last = pool.lease().await.unwrap();
+        last.discard();
+        let err = pool.lease().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_on_demand_pool() {
+        let created = Arc::new(Mutex::new(0));
+        let counter = created.clone();
+        let pool = MachinePool::on_demand(2, move || {
+            *counter.lock().unwrap() += 1;
+            machine()
+        });
+
+        let lease = pool.lease().await.unwrap();
+        drop(lease);
+        let lease = pool.lease().await.unwrap();
+        assert_eq!(*created.lock().unwrap(), 1);
+
+        // The machine of a panicked test is replaced
+        let panicked = std::panic::AssertUnwindSafe(move || {
+            let _lease = lease;
+            panic!("test failure");
+        });
+        assert!(std::panic::catch_unwind(panicked).is_err());
+        assert_eq!(pool.available(), 2);
+        let _first = pool.lease().await.unwrap();
+        let _second = pool.lease().await.unwrap();
+        assert_eq!(*created.lock().unwrap(), 3);
+    }
+}
@@ -0,0 +1,676 @@
+//! Transport-free implementation of the qtest wire protocol.
+//!
+//! This module only depends on `core` and `alloc`, so it can be reused by firmware-side tooling
+//! and wasm frontends that do not have tokio or sockets available.
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, str::FromStr};
+
+/// QTest Response enum
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Response {
+    /// Successfull response, without any additional data
+    Ok,
+    /// Successfull response, with additional data
+    OkVal(String),
+    /// Error in processing the request
+    Err(String),
+}
+
+// Converts a qtest response string to a Response enum
+impl From<&str> for Response {
+    fn from(s: &str) -> Self {
+        let mut s_parts = s.split_whitespace();
+        if s_parts.next() != Some("OK") {
+            return Self::Err(s.to_string());
+        }
+        match s_parts.next() {
+            Some(val) => {
+                let msg = core::iter::once(val)
+                    .chain(s_parts)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                Self::OkVal(msg)
+            }
+            None => Self::Ok,
+        }
+    }
+}
+
+// Converts a Response enum back to its qtest response string
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "OK"),
+            Self::OkVal(val) => write!(f, "OK {val}"),
+            Self::Err(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Byte order of the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endianness {
+    /// Least significant byte first
+    Little,
+    /// Most significant byte first
+    Big,
+}
+
+// Parses the endianness reported by the qtest `endianness` command
+impl FromStr for Endianness {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "little" => Ok(Self::Little),
+            "big" => Ok(Self::Big),
+            _ => Err("Unknown endianness"),
+        }
+    }
+}
+
+/// Struct for defining IRQ events propagated by QEMU.
+///
+/// The line and state depends on the machine that emits the event.
+/// Refer to QEMU documentation for your desired machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Irq {
+    /// The line of the IRQ event
+    pub line: usize,
+    /// The state of the IRQ event
+    pub state: IrqState,
+}
+
+impl Irq {
+    /// Creates a new IRQ instance
+    pub fn new(line: usize, state: IrqState) -> Self {
+        Irq { line, state }
+    }
+}
+
+/// Enum for defining the state of an IRQ event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IrqState {
+    /// The IRQ event is raised
+    Raise,
+    /// The IRQ event is lowered
+    Lower,
+}
+
+impl IrqState {
+    /// Returns the name of the state in qtest IRQ events
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IrqState::Raise => "raise",
+            IrqState::Lower => "lower",
+        }
+    }
+}
+
+// Converts an IRQ back to its qtest event string
+impl fmt::Display for Irq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IRQ {} {}", self.state.as_str(), self.line)
+    }
+}
+
+impl TryFrom<&str> for Irq {
+    type Error = &'static str;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let mut s_parts = s.split_whitespace();
+
+        if s_parts.next() != Some("IRQ") {
+            return Err("Invalid IRQ string");
+        }
+        let ty = match s_parts.next() {
+            Some("raise") => IrqState::Raise,
+            Some("lower") => IrqState::Lower,
+            _ => return Err("Invalid IRQ type"),
+        };
+        let line = s_parts
+            .next()
+            .ok_or("Invalid IRQ line")?
+            .parse()
+            .map_err(|_| "Invalid IRQ line")?;
+
+        match s_parts.next() {
+            Some(_) => Err("Invalid IRQ string"),
+            None => Ok(Irq::new(line, ty)),
+        }
+    }
+}
+
+/// Message sent by QEMU: either the response to a command or an asynchronous IRQ event
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Message {
+    /// Response to the last command
+    Response(Response),
+    /// IRQ event propagated by QEMU
+    Irq(Irq),
+}
+
+// Converts a qtest line to a Message enum. Lines that are not IRQ events are responses.
+impl From<&str> for Message {
+    fn from(s: &str) -> Self {
+        match Irq::try_from(s) {
+            Ok(irq) => Self::Irq(irq),
+            Err(_) => Self::Response(Response::from(s)),
+        }
+    }
+}
+
+// Converts a Message enum back to its qtest line
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Response(response) => write!(f, "{response}"),
+            Self::Irq(irq) => write!(f, "{irq}"),
+        }
+    }
+}
+
+/// Width of a single memory or port access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Width {
+    /// 8-bit access (`b` suffix)
+    Byte,
+    /// 16-bit access (`w` suffix)
+    Word,
+    /// 32-bit access (`l` suffix)
+    Long,
+    /// 64-bit access (`q` suffix). Not available for port I/O.
+    Quad,
+}
+
+impl Width {
+    /// Returns the suffix of the qtest commands with this width
+    pub fn suffix(&self) -> char {
+        match self {
+            Self::Byte => 'b',
+            Self::Word => 'w',
+            Self::Long => 'l',
+            Self::Quad => 'q',
+        }
+    }
+
+    /// Returns the number of bytes of an access with this width
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Word => 2,
+            Self::Long => 4,
+            Self::Quad => 8,
+        }
+    }
+
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "b" => Some(Self::Byte),
+            "w" => Some(Self::Word),
+            "l" => Some(Self::Long),
+            "q" => Some(Self::Quad),
+            _ => None,
+        }
+    }
+}
+
+/// Command sent to QEMU.
+///
+/// The [`fmt::Display`] implementation produces the command string without the trailing newline,
+/// use [`Command::to_line`] to get the complete line to be sent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// `endianness`
+    Endianness,
+    /// `clock_step [ns]`
+    ClockStep(Option<usize>),
+    /// `clock_set <ns>`
+    ClockSet(usize),
+    /// `irq_intercept_in <qom_path>`
+    IrqInterceptIn(String),
+    /// `irq_intercept_out <qom_path>`
+    IrqInterceptOut(String),
+    /// `set_irq_in <qom_path> <irq_name> <line> <level>`
+    SetIrqIn {
+        /// QOM path of the device
+        qom_path: String,
+        /// Name of the GPIO input
+        irq_name: String,
+        /// Line of the GPIO input
+        line: usize,
+        /// Level to set
+        level: isize,
+    },
+    /// `in{b,w,l} <addr>`
+    In {
+        /// Width of the access
+        width: Width,
+        /// Port address
+        addr: usize,
+    },
+    /// `out{b,w,l} <addr> <val>`
+    Out {
+        /// Width of the access
+        width: Width,
+        /// Port address
+        addr: usize,
+        /// Value to write
+        val: u64,
+    },
+    /// `read{b,w,l,q} <addr>`
+    ReadValue {
+        /// Width of the access
+        width: Width,
+        /// Memory address
+        addr: usize,
+    },
+    /// `write{b,w,l,q} <addr> <val>`
+    WriteValue {
+        /// Width of the access
+        width: Width,
+        /// Memory address
+        addr: usize,
+        /// Value to write
+        val: u64,
+    },
+    /// `read <addr> <len>`
+    Read {
+        /// Memory address
+        addr: usize,
+        /// Number of bytes to read
+        len: usize,
+    },
+    /// `write <addr> <len> 0x<data>`
+    Write {
+        /// Memory address
+        addr: usize,
+        /// Number of bytes to write
+        len: usize,
+        /// Hex-encoded data, without the `0x` prefix
+        data: String,
+    },
+    /// `memset <addr> <len> <val>`
+    Memset {
+        /// Memory address
+        addr: usize,
+        /// Number of bytes to fill
+        len: usize,
+        /// Byte written to every address
+        val: u8,
+    },
+    /// `b64read <addr> <len>`
+    B64Read {
+        /// Memory address
+        addr: usize,
+        /// Number of bytes to read
+        len: usize,
+    },
+    /// `b64write <addr> <len> <data>`
+    B64Write {
+        /// Memory address
+        addr: usize,
+        /// Number of bytes to write
+        len: usize,
+        /// Base64-encoded data
+        data: String,
+    },
+}
+
+impl Command {
+    /// Returns the complete line to be sent to QEMU, including the trailing newline
+    pub fn to_line(&self) -> String {
+        format!("{self}\n")
+    }
+
+    /// Returns the name of the command, e.g. `readl`
+    pub fn verb(&self) -> &'static str {
+        /// Picks the name of the given width among the byte, word, long and quad names
+        fn sized(width: &Width, names: [&'static str; 4]) -> &'static str {
+            match width {
+                Width::Byte => names[0],
+                Width::Word => names[1],
+                Width::Long => names[2],
+                Width::Quad => names[3],
+            }
+        }
+        match self {
+            Self::Endianness => "endianness",
+            Self::ClockStep(_) => "clock_step",
+            Self::ClockSet(_) => "clock_set",
+            Self::IrqInterceptIn(_) => "irq_intercept_in",
+            Self::IrqInterceptOut(_) => "irq_intercept_out",
+            Self::SetIrqIn { .. } => "set_irq_in",
+            Self::In { width, .. } => sized(width, ["inb", "inw", "inl", "inq"]),
+            Self::Out { width, .. } => sized(width, ["outb", "outw", "outl", "outq"]),
+            Self::ReadValue { width, .. } => sized(width, ["readb", "readw", "readl", "readq"]),
+            Self::WriteValue { width, .. } => {
+                sized(width, ["writeb", "writew", "writel", "writeq"])
+            }
+            Self::Read { .. } => "read",
+            Self::Write { .. } => "write",
+            Self::Memset { .. } => "memset",
+            Self::B64Read { .. } => "b64read",
+            Self::B64Write { .. } => "b64write",
+        }
+    }
+
+    /// Returns the address accessed by the command, if any
+    pub fn addr(&self) -> Option<usize> {
+        match self {
+            Self::In { addr, .. }
+            | Self::Out { addr, .. }
+            | Self::ReadValue { addr, .. }
+            | Self::WriteValue { addr, .. }
+            | Self::Read { addr, .. }
+            | Self::Write { addr, .. }
+            | Self::Memset { addr, .. }
+            | Self::B64Read { addr, .. }
+            | Self::B64Write { addr, .. } => Some(*addr),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes accessed by the command, if any
+    pub fn access_len(&self) -> Option<usize> {
+        match self {
+            Self::In { width, .. }
+            | Self::Out { width, .. }
+            | Self::ReadValue { width, .. }
+            | Self::WriteValue { width, .. } => Some(width.bytes()),
+            Self::Read { len, .. }
+            | Self::Write { len, .. }
+            | Self::Memset { len, .. }
+            | Self::B64Read { len, .. }
+            | Self::B64Write { len, .. } => Some(*len),
+            _ => None,
+        }
+    }
+}
+
+// Converts a Command enum to its qtest command string
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Endianness => write!(f, "endianness"),
+            Self::ClockStep(None) => write!(f, "clock_step"),
+            Self::ClockStep(Some(ns)) => write!(f, "clock_step {ns}"),
+            Self::ClockSet(ns) => write!(f, "clock_set {ns}"),
+            Self::IrqInterceptIn(qom_path) => write!(f, "irq_intercept_in {qom_path}"),
+            Self::IrqInterceptOut(qom_path) => write!(f, "irq_intercept_out {qom_path}"),
+            Self::SetIrqIn {
+                qom_path,
+                irq_name,
+                line,
+                level,
+            } => write!(f, "set_irq_in {qom_path} {irq_name} {line} {level}"),
+            Self::In { width, addr } => write!(f, "in{} {addr:#x}", width.suffix()),
+            Self::Out { width, addr, val } => {
+                write!(f, "out{} {addr:#x} {val:#x}", width.suffix())
+            }
+            Self::ReadValue { width, addr } => write!(f, "read{} {addr:#x}", width.suffix()),
+            Self::WriteValue { width, addr, val } => {
+                write!(f, "write{} {addr:#x} {val:#x}", width.suffix())
+            }
+            Self::Read { addr, len } => write!(f, "read {addr:#x} {len}"),
+            Self::Write { addr, len, data } => write!(f, "write {addr:#x} {len} 0x{data}"),
+            Self::Memset { addr, len, val } => write!(f, "memset {addr:#x} {len} {val:#x}"),
+            Self::B64Read { addr, len } => write!(f, "b64read {addr:#x} {len}"),
+            Self::B64Write { addr, len, data } => write!(f, "b64write {addr:#x} {len} {data}"),
+        }
+    }
+}
+
+// Parses a qtest command string, with or without the trailing newline
+impl FromStr for Command {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args: Vec<&str> = s.split_whitespace().collect();
+        let Some((verb, args)) = args.split_first() else {
+            return Err("Empty command");
+        };
+
+        let command = match (*verb, args) {
+            ("endianness", []) => Self::Endianness,
+            ("clock_step", []) => Self::ClockStep(None),
+            ("clock_step", [ns]) => Self::ClockStep(Some(parse_num(ns)?)),
+            ("clock_set", [ns]) => Self::ClockSet(parse_num(ns)?),
+            ("irq_intercept_in", [qom_path]) => Self::IrqInterceptIn(qom_path.to_string()),
+            ("irq_intercept_out", [qom_path]) => Self::IrqInterceptOut(qom_path.to_string()),
+            ("set_irq_in", [qom_path, irq_name, line, level]) => Self::SetIrqIn {
+                qom_path: qom_path.to_string(),
+                irq_name: irq_name.to_string(),
+                line: parse_num(line)?,
+                level: level.parse().map_err(|_| "Invalid IRQ level")?,
+            },
+            ("read", [addr, len]) => Self::Read {
+                addr: parse_num(addr)?,
+                len: parse_num(len)?,
+            },
+            ("write", [addr, len, data]) => Self::Write {
+                addr: parse_num(addr)?,
+                len: parse_num(len)?,
+                data: data.trim_start_matches("0x").to_string(),
+            },
+            ("memset", [addr, len, val]) => Self::Memset {
+                addr: parse_num(addr)?,
+                len: parse_num(len)?,
+                val: parse_num(val)?,
+            },
+            ("b64read", [addr, len]) => Self::B64Read {
+                addr: parse_num(addr)?,
+                len: parse_num(len)?,
+            },
+            ("b64write", [addr, len, data]) => Self::B64Write {
+                addr: parse_num(addr)?,
+                len: parse_num(len)?,
+                data: data.to_string(),
+            },
+            (verb, [addr]) => {
+                let (width, addr) = (width_of(verb)?, parse_num(addr)?);
+                match verb {
+                    v if v.starts_with("in") && width != Width::Quad => Self::In { width, addr },
+                    v if v.starts_with("read") => Self::ReadValue { width, addr },
+                    _ => return Err("Unknown command"),
+                }
+            }
+            (verb, [addr, val]) => {
+                let (width, addr, val) = (width_of(verb)?, parse_num(addr)?, parse_num(val)?);
+                match verb {
+                    v if v.starts_with("out") && width != Width::Quad => {
+                        Self::Out { width, addr, val }
+                    }
+                    v if v.starts_with("write") => Self::WriteValue { width, addr, val },
+                    _ => return Err("Unknown command"),
+                }
+            }
+            _ => return Err("Unknown command"),
+        };
+        Ok(command)
+    }
+}
+
+/// Returns the width of a sized access command (`inb`, `writeq`...)
+fn width_of(verb: &str) -> Result<Width, &'static str> {
+    ["in", "out", "read", "write"]
+        .iter()
+        .find_map(|prefix| verb.strip_prefix(prefix))
+        .and_then(Width::from_suffix)
+        .ok_or("Unknown command")
+}
+
+/// Parses a number the way QEMU does: hexadecimal with the `0x` prefix, decimal otherwise
+fn parse_num<N: TryFrom<u64>>(s: &str) -> Result<N, &'static str> {
+    let val = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    val.ok()
+        .and_then(|val| N::try_from(val).ok())
+        .ok_or("Invalid number")
+}
+
+/// Splits the byte stream received from QEMU into complete lines.
+///
+/// Bytes are pushed as they arrive with [`LineDecoder::push`], regardless of how the transport
+/// fragments them. Complete lines are then taken with [`LineDecoder::next_line`] or
+/// [`LineDecoder::next_message`]; an incomplete trailing line is kept until the rest arrives.
+#[derive(Debug, Clone, Default)]
+pub struct LineDecoder {
+    buf: Vec<u8>,
+}
+
+impl LineDecoder {
+    /// Creates a new, empty, line decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the given bytes to the decoder
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the next complete line, without the trailing newline (`\n` or `\r\n`)
+    pub fn next_line(&mut self) -> Option<String> {
+        let end = self.buf.iter().position(|&b| b == b'\n')?;
+        let line: Vec<u8> = self.buf.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line[..end]);
+        Some(line.trim_end_matches('\r').to_string())
+    }
+
+    /// Returns the next message, skipping empty lines
+    pub fn next_message(&mut self) -> Option<Message> {
+        loop {
+            let line = self.next_line()?;
+            if !line.trim().is_empty() {
+                return Some(Message::from(line.as_str()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_response_from() {
+        let response = Response::from("OK");
+        assert_eq!(response, Response::Ok);
+
+        let response = Response::from("OK val");
+        assert_eq!(response, Response::OkVal("val".to_string()));
+
+        let response = Response::from("ERR error");
+        assert_eq!(response, Response::Err("ERR error".to_string()));
+    }
+
+    #[test]
+    fn test_irq_try_from() {
+        let irq = Irq::try_from("invalid");
+        assert_eq!(irq, Err("Invalid IRQ string"));
+
+        let irq = Irq::try_from("IRQ invalid");
+        assert_eq!(irq, Err("Invalid IRQ type"));
+
+        let irq = Irq::try_from("IRQ raise -1");
+        assert_eq!(irq, Err("Invalid IRQ line"));
+
+        let irq = Irq::try_from("IRQ raise 1 invalid");
+        assert_eq!(irq, Err("Invalid IRQ string"));
+
+        let irq = Irq::try_from("IRQ raise 1");
+        assert_eq!(irq, Ok(Irq::new(1, IrqState::Raise)));
+
+        let irq = Irq::try_from("IRQ lower 2");
+        assert_eq!(irq, Ok(Irq::new(2, IrqState::Lower)));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for s in ["OK", "OK 0x10", "FAIL Unknown command"] {
+            assert_eq!(Response::from(s).to_string(), s);
+        }
+        for s in ["IRQ raise 1", "IRQ lower 2"] {
+            assert_eq!(Irq::try_from(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        for s in [
+            "endianness",
+            "clock_step",
+            "clock_step 100",
+            "clock_set 2000",
+            "irq_intercept_in /machine/soc",
+            "irq_intercept_out /machine/soc",
+            "set_irq_in /machine/soc gpio 3 -1",
+            "inb 0x60",
+            "outw 0x60 0x1234",
+            "readq 0x40000000",
+            "writel 0x40000000 0xdeadbeef",
+            "read 0x40000000 16",
+            "write 0x40000000 2 0xabcd",
+            "memset 0x40000000 16 0xff",
+            "b64read 0x40000000 2",
+            "b64write 0x40000000 2 q80=",
+        ] {
+            let command = s.parse::<Command>().unwrap();
+            assert_eq!(command.to_string(), s);
+            assert_eq!(Some(command.verb()), s.split_whitespace().next());
+        }
+        assert_eq!(
+            "writeb 16 255".parse(),
+            Ok(Command::WriteValue {
+                width: Width::Byte,
+                addr: 0x10,
+                val: 0xff
+            })
+        );
+        let read = "read 0x40000000 16".parse::<Command>().unwrap();
+        assert_eq!(
+            (read.addr(), read.access_len()),
+            (Some(0x4000_0000), Some(16))
+        );
+        let readl = "readl 0x10".parse::<Command>().unwrap();
+        assert_eq!((readl.addr(), readl.access_len()), (Some(0x10), Some(4)));
+        let step = Command::ClockStep(None);
+        assert_eq!((step.addr(), step.access_len()), (None, None));
+        assert!("inq 0x60".parse::<Command>().is_err());
+        assert!("readx 0x60".parse::<Command>().is_err());
+        assert!("clock_step ten".parse::<Command>().is_err());
+        assert!("".parse::<Command>().is_err());
+        assert_eq!("little".parse(), Ok(Endianness::Little));
+        assert_eq!("big\n".parse(), Ok(Endianness::Big));
+        assert!("middle".parse::<Endianness>().is_err());
+    }
+
+    #[test]
+    fn test_line_decoder() {
+        let mut decoder = LineDecoder::new();
+        decoder.push(b"OK 0x");
+        assert_eq!(decoder.next_message(), None);
+        decoder.push(b"10\r\n\nIRQ raise 3\nFAIL");
+        assert_eq!(
+            decoder.next_message(),
+            Some(Message::Response(Response::OkVal("0x10".to_string())))
+        );
+        assert_eq!(
+            decoder.next_message(),
+            Some(Message::Irq(Irq::new(3, IrqState::Raise)))
+        );
+        assert_eq!(decoder.next_message(), None);
+        decoder.push(b"\n");
+        assert_eq!(
+            decoder.next_message(),
+            Some(Message::Response(Response::Err("FAIL".to_string())))
+        );
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+};
+use tokio::{
+    process::{Child, ChildStderr, ChildStdout, Command},
+    sync::mpsc,
+};
+
+use crate::parser::{Parser, ParserBuilder};
+use crate::socket::{stdio, Mode, Socket};
+use crate::Irq;
+
+mod container;
+mod resolve;
+
+pub use crate::icount::Icount;
+pub use container::{Container, ContainerRuntime};
+pub use resolve::{QemuResolver, QemuVersion, ResolvedQemu, VersionReq, QEMU_BINARY_ENV};
+
+/// Mode of the record/replay subsystem of QEMU (`-icount rr=...`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RrMode {
+    /// Record the non-deterministic inputs of the run to the replay file
+    Record,
+    /// Replay the inputs of the replay file
+    Replay,
+}
+
+/// Builder of the command line of QEMU.
+///
+/// # Example
+///
+/// ```no_run
+/// # use qtest::{qemu::{Icount, QemuCommandBuilder}, socket::Mode};
+/// let command = QemuCommandBuilder::new("qemu-system-arm")
+///     .machine("netduino2")
+///     .cpu("cortex-m3")
+///     .device("loader,file=firmware.elf")
+///     .qtest_tcp("localhost:3000", Mode::Listen)
+///     .icount(Icount::fixed(3))
+///     .arg("-nographic")
+///     .command()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuCommandBuilder {
+    binary: String,
+    machine: Option<String>,
+    cpu: Option<String>,
+    devices: Vec<String>,
+    qtest: Option<String>,
+    qtest_socket: Option<QtestSocket>,
+    icount: Option<Icount>,
+    rr: Option<(RrMode, PathBuf)>,
+    args: Vec<String>,
+    capture_output: bool,
+}
+
+impl QemuCommandBuilder {
+    /// Creates a builder for the given QEMU binary (e.g. `qemu-system-arm`)
+    pub fn new(binary: &str) -> Self {
+        Self {
+            binary: binary.to_string(),
+            machine: None,
+            cpu: None,
+            devices: Vec::new(),
+            qtest: None,
+            qtest_socket: None,
+            icount: None,
+            rr: None,
+            args: Vec::new(),
+            capture_output: false,
+        }
+    }
+
+    /// Creates a builder with the QEMU binary, arguments and icount settings of the given configuration,
+    /// with the qtest socket pointing at its endpoint.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the configuration has no QEMU binary.
+    #[cfg(feature = "config")]
+    pub fn from_config(config: &crate::config::Config) -> io::Result<Self> {
+        use crate::config::Scheme;
+
+        let binary = config
+            .qemu
+            .binary
+            .as_deref()
+            .ok_or_else(|| invalid("The configuration has no QEMU binary"))?;
+        let endpoint = &config.endpoint;
+        let mut builder = Self::new(binary).args(&config.qemu.args);
+        builder = match endpoint.scheme {
+            Scheme::Tcp => builder.qtest_tcp(&endpoint.url, endpoint.mode),
+            Scheme::Unix => builder.qtest_unix(&endpoint.url, endpoint.mode),
+            // QEMU runs on the remote host, at the other end of the tunnel
+            #[cfg(all(feature = "ssh", unix))]
+            Scheme::Ssh => {
+                let remote: crate::socket::ssh::SshEndpoint = endpoint.url.parse()?;
+                builder.qtest_unix(&remote.path, endpoint.mode)
+            }
+        };
+        builder.icount = config.qemu.icount;
+        Ok(builder)
+    }
+
+    /// Sets the emulated machine (`-M`)
+    pub fn machine(mut self, machine: &str) -> Self {
+        self.machine = Some(machine.to_string());
+        self
+    }
+
+    /// Sets the emulated CPU (`-cpu`)
+    pub fn cpu(mut self, cpu: &str) -> Self {
+        self.cpu = Some(cpu.to_string());
+        self
+    }
+
+    /// Adds a device (`-device`), e.g. `loader,file=firmware.elf`
+    pub fn device(mut self, device: &str) -> Self {
+        self.devices.push(device.to_string());
+        self
+    }
+
+    /// Sets the character device of the qtest socket (`-qtest`), e.g. `unix:/tmp/qtest.sock`
+    pub fn qtest(mut self, chardev: &str) -> Self {
+        self.qtest = Some(chardev.to_string());
+        self.qtest_socket = None;
+        self
+    }
+
+    /// Points the qtest socket at a TCP socket of this crate, created with the given mode
+    pub fn qtest_tcp(self, addr: &str, mode: Mode) -> Self {
+        let mut builder = self.qtest(&chardev("tcp", addr, mode));
+        builder.qtest_socket = Some(QtestSocket::Tcp(addr.to_string(), mode));
+        builder
+    }
+
+    /// Points the qtest socket at a UNIX socket of this crate, created with the given mode
+    pub fn qtest_unix(self, path: &str, mode: Mode) -> Self {
+        let mut builder = self.qtest(&chardev("unix", path, mode));
+        builder.qtest_socket = Some(QtestSocket::Unix(PathBuf::from(path), mode));
+        builder
+    }
+
+    /// Points the qtest socket at the standard input and output of QEMU (`-qtest stdio`), for a
+    /// [`crate::socket::stdio::SocketStdio`]. [`QemuInstance::launch`] connects the pipes of the process to it.
+    pub fn qtest_stdio(self) -> Self {
+        let mut builder = self.qtest("stdio");
+        builder.qtest_socket = Some(QtestSocket::Stdio);
+        builder
+    }
+
+    /// Enables instruction counting with the given settings (`-icount`)
+    pub fn icount(mut self, icount: Icount) -> Self {
+        self.icount = Some(icount);
+        self
+    }
+
+    /// Records the run to the given replay file (`rr=record,rrfile=...`). Requires [`QemuCommandBuilder::icount`].
+    pub fn record<P: AsRef<Path>>(mut self, rrfile: P) -> Self {
+        self.rr = Some((RrMode::Record, rrfile.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Replays the run recorded in the given replay file (`rr=replay,rrfile=...`).
+    /// Requires the [`QemuCommandBuilder::icount`] settings of the recording.
+    pub fn replay<P: AsRef<Path>>(mut self, rrfile: P) -> Self {
+        self.rr = Some((RrMode::Replay, rrfile.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Captures the standard output and error of QEMU, see [`QemuInstance::take_stdout`].
+    /// Otherwise, they are inherited from this process.
+    pub fn capture_output(mut self, capture: bool) -> Self {
+        self.capture_output = capture;
+        self
+    }
+
+    /// Appends an argument
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Appends several arguments
+    pub fn args<S: AsRef<str>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.args
+            .extend(args.into_iter().map(|a| a.as_ref().to_string()));
+        self
+    }
+
+    /// Returns the QEMU binary
+    pub fn binary(&self) -> &str {
+        &self.binary
+    }
+
+    /// Returns the icount settings, if enabled
+    pub fn icount_settings(&self) -> Option<Icount> {
+        self.icount
+    }
+
+    /// Returns the arguments of the command line, without the binary
+    pub fn build_args(&self) -> io::Result<Vec<String>> {
+        let mut args = Vec::new();
+        if let Some(machine) = &self.machine {
+            args.extend(["-M".to_string(), machine.clone()]);
+        }
+        if let Some(cpu) = &self.cpu {
+            args.extend(["-cpu".to_string(), cpu.clone()]);
+        }
+        for device in &self.devices {
+            args.extend(["-device".to_string(), device.clone()]);
+        }
+        if let Some(qtest) = &self.qtest {
+            args.extend(["-qtest".to_string(), qtest.clone()]);
+        }
+        match (&self.icount, &self.rr) {
+            (Some(icount), None) => args.extend(["-icount".to_string(), icount.to_arg()?]),
+            (Some(icount), Some((mode, rrfile))) => {
+                let mode = match mode {
+                    RrMode::Record => "record",
+                    RrMode::Replay => "replay",
+                };
+                let icount = format!("{},rr={mode},rrfile={}", icount.to_arg()?, rrfile.display());
+                args.extend(["-icount".to_string(), icount]);
+            }
+            (None, Some(_)) => return Err(invalid("Record/replay requires icount settings")),
+            (None, None) => {}
+        }
+        args.extend(self.args.iter().cloned());
+        Ok(args)
+    }
+
+    /// Returns the command running QEMU
+    pub fn command(&self) -> io::Result<Command> {
+        let mut command = Command::new(&self.binary);
+        command.args(self.build_args()?);
+        Ok(command)
+    }
+}
+
+/// Running QEMU process.
+///
+/// In record/replay runs, the qtest session should be recorded with a [`crate::transcript::Recorder`] while recording,
+/// and checked with a [`crate::transcript::ReplayVerifier`] while replaying, so the replayed run is known to
+/// observe the same responses and IRQs.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, qemu::{Icount, QemuCommandBuilder, QemuInstance}, socket::{tcp::SocketTcp, Mode}};
+/// # use qtest::transcript::{Recorder, ReplayVerifier};
+/// let builder = QemuCommandBuilder::new("qemu-system-arm")
+///     .machine("netduino2")
+///     .qtest_tcp("localhost:3000", Mode::Listen)
+///     .icount(Icount::fixed(3));
+///
+/// // Record a run
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// let recorder = Recorder::start(parser.events());
+/// let mut qemu = QemuInstance::spawn(&builder.clone().record("run.rr")).unwrap();
+/// parser.attach_connection().await.unwrap();
+/// parser.clock_step(Some(1_000_000)).await.unwrap();
+/// qemu.kill().await.unwrap();
+/// let transcript = recorder.finish().await;
+///
+/// // Replay it, checking that the session is the same
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// let verifier = ReplayVerifier::start(parser.events(), &transcript);
+/// let mut qemu = QemuInstance::spawn(&builder.replay("run.rr")).unwrap();
+/// parser.attach_connection().await.unwrap();
+/// parser.clock_step(Some(1_000_000)).await.unwrap();
+/// qemu.kill().await.unwrap();
+/// assert!(verifier.finish().await.matches());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct QemuInstance {
+    child: Child,
+    rr: Option<RrMode>,
+    container: Option<(ContainerRuntime, String)>,
+}
+
+impl QemuInstance {
+    /// Spawns QEMU with the command line of the given builder. QEMU is killed when the instance is dropped.
+    pub fn spawn(builder: &QemuCommandBuilder) -> io::Result<Self> {
+        let child = spawn_child(builder, builder.command()?)?;
+        Ok(Self {
+            child,
+            rr: builder.rr.as_ref().map(|(mode, _)| *mode),
+            container: None,
+        })
+    }
+
+    /// Spawns QEMU inside the given container, with the command line of the given builder.
+    /// The container is removed when the instance is killed or dropped.
+    pub fn spawn_in(container: &Container, builder: &QemuCommandBuilder) -> io::Result<Self> {
+        let name = container.container_name();
+        let child = spawn_child(builder, container.command_named(builder, &name)?)?;
+        Ok(Self {
+            child,
+            rr: builder.rr.as_ref().map(|(mode, _)| *mode),
+            container: Some((container.runtime(), name)),
+        })
+    }
+
+    /// Spawns QEMU with the command line of the given builder and waits for it to connect to the parser
+    /// of the given parser builder, which must point at the qtest socket of the command line.
+    /// With [`QemuCommandBuilder::qtest_stdio`], the pipes of QEMU are connected to the parser socket,
+    /// see [`QemuInstance::connect_stdio`].
+    ///
+    /// Returns the instance together with the attached parser and its IRQ receiver.
+    /// Returns an error of kind [`io::ErrorKind::ConnectionAborted`] if QEMU exits before connecting.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "tcp")]
+    /// # async fn example() {
+    /// # use qtest::{parser::ParserBuilder, qemu::{QemuCommandBuilder, QemuInstance}, socket::{tcp::SocketTcp, Mode}};
+    /// let builder = QemuCommandBuilder::new("qemu-system-arm")
+    ///     .machine("netduino2")
+    ///     .qtest_tcp("localhost:3000", Mode::Listen)
+    ///     .arg("-nographic");
+    /// let parser = ParserBuilder::<SocketTcp>::new("localhost:3000");
+    ///
+    /// let (mut qemu, mut parser, _irq_rx) = QemuInstance::launch(&builder, parser).await.unwrap();
+    /// parser.readl(0x2000_0000).await.unwrap();
+    /// qemu.kill().await.unwrap();
+    /// # }
+    /// ```
+    pub async fn launch<T: Socket>(
+        builder: &QemuCommandBuilder,
+        parser: ParserBuilder<T>,
+    ) -> io::Result<(Self, Parser<T>, mpsc::Receiver<Irq>)> {
+        // The socket must be listening before QEMU connects to it
+        let (mut parser, irq_rx) = parser.build().await?;
+        let mut qemu = Self::spawn(builder)?;
+        if builder.qtest_socket == Some(QtestSocket::Stdio) {
+            qemu.connect_stdio(&parser.socket().address()).await?;
+        }
+        tokio::select! {
+            attached = parser.attach_connection() => attached?,
+            status = qemu.child.wait() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    format!("QEMU exited before connecting: {}", status?),
+                ));
+            }
+        }
+        Ok((qemu, parser, irq_rx))
+    }
+
+    /// Connects the standard input and output of QEMU, started with [`QemuCommandBuilder::qtest_stdio`],
+    /// to the [`crate::socket::stdio::SocketStdio`] created with the given name.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotConnected`] if the pipes were already taken
+    /// or QEMU does not use `-qtest stdio`.
+    pub async fn connect_stdio(&mut self, name: &str) -> io::Result<()> {
+        match (self.child.stdin.take(), self.child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => stdio::connect(name, stdin, stdout).await,
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "The standard input and output of QEMU are not available",
+            )),
+        }
+    }
+
+    /// Takes the standard output of QEMU, if captured with [`QemuCommandBuilder::capture_output`].
+    /// With [`QemuCommandBuilder::qtest_stdio`], it carries the qtest protocol instead.
+    ///
+    /// It should be read while QEMU runs, otherwise QEMU blocks once the pipe is full.
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    /// Takes the standard error of QEMU, if captured with [`QemuCommandBuilder::capture_output`].
+    ///
+    /// It should be read while QEMU runs, otherwise QEMU blocks once the pipe is full.
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
+    /// Returns the OS identifier of the process, or `None` if it has exited
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// Returns the record/replay mode of the run, if any
+    pub fn rr_mode(&self) -> Option<RrMode> {
+        self.rr
+    }
+
+    /// Returns the name of the container QEMU runs in, if any
+    pub fn container_name(&self) -> Option<&str> {
+        self.container.as_ref().map(|(_, name)| name.as_str())
+    }
+
+    /// Kills QEMU and waits for it to exit, removing its container
+    pub async fn kill(&mut self) -> io::Result<()> {
+        if let Some((runtime, name)) = self.container.take() {
+            // Killing the client of the runtime does not stop the container
+            runtime.remove(&name).await?;
+        }
+        self.child.kill().await
+    }
+
+    /// Waits for QEMU to exit
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+}
+
+impl Drop for QemuInstance {
+    fn drop(&mut self) {
+        if let Some((runtime, name)) = self.container.take() {
+            runtime.remove_detached(&name);
+        }
+    }
+}
+
+/// Spawns the given command running QEMU, killed on drop, capturing its output if requested by the builder
+/// and piping its standard input and output if they carry the qtest protocol
+fn spawn_child(builder: &QemuCommandBuilder, mut command: Command) -> io::Result<Child> {
+    if builder.capture_output {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    if builder.qtest_socket == Some(QtestSocket::Stdio) {
+        command.stdin(Stdio::piped()).stdout(Stdio::piped());
+    }
+    command.kill_on_drop(true).spawn()
+}
+
+/// Socket of this crate the qtest socket of QEMU points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum QtestSocket {
+    Tcp(String, Mode),
+    Unix(PathBuf, Mode),
+    Stdio,
+}
+
+/// Returns the QEMU character device of a socket of this crate.
+///
+/// If this crate listens, QEMU connects as a client. If this crate connects, QEMU listens without waiting.
+fn chardev(backend: &str, addr: &str, mode: Mode) -> String {
+    match mode {
+        Mode::Listen => format!("{backend}:{addr}"),
+        Mode::Connect => format!("{backend}:{addr},server=on,wait=off"),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_args() {
+        let builder = QemuCommandBuilder::new("qemu-system-arm")
+            .machine("netduino2")
+            .cpu("cortex-m3")
+            .device("loader,file=fw.elf")
+            .qtest_unix("/tmp/qtest.sock", Mode::Connect)
+            .icount(Icount::fixed(0))
+            .args(["-nographic"]);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            [
+                "-M",
+                "netduino2",
+                "-cpu",
+                "cortex-m3",
+                "-device",
+                "loader,file=fw.elf",
+                "-qtest",
+                "unix:/tmp/qtest.sock,server=on,wait=off",
+                "-icount",
+                "shift=0,sleep=off,align=off",
+                "-nographic",
+            ]
+        );
+        let builder =
+            QemuCommandBuilder::new("qemu-system-arm").qtest_tcp("localhost:3000", Mode::Listen);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            ["-qtest", "tcp:localhost:3000"]
+        );
+
+        let builder = builder.replay("run.rr");
+        assert!(builder.build_args().is_err());
+        let builder = builder.icount(Icount::auto());
+        assert_eq!(
+            builder.build_args().unwrap()[3],
+            "shift=auto,sleep=on,align=off,rr=replay,rrfile=run.rr"
+        );
+    }
+
+    #[cfg(all(unix, feature = "tcp"))]
+    #[tokio::test]
+    async fn test_launch() {
+        use crate::socket::tcp::SocketTcp;
+        use tokio::io::AsyncReadExt;
+
+        // A fake QEMU that exits without connecting
+        let builder = QemuCommandBuilder::new("sh")
+            .args(["-c", "echo booting; exit 3"])
+            .capture_output(true);
+
+        let mut qemu = QemuInstance::spawn(&builder).unwrap();
+        let mut stdout = String::new();
+        let mut pipe = qemu.take_stdout().unwrap();
+        pipe.read_to_string(&mut stdout).await.unwrap();
+        assert_eq!(stdout, "booting\n");
+        assert!(qemu.take_stdout().is_none());
+        assert_eq!(qemu.wait().await.unwrap().code(), Some(3));
+
+        let parser = ParserBuilder::<SocketTcp>::new("127.0.0.1:0");
+        let err = QemuInstance::launch(&builder, parser).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_launch_stdio() {
+        use crate::socket::stdio::SocketStdio;
+
+        let fake = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/fake-qemu-stdio.sh"
+        );
+        let builder = QemuCommandBuilder::new(fake).qtest_stdio();
+        assert_eq!(builder.build_args().unwrap(), ["-qtest", "stdio"]);
+
+        let parser = ParserBuilder::<SocketStdio>::new("qemu-stdio");
+        let (mut qemu, mut parser, _irq_rx) = QemuInstance::launch(&builder, parser).await.unwrap();
+        assert_eq!(parser.readl(0x2000_0000).await.unwrap(), 0x2a);
+        let err = qemu.connect_stdio("qemu-stdio").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        qemu.kill().await.unwrap();
+    }
+}
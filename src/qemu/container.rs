@@ -0,0 +1,285 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::process::Command;
+
+use super::{invalid, QemuCommandBuilder, QtestSocket};
+use crate::socket::Mode;
+
+/// Container engine used to run QEMU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ContainerRuntime {
+    /// Docker (`docker`)
+    #[default]
+    Docker,
+    /// Podman (`podman`)
+    Podman,
+}
+
+impl ContainerRuntime {
+    /// Returns the program of the engine
+    pub fn program(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+
+    /// Removes the container with the given name, stopping it if it is running
+    pub(super) async fn remove(&self, name: &str) -> io::Result<()> {
+        let status = Command::new(self.program())
+            .args(["rm", "-f", name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} rm {name} failed with {status}",
+                self.program()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Removes the container with the given name without waiting, ignoring errors
+    pub(super) fn remove_detached(&self, name: &str) {
+        let _ = std::process::Command::new(self.program())
+            .args(["rm", "-f", name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+    }
+}
+
+/// Container image QEMU runs in, so hosts don't need QEMU installed and its version is pinned by the image.
+///
+/// The qtest socket is mapped out of the container:
+///
+/// - UNIX sockets are bind-mounted, with their directory at the same path inside the container.
+///   The path of the socket must be absolute.
+/// - TCP sockets of this crate that listen are reached through the network of the host (`--network host`).
+/// - TCP sockets of this crate that connect are reached through a published port, with QEMU listening on all
+///   the interfaces of the container.
+///
+/// The directory of the replay file of record/replay runs is bind-mounted the same way.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() {
+/// # use qtest::{qemu::{Container, ContainerRuntime, QemuCommandBuilder, QemuInstance}, socket::Mode};
+/// let container = Container::new(ContainerRuntime::Podman, "ghcr.io/example/qemu-arm:8.2.2");
+/// let builder = QemuCommandBuilder::new("qemu-system-arm")
+///     .machine("netduino2")
+///     .qtest_unix("/tmp/qtest/qtest.sock", Mode::Listen);
+/// let mut qemu = QemuInstance::spawn_in(&container, &builder).unwrap();
+/// // ...
+/// qemu.kill().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    runtime: ContainerRuntime,
+    image: String,
+    name: Option<String>,
+    mounts: Vec<(PathBuf, PathBuf)>,
+    args: Vec<String>,
+}
+
+impl Container {
+    /// Creates a container of the given image, run with the given engine
+    pub fn new(runtime: ContainerRuntime, image: &str) -> Self {
+        Self {
+            runtime,
+            image: image.to_string(),
+            name: None,
+            mounts: Vec::new(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Sets the name of the container, instead of a generated one.
+    /// Only one instance can run at a time with a fixed name.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Bind-mounts a host path into the container, e.g. the directory of the firmware
+    pub fn mount<P: AsRef<Path>, Q: AsRef<Path>>(mut self, host: P, container: Q) -> Self {
+        self.mounts.push((
+            host.as_ref().to_path_buf(),
+            container.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    /// Appends an argument of the engine, placed before the image (e.g. `--platform=linux/arm64`)
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Returns the engine
+    pub fn runtime(&self) -> ContainerRuntime {
+        self.runtime
+    }
+
+    /// Returns the image
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    /// Returns the command running QEMU with the command line of the given builder inside the container
+    pub fn command(&self, builder: &QemuCommandBuilder) -> io::Result<Command> {
+        self.command_named(builder, &self.container_name())
+    }
+
+    /// Returns the name of the next container, generating a unique one if not set
+    pub(super) fn container_name(&self) -> String {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        self.name.clone().unwrap_or_else(|| {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            format!("qtest-qemu-{}-{n}", std::process::id())
+        })
+    }
+
+    /// Returns the command running QEMU inside a container with the given name
+    pub(super) fn command_named(
+        &self,
+        builder: &QemuCommandBuilder,
+        name: &str,
+    ) -> io::Result<Command> {
+        let mut command = Command::new(self.runtime.program());
+        command.args(self.build_args(builder, name)?);
+        Ok(command)
+    }
+
+    /// Returns the arguments of the engine, without its program
+    fn build_args(&self, builder: &QemuCommandBuilder, name: &str) -> io::Result<Vec<String>> {
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(),
+            name.to_string(),
+        ];
+        for (host, container) in &self.mounts {
+            args.extend(volume(host, container));
+        }
+
+        let mut builder = builder.clone();
+        match builder.qtest_socket.clone() {
+            Some(QtestSocket::Unix(path, _)) => args.extend(bind_parent(&path)?),
+            Some(QtestSocket::Tcp(_, Mode::Listen)) => {
+                args.extend(["--network".to_string(), "host".to_string()])
+            }
+            Some(QtestSocket::Tcp(addr, Mode::Connect)) => {
+                let (host, port) = addr
+                    .rsplit_once(':')
+                    .ok_or_else(|| invalid("The qtest address has no port"))?;
+                let publish = match host.trim_start_matches('[').trim_end_matches(']') {
+                    "" | "0.0.0.0" | "::" => format!("{port}:{port}"),
+                    "localhost" => format!("127.0.0.1:{port}:{port}"),
+                    ip => format!("{ip}:{port}:{port}"),
+                };
+                args.extend(["--publish".to_string(), publish]);
+                builder = builder.qtest_tcp(&format!("0.0.0.0:{port}"), Mode::Connect);
+            }
+            // Keeps the standard input of QEMU open
+            Some(QtestSocket::Stdio) => args.push("--interactive".to_string()),
+            None => {}
+        }
+        if let Some((_, rrfile)) = &builder.rr {
+            args.extend(bind_parent(rrfile)?);
+        }
+
+        args.extend(self.args.iter().cloned());
+        args.push(self.image.clone());
+        args.push(builder.binary.clone());
+        args.extend(builder.build_args()?);
+        Ok(args)
+    }
+}
+
+/// Returns the arguments bind-mounting the directory of the given path at the same path inside the container
+fn bind_parent(path: &Path) -> io::Result<[String; 2]> {
+    if !path.is_absolute() {
+        return Err(invalid(&format!(
+            "{} must be absolute to be mounted in a container",
+            path.display()
+        )));
+    }
+    let dir = path.parent().unwrap_or(path);
+    Ok(volume(dir, dir))
+}
+
+fn volume(host: &Path, container: &Path) -> [String; 2] {
+    [
+        "--volume".to_string(),
+        format!("{}:{}", host.display(), container.display()),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qemu::Icount;
+
+    #[test]
+    fn test_build_args() {
+        let container = Container::new(ContainerRuntime::Podman, "qemu:8.2").mount("/fw", "/fw");
+        let builder = QemuCommandBuilder::new("qemu-system-arm")
+            .machine("netduino2")
+            .qtest_unix("/tmp/qtest/qtest.sock", Mode::Listen)
+            .icount(Icount::fixed(0))
+            .record("/tmp/rr/run.rr");
+        assert_eq!(
+            container.build_args(&builder, "qemu").unwrap(),
+            [
+                "run",
+                "--rm",
+                "--name",
+                "qemu",
+                "--volume",
+                "/fw:/fw",
+                "--volume",
+                "/tmp/qtest:/tmp/qtest",
+                "--volume",
+                "/tmp/rr:/tmp/rr",
+                "qemu:8.2",
+                "qemu-system-arm",
+                "-M",
+                "netduino2",
+                "-qtest",
+                "unix:/tmp/qtest/qtest.sock",
+                "-icount",
+                "shift=0,sleep=off,align=off,rr=record,rrfile=/tmp/rr/run.rr",
+            ]
+        );
+
+        let container = Container::new(ContainerRuntime::Docker, "qemu:8.2");
+        let builder =
+            QemuCommandBuilder::new("qemu-system-arm").qtest_tcp("localhost:3000", Mode::Connect);
+        let args = container.build_args(&builder, "qemu").unwrap();
+        assert_eq!(args[4..6], ["--publish", "127.0.0.1:3000:3000"]);
+        assert_eq!(args[8..], ["-qtest", "tcp:0.0.0.0:3000,server=on,wait=off"]);
+
+        let builder = builder.qtest_tcp("localhost:3000", Mode::Listen);
+        let args = container.build_args(&builder, "qemu").unwrap();
+        assert_eq!(args[4..6], ["--network", "host"]);
+        assert_eq!(args[8..], ["-qtest", "tcp:localhost:3000"]);
+
+        let builder = builder.qtest_stdio();
+        let args = container.build_args(&builder, "qemu").unwrap();
+        assert_eq!(args[4], "--interactive");
+        assert_eq!(args[7..], ["-qtest", "stdio"]);
+
+        let builder = builder.qtest_unix("qtest.sock", Mode::Listen);
+        let err = container.build_args(&builder, "qemu").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
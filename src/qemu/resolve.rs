@@ -0,0 +1,363 @@
+use std::{
+    env, fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::process::Command;
+
+use super::QemuCommandBuilder;
+
+/// Environment variable with the path of the QEMU binary, as used by the QEMU qtest suite
+pub const QEMU_BINARY_ENV: &str = "QTEST_QEMU_BINARY";
+
+/// Version of QEMU, as reported by `qemu-system-* --version`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QemuVersion {
+    /// Major version
+    pub major: u32,
+    /// Minor version
+    pub minor: u32,
+    /// Patch version
+    pub patch: u32,
+}
+
+impl QemuVersion {
+    /// Creates a version from its components
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Extracts the version from the output of `--version` (e.g. `QEMU emulator version 8.2.2 (Debian ...)`)
+    pub fn from_version_output(output: &str) -> io::Result<Self> {
+        output
+            .lines()
+            .next()
+            .and_then(|line| line.split("version ").nth(1))
+            .and_then(|rest| rest.split_whitespace().next())
+            .ok_or_else(|| invalid_data(format!("Unexpected QEMU version output: {output}")))?
+            .parse()
+    }
+}
+
+impl FromStr for QemuVersion {
+    type Err = io::Error;
+
+    /// Parses `major[.minor[.patch]]`, ignoring any suffix of the patch (e.g. `8.2.50-rc1`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || invalid_data(format!("Invalid QEMU version: {s}"));
+        let mut parts = s.trim().splitn(3, '.');
+        let mut next = |required: bool| match parts.next() {
+            Some(part) => {
+                let digits = part
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(part, |end| &part[..end]);
+                digits.parse().map_err(|_| invalid())
+            }
+            None if required => Err(invalid()),
+            None => Ok(0),
+        };
+        Ok(Self::new(next(true)?, next(false)?, next(false)?))
+    }
+}
+
+impl fmt::Display for QemuVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Range of accepted QEMU versions, such as `>=8.0, <9`.
+///
+/// The range is a comma-separated list of comparisons (`>=`, `>`, `<=`, `<` or `=`) that must all hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct VersionReq {
+    comparators: Vec<(Op, QemuVersion)>,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Op {
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Eq,
+}
+
+impl VersionReq {
+    /// Returns true if the given version is in the range
+    pub fn matches(&self, version: &QemuVersion) -> bool {
+        self.comparators.iter().all(|(op, bound)| match op {
+            Op::Ge => version >= bound,
+            Op::Gt => version > bound,
+            Op::Le => version <= bound,
+            Op::Lt => version < bound,
+            Op::Eq => version == bound,
+        })
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut comparators = Vec::new();
+        for comparator in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let (op, version) = [
+                (">=", Op::Ge),
+                ("<=", Op::Le),
+                (">", Op::Gt),
+                ("<", Op::Lt),
+                ("=", Op::Eq),
+            ]
+            .into_iter()
+            .find_map(|(prefix, op)| comparator.strip_prefix(prefix).map(|v| (op, v)))
+            .unwrap_or((Op::Eq, comparator));
+            comparators.push((op, version.parse()?));
+        }
+        Ok(Self {
+            comparators,
+            text: s.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// QEMU binary found by a [`QemuResolver`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResolvedQemu {
+    /// Path of the binary
+    pub path: PathBuf,
+    /// Version of the binary
+    pub version: QemuVersion,
+}
+
+impl ResolvedQemu {
+    /// Returns a command line builder for the binary
+    pub fn command_builder(&self) -> QemuCommandBuilder {
+        QemuCommandBuilder::new(&self.path.to_string_lossy())
+    }
+}
+
+/// Locates the QEMU binary of a target and checks its version.
+///
+/// The binary is looked up, in order, in the [`QEMU_BINARY_ENV`] environment variable, in the configured path,
+/// and as `qemu-system-<arch>` in the directories of `PATH`. The first binary found must be in the required
+/// version range, otherwise resolution fails instead of silently using another QEMU.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() {
+/// # use qtest::qemu::QemuResolver;
+/// let qemu = QemuResolver::new("arm")
+///     .version(">=8.0, <10".parse().unwrap())
+///     .resolve()
+///     .await
+///     .unwrap();
+/// let builder = qemu.command_builder().machine("netduino2");
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuResolver {
+    name: String,
+    env_var: String,
+    binary: Option<PathBuf>,
+    version: VersionReq,
+}
+
+impl QemuResolver {
+    /// Creates a resolver for the `qemu-system-<arch>` binary, accepting any version
+    pub fn new(arch: &str) -> Self {
+        Self {
+            name: format!("qemu-system-{arch}"),
+            env_var: QEMU_BINARY_ENV.to_string(),
+            binary: None,
+            version: VersionReq::default(),
+        }
+    }
+
+    /// Creates a resolver with the QEMU binary and version range of the given configuration.
+    ///
+    /// A configured binary name without path separators (e.g. `qemu-system-arm`) is searched in `PATH`.
+    #[cfg(feature = "config")]
+    pub fn from_config(arch: &str, config: &crate::config::QemuConfig) -> io::Result<Self> {
+        let mut resolver = Self::new(arch);
+        match config.binary.as_deref() {
+            Some(binary) if binary.contains(std::path::MAIN_SEPARATOR) => {
+                resolver = resolver.binary(binary)
+            }
+            Some(binary) => resolver.name = binary.to_string(),
+            None => {}
+        }
+        if let Some(version) = &config.version {
+            resolver = resolver.version(version.parse()?);
+        }
+        Ok(resolver)
+    }
+
+    /// Sets the environment variable checked first, instead of [`QEMU_BINARY_ENV`]
+    pub fn env_var(mut self, name: &str) -> Self {
+        self.env_var = name.to_string();
+        self
+    }
+
+    /// Sets the path of the binary, used if the environment variable is not set
+    pub fn binary<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.binary = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the range of accepted versions
+    pub fn version(mut self, version: VersionReq) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Returns the name of the binary searched in `PATH`
+    pub fn binary_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Locates the binary and checks its version.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if no binary is found,
+    /// and of kind [`io::ErrorKind::Unsupported`] if its version is not in the required range.
+    pub async fn resolve(&self) -> io::Result<ResolvedQemu> {
+        let path = self.locate()?;
+        let output = Command::new(&path).arg("--version").output().await?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} --version failed with {}",
+                path.display(),
+                output.status
+            )));
+        }
+        let version = QemuVersion::from_version_output(&String::from_utf8_lossy(&output.stdout))?;
+        if !self.version.matches(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} is QEMU {version}, but {} is required (set {} to use another binary)",
+                    path.display(),
+                    self.version,
+                    self.env_var
+                ),
+            ));
+        }
+        Ok(ResolvedQemu { path, version })
+    }
+
+    /// Returns the path of the binary, without checking its version
+    fn locate(&self) -> io::Result<PathBuf> {
+        if let Some(path) = env::var_os(&self.env_var).filter(|p| !p.is_empty()) {
+            return Ok(PathBuf::from(path));
+        }
+        if let Some(path) = &self.binary {
+            return Ok(path.clone());
+        }
+        let name = &self.name;
+        env::var_os("PATH")
+            .iter()
+            .flat_map(env::split_paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "{name} not found: {} is not set, no binary is configured and it is not in PATH",
+                        self.env_var
+                    ),
+                )
+            })
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        let output =
+            "QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1)\nCopyright (c) 2003-2023";
+        let version = QemuVersion::from_version_output(output).unwrap();
+        assert_eq!(version, QemuVersion::new(8, 2, 2));
+        assert_eq!(version.to_string(), "8.2.2");
+        assert_eq!(
+            "9".parse::<QemuVersion>().unwrap(),
+            QemuVersion::new(9, 0, 0)
+        );
+        assert_eq!(
+            "8.2.50-rc1".parse::<QemuVersion>().unwrap(),
+            QemuVersion::new(8, 2, 50)
+        );
+        assert!("abc".parse::<QemuVersion>().is_err());
+        assert!(QemuVersion::from_version_output("qemu").is_err());
+    }
+
+    #[test]
+    fn test_version_req() {
+        let req: VersionReq = ">=8.0, <9".parse().unwrap();
+        assert!(req.matches(&QemuVersion::new(8, 2, 2)));
+        assert!(!req.matches(&QemuVersion::new(9, 0, 0)));
+        assert!(!req.matches(&QemuVersion::new(7, 2, 0)));
+        assert_eq!(req.to_string(), ">=8.0, <9");
+
+        let req: VersionReq = "8.1.0".parse().unwrap();
+        assert!(req.matches(&QemuVersion::new(8, 1, 0)));
+        assert!(!req.matches(&QemuVersion::new(8, 1, 1)));
+        assert!(VersionReq::default().matches(&QemuVersion::new(2, 0, 0)));
+        assert!(">=x".parse::<VersionReq>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resolve() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("qemu-resolve-{}", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\necho 'QEMU emulator version 8.2.2'\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let resolver = QemuResolver::new("arm")
+            .env_var("QTEST_RESOLVE_UNSET")
+            .binary(&path);
+        let qemu = resolver
+            .clone()
+            .version(">=8".parse().unwrap())
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(qemu.path, path);
+        assert_eq!(qemu.version, QemuVersion::new(8, 2, 2));
+
+        let err = resolver
+            .version(">=9".parse().unwrap())
+            .resolve()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        std::fs::remove_file(path).unwrap();
+
+        let err = QemuResolver::new("does-not-exist")
+            .env_var("QTEST_RESOLVE_UNSET")
+            .locate()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,416 @@
+use serde_json::{json, Map, Value};
+use std::{fmt, io, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{broadcast, mpsc},
+};
+
+use crate::logging::log_warn;
+
+/// Capacity of the channel of QMP events
+const QMP_EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// Asynchronous event sent by QEMU over QMP, e.g. `STOP`, `RESUME` or `SHUTDOWN`
+#[derive(Debug, Clone, PartialEq)]
+pub struct QmpEvent {
+    /// Name of the event
+    pub name: String,
+    /// Data of the event, `null` if it has none
+    pub data: Value,
+    /// Host time of the event since the UNIX epoch, if QEMU sent it
+    pub timestamp: Option<Duration>,
+}
+
+impl QmpEvent {
+    /// Returns the event of the given QMP message, if it is one
+    fn from_message(message: &Value) -> Option<Self> {
+        let name = message.get("event")?.as_str()?.to_string();
+        let timestamp = message.get("timestamp").and_then(|t| {
+            let secs = t.get("seconds")?.as_u64()?;
+            let micros = t.get("microseconds")?.as_u64()?;
+            Some(Duration::from_secs(secs) + Duration::from_micros(micros))
+        });
+        Some(Self {
+            name,
+            data: message.get("data").cloned().unwrap_or(Value::Null),
+            timestamp,
+        })
+    }
+}
+
+/// Client of the QMP monitor of QEMU, used alongside the qtest socket to control the machine
+/// (`quit`, `stop`, `device_add`, `qom-get`...).
+///
+/// QEMU must be started with a QMP monitor, e.g. `-qmp tcp:localhost:4444,server=on,wait=off`.
+/// The client performs the capabilities negotiation when connecting. Commands are sent one at a time,
+/// and the events sent by QEMU meanwhile are delivered to the receivers of [`QmpClient::events`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::qmp::QmpClient;
+/// let mut qmp = QmpClient::connect_tcp("localhost:4444").await.unwrap();
+///
+/// let model = qmp.qom_get("/machine", "type").await.unwrap();
+/// qmp.stop().await.unwrap();
+/// qmp.quit().await.unwrap();
+/// # }
+/// ```
+pub struct QmpClient {
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    responses: mpsc::Receiver<Value>,
+    events: broadcast::Sender<QmpEvent>,
+    greeting: Value,
+    next_id: u64,
+}
+
+impl fmt::Debug for QmpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QmpClient")
+            .field("greeting", &self.greeting)
+            .field("next_id", &self.next_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl QmpClient {
+    /// Connects to the QMP monitor listening at the given TCP address
+    #[cfg(feature = "tcp")]
+    pub async fn connect_tcp(addr: &str) -> io::Result<Self> {
+        Self::handshake(tokio::net::TcpStream::connect(addr).await?).await
+    }
+
+    /// Connects to the QMP monitor listening at the given UNIX socket path
+    #[cfg(feature = "unix")]
+    pub async fn connect_unix(path: &str) -> io::Result<Self> {
+        Self::handshake(tokio::net::UnixStream::connect(path).await?).await
+    }
+
+    /// Negotiates the capabilities with the QMP monitor at the other end of the given stream.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if the peer does not greet like a QMP monitor.
+    pub async fn handshake<S>(stream: S) -> io::Result<Self>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "QMP connection closed before the greeting",
+            ));
+        }
+        let greeting = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|message| message.get("QMP").cloned())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid QMP greeting: {}", line.trim()),
+                )
+            })?;
+
+        let (tx_response, rx_response) = mpsc::channel(1);
+        let (tx_event, _) = broadcast::channel(QMP_EVENT_CHANNEL_CAPACITY);
+        tokio::spawn(read_messages(reader, tx_response, tx_event.clone()));
+
+        let mut client = Self {
+            writer: Box::new(writer),
+            responses: rx_response,
+            events: tx_event,
+            greeting,
+            next_id: 0,
+        };
+        client.execute("qmp_capabilities", None).await?;
+        Ok(client)
+    }
+
+    /// Returns the greeting of the QMP monitor, with the QEMU version and the capabilities it offers
+    pub fn greeting(&self) -> &Value {
+        &self.greeting
+    }
+
+    /// Returns a receiver of the events sent by QEMU from now on
+    pub fn events(&self) -> broadcast::Receiver<QmpEvent> {
+        self.events.subscribe()
+    }
+
+    /// Executes the given QMP command with the given arguments, and returns the value it returned.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Other`] with the class and description of the error
+    /// if QEMU rejected the command, and of kind [`io::ErrorKind::BrokenPipe`] if the connection was closed.
+    pub async fn execute(&mut self, command: &str, arguments: Option<Value>) -> io::Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut message = json!({ "execute": command, "id": id });
+        if let Some(arguments) = arguments {
+            message["arguments"] = arguments;
+        }
+        self.writer
+            .write_all(format!("{message}\n").as_bytes())
+            .await?;
+        self.writer.flush().await?;
+
+        loop {
+            let response = self.responses.recv().await.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "QMP connection closed")
+            })?;
+            // Responses of abandoned commands, e.g. after a timeout, are discarded
+            if response.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            if let Some(error) = response.get("error") {
+                let class = error.get("class").and_then(Value::as_str);
+                let desc = error.get("desc").and_then(Value::as_str);
+                return Err(io::Error::other(format!(
+                    "QMP {command} failed: {}: {}",
+                    class.unwrap_or("GenericError"),
+                    desc.unwrap_or_default()
+                )));
+            }
+            return Ok(response.get("return").cloned().unwrap_or(Value::Null));
+        }
+    }
+
+    /// Stops the VM (`stop`)
+    pub async fn stop(&mut self) -> io::Result<()> {
+        self.execute("stop", None).await.map(|_| ())
+    }
+
+    /// Resumes the VM (`cont`)
+    pub async fn cont(&mut self) -> io::Result<()> {
+        self.execute("cont", None).await.map(|_| ())
+    }
+
+    /// Resets the machine (`system_reset`)
+    pub async fn system_reset(&mut self) -> io::Result<()> {
+        self.execute("system_reset", None).await.map(|_| ())
+    }
+
+    /// Terminates QEMU (`quit`)
+    pub async fn quit(&mut self) -> io::Result<()> {
+        self.execute("quit", None).await.map(|_| ())
+    }
+
+    /// Returns the run state of the VM (`query-status`), e.g. `running` or `paused`
+    pub async fn query_status(&mut self) -> io::Result<String> {
+        let status = self.execute("query-status", None).await?;
+        status
+            .get("status")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid QMP status"))
+    }
+
+    /// Returns the value of the given property of the QOM object at the given path (`qom-get`)
+    pub async fn qom_get(&mut self, path: &str, property: &str) -> io::Result<Value> {
+        let arguments = json!({ "path": path, "property": property });
+        self.execute("qom-get", Some(arguments)).await
+    }
+
+    /// Sets the given property of the QOM object at the given path (`qom-set`)
+    pub async fn qom_set(&mut self, path: &str, property: &str, value: Value) -> io::Result<()> {
+        let arguments = json!({ "path": path, "property": property, "value": value });
+        self.execute("qom-set", Some(arguments)).await.map(|_| ())
+    }
+
+    /// Adds a device of the given driver with the given identifier and properties (`device_add`)
+    pub async fn device_add(
+        &mut self,
+        driver: &str,
+        id: &str,
+        properties: Map<String, Value>,
+    ) -> io::Result<()> {
+        let mut arguments = properties;
+        arguments.insert("driver".to_string(), driver.into());
+        arguments.insert("id".to_string(), id.into());
+        self.execute("device_add", Some(Value::Object(arguments)))
+            .await
+            .map(|_| ())
+    }
+
+    /// Removes the device with the given identifier (`device_del`)
+    pub async fn device_del(&mut self, id: &str) -> io::Result<()> {
+        let arguments = json!({ "id": id });
+        self.execute("device_del", Some(arguments))
+            .await
+            .map(|_| ())
+    }
+}
+
+/// Reads the messages of the QMP monitor until the connection is closed,
+/// forwarding the responses to the client and broadcasting the events
+async fn read_messages<R: AsyncRead + Unpin>(
+    mut reader: BufReader<R>,
+    responses: mpsc::Sender<Value>,
+    events: broadcast::Sender<QmpEvent>,
+) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) => {
+                log_warn!(target: "qtest::qmp", "connection error: {e}");
+                return;
+            }
+        }
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            log_warn!(target: "qtest::qmp", "invalid message: {}", line.trim());
+            continue;
+        };
+        match QmpEvent::from_message(&message) {
+            Some(event) => {
+                let _ = events.send(event);
+            }
+            None => {
+                if responses.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+
+    /// Fake QMP monitor at the other end of the client
+    struct Monitor {
+        reader: BufReader<ReadHalf<DuplexStream>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl Monitor {
+        async fn send(&mut self, message: Value) {
+            let line = format!("{message}\n");
+            self.writer.write_all(line.as_bytes()).await.unwrap();
+        }
+
+        /// Receives a command, checks its name and arguments and returns its identifier
+        async fn expect(&mut self, command: &str, arguments: Option<Value>) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).await.unwrap();
+            let message: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(message["execute"], command);
+            assert_eq!(message.get("arguments").cloned(), arguments);
+            message["id"].clone()
+        }
+
+        async fn reply(&mut self, command: &str, arguments: Option<Value>, response: Value) {
+            let id = self.expect(command, arguments).await;
+            let mut response = response;
+            response["id"] = id;
+            self.send(response).await;
+        }
+    }
+
+    async fn connect() -> (QmpClient, Monitor) {
+        let (client, server) = tokio::io::duplex(4096);
+        let (reader, writer) = tokio::io::split(server);
+        let mut monitor = Monitor {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        let greeting =
+            json!({ "QMP": { "version": { "qemu": { "major": 9 } }, "capabilities": [] } });
+        monitor.send(greeting).await;
+        let (client, _) = tokio::join!(
+            QmpClient::handshake(client),
+            monitor.reply("qmp_capabilities", None, json!({ "return": {} }))
+        );
+        (client.unwrap(), monitor)
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let (client, _monitor) = connect().await;
+        assert_eq!(client.greeting()["version"]["qemu"]["major"], 9);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let (_, mut writer) = tokio::io::split(server);
+        writer.write_all(b"OK\n").await.unwrap();
+        let err = QmpClient::handshake(client).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let (mut client, mut monitor) = connect().await;
+
+        let arguments = json!({ "path": "/machine", "property": "type" });
+        let (value, _) = tokio::join!(
+            client.qom_get("/machine", "type"),
+            monitor.reply(
+                "qom-get",
+                Some(arguments),
+                json!({ "return": "stm32vldiscovery-machine" })
+            )
+        );
+        assert_eq!(value.unwrap(), "stm32vldiscovery-machine");
+
+        let arguments = json!({ "driver": "usb-kbd", "id": "kbd0", "bus": "usb.0" });
+        let mut properties = Map::new();
+        properties.insert("bus".to_string(), "usb.0".into());
+        let error = json!({ "error": { "class": "GenericError", "desc": "Bus not found" } });
+        let (result, _) = tokio::join!(
+            client.device_add("usb-kbd", "kbd0", properties),
+            monitor.reply("device_add", Some(arguments), error)
+        );
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            err.to_string(),
+            "QMP device_add failed: GenericError: Bus not found"
+        );
+
+        let (status, _) = tokio::join!(
+            client.query_status(),
+            monitor.reply(
+                "query-status",
+                None,
+                json!({ "return": { "running": false, "status": "paused" } })
+            )
+        );
+        assert_eq!(status.unwrap(), "paused");
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let (mut client, mut monitor) = connect().await;
+        let mut events = client.events();
+
+        let stop = json!({
+            "event": "STOP",
+            "timestamp": { "seconds": 10, "microseconds": 500 }
+        });
+        let monitor_side = async {
+            let id = monitor.expect("stop", None).await;
+            // Events can arrive before the response of the command
+            monitor.send(stop).await;
+            monitor.send(json!({ "return": {}, "id": id })).await;
+        };
+        let (result, _) = tokio::join!(client.stop(), monitor_side);
+        result.unwrap();
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.name, "STOP");
+        assert_eq!(event.data, Value::Null);
+        assert_eq!(
+            event.timestamp,
+            Some(Duration::from_secs(10) + Duration::from_micros(500))
+        );
+
+        drop(monitor);
+        let err = client.cont().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{io, ops::Range};
+
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::protocol::{Command, Width};
+use crate::socket::Socket;
+use crate::transfer::{read_chunked, write_chunked};
+use crate::Response;
+
+/// Unsigned integer read or written with a single access of its width by [`MemRegion::read_at`]
+/// and [`MemRegion::write_at`]
+pub trait RegValue: Copy {
+    /// Width of the access
+    const WIDTH: Width;
+
+    /// Converts the value of an access, which fits in the width
+    fn from_u64(val: u64) -> Self;
+
+    /// Converts the value to the value of an access
+    fn to_u64(self) -> u64;
+}
+
+macro_rules! impl_reg_value {
+    ($($ty:ty => $width:expr),*) => {
+        $(
+            impl RegValue for $ty {
+                const WIDTH: Width = $width;
+
+                fn from_u64(val: u64) -> Self {
+                    val as $ty
+                }
+
+                fn to_u64(self) -> u64 {
+                    self.into()
+                }
+            }
+        )*
+    };
+}
+
+impl_reg_value!(u8 => Width::Byte, u16 => Width::Word, u32 => Width::Long, u64 => Width::Quad);
+
+/// Block of guest memory, such as the registers of a peripheral, accessed by offset.
+///
+/// Every access is checked to lie within the block, so an offset meant for one peripheral
+/// can never reach the registers of another one. Obtained from a board description with
+/// [`crate::board::Peripheral::region`], or created with [`MemRegion::new`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, region::MemRegion, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let uart = MemRegion::new(0x4001_1000, 0x400);
+/// let status = uart.read_at::<u32>(&mut parser, 0x0).await.unwrap();
+/// uart.write_at(&mut parser, 0x4, b'A').await.unwrap();
+/// // Out of the block: returns an error instead of touching the next peripheral
+/// assert!(uart.read_at::<u32>(&mut parser, 0x400).await.is_err());
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemRegion {
+    base: usize,
+    len: usize,
+}
+
+impl MemRegion {
+    /// Creates a region of the given number of bytes from the given base address
+    pub fn new(base: usize, len: usize) -> Self {
+        Self { base, len }
+    }
+
+    /// Returns the base address of the region
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Returns the size in bytes of the region
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the region is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the range of addresses of the region
+    pub fn range(&self) -> Range<usize> {
+        self.base..self.base + self.len
+    }
+
+    /// Returns the absolute address of the given number of bytes at the given offset.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if they are not all within the region.
+    pub fn addr(&self, offset: usize, len: usize) -> Result<usize, QtestError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(self.base + offset),
+            _ => Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{len} bytes at offset {offset:#x} are out of the {:#x} bytes region at {:#x}",
+                    self.len, self.base
+                ),
+            ))),
+        }
+    }
+
+    /// Returns the sub-region of the given number of bytes at the given offset, checked as [`MemRegion::addr`]
+    pub fn subregion(&self, offset: usize, len: usize) -> Result<Self, QtestError> {
+        Ok(Self::new(self.addr(offset, len)?, len))
+    }
+
+    /// Reads a value at the given offset with a single access of its width
+    pub async fn read_at<V: RegValue>(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+    ) -> Result<V, QtestError> {
+        let addr = self.addr(offset, V::WIDTH.bytes())?;
+        read_value(parser, V::WIDTH, addr).await.map(V::from_u64)
+    }
+
+    /// Writes a value at the given offset with a single access of its width
+    pub async fn write_at<V: RegValue>(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+        val: V,
+    ) -> Result<(), QtestError> {
+        let addr = self.addr(offset, V::WIDTH.bytes())?;
+        write_value(parser, V::WIDTH, addr, val.to_u64()).await
+    }
+
+    /// Reads the given number of bytes at the given offset with bulk transfers
+    pub async fn read_bytes(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, QtestError> {
+        let addr = self.addr(offset, len)?;
+        read_chunked(parser, addr, len, |_, _| {}).await
+    }
+
+    /// Writes the given bytes at the given offset with bulk transfers
+    pub async fn write_bytes(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), QtestError> {
+        let addr = self.addr(offset, data.len())?;
+        write_chunked(parser, addr, data, |_, _| {}).await
+    }
+}
+
+/// Reads a value at the given address with a single access of the given width
+pub(crate) async fn read_value(
+    parser: &mut Parser<impl Socket>,
+    width: Width,
+    addr: usize,
+) -> Result<u64, QtestError> {
+    let response = parser.execute(Command::ReadValue { width, addr }).await?;
+    match response {
+        Response::OkVal(val) => {
+            u64::from_str_radix(val.trim_start_matches("0x"), 16).map_err(|e| {
+                QtestError::Parse(format!("Could not parse value: {}\n error {}", val, e))
+            })
+        }
+        Response::Err(e) => Err(QtestError::Fail(e)),
+        _ => Err(QtestError::Protocol("Invalid response".into())),
+    }
+}
+
+/// Writes a value at the given address with a single access of the given width
+pub(crate) async fn write_value(
+    parser: &mut Parser<impl Socket>,
+    width: Width,
+    addr: usize,
+    val: u64,
+) -> Result<(), QtestError> {
+    let response = parser
+        .execute(Command::WriteValue { width, addr, val })
+        .await?;
+    match response {
+        Response::Ok => Ok(()),
+        Response::Err(e) => Err(QtestError::Fail(e)),
+        response => Err(QtestError::Protocol(format!(
+            "Invalid response: {response}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::dry_run::SocketDryRun;
+
+    #[test]
+    fn test_bounds() {
+        let region = MemRegion::new(0x4000_0000, 0x100);
+        assert_eq!(region.addr(0xfc, 4).unwrap(), 0x4000_00fc);
+        assert_eq!(
+            region.addr(0xfd, 4).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(region.addr(usize::MAX, 2).is_err());
+
+        let sub = region.subregion(0x80, 0x10).unwrap();
+        assert_eq!(sub.range(), 0x4000_0080..0x4000_0090);
+        assert!(region.subregion(0x80, 0x81).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_access() {
+        let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+        parser.attach_connection().await.unwrap();
+        let region = MemRegion::new(0x4000_0000, 0x8);
+
+        assert_eq!(region.read_at::<u32>(&mut parser, 0x4).await.unwrap(), 0);
+        region.write_at(&mut parser, 0x6, 0x1234u16).await.unwrap();
+        assert!(region.read_at::<u64>(&mut parser, 0x4).await.is_err());
+        assert_eq!(region.read_bytes(&mut parser, 0, 8).await.unwrap(), [0; 8]);
+        assert!(region.write_bytes(&mut parser, 4, &[0; 5]).await.is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use std::{collections::BTreeMap, io};
+
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::protocol::Width;
+use crate::region::{read_value, write_value, MemRegion};
+use crate::socket::Socket;
+
+/// Access allowed to a register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// The register can only be read
+    ReadOnly,
+    /// The register can only be written
+    WriteOnly,
+    /// The register can be read and written
+    ReadWrite,
+}
+
+impl Access {
+    /// Returns true if the register can be read
+    pub fn readable(&self) -> bool {
+        matches!(self, Access::ReadOnly | Access::ReadWrite)
+    }
+
+    /// Returns true if the register can be written
+    pub fn writable(&self) -> bool {
+        matches!(self, Access::WriteOnly | Access::ReadWrite)
+    }
+}
+
+/// Bitfield of a register, as a number of contiguous bits from a shift
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Field {
+    /// Position of the least significant bit of the field
+    pub shift: u32,
+    /// Number of bits of the field
+    pub bits: u32,
+}
+
+impl Field {
+    /// Creates a field of the given number of bits from the given shift
+    pub const fn new(shift: u32, bits: u32) -> Self {
+        Self { shift, bits }
+    }
+
+    /// Creates a field of a single bit
+    pub const fn bit(shift: u32) -> Self {
+        Self::new(shift, 1)
+    }
+
+    /// Returns the mask of the field in the register
+    pub fn mask(&self) -> u64 {
+        let ones = match self.bits {
+            64.. => u64::MAX,
+            bits => (1 << bits) - 1,
+        };
+        ones << self.shift
+    }
+
+    /// Returns the value of the field in the given value of the register
+    pub fn extract(&self, reg: u64) -> u64 {
+        (reg & self.mask()) >> self.shift
+    }
+
+    /// Returns the given value of the register with the field set to the given value
+    pub fn insert(&self, reg: u64, val: u64) -> u64 {
+        (reg & !self.mask()) | ((val << self.shift) & self.mask())
+    }
+
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the field does not fit in the given width
+    fn check(&self, width: Width) -> Result<(), QtestError> {
+        let bits = width.bytes() as u32 * 8;
+        match self.shift.checked_add(self.bits) {
+            Some(end) if self.bits > 0 && end <= bits => Ok(()),
+            _ => Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Field {self:?} does not fit in a {bits}-bit register"),
+            ))),
+        }
+    }
+}
+
+/// Register of a [`RegisterMap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Register {
+    /// Offset of the register from the base address of the map
+    pub offset: usize,
+    /// Width of the accesses to the register
+    pub width: Width,
+    /// Access allowed to the register
+    pub access: Access,
+}
+
+/// Registers of a peripheral, accessed by name.
+///
+/// Every register is checked to lie within the register block when it is defined, and every access
+/// is checked against the access type and width of the register before any command is sent,
+/// so a typo in a register name or an oversized value never reaches QEMU.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, protocol::Width, regmap::{Access, RegisterMap}, socket::tcp::SocketTcp};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let timer = RegisterMap::new(0x4000_0000, 0x400)
+///     .register("CTRL", 0x00, Width::Long, Access::ReadWrite)
+///     .unwrap()
+///     .register("STATUS", 0x04, Width::Long, Access::ReadOnly)
+///     .unwrap();
+/// timer.write(&mut parser, "CTRL", 0x1).await.unwrap();
+/// let status = timer.read(&mut parser, "STATUS").await.unwrap();
+/// // Read-only register: returns an error instead of writing it
+/// assert!(timer.write(&mut parser, "STATUS", 0x0).await.is_err());
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterMap {
+    region: MemRegion,
+    registers: BTreeMap<String, Register>,
+}
+
+impl RegisterMap {
+    /// Creates an empty map of the register block of the given number of bytes from the given base address
+    pub fn new(base: usize, len: usize) -> Self {
+        Self::from_region(MemRegion::new(base, len))
+    }
+
+    /// Creates an empty map of the given register block
+    pub fn from_region(region: MemRegion) -> Self {
+        Self {
+            region,
+            registers: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the register of the given name at the given offset.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the register is not within the register block,
+    /// or of kind [`io::ErrorKind::AlreadyExists`] if there is already a register with the same name.
+    pub fn register(
+        mut self,
+        name: &str,
+        offset: usize,
+        width: Width,
+        access: Access,
+    ) -> Result<Self, QtestError> {
+        self.region.addr(offset, width.bytes())?;
+        if self.registers.contains_key(name) {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Register {name} is already defined"),
+            )));
+        }
+        let register = Register {
+            offset,
+            width,
+            access,
+        };
+        self.registers.insert(name.to_string(), register);
+        Ok(self)
+    }
+
+    /// Returns the register block of the map
+    pub fn region(&self) -> MemRegion {
+        self.region
+    }
+
+    /// Returns the register of the given name, if defined
+    pub fn get(&self, name: &str) -> Option<&Register> {
+        self.registers.get(name)
+    }
+
+    /// Returns an iterator over the names and registers of the map, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Register)> {
+        self.registers
+            .iter()
+            .map(|(name, reg)| (name.as_str(), reg))
+    }
+
+    /// Returns the address of the register of the given name
+    pub fn addr(&self, name: &str) -> Result<usize, QtestError> {
+        let register = self.lookup(name)?;
+        Ok(self.region.base() + register.offset)
+    }
+
+    /// Reads the register of the given name with a single access of its width.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the register is not defined,
+    /// or of kind [`io::ErrorKind::PermissionDenied`] if it cannot be read.
+    pub async fn read(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+    ) -> Result<u64, QtestError> {
+        let register = self.lookup(name)?;
+        if !register.access.readable() {
+            return Err(denied(name, "read"));
+        }
+        let addr = self.region.base() + register.offset;
+        read_value(parser, register.width, addr).await
+    }
+
+    /// Writes the register of the given name with a single access of its width.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the register is not defined,
+    /// of kind [`io::ErrorKind::PermissionDenied`] if it cannot be written,
+    /// or of kind [`io::ErrorKind::InvalidInput`] if the value does not fit in its width.
+    pub async fn write(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        val: u64,
+    ) -> Result<(), QtestError> {
+        let register = self.lookup(name)?;
+        if !register.access.writable() {
+            return Err(denied(name, "written"));
+        }
+        let bits = register.width.bytes() * 8;
+        if bits < 64 && val >> bits != 0 {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Value {val:#x} does not fit in the {bits}-bit register {name}"),
+            )));
+        }
+        let addr = self.region.base() + register.offset;
+        write_value(parser, register.width, addr, val).await
+    }
+
+    /// Reads the register of the given name, applies the given function to its value and writes the result back.
+    ///
+    /// The register must be readable and writable. Returns the value written.
+    pub async fn modify<F>(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        f: F,
+    ) -> Result<u64, QtestError>
+    where
+        F: FnOnce(u64) -> u64,
+    {
+        let val = f(self.read(parser, name).await?);
+        self.write(parser, name, val).await?;
+        Ok(val)
+    }
+
+    /// Reads the given field of the register of the given name
+    pub async fn read_field(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        field: Field,
+    ) -> Result<u64, QtestError> {
+        field.check(self.lookup(name)?.width)?;
+        Ok(field.extract(self.read(parser, name).await?))
+    }
+
+    /// Writes the given field of the register of the given name, keeping the rest of its bits.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the field does not fit in the register,
+    /// or the value does not fit in the field.
+    pub async fn write_field(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        field: Field,
+        val: u64,
+    ) -> Result<(), QtestError> {
+        field.check(self.lookup(name)?.width)?;
+        if field.extract(field.insert(0, val)) != val {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Value {val:#x} does not fit in the {}-bit field",
+                    field.bits
+                ),
+            )));
+        }
+        self.modify(parser, name, |reg| field.insert(reg, val))
+            .await
+            .map(|_| ())
+    }
+
+    /// Sets the bits of the given mask in the register of the given name, keeping the rest of its bits
+    pub async fn set_bits(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        mask: u64,
+    ) -> Result<(), QtestError> {
+        self.modify(parser, name, |reg| reg | mask)
+            .await
+            .map(|_| ())
+    }
+
+    /// Clears the bits of the given mask in the register of the given name, keeping the rest of its bits
+    pub async fn clear_bits(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        mask: u64,
+    ) -> Result<(), QtestError> {
+        self.modify(parser, name, |reg| reg & !mask)
+            .await
+            .map(|_| ())
+    }
+
+    /// Toggles the bits of the given mask in the register of the given name, keeping the rest of its bits
+    pub async fn toggle_bits(
+        &self,
+        parser: &mut Parser<impl Socket>,
+        name: &str,
+        mask: u64,
+    ) -> Result<(), QtestError> {
+        self.modify(parser, name, |reg| reg ^ mask)
+            .await
+            .map(|_| ())
+    }
+
+    /// Returns the register of the given name, or an error of kind [`io::ErrorKind::NotFound`]
+    fn lookup(&self, name: &str) -> Result<&Register, QtestError> {
+        self.registers.get(name).ok_or_else(|| {
+            QtestError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Register {name} is not defined"),
+            ))
+        })
+    }
+}
+
+/// Returns the error of an access not allowed to the given register
+fn denied(name: &str, action: &str) -> QtestError {
+    QtestError::Io(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Register {name} cannot be {action}"),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::dry_run::SocketDryRun;
+
+    fn timer() -> RegisterMap {
+        RegisterMap::new(0x4000_0000, 0x10)
+            .register("CTRL", 0x0, Width::Long, Access::ReadWrite)
+            .unwrap()
+            .register("STATUS", 0x4, Width::Word, Access::ReadOnly)
+            .unwrap()
+            .register("CLEAR", 0x8, Width::Byte, Access::WriteOnly)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_definition() {
+        let map = timer();
+        assert_eq!(map.addr("STATUS").unwrap(), 0x4000_0004);
+        assert_eq!(map.get("CLEAR").unwrap().width, Width::Byte);
+        let names: Vec<_> = map.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["CLEAR", "CTRL", "STATUS"]);
+
+        let err = timer()
+            .register("DATA", 0xe, Width::Long, Access::ReadWrite)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = timer()
+            .register("CTRL", 0xc, Width::Long, Access::ReadWrite)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_fields() {
+        let field = Field::new(4, 3);
+        assert_eq!(field.mask(), 0x70);
+        assert_eq!(field.extract(0xf5), 0x7);
+        assert_eq!(field.insert(0xff, 0x2), 0xaf);
+        assert_eq!(Field::new(0, 64).mask(), u64::MAX);
+        assert!(Field::new(30, 3).check(Width::Long).is_err());
+        assert!(Field::bit(31).check(Width::Long).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_checked_accesses() {
+        let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+        parser.attach_connection().await.unwrap();
+        let map = timer();
+
+        map.write(&mut parser, "CTRL", 0xffff_ffff).await.unwrap();
+        assert_eq!(map.read(&mut parser, "STATUS").await.unwrap(), 0);
+        map.write(&mut parser, "CLEAR", 0x1).await.unwrap();
+
+        assert_eq!(
+            map.write(&mut parser, "CTRL", 1 << 32)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            map.write(&mut parser, "STATUS", 0)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            map.read(&mut parser, "CLEAR").await.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            map.write(&mut parser, "CTLR", 0).await.unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
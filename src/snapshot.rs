@@ -0,0 +1,351 @@
+use std::{
+    io::{self, Read, Write},
+    ops::Range,
+    path::Path,
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+use crate::compression;
+use crate::error::QtestError;
+use crate::parser::{Parser, ParserHandle};
+use crate::socket::Socket;
+use crate::transfer::{read_chunked, write_chunked};
+
+/// Magic number at the beginning of snapshot files
+const SNAPSHOT_MAGIC: &[u8; 4] = b"QSNP";
+/// Version of the snapshot file format
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// Contents of a contiguous region of guest memory
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SnapshotRegion {
+    /// Start address of the region
+    pub addr: usize,
+    /// Contents of the region
+    pub data: Vec<u8>,
+}
+
+/// Contiguous run of bytes that changed between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemChange {
+    /// Address of the first changed byte
+    pub addr: usize,
+    /// Contents of the run in the older snapshot
+    pub before: Vec<u8>,
+    /// Contents of the run in the newer snapshot
+    pub after: Vec<u8>,
+}
+
+/// Snapshot of one or more regions of guest memory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct MemSnapshot {
+    regions: Vec<SnapshotRegion>,
+}
+
+impl MemSnapshot {
+    /// Creates a snapshot from already captured regions
+    pub fn new(regions: Vec<SnapshotRegion>) -> Self {
+        Self { regions }
+    }
+
+    /// Reads the given address ranges of guest memory and stores their contents in a new snapshot
+    pub async fn capture<T: Socket>(
+        parser: &mut Parser<T>,
+        ranges: &[Range<usize>],
+    ) -> Result<Self, QtestError> {
+        let mut regions = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let data = read_chunked(parser, range.start, range.len(), |_, _| {}).await?;
+            regions.push(SnapshotRegion {
+                addr: range.start,
+                data,
+            });
+        }
+        Ok(Self { regions })
+    }
+
+    /// Returns the captured regions
+    pub fn regions(&self) -> &[SnapshotRegion] {
+        &self.regions
+    }
+
+    /// Writes the contents of every region back to guest memory, rolling it back to the captured state
+    pub async fn restore<T: Socket>(&self, parser: &mut Parser<T>) -> Result<(), QtestError> {
+        for region in &self.regions {
+            write_chunked(parser, region.addr, &region.data, |_, _| {}).await?;
+        }
+        Ok(())
+    }
+
+    /// Serializes the snapshot in the snapshot file format.
+    ///
+    /// The format is the magic number `QSNP`, a version byte, the number of regions as a little endian `u32`
+    /// and, for every region, its address and length as little endian `u64` followed by its contents.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len: usize = self.regions.iter().map(|r| 16 + r.data.len()).sum();
+        let mut bytes = Vec::with_capacity(9 + len);
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec does not fail");
+        bytes
+    }
+
+    /// Deserializes a snapshot in the snapshot file format
+    pub fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+        Self::read_from(&mut bytes)
+    }
+
+    /// Writes the snapshot in the snapshot file format, see [`MemSnapshot::to_bytes`]
+    fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&[SNAPSHOT_VERSION])?;
+        writer.write_all(&(self.regions.len() as u32).to_le_bytes())?;
+        for region in &self.regions {
+            writer.write_all(&(region.addr as u64).to_le_bytes())?;
+            writer.write_all(&(region.data.len() as u64).to_le_bytes())?;
+            writer.write_all(&region.data)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot in the snapshot file format, see [`MemSnapshot::to_bytes`]
+    fn read_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut take = |n: usize| -> io::Result<Vec<u8>> {
+            // The length comes from the file, so the buffer grows with the data actually read
+            let mut data = Vec::new();
+            reader.take(n as u64).read_to_end(&mut data)?;
+            if data.len() < n {
+                return Err(invalid("truncated snapshot file"));
+            }
+            Ok(data)
+        };
+
+        if take(4)? != SNAPSHOT_MAGIC {
+            return Err(invalid("not a snapshot file"));
+        }
+        if take(1)?[0] != SNAPSHOT_VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+        let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let mut regions = Vec::new();
+        for _ in 0..count {
+            let addr = u64::from_le_bytes(take(8)?.try_into().unwrap()) as usize;
+            let len = u64::from_le_bytes(take(8)?.try_into().unwrap()) as usize;
+            let data = take(len)?;
+            regions.push(SnapshotRegion { addr, data });
+        }
+        Ok(Self { regions })
+    }
+
+    /// Writes the snapshot to the given file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        compression::create(path, |w| self.write_to(w))
+    }
+
+    /// Writes the snapshot to the given file, compressed with zstd at the given level (1-22, 0 for the default level)
+    #[cfg(feature = "zstd")]
+    pub fn save_compressed<P: AsRef<Path>>(&self, path: P, level: i32) -> io::Result<()> {
+        compression::create_compressed(path, level, |w| self.write_to(w))
+    }
+
+    /// Reads a snapshot from the given file, decompressing it if it was saved with compression
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(&mut compression::open(path)?)
+    }
+
+    /// Compares this snapshot with a newer one, returning the runs of bytes that changed.
+    ///
+    /// Only regions with the same start address in both snapshots are compared, up to the length of the shorter one.
+    pub fn diff(&self, other: &MemSnapshot) -> Vec<MemChange> {
+        let mut changes = Vec::new();
+        for before in &self.regions {
+            let Some(after) = other.regions.iter().find(|r| r.addr == before.addr) else {
+                continue;
+            };
+
+            let mut current: Option<MemChange> = None;
+            for (offset, (&b, &a)) in before.data.iter().zip(&after.data).enumerate() {
+                if a == b {
+                    changes.extend(current.take());
+                    continue;
+                }
+                let change = current.get_or_insert_with(|| MemChange {
+                    addr: before.addr + offset,
+                    before: Vec::new(),
+                    after: Vec::new(),
+                });
+                change.before.push(b);
+                change.after.push(a);
+            }
+            changes.extend(current);
+        }
+        changes
+    }
+}
+
+/// Checkpoints the given address ranges of guest memory, so they can be rolled back later with [`restore`]
+pub async fn save_region<T: Socket>(
+    parser: &mut Parser<T>,
+    ranges: &[Range<usize>],
+) -> Result<MemSnapshot, QtestError> {
+    MemSnapshot::capture(parser, ranges).await
+}
+
+/// Rolls guest memory back to the state captured in the given snapshot
+pub async fn restore<T: Socket>(
+    parser: &mut Parser<T>,
+    snapshot: &MemSnapshot,
+) -> Result<(), QtestError> {
+    snapshot.restore(parser).await
+}
+
+/// Spawns a poller that watches the given region of guest memory until its contents change,
+/// emulating a hardware watchpoint.
+///
+/// The region is read through the given handle every `interval` of host time, so other clients of the parser
+/// keep working while it is watched. If `clock_step` is given, the virtual clock is stepped by that number of
+/// nanoseconds before each poll, so the guest makes progress even when the clock is stopped.
+/// The task resolves with the changes between the initial contents and the first poll that differs,
+/// or with an error of kind [`io::ErrorKind::InvalidInput`] if the region wraps around the address space.
+///
+/// The poller runs until the region changes or a command fails; abort the returned task to stop watching earlier.
+pub fn watch_region(
+    handle: &ParserHandle,
+    addr: usize,
+    len: usize,
+    interval: Duration,
+    clock_step: Option<usize>,
+) -> JoinHandle<Result<Vec<MemChange>, QtestError>> {
+    let handle = handle.clone();
+    tokio::spawn(async move {
+        if addr.checked_add(len).is_none() {
+            return Err(QtestError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The region of {len} bytes at {addr:#x} wraps around the address space"),
+            )));
+        }
+        let capture = || async {
+            let data = handle.read_chunked(addr, len).await?;
+            Ok::<_, QtestError>(MemSnapshot::new(vec![SnapshotRegion { addr, data }]))
+        };
+        let initial = capture().await?;
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Some(ns) = clock_step {
+                handle.clock_step(Some(ns)).await?;
+            }
+            let changes = initial.diff(&capture().await?);
+            if !changes.is_empty() {
+                return Ok(changes);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_diff() {
+        let before = MemSnapshot::new(vec![
+            SnapshotRegion {
+                addr: 0x1000,
+                data: vec![0, 1, 2, 3, 4, 5],
+            },
+            SnapshotRegion {
+                addr: 0x2000,
+                data: vec![0xff; 4],
+            },
+        ]);
+        let after = MemSnapshot::new(vec![
+            SnapshotRegion {
+                addr: 0x1000,
+                data: vec![0, 9, 9, 3, 4, 8],
+            },
+            SnapshotRegion {
+                addr: 0x2000,
+                data: vec![0xff; 4],
+            },
+        ]);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                MemChange {
+                    addr: 0x1001,
+                    before: vec![1, 2],
+                    after: vec![9, 9],
+                },
+                MemChange {
+                    addr: 0x1005,
+                    before: vec![5],
+                    after: vec![8],
+                },
+            ]
+        );
+        assert!(after.diff(&after).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_watch_region() {
+        use crate::mock::MockQtestDevice;
+        use crate::socket::mem::{self, SocketPair};
+
+        let device = MockQtestDevice::new();
+        device.write_mem(0x2000, &[0, 1, 2, 3]);
+        let (mut parser, _irq_rx) = Parser::<SocketPair>::new("watch-region").await.unwrap();
+        device.serve(mem::connect("watch-region").await.unwrap());
+        parser.attach_connection().await.unwrap();
+        let handle = parser.into_handle();
+
+        let interval = Duration::from_millis(1);
+        let watch = watch_region(&handle, 0x2000, 4, interval, Some(100));
+        // The guest changes the region after the third poll
+        while device.clock_ns() < 300 {
+            tokio::time::sleep(interval).await;
+        }
+        device.write_mem(0x2002, &[9]);
+        let changes = watch.await.unwrap().unwrap();
+        assert_eq!(
+            changes,
+            [MemChange {
+                addr: 0x2002,
+                before: vec![2],
+                after: vec![9],
+            }]
+        );
+        assert!(device.clock_ns() >= 300);
+
+        // The handle is still usable while the region is watched
+        let watch = watch_region(&handle, 0x2000, 4, interval, None);
+        assert_eq!(handle.read_chunked(0x2000, 2).await.unwrap(), [0, 1]);
+        watch.abort();
+
+        let err = watch_region(&handle, usize::MAX, 2, interval, None)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_snapshot_bytes_round_trip() {
+        let snapshot = MemSnapshot::new(vec![
+            SnapshotRegion {
+                addr: 0x1000,
+                data: vec![0, 1, 2, 3],
+            },
+            SnapshotRegion {
+                addr: 0x2000,
+                data: Vec::new(),
+            },
+        ]);
+        let bytes = snapshot.to_bytes();
+        assert_eq!(MemSnapshot::from_bytes(&bytes).unwrap(), snapshot);
+
+        assert!(MemSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(MemSnapshot::from_bytes(b"QSNQ\x01\0\0\0\0").is_err());
+    }
+}
@@ -1,10 +1,51 @@
-use std::{io, str};
-use tokio::{io::AsyncReadExt, sync::mpsc};
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, watch};
 
+use crate::logging::{log_error, log_info, log_warn};
+
+pub mod dry_run;
+pub mod fault;
+pub mod mem;
+pub mod replay;
+#[cfg(all(feature = "ssh", unix))]
+pub mod ssh;
+pub mod stdio;
+#[cfg(feature = "tcp")]
 pub mod tcp;
+#[cfg(feature = "unix")]
 pub mod unix;
 
+/// Role of this crate when establishing the connection with QEMU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "lowercase"))]
+pub enum Mode {
+    /// Listen at the URL and wait for QEMU to connect (`-qtest <chardev>` without `server`)
+    #[default]
+    Listen,
+    /// Connect to QEMU listening at the URL (`-qtest <chardev>,server=on`).
+    /// Connections refused while QEMU starts are retried.
+    Connect,
+}
+
+/// State of the connection attached to a socket, as reported by [`Socket::link_state`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LinkState {
+    /// No connection was attached yet
+    #[default]
+    Detached,
+    /// A connection is attached
+    Attached,
+    /// The attached connection was closed by QEMU, e.g. because it exited or restarted
+    Closed,
+}
+
 /// Interface for the socket implementations.
+///
+/// Sockets only move bytes, so their methods return [`io::Result`] rather than [`crate::error::QtestError`],
+/// and implementations outside the crate need no qtest types. The parser wraps their errors in
+/// [`crate::error::QtestError::Socket`], keeping the original [`io::Error`] and its kind.
 pub trait Socket {
     /// Creates a new socket instance.
     ///
@@ -17,13 +58,45 @@ pub trait Socket {
     where
         Self: Sized;
 
+    /// Creates a new socket instance that listens or connects according to the given mode.
+    ///
+    /// The default implementation only supports [`Mode::Listen`], creating the socket with [`Socket::new`].
+    fn with_mode(
+        url: &str,
+        out_handler: mpsc::Sender<String>,
+        mode: Mode,
+    ) -> impl std::future::Future<Output = io::Result<Self>> + Send
+    where
+        Self: Sized,
+    {
+        async move {
+            match mode {
+                Mode::Listen => Self::new(url, out_handler).await,
+                Mode::Connect => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "The socket does not support connect mode",
+                )),
+            }
+        }
+    }
+
     /// Attaches a connection to the socket.
     ///
+    /// In [`Mode::Listen`], waits for QEMU to connect. In [`Mode::Connect`], connects to QEMU.
+    ///
+    /// It can be called repeatedly on the same socket, e.g. after QEMU disconnects:
+    /// the listener is kept alive, and the previous connection, if any, is dropped.
+    ///
     /// The [`send`] and [`receive`] methods will not work until this method is called.
     fn attach_connection(&mut self) -> impl std::future::Future<Output = io::Result<()>> + Send;
 
     /// Sends a message to the socket and returns the size of the message sent.
     ///
+    /// The whole message is written before returning `Ok`, so it is always the length of `data`: a command
+    /// is never silently truncated. Otherwise, the error tells a closed connection, for which [`is_disconnection`]
+    /// is true and the link state becomes [`LinkState::Closed`], apart from any other failure; in both cases,
+    /// its message tells how many bytes were written.
+    ///
     /// # Note
     ///
     /// QTest uses a newline character to delimit messages and will not start parsing the message until it receives it.
@@ -31,6 +104,14 @@ pub trait Socket {
     /// This method will not work before calling [`attach_connection`].
     fn send(&mut self, data: &str) -> impl std::future::Future<Output = io::Result<usize>> + Send;
 
+    /// Returns a receiver of the state of the attached connection, or `None` if the socket does not track it.
+    ///
+    /// Sockets that track it report [`LinkState::Closed`] as soon as QEMU closes the connection,
+    /// until the next [`attach_connection`].
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        None
+    }
+
     /// Returns the address of the socket.
     fn address(&self) -> String;
 
@@ -38,35 +119,221 @@ pub trait Socket {
     fn close(&self) -> io::Result<()>;
 }
 
-/// Reads messages from the socket. Returns Err if the connection was closed by peer or an error occurred.
+/// Returns true if the given error means that the connection was closed by the peer
+pub fn is_disconnection(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Delay between attempts to connect to QEMU in [`Mode::Connect`]
+#[cfg(any(feature = "tcp", feature = "unix"))]
+const CONNECT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Connects with the given function, retrying while QEMU is not listening yet
+#[cfg(any(feature = "tcp", feature = "unix"))]
+async fn connect_retrying<S, F, Fut>(connect: F) -> io::Result<S>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = io::Result<S>>,
+{
+    loop {
+        match connect().await {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound
+                ) =>
+            {
+                tokio::time::sleep(CONNECT_RETRY_DELAY).await
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Reads protocol lines from the socket. Returns when the connection was closed by peer or an error occurred,
+/// reporting [`LinkState::Closed`] to `link`.
 ///
-/// The messages are sent to the `out_handler` channel that was passed to the new method.
-async fn reader<T: AsyncReadExt + Unpin + Send>(
-    mut owned_read_half: T,
+/// Every complete line, with its trailing newline, is sent as one message to the `out_handler` channel
+/// that was passed to the new method. A partial line left when the connection closes is discarded.
+async fn reader<T: AsyncRead + Unpin + Send>(
+    read_half: T,
     out_handler: mpsc::Sender<String>,
+    link: watch::Sender<LinkState>,
 ) {
-    let mut buf = [0; 1024];
+    let mut read_half = BufReader::new(read_half);
+    let mut line = Vec::new();
     loop {
-        let mut msg = String::new();
-
-        while !msg.contains('\n') {
-            buf.fill(0);
-
-            let msg_part = match owned_read_half.read(&mut buf).await {
-                Ok(0) => {
-                    println!("[QTEST_SOCKET] Connection closed by peer");
+        line.clear();
+        match read_half.read_until(b'\n', &mut line).await {
+            Ok(_) if line.last() == Some(&b'\n') => {
+                let msg = String::from_utf8_lossy(&line).into_owned();
+                if out_handler.send(msg).await.is_err() {
                     return;
                 }
-                Ok(_) => str::from_utf8(&buf).unwrap().to_string(),
-                Err(e) => {
-                    println!("[QTEST_SOCKET] [ERROR] read error: {:?}", e);
-                    break;
+            }
+            Ok(_) => {
+                if !line.is_empty() {
+                    log_warn!(target: "qtest::socket", "Discarding partial line: {:?}", String::from_utf8_lossy(&line));
                 }
-            };
+                log_info!(target: "qtest::socket", "Connection closed by peer");
+                link.send_replace(LinkState::Closed);
+                return;
+            }
+            Err(e) => {
+                log_error!(target: "qtest::socket", "read error: {e:?}");
+                link.send_replace(LinkState::Closed);
+                return;
+            }
+        }
+    }
+}
+
+/// Writes the whole message to the attached connection, if any, as required by [`Socket::send`].
+///
+/// Short writes are retried until every byte is written. If the connection was closed by the peer,
+/// reports [`LinkState::Closed`] to `link`; errors keep their kind and tell how many bytes were written.
+async fn writer<T: AsyncWrite + Unpin>(
+    write_half: Option<&mut T>,
+    data: &str,
+    link: &watch::Sender<LinkState>,
+) -> io::Result<usize> {
+    let Some(write_half) = write_half else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "No connection attached",
+        ));
+    };
+    let data = data.as_bytes();
+    let mut written = 0;
+    while written < data.len() {
+        match write_half.write(&data[written..]).await {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!("Partial write: {written} of {} bytes written", data.len()),
+                ))
+            }
+            Ok(n) => written += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if is_disconnection(&e) => {
+                log_info!(target: "qtest::socket", "Connection closed by peer");
+                link.send_replace(LinkState::Closed);
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Connection closed after {written} of {} bytes written: {e}",
+                        data.len()
+                    ),
+                ));
+            }
+            Err(e) => {
+                log_error!(target: "qtest::socket", "write error: {e:?}");
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Write failed after {written} of {} bytes: {e}", data.len()),
+                ));
+            }
+        }
+    }
+    write_half.flush().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_reader_framing() {
+        let (mut qemu, read_half) = tokio::io::duplex(64);
+        let (tx, mut rx) = mpsc::channel(8);
+        let link = watch::Sender::new(LinkState::Attached);
+        let mut state = link.subscribe();
+        tokio::spawn(reader(read_half, tx, link));
 
-            msg.push_str(&msg_part);
+        // Lines split across writes, and several lines in one write
+        qemu.write_all(b"OK 0x").await.unwrap();
+        qemu.write_all(b"12\nIRQ raise 3\nOK\nFAIL").await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "OK 0x12\n");
+        assert_eq!(rx.recv().await.unwrap(), "IRQ raise 3\n");
+        assert_eq!(rx.recv().await.unwrap(), "OK\n");
+
+        // The partial line is discarded when the connection closes
+        drop(qemu);
+        assert_eq!(rx.recv().await, None);
+        state.wait_for(|s| *s == LinkState::Closed).await.unwrap();
+    }
+
+    /// Writer that never accepts any byte
+    struct Stalled;
+
+    impl AsyncWrite for Stalled {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            _: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::task::Poll::Ready(Ok(0))
         }
 
-        out_handler.send(msg).await.unwrap();
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writer() {
+        use tokio::io::AsyncReadExt;
+
+        let link = watch::Sender::new(LinkState::Attached);
+        let err = writer::<tokio::io::DuplexStream>(None, "readl 0x0\n", &link)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+        // Short writes are retried until the whole message is written
+        let (mut write_half, mut qemu) = tokio::io::duplex(4);
+        let message = "writel 0x40000000 0x12345678\n";
+        let (written, received) =
+            tokio::join!(writer(Some(&mut write_half), message, &link), async {
+                let mut received = vec![0; message.len()];
+                qemu.read_exact(&mut received).await.unwrap();
+                received
+            });
+        assert_eq!(written.unwrap(), message.len());
+        assert_eq!(received, message.as_bytes());
+
+        let err = writer(Some(&mut Stalled), message, &link)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert!(!is_disconnection(&err));
+        assert_eq!(*link.borrow(), LinkState::Attached);
+
+        // A closed connection is reported apart from the partial write
+        drop(qemu);
+        let err = writer(Some(&mut write_half), message, &link)
+            .await
+            .unwrap_err();
+        assert!(is_disconnection(&err));
+        assert_eq!(*link.borrow(), LinkState::Closed);
     }
 }
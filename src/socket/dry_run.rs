@@ -0,0 +1,118 @@
+use std::io;
+
+use tokio::sync::mpsc;
+
+use super::{Mode, Socket};
+use crate::logging::log_info;
+use crate::protocol::{Command, LineDecoder};
+
+/// Socket that validates and logs every command without QEMU, answering with canned responses.
+///
+/// Used with [crate::parser::Parser], it checks scenario scripts and generated test code quickly,
+/// e.g. in CI environments without QEMU:
+///
+/// - memory and I/O reads return zeroes,
+/// - clock commands return a virtual clock that advances by the requested steps,
+/// - any other command returns `OK`.
+///
+/// Lines that are not valid qtest commands are answered with `FAIL`.
+/// Every command is logged with the `qtest::dry_run` target.
+#[derive(Debug)]
+pub struct SocketDryRun {
+    url: String,
+    out_handler: mpsc::Sender<String>,
+    decoder: LineDecoder,
+    clock_ns: usize,
+}
+
+impl SocketDryRun {
+    /// Returns the canned response to the given command line
+    fn respond(&mut self, line: &str) -> String {
+        let command = match line.parse::<Command>() {
+            Ok(command) => command,
+            Err(e) => {
+                log_info!(target: "qtest::dry_run", "invalid command {line:?}: {e}");
+                return format!("FAIL {e}");
+            }
+        };
+        log_info!(target: "qtest::dry_run", "{command}");
+        match command {
+            Command::ClockStep(ns) => {
+                self.clock_ns += ns.unwrap_or_default();
+                format!("OK {}", self.clock_ns)
+            }
+            Command::ClockSet(ns) => {
+                self.clock_ns = ns;
+                format!("OK {}", self.clock_ns)
+            }
+            Command::In { width, .. } | Command::ReadValue { width, .. } => {
+                format!("OK 0x{}", "00".repeat(width.bytes()))
+            }
+            Command::Read { len, .. } => format!("OK 0x{}", "00".repeat(len)),
+            _ => "OK".to_string(),
+        }
+    }
+}
+
+impl Socket for SocketDryRun {
+    async fn new(url: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            out_handler,
+            decoder: LineDecoder::new(),
+            clock_ns: 0,
+        })
+    }
+
+    /// Both modes are supported, as there is no connection
+    async fn with_mode(
+        url: &str,
+        out_handler: mpsc::Sender<String>,
+        _mode: Mode,
+    ) -> io::Result<Self> {
+        Self::new(url, out_handler).await
+    }
+
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn address(&self) -> String {
+        self.url.clone()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        self.decoder.push(data.as_bytes());
+        while let Some(line) = self.decoder.next_line() {
+            let response = self.respond(&line);
+            self.out_handler
+                .send(response + "\n")
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Parser is closed"))?;
+        }
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_canned_responses() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut socket = SocketDryRun::new("dry-run", tx).await.unwrap();
+        assert_eq!(socket.respond("readl 0x1000"), "OK 0x00000000");
+        assert_eq!(socket.respond("inb 0x60"), "OK 0x00");
+        assert_eq!(socket.respond("read 0x1000 3"), "OK 0x000000");
+        assert_eq!(socket.respond("writeb 0x1000 0x1"), "OK");
+        assert_eq!(socket.respond("clock_step 100"), "OK 100");
+        assert_eq!(socket.respond("clock_step"), "OK 100");
+        assert_eq!(socket.respond("clock_set 500"), "OK 500");
+        assert!(socket.respond("bogus 1").starts_with("FAIL"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, watch};
+
+use super::{LinkState, Mode, Socket};
+use crate::protocol::LineDecoder;
+
+/// Capacity of the channel between the wrapped socket and the fault injector
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Faults injected in one direction of a [`SocketFaulty`]. Probabilities are applied to every line.
+///
+/// The default configuration injects no faults.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Maximum delay before every part of a line is delivered, chosen uniformly
+    pub delay: Duration,
+    /// Probability of dropping a line
+    pub drop: f64,
+    /// Probability of delivering a line twice
+    pub duplicate: f64,
+    /// Probability of delivering a line in two separate writes
+    pub split: f64,
+    /// Probability of delivering a line of garbage bytes before a line
+    pub garbage: f64,
+}
+
+/// Fault configurations and random generator of a [`SocketFaulty`]
+#[derive(Debug)]
+struct State {
+    outgoing: FaultConfig,
+    incoming: FaultConfig,
+    rng: u64,
+}
+
+impl State {
+    /// Returns the next pseudo-random number (xorshift64*)
+    fn next(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns true with the given probability
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    /// Returns a random number in `0..=max`
+    fn upto(&mut self, max: u64) -> u64 {
+        match max {
+            0 => 0,
+            u64::MAX => self.next(),
+            _ => self.next() % (max + 1),
+        }
+    }
+
+    /// Returns a random delay of at most `max`
+    fn delay(&mut self, max: Duration) -> Duration {
+        Duration::from_nanos(self.upto(max.as_nanos().min(u64::MAX as u128) as u64))
+    }
+
+    /// Returns the parts to deliver in place of the given line, each with the delay to wait before
+    fn corrupt(&mut self, config: FaultConfig, line: &str) -> Vec<(Duration, String)> {
+        let mut parts = Vec::new();
+        if self.chance(config.drop) {
+            return parts;
+        }
+        let copies = if self.chance(config.duplicate) { 2 } else { 1 };
+        for _ in 0..copies {
+            if self.chance(config.garbage) {
+                let garbage: String = (0..1 + self.upto(15))
+                    .map(|_| char::from(b'!' + self.upto(93) as u8))
+                    .collect();
+                parts.push((self.delay(config.delay), garbage + "\n"));
+            }
+            if line.len() > 1 && self.chance(config.split) {
+                let mut at = 1 + self.upto(line.len() as u64 - 2) as usize;
+                while !line.is_char_boundary(at) {
+                    at -= 1;
+                }
+                parts.push((self.delay(config.delay), line[..at].to_string()));
+                parts.push((self.delay(config.delay), line[at..].to_string()));
+            } else {
+                parts.push((self.delay(config.delay), line.to_string()));
+            }
+        }
+        parts
+    }
+}
+
+/// Shared handle to the faults injected by a [`SocketFaulty`], which can be changed at any time
+#[derive(Debug, Clone)]
+pub struct Faults(Arc<Mutex<State>>);
+
+impl Faults {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(State {
+            outgoing: FaultConfig::default(),
+            incoming: FaultConfig::default(),
+            rng: 0x9e37_79b9_7f4a_7c15,
+        })))
+    }
+
+    /// Sets the faults injected in the commands sent to QEMU
+    pub fn set_outgoing(&self, config: FaultConfig) {
+        self.0.lock().unwrap().outgoing = config;
+    }
+
+    /// Sets the faults injected in the lines received from QEMU
+    pub fn set_incoming(&self, config: FaultConfig) {
+        self.0.lock().unwrap().incoming = config;
+    }
+
+    /// Stops injecting faults
+    pub fn clear(&self) {
+        self.set_outgoing(FaultConfig::default());
+        self.set_incoming(FaultConfig::default());
+    }
+
+    /// Seeds the random generator, so that a faulty session can be reproduced
+    pub fn seed(&self, seed: u64) {
+        // xorshift gets stuck at zero
+        self.0.lock().unwrap().rng = seed.max(1);
+    }
+
+    fn corrupt_outgoing(&self, line: &str) -> Vec<(Duration, String)> {
+        let mut state = self.0.lock().unwrap();
+        let config = state.outgoing;
+        state.corrupt(config, line)
+    }
+
+    fn corrupt_incoming(&self, line: &str) -> Vec<(Duration, String)> {
+        let mut state = self.0.lock().unwrap();
+        let config = state.incoming;
+        state.corrupt(config, line)
+    }
+}
+
+/// Socket that wraps another socket and injects faults between it and the [crate::parser::Parser],
+/// to test that retries, timeouts and framing survive hostile conditions.
+///
+/// Faults are injected per line, in both directions, according to the configurations
+/// set through [`SocketFaulty::faults`]. No faults are injected until configured.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::{fault::{FaultConfig, SocketFaulty}, tcp::SocketTcp}};
+/// # use std::time::Duration;
+/// let (mut parser, _irq_rx) = Parser::<SocketFaulty<SocketTcp>>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let faults = parser.socket().faults();
+/// faults.seed(42);
+/// faults.set_incoming(FaultConfig {
+///     delay: Duration::from_millis(5),
+///     split: 0.5,
+///     ..Default::default()
+/// });
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SocketFaulty<S> {
+    inner: S,
+    faults: Faults,
+}
+
+impl<S> SocketFaulty<S> {
+    /// Returns the handle to the injected faults
+    pub fn faults(&self) -> Faults {
+        self.faults.clone()
+    }
+
+    /// Returns the wrapped socket
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+/// Forwards the lines received by the wrapped socket to the parser, injecting faults
+async fn forward(
+    mut rx: mpsc::Receiver<String>,
+    out_handler: mpsc::Sender<String>,
+    faults: Faults,
+) {
+    let mut decoder = LineDecoder::new();
+    while let Some(msg) = rx.recv().await {
+        decoder.push(msg.as_bytes());
+        while let Some(line) = decoder.next_line() {
+            for (delay, part) in faults.corrupt_incoming(&(line + "\n")) {
+                tokio::time::sleep(delay).await;
+                if out_handler.send(part).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<S: Socket + Send> Socket for SocketFaulty<S> {
+    async fn new(url: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        Self::with_mode(url, out_handler, Mode::Listen).await
+    }
+
+    async fn with_mode(
+        url: &str,
+        out_handler: mpsc::Sender<String>,
+        mode: Mode,
+    ) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let inner = S::with_mode(url, tx, mode).await?;
+        let faults = Faults::new();
+        tokio::spawn(forward(rx, out_handler, faults.clone()));
+        Ok(Self { inner, faults })
+    }
+
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        self.inner.attach_connection().await
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        self.inner.link_state()
+    }
+
+    fn address(&self) -> String {
+        self.inner.address()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.inner.close()
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        for line in data.split_inclusive('\n') {
+            for (delay, part) in self.faults.corrupt_outgoing(line) {
+                tokio::time::sleep(delay).await;
+                self.inner.send(&part).await?;
+            }
+        }
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state() -> State {
+        State {
+            outgoing: FaultConfig::default(),
+            incoming: FaultConfig::default(),
+            rng: 42,
+        }
+    }
+
+    fn joined(parts: &[(Duration, String)]) -> String {
+        parts.iter().map(|(_, part)| part.as_str()).collect()
+    }
+
+    #[test]
+    fn test_no_faults() {
+        let parts = state().corrupt(FaultConfig::default(), "OK 0x1\n");
+        assert_eq!(parts, [(Duration::ZERO, "OK 0x1\n".to_string())]);
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut state = state();
+        let drop = FaultConfig {
+            drop: 1.0,
+            ..Default::default()
+        };
+        assert!(state.corrupt(drop, "OK\n").is_empty());
+
+        let duplicate = FaultConfig {
+            duplicate: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(joined(&state.corrupt(duplicate, "OK\n")), "OK\nOK\n");
+
+        let split = FaultConfig {
+            split: 1.0,
+            delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let parts = state.corrupt(split, "OK 0x1\n");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(joined(&parts), "OK 0x1\n");
+        assert!(parts.iter().all(|(delay, _)| *delay <= split.delay));
+
+        let garbage = FaultConfig {
+            garbage: 1.0,
+            ..Default::default()
+        };
+        let parts = state.corrupt(garbage, "OK\n");
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].1.ends_with('\n') && parts[0].1 != "OK\n");
+        assert_eq!(parts[1].1, "OK\n");
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Mutex, OnceLock},
+};
+
+use tokio::{
+    io::{DuplexStream, ReadHalf, WriteHalf},
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::{reader, writer, LinkState, Socket};
+use crate::logging::log_info;
+
+/// Size of the in-memory buffer of each direction of a connection
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// In-memory sockets waiting for a peer, by name
+fn listeners() -> &'static Mutex<HashMap<String, mpsc::Sender<DuplexStream>>> {
+    static LISTENERS: OnceLock<Mutex<HashMap<String, mpsc::Sender<DuplexStream>>>> =
+        OnceLock::new();
+    LISTENERS.get_or_init(Default::default)
+}
+
+/// Connects to the [`SocketPair`] created with the given name, returning the peer side of the connection.
+///
+/// The peer side plays the role of QEMU: it reads the commands sent by the parser and writes
+/// the responses and IRQs, with the [`tokio::io::AsyncReadExt`] and [`tokio::io::AsyncWriteExt`] traits.
+pub async fn connect(name: &str) -> io::Result<DuplexStream> {
+    let listener = listeners().lock().unwrap().get(name).cloned();
+    let listener = listener.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("No in-memory socket named {name}"),
+        )
+    })?;
+    let (local, peer) = tokio::io::duplex(BUFFER_SIZE);
+    listener.send(local).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("In-memory socket {name} is closed"),
+        )
+    })?;
+    Ok(peer)
+}
+
+/// This struct should be used to test the [crate::parser::Parser] struct without OS resources.
+///
+/// The socket is registered with the name given as URL, and the peer side is obtained with [`connect`].
+/// Names are unique within the process while the socket is alive.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::mem::{self, SocketPair}};
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+///
+/// let (mut parser, _irq_rx) = Parser::<SocketPair>::new("qemu").await.unwrap();
+/// let mut qemu = BufReader::new(mem::connect("qemu").await.unwrap());
+/// parser.attach_connection().await.unwrap();
+///
+/// let qemu = async move {
+///     let mut line = String::new();
+///     qemu.read_line(&mut line).await.unwrap();
+///     qemu.get_mut().write_all(b"OK 0x2a\n").await.unwrap();
+/// };
+/// let (value, _) = tokio::join!(parser.readl(0x1000), qemu);
+/// assert_eq!(value.unwrap(), 0x2a);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SocketPair {
+    name: String,
+    out_handler: mpsc::Sender<String>,
+    /// Registered channel of the socket, which does not keep it open once unregistered
+    listener: mpsc::WeakSender<DuplexStream>,
+    peers: mpsc::Receiver<DuplexStream>,
+    write_stream: Option<WriteHalf<DuplexStream>>,
+    reader: Option<JoinHandle<()>>,
+    link: watch::Sender<LinkState>,
+}
+
+impl Socket for SocketPair {
+    async fn new(name: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        let mut listeners = listeners().lock().unwrap();
+        if listeners.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("In-memory socket {name} already exists"),
+            ));
+        }
+        let (tx, peers) = mpsc::channel(1);
+        let listener = tx.downgrade();
+        listeners.insert(name.to_string(), tx);
+        Ok(Self {
+            name: name.to_string(),
+            out_handler,
+            listener,
+            peers,
+            write_stream: None,
+            reader: None,
+            link: watch::Sender::new(LinkState::Detached),
+        })
+    }
+
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        let stream = self.peers.recv().await.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "In-memory socket is closed")
+        })?;
+        let (read_stream, write_stream) = tokio::io::split(stream);
+        self.write_stream = Some(write_stream);
+        // Stop reading from the previous connection, if any
+        if let Some(previous) = self.reader.take() {
+            previous.abort();
+        }
+        self.link.send_replace(LinkState::Attached);
+        log_info!(target: "qtest::socket", "Connection attached at {}", self.address());
+        let cloned_out_handler = self.out_handler.clone();
+        let link = self.link.clone();
+        self.reader = Some(tokio::spawn(async move {
+            reader::<ReadHalf<DuplexStream>>(read_stream, cloned_out_handler, link).await;
+        }));
+        Ok(())
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        Some(self.link.subscribe())
+    }
+
+    fn address(&self) -> String {
+        self.name.clone()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        let mut listeners = listeners().lock().unwrap();
+        // Once closed, the name may be taken by a new socket, which must stay registered
+        let registered = self.listener.upgrade().is_some_and(|own| {
+            listeners
+                .get(&self.name)
+                .is_some_and(|listener| listener.same_channel(&own))
+        });
+        if registered {
+            listeners.remove(&self.name);
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        writer(self.write_stream.as_mut(), data, &self.link).await
+    }
+}
+
+impl Drop for SocketPair {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reuse_name() {
+        let (tx, _rx) = mpsc::channel(32);
+        let old = SocketPair::new("mem-reuse", tx.clone()).await.unwrap();
+        let err = SocketPair::new("mem-reuse", tx.clone()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // Dropping a closed socket does not unregister the new socket with its name
+        old.close().unwrap();
+        let mut new = SocketPair::new("mem-reuse", tx).await.unwrap();
+        drop(old);
+        let _peer = connect("mem-reuse").await.unwrap();
+        new.attach_connection().await.unwrap();
+
+        drop(new);
+        let err = connect("mem-reuse").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
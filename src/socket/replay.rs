@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, io};
+
+use tokio::sync::mpsc;
+
+use super::{Mode, Socket};
+use crate::event::{Event, EventKind};
+use crate::protocol::LineDecoder;
+use crate::transcript::Transcript;
+
+/// Socket that plays back a recorded [`Transcript`] instead of talking to QEMU.
+///
+/// The URL is the path of the transcript, e.g. saved from a [`crate::transcript::Recorder`].
+/// Every command sent must be the next command of the transcript, and is answered with the responses
+/// and IRQs recorded after it, up to the next command. The events recorded before the first command
+/// are played when the connection is attached. Playback ignores the timestamps, so parser-level tests
+/// run deterministically and offline.
+///
+/// Sending a command that differs from the recorded one, or that is past the end of the transcript,
+/// fails with [`io::ErrorKind::InvalidData`] or [`io::ErrorKind::UnexpectedEof`]; the recorded command
+/// is kept, so the expected command can still be sent.
+/// Notifications of [`crate::parser::LineHook`]s are not played back, as their raw line is not recorded.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::replay::SocketReplay};
+/// let (mut parser, _irq_rx) = Parser::<SocketReplay>::new("session.qtest").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// assert_eq!(parser.readl(0x4000_0004).await.unwrap(), 0x12);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SocketReplay {
+    path: String,
+    out_handler: mpsc::Sender<String>,
+    decoder: LineDecoder,
+    events: VecDeque<Event>,
+}
+
+impl SocketReplay {
+    /// Creates a socket that plays back the given transcript
+    pub fn from_transcript(
+        transcript: Transcript,
+        path: &str,
+        out_handler: mpsc::Sender<String>,
+    ) -> Self {
+        Self {
+            path: path.to_string(),
+            out_handler,
+            decoder: LineDecoder::new(),
+            events: transcript.events.into(),
+        }
+    }
+
+    /// Returns the number of events of the transcript not played back yet
+    pub fn remaining(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Checks the given command line against the next recorded command, consuming it if it matches
+    fn expect_command(&mut self, line: &str) -> io::Result<()> {
+        let error = match self.events.front().map(|event| &event.kind) {
+            Some(EventKind::Command(cmd)) if cmd == line => {
+                self.events.pop_front();
+                return Ok(());
+            }
+            Some(EventKind::Command(cmd)) => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected command {cmd:?}, got {line:?}"),
+            ),
+            Some(kind) => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected {kind:?}, got command {line:?}"),
+            ),
+            None => io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("The transcript ended before command {line:?}"),
+            ),
+        };
+        Err(error)
+    }
+
+    /// Sends the recorded responses and IRQs up to the next command or connection
+    async fn play(&mut self) -> io::Result<()> {
+        while let Some(event) = self.events.front() {
+            let line = match &event.kind {
+                EventKind::Command(_) | EventKind::Connected => return Ok(()),
+                EventKind::Response(response) => Some(format!("{response}\n")),
+                EventKind::Irq(irq) => Some(format!("{irq}\n")),
+                EventKind::Notification(_) => None,
+            };
+            self.events.pop_front();
+            if let Some(line) = line {
+                self.out_handler
+                    .send(line)
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Parser is closed"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Socket for SocketReplay {
+    async fn new(path: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        let transcript = Transcript::load(path)?;
+        Ok(Self::from_transcript(transcript, path, out_handler))
+    }
+
+    /// Both modes are supported, as there is no connection
+    async fn with_mode(
+        path: &str,
+        out_handler: mpsc::Sender<String>,
+        _mode: Mode,
+    ) -> io::Result<Self> {
+        Self::new(path, out_handler).await
+    }
+
+    /// Plays the recorded connection, if any, and the events recorded before the next command
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        if let Some(EventKind::Connected) = self.events.front().map(|event| &event.kind) {
+            self.events.pop_front();
+        }
+        self.play().await
+    }
+
+    fn address(&self) -> String {
+        self.path.clone()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        self.decoder.push(data.as_bytes());
+        while let Some(line) = self.decoder.next_line() {
+            self.expect_command(&line)?;
+            self.play().await?;
+        }
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_playback() {
+        let transcript: Transcript = "0 * connected\n\
+                                      1 ! IRQ raise 3\n\
+                                      2 > readl 0x1000\n\
+                                      3 < OK 0x12\n\
+                                      4 ! IRQ lower 3\n\
+                                      5 > writel 0x1000 0x1\n\
+                                      6 < OK\n"
+            .parse()
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut socket = SocketReplay::from_transcript(transcript, "replay", tx);
+
+        socket.attach_connection().await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "IRQ raise 3\n");
+
+        socket.send("readl 0x1000\n").await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "OK 0x12\n");
+        assert_eq!(rx.recv().await.unwrap(), "IRQ lower 3\n");
+
+        // A mismatch does not consume the recorded command
+        let err = socket.send("writel 0x1000 0x2\n").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(socket.remaining(), 2);
+        socket.send("writel 0x1000 0x1\n").await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "OK\n");
+        assert_eq!(socket.remaining(), 0);
+
+        let err = socket.send("readl 0x1000\n").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fmt, fs, io,
+    path::PathBuf,
+    process::Stdio,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use tokio::{
+    process::{Child, Command},
+    sync::{mpsc, watch},
+};
+
+use super::{unix::SocketUnix, LinkState, Mode, Socket};
+
+/// Remote UNIX socket reached over SSH, written as `ssh://[user@]host[:port]/path/to/qtest.sock[?identity_file=path]`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SshEndpoint {
+    /// User to log in as, if not the default of the SSH configuration
+    pub user: Option<String>,
+    /// Host running QEMU, which may be an alias of the SSH configuration
+    pub host: String,
+    /// SSH port, if not the default of the SSH configuration
+    pub port: Option<u16>,
+    /// Absolute path of the qtest socket on the host
+    pub path: String,
+    /// Private key to authenticate with, if not the keys of the SSH configuration
+    pub identity_file: Option<String>,
+}
+
+impl SshEndpoint {
+    /// Returns the arguments of `ssh` forwarding the given local socket to the remote one.
+    ///
+    /// In [`Mode::Listen`], the remote socket is forwarded to the local one (`-R`), where this crate listens.
+    /// In [`Mode::Connect`], the local socket is forwarded to the remote one (`-L`), where QEMU listens.
+    fn ssh_args(&self, local: &str, mode: Mode) -> Vec<String> {
+        let mut args: Vec<String> = [
+            "-N",
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-o",
+            "StreamLocalBindUnlink=yes",
+        ]
+        .map(String::from)
+        .into();
+        if let Some(port) = self.port {
+            args.extend(["-p".to_string(), port.to_string()]);
+        }
+        if let Some(identity_file) = &self.identity_file {
+            args.extend(["-i".to_string(), identity_file.clone()]);
+        }
+        match mode {
+            Mode::Listen => args.extend(["-R".to_string(), format!("{}:{local}", self.path)]),
+            Mode::Connect => args.extend(["-L".to_string(), format!("{local}:{}", self.path)]),
+        }
+        args.push(match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        });
+        args
+    }
+}
+
+impl fmt::Display for SshEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ssh://")?;
+        if let Some(user) = &self.user {
+            write!(f, "{user}@")?;
+        }
+        write!(f, "{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(identity_file) = &self.identity_file {
+            write!(f, "?identity_file={identity_file}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SshEndpoint {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{msg}: {s}"));
+        let rest = s
+            .strip_prefix("ssh://")
+            .ok_or_else(|| invalid("SSH endpoints start with ssh://"))?;
+        let (authority, path) = rest
+            .find('/')
+            .map(|i| rest.split_at(i))
+            .ok_or_else(|| invalid("SSH endpoints need the path of the remote socket"))?;
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse().map_err(|_| invalid("Invalid SSH port"))?;
+                (host, Some(port))
+            }
+            None => (host, None),
+        };
+        let (path, identity_file) = match path.split_once('?') {
+            Some((path, query)) => {
+                let identity_file = query
+                    .strip_prefix("identity_file=")
+                    .ok_or_else(|| invalid("Unknown SSH endpoint option"))?;
+                (path, Some(identity_file.to_string()))
+            }
+            None => (path, None),
+        };
+        if host.is_empty() || path.len() < 2 {
+            return Err(invalid("SSH endpoints need a host and a socket path"));
+        }
+        Ok(Self {
+            user,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            identity_file,
+        })
+    }
+}
+
+/// This struct should be used to interact with a remote QEMU over SSH via [crate::parser::Parser] struct.
+///
+/// The URL is an [`SshEndpoint`] such as `ssh://lab-host/tmp/qtest.sock`. The qtest stream runs through
+/// an `ssh` child process forwarding a local UNIX socket to the remote one, so authentication, jump hosts
+/// and aliases are taken from the SSH configuration of the user. Authentication must not be interactive.
+///
+/// In [`Mode::Listen`], QEMU connects to the remote socket (`-qtest unix:/tmp/qtest.sock`).
+/// In [`Mode::Connect`], QEMU must listen at the remote socket (`-qtest unix:/tmp/qtest.sock,server=on,wait=off`)
+/// before the connection is attached.
+pub struct SocketSsh {
+    inner: SocketUnix,
+    ssh: Mutex<Child>,
+    endpoint: SshEndpoint,
+    local: PathBuf,
+    mode: Mode,
+}
+
+impl SocketSsh {
+    /// Returns the remote endpoint
+    pub fn endpoint(&self) -> &SshEndpoint {
+        &self.endpoint
+    }
+}
+
+/// Returns a unique path for the local end of a forwarding
+fn local_path() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("qtest-ssh-{}-{n}.sock", std::process::id()))
+}
+
+impl Socket for SocketSsh {
+    async fn new(url: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        Self::with_mode(url, out_handler, Mode::Listen).await
+    }
+
+    async fn with_mode(
+        url: &str,
+        out_handler: mpsc::Sender<String>,
+        mode: Mode,
+    ) -> io::Result<Self> {
+        let endpoint: SshEndpoint = url.parse()?;
+        let local = local_path();
+        let local_str = local.to_string_lossy().into_owned();
+        // The local socket must exist before a remote forwarding to it is opened
+        let inner = SocketUnix::with_mode(&local_str, out_handler, mode).await?;
+        let ssh = Command::new("ssh")
+            .args(endpoint.ssh_args(&local_str, mode))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        Ok(Self {
+            inner,
+            ssh: Mutex::new(ssh),
+            endpoint,
+            local,
+            mode,
+        })
+    }
+
+    /// Attaches a connection through the tunnel. Returns Err if `ssh` exits first, e.g. if the host is unreachable.
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        tokio::select! {
+            result = self.inner.attach_connection() => result,
+            status = self.ssh.get_mut().unwrap().wait() => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("ssh to {} exited with {}", self.endpoint.host, status?),
+            )),
+        }
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        self.inner.send(data).await
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        self.inner.link_state()
+    }
+
+    fn address(&self) -> String {
+        self.local.to_string_lossy().into_owned()
+    }
+
+    /// Closes the tunnel and removes the local socket file
+    fn close(&self) -> io::Result<()> {
+        // Fails if ssh already exited
+        let _ = self.ssh.lock().unwrap().start_kill();
+        match self.mode {
+            Mode::Listen => self.inner.close(),
+            Mode::Connect => match fs::remove_file(&self.local) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        let endpoint: SshEndpoint = "ssh://lab-host/tmp/qtest.sock".parse().unwrap();
+        assert_eq!(endpoint.user, None);
+        assert_eq!(endpoint.host, "lab-host");
+        assert_eq!(endpoint.port, None);
+        assert_eq!(endpoint.path, "/tmp/qtest.sock");
+        assert_eq!(
+            endpoint.ssh_args("/tmp/local.sock", Mode::Connect)[7..],
+            ["-L", "/tmp/local.sock:/tmp/qtest.sock", "lab-host"]
+        );
+
+        let endpoint: SshEndpoint = "ssh://ci@farm:2222/run/qtest.sock".parse().unwrap();
+        assert_eq!(endpoint.user.as_deref(), Some("ci"));
+        assert_eq!(endpoint.port, Some(2222));
+        assert_eq!(
+            endpoint.ssh_args("/tmp/local.sock", Mode::Listen)[7..],
+            [
+                "-p",
+                "2222",
+                "-R",
+                "/run/qtest.sock:/tmp/local.sock",
+                "ci@farm"
+            ]
+        );
+
+        let url = "ssh://ci@farm:2222/run/qtest.sock?identity_file=/home/ci/.ssh/farm";
+        let endpoint: SshEndpoint = url.parse().unwrap();
+        assert_eq!(
+            endpoint.identity_file.as_deref(),
+            Some("/home/ci/.ssh/farm")
+        );
+        assert_eq!(endpoint.to_string(), url);
+        assert_eq!(
+            endpoint.ssh_args("/tmp/local.sock", Mode::Connect)[9..11],
+            ["-i", "/home/ci/.ssh/farm"]
+        );
+
+        assert!("tcp://lab-host/tmp/qtest.sock"
+            .parse::<SshEndpoint>()
+            .is_err());
+        assert!("ssh://lab-host".parse::<SshEndpoint>().is_err());
+        assert!("ssh://lab-host:x/tmp/qtest.sock"
+            .parse::<SshEndpoint>()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_host() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut socket = match SocketSsh::with_mode(
+            "ssh://qtest-unreachable.invalid/tmp/qtest.sock",
+            tx,
+            Mode::Connect,
+        )
+        .await
+        {
+            Ok(socket) => socket,
+            // No ssh client installed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => panic!("{e}"),
+        };
+        let err = socket.attach_connection().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        socket.close().unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    sync::{Mutex, OnceLock},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::{reader, writer, LinkState, Socket};
+use crate::logging::log_info;
+
+/// Pipe written with the commands, the standard input of QEMU
+type CommandPipe = Box<dyn AsyncWrite + Send + Unpin>;
+/// Pipe read for the responses and IRQs, the standard output of QEMU
+type ResponsePipe = Box<dyn AsyncRead + Send + Unpin>;
+/// Channel of a stdio socket, through which it receives the pipes of each QEMU process
+type Listener = mpsc::Sender<(CommandPipe, ResponsePipe)>;
+
+/// Stdio sockets waiting for the pipes of a QEMU process, by name
+fn listeners() -> &'static Mutex<HashMap<String, Listener>> {
+    static LISTENERS: OnceLock<Mutex<HashMap<String, Listener>>> = OnceLock::new();
+    LISTENERS.get_or_init(Default::default)
+}
+
+/// Connects the pipes of a QEMU process started with `-qtest stdio` to the [`SocketStdio`] created with the given name.
+///
+/// `stdin` is written with the commands and `stdout` is read for the responses and IRQs, e.g. the
+/// [`tokio::process::ChildStdin`] and [`tokio::process::ChildStdout`] of the process.
+/// [`crate::qemu::QemuInstance::launch`] connects them for the command lines built with
+/// [`crate::qemu::QemuCommandBuilder::qtest_stdio`].
+pub async fn connect<W, R>(name: &str, stdin: W, stdout: R) -> io::Result<()>
+where
+    W: AsyncWrite + Send + Unpin + 'static,
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let listener = listeners().lock().unwrap().get(name).cloned();
+    let listener = listener.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("No stdio socket named {name}"),
+        )
+    })?;
+    listener
+        .send((Box::new(stdin), Box::new(stdout)))
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("Stdio socket {name} is closed"),
+            )
+        })
+}
+
+/// Socket talking to QEMU through the standard input and output of its process (`-qtest stdio`).
+///
+/// No port or socket file is allocated, so concurrent QEMU instances never collide. The socket is registered
+/// with the name given as URL, and the pipes of the process are handed to it with [`connect`], which
+/// [`crate::qemu::QemuInstance::launch`] does for the command lines built with
+/// [`crate::qemu::QemuCommandBuilder::qtest_stdio`]. Names are unique within the process while the socket is alive.
+///
+/// The serial ports and monitor of QEMU must not use its standard output, e.g. with `-display none`,
+/// otherwise their output is mixed with the qtest protocol.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "qemu-launcher")]
+/// # async fn example() {
+/// # use qtest::{parser::ParserBuilder, qemu::{QemuCommandBuilder, QemuInstance}, socket::stdio::SocketStdio};
+/// let builder = QemuCommandBuilder::new("qemu-system-arm")
+///     .machine("netduino2")
+///     .qtest_stdio()
+///     .args(["-display", "none"]);
+/// let parser = ParserBuilder::<SocketStdio>::new("netduino2");
+///
+/// let (mut qemu, mut parser, _irq_rx) = QemuInstance::launch(&builder, parser).await.unwrap();
+/// parser.readl(0x2000_0000).await.unwrap();
+/// qemu.kill().await.unwrap();
+/// # }
+/// ```
+pub struct SocketStdio {
+    name: String,
+    out_handler: mpsc::Sender<String>,
+    /// Registered channel of the socket, which does not keep it open once unregistered
+    listener: mpsc::WeakSender<(CommandPipe, ResponsePipe)>,
+    processes: mpsc::Receiver<(CommandPipe, ResponsePipe)>,
+    write_stream: Option<CommandPipe>,
+    reader: Option<JoinHandle<()>>,
+    link: watch::Sender<LinkState>,
+}
+
+impl fmt::Debug for SocketStdio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SocketStdio")
+            .field("name", &self.name)
+            .field("attached", &self.write_stream.is_some())
+            .field("link", &*self.link.borrow())
+            .finish()
+    }
+}
+
+impl Socket for SocketStdio {
+    async fn new(name: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        let mut listeners = listeners().lock().unwrap();
+        if listeners.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Stdio socket {name} already exists"),
+            ));
+        }
+        let (tx, processes) = mpsc::channel(1);
+        let listener = tx.downgrade();
+        listeners.insert(name.to_string(), tx);
+        Ok(Self {
+            name: name.to_string(),
+            out_handler,
+            listener,
+            processes,
+            write_stream: None,
+            reader: None,
+            link: watch::Sender::new(LinkState::Detached),
+        })
+    }
+
+    /// Waits for the pipes of the next QEMU process, handed with [`connect`]
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        let (stdin, stdout) =
+            self.processes.recv().await.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotConnected, "Stdio socket is closed")
+            })?;
+        self.write_stream = Some(stdin);
+        // Stop reading from the previous process, if any
+        if let Some(previous) = self.reader.take() {
+            previous.abort();
+        }
+        self.link.send_replace(LinkState::Attached);
+        log_info!(target: "qtest::socket", "Connection attached at {}", self.address());
+        let cloned_out_handler = self.out_handler.clone();
+        let link = self.link.clone();
+        self.reader = Some(tokio::spawn(async move {
+            reader::<ResponsePipe>(stdout, cloned_out_handler, link).await;
+        }));
+        Ok(())
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        Some(self.link.subscribe())
+    }
+
+    fn address(&self) -> String {
+        self.name.clone()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        let mut listeners = listeners().lock().unwrap();
+        // Once closed, the name may be taken by a new socket, which must stay registered
+        let registered = self.listener.upgrade().is_some_and(|own| {
+            listeners
+                .get(&self.name)
+                .is_some_and(|listener| listener.same_channel(&own))
+        });
+        if registered {
+            listeners.remove(&self.name);
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        writer(self.write_stream.as_mut(), data, &self.link).await
+    }
+}
+
+impl Drop for SocketStdio {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reuse_name() {
+        let (tx, _rx) = mpsc::channel(32);
+        let old = SocketStdio::new("stdio-reuse", tx.clone()).await.unwrap();
+        let err = SocketStdio::new("stdio-reuse", tx.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // Dropping a closed socket does not unregister the new socket with its name
+        old.close().unwrap();
+        let mut new = SocketStdio::new("stdio-reuse", tx).await.unwrap();
+        drop(old);
+        let (stdin, _qemu_stdin) = tokio::io::duplex(64);
+        let (_qemu_stdout, stdout) = tokio::io::duplex(64);
+        connect("stdio-reuse", stdin, stdout).await.unwrap();
+        new.attach_connection().await.unwrap();
+
+        new.close().unwrap();
+        let (stdin, _qemu_stdin) = tokio::io::duplex(64);
+        let (_qemu_stdout, stdout) = tokio::io::duplex(64);
+        let err = connect("stdio-reuse", stdin, stdout).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -1,56 +1,89 @@
 use std::io;
 
 use tokio::{
-    io::AsyncWriteExt,
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpListener,
+        TcpListener, TcpStream,
     },
-    sync::mpsc,
+    sync::{mpsc, watch},
+    task::JoinHandle,
 };
 
-use super::{reader, Socket};
+use super::{connect_retrying, reader, writer, LinkState, Mode, Socket};
+use crate::logging::log_info;
 
 /// This struct should be used to interact with QEMU using a tcp socket via [crate::parser::Parser] struct.
 #[derive(Debug)]
 pub struct SocketTcp {
-    socket: TcpListener,
+    /// Listener in [`Mode::Listen`], `None` in [`Mode::Connect`]
+    socket: Option<TcpListener>,
+
+    url: String,
 
     out_handler: mpsc::Sender<String>,
 
     write_stream: Option<OwnedWriteHalf>,
+    reader: Option<JoinHandle<()>>,
+    link: watch::Sender<LinkState>,
 }
 
 impl Socket for SocketTcp {
     async fn new(url: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
-        match TcpListener::bind(url).await {
-            Ok(socket) => Ok(Self {
-                socket,
-                out_handler,
-                write_stream: None,
-            }),
-            Err(e) => Err(e),
-        }
+        Self::with_mode(url, out_handler, Mode::Listen).await
+    }
+
+    async fn with_mode(
+        url: &str,
+        out_handler: mpsc::Sender<String>,
+        mode: Mode,
+    ) -> io::Result<Self> {
+        let socket = match mode {
+            Mode::Listen => Some(TcpListener::bind(url).await?),
+            Mode::Connect => None,
+        };
+        Ok(Self {
+            socket,
+            url: url.to_string(),
+            out_handler,
+            write_stream: None,
+            reader: None,
+            link: watch::Sender::new(LinkState::Detached),
+        })
     }
 
     async fn attach_connection(&mut self) -> io::Result<()> {
-        match self.socket.accept().await {
-            Ok((stream, _)) => {
-                let (read_stream, write_stream) = stream.into_split();
-                self.write_stream = Some(write_stream);
-                let cloned_out_handler = self.out_handler.clone();
-                tokio::spawn(async move {
-                    reader::<OwnedReadHalf>(read_stream, cloned_out_handler).await;
-                });
-                Ok(())
-            }
-            Err(e) => Err(e),
+        let stream = match &self.socket {
+            Some(socket) => socket.accept().await?.0,
+            None => connect_retrying(|| TcpStream::connect(&self.url)).await?,
+        };
+        let (read_stream, write_stream) = stream.into_split();
+        self.write_stream = Some(write_stream);
+        // Stop reading from the previous connection, if any
+        if let Some(previous) = self.reader.take() {
+            previous.abort();
         }
+        self.link.send_replace(LinkState::Attached);
+        log_info!(target: "qtest::socket", "Connection attached at {}", self.address());
+        let cloned_out_handler = self.out_handler.clone();
+        let link = self.link.clone();
+        self.reader = Some(tokio::spawn(async move {
+            reader::<OwnedReadHalf>(read_stream, cloned_out_handler, link).await;
+        }));
+        Ok(())
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        Some(self.link.subscribe())
     }
 
     fn address(&self) -> String {
-        let addr = self.socket.local_addr().unwrap();
-        format!("{}:{}", addr.ip(), addr.port())
+        match &self.socket {
+            Some(socket) => {
+                let addr = socket.local_addr().unwrap();
+                format!("{}:{}", addr.ip(), addr.port())
+            }
+            None => self.url.clone(),
+        }
     }
 
     fn close(&self) -> io::Result<()> {
@@ -58,9 +91,51 @@ impl Socket for SocketTcp {
     }
 
     async fn send(&mut self, data: &str) -> io::Result<usize> {
-        match self.write_stream.as_mut() {
-            Some(stream) => stream.write(data.as_bytes()).await,
-            None => Err(io::Error::new(io::ErrorKind::NotConnected, "No connection")),
-        }
+        writer(self.write_stream.as_mut(), data, &self.link).await
+    }
+}
+
+/// TCP socket that connects to QEMU listening at the URL (`-qtest tcp:host:port,server=on`)
+/// instead of waiting for QEMU to connect.
+///
+/// It is a [`SocketTcp`] that always works in [`Mode::Connect`], so the mode can be picked
+/// by the socket type, e.g. with [`crate::parser::Parser::new`].
+#[derive(Debug)]
+pub struct SocketTcpClient(SocketTcp);
+
+impl Socket for SocketTcpClient {
+    async fn new(url: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        SocketTcp::with_mode(url, out_handler, Mode::Connect)
+            .await
+            .map(Self)
+    }
+
+    /// Creates the socket in [`Mode::Connect`], whatever the given mode
+    async fn with_mode(
+        url: &str,
+        out_handler: mpsc::Sender<String>,
+        _mode: Mode,
+    ) -> io::Result<Self> {
+        Self::new(url, out_handler).await
+    }
+
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        self.0.attach_connection().await
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        self.0.link_state()
+    }
+
+    fn address(&self) -> String {
+        self.0.address()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.0.close()
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        self.0.send(data).await
     }
 }
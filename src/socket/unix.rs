@@ -3,84 +3,143 @@ use std::{fs, io};
 use tokio::{
     net::{
         unix::{OwnedReadHalf, OwnedWriteHalf},
-        UnixListener,
+        UnixListener, UnixStream,
     },
-    sync::mpsc,
+    sync::{mpsc, watch},
+    task::JoinHandle,
 };
 
-use super::{reader, Socket};
+use super::{connect_retrying, reader, writer, LinkState, Mode, Socket};
+use crate::logging::log_info;
 
 /// This struct should be used to interact with QEMU using a UNIX socket via [crate::parser::Parser] struct.
 pub struct SocketUnix {
-    socket: UnixListener,
+    /// Listener in [`Mode::Listen`], `None` in [`Mode::Connect`]
+    socket: Option<UnixListener>,
     out_handler: mpsc::Sender<String>,
     write_stream: Option<OwnedWriteHalf>,
+    reader: Option<JoinHandle<()>>,
+    link: watch::Sender<LinkState>,
     path: String,
 }
 
+/// Binds a listener at the given path, replacing any stale socket file
+fn bind(path: &str) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
 impl Socket for SocketUnix {
     async fn new(path: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
-        match UnixListener::bind(path) {
-            Ok(socket) => Ok(Self {
-                socket,
-                out_handler,
-                write_stream: None,
-                path: path.to_string(),
-            }),
-            Err(e) => match e.kind() {
-                io::ErrorKind::AddrInUse => {
-                    match fs::remove_file(path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            return Err(e);
-                        }
-                    };
-
-                    match UnixListener::bind(path) {
-                        Ok(socket) => Ok(Self {
-                            socket,
-                            out_handler,
-                            write_stream: None,
-                            path: path.to_string(),
-                        }),
-                        Err(e) => Err(e),
-                    }
-                }
-                _ => Err(e),
-            },
-        }
+        Self::with_mode(path, out_handler, Mode::Listen).await
+    }
+
+    async fn with_mode(
+        path: &str,
+        out_handler: mpsc::Sender<String>,
+        mode: Mode,
+    ) -> io::Result<Self> {
+        let socket = match mode {
+            Mode::Listen => Some(bind(path)?),
+            Mode::Connect => None,
+        };
+        Ok(Self {
+            socket,
+            out_handler,
+            write_stream: None,
+            reader: None,
+            link: watch::Sender::new(LinkState::Detached),
+            path: path.to_string(),
+        })
     }
 
     async fn attach_connection(&mut self) -> io::Result<()> {
-        match self.socket.accept().await {
-            Ok((stream, _)) => {
-                let (read_stream, write_stream) = stream.into_split();
-                self.write_stream = Some(write_stream);
-                let cloned_out_handler = self.out_handler.clone();
-                tokio::spawn(async move {
-                    reader::<OwnedReadHalf>(read_stream, cloned_out_handler).await;
-                });
-                Ok(())
-            }
-            Err(e) => Err(e),
+        let stream = match &self.socket {
+            Some(socket) => socket.accept().await?.0,
+            None => connect_retrying(|| UnixStream::connect(&self.path)).await?,
+        };
+        let (read_stream, write_stream) = stream.into_split();
+        self.write_stream = Some(write_stream);
+        // Stop reading from the previous connection, if any
+        if let Some(previous) = self.reader.take() {
+            previous.abort();
         }
+        self.link.send_replace(LinkState::Attached);
+        log_info!(target: "qtest::socket", "Connection attached at {}", self.address());
+        let cloned_out_handler = self.out_handler.clone();
+        let link = self.link.clone();
+        self.reader = Some(tokio::spawn(async move {
+            reader::<OwnedReadHalf>(read_stream, cloned_out_handler, link).await;
+        }));
+        Ok(())
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        Some(self.link.subscribe())
     }
 
     fn address(&self) -> String {
         self.path.clone()
     }
 
+    /// Removes the socket file in [`Mode::Listen`]; in [`Mode::Connect`], the file belongs to QEMU
     fn close(&self) -> io::Result<()> {
-        fs::remove_file(self.path.clone())
+        match self.socket {
+            Some(_) => fs::remove_file(self.path.clone()),
+            None => Ok(()),
+        }
     }
 
     async fn send(&mut self, data: &str) -> io::Result<usize> {
-        match self.write_stream.as_mut() {
-            Some(stream) => stream.try_write(data.as_bytes()),
-            None => Err(io::Error::new(
-                io::ErrorKind::NotConnected,
-                "No connection attached",
-            )),
-        }
+        writer(self.write_stream.as_mut(), data, &self.link).await
+    }
+}
+
+/// UNIX socket that connects to QEMU listening at the path (`-qtest unix:path,server=on`)
+/// instead of waiting for QEMU to connect.
+///
+/// It is a [`SocketUnix`] that always works in [`Mode::Connect`], so the mode can be picked
+/// by the socket type, e.g. with [`crate::parser::Parser::new`].
+pub struct SocketUnixClient(SocketUnix);
+
+impl Socket for SocketUnixClient {
+    async fn new(path: &str, out_handler: mpsc::Sender<String>) -> io::Result<Self> {
+        SocketUnix::with_mode(path, out_handler, Mode::Connect)
+            .await
+            .map(Self)
+    }
+
+    /// Creates the socket in [`Mode::Connect`], whatever the given mode
+    async fn with_mode(
+        path: &str,
+        out_handler: mpsc::Sender<String>,
+        _mode: Mode,
+    ) -> io::Result<Self> {
+        Self::new(path, out_handler).await
+    }
+
+    async fn attach_connection(&mut self) -> io::Result<()> {
+        self.0.attach_connection().await
+    }
+
+    fn link_state(&self) -> Option<watch::Receiver<LinkState>> {
+        self.0.link_state()
+    }
+
+    fn address(&self) -> String {
+        self.0.address()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.0.close()
+    }
+
+    async fn send(&mut self, data: &str) -> io::Result<usize> {
+        self.0.send(data).await
     }
 }
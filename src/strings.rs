@@ -0,0 +1,115 @@
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::parser::Parser;
+use crate::socket::Socket;
+use crate::transfer::read_chunked;
+use crate::Endianness;
+
+/// Number of bytes read at once while looking for the NUL terminator of a string
+const CSTRING_CHUNK_SIZE: usize = 64;
+
+/// Reads a NUL-terminated UTF-8 string from guest memory.
+///
+/// At most `max_len` bytes are read. If no NUL terminator is found within them, the string is truncated to `max_len` bytes.
+pub async fn read_cstring<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    max_len: usize,
+) -> Result<String, QtestError> {
+    let addr = parser.resolve(addr)?;
+    let mut bytes = Vec::new();
+    while bytes.len() < max_len {
+        let chunk_len = CSTRING_CHUNK_SIZE.min(max_len - bytes.len());
+        let chunk = read_chunked(parser, addr + bytes.len(), chunk_len, |_, _| {}).await?;
+        match chunk.iter().position(|&b| b == 0) {
+            Some(nul) => {
+                bytes.extend_from_slice(&chunk[..nul]);
+                break;
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+    }
+    decode_utf8(bytes)
+}
+
+/// Reads a UTF-8 string of exactly `len` bytes from guest memory
+pub async fn read_utf8<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+) -> Result<String, QtestError> {
+    decode_utf8(read_chunked(parser, addr, len, |_, _| {}).await?)
+}
+
+/// Reads a UTF-16 string of exactly `units` code units (two bytes each) from guest memory
+pub async fn read_utf16<T: Socket>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    units: usize,
+    endianness: Endianness,
+) -> Result<String, QtestError> {
+    let bytes = read_chunked(parser, addr, 2 * units, |_, _| {}).await?;
+    decode_utf16(&bytes, endianness)
+}
+
+fn decode_utf8(bytes: Vec<u8>) -> Result<String, QtestError> {
+    String::from_utf8(bytes).map_err(|e| QtestError::Parse(format!("Invalid UTF-8 string: {e}")))
+}
+
+fn decode_utf16(bytes: &[u8], endianness: Endianness) -> Result<String, QtestError> {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|unit| match endianness {
+            Endianness::Little => u16::from_le_bytes([unit[0], unit[1]]),
+            Endianness::Big => u16::from_be_bytes([unit[0], unit[1]]),
+        })
+        .collect();
+    String::from_utf16(&units).map_err(|e| QtestError::Parse(format!("Invalid UTF-16 string: {e}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockQtestDevice;
+    use crate::socket::mem::{self, SocketPair};
+
+    #[tokio::test]
+    async fn test_read_strings() {
+        let device = MockQtestDevice::new();
+        device.write_mem(0x1000, b"hello\0world");
+        device.write_mem(0x2000, &[b'a'; 100]);
+        device.write_mem(0x3000, &[b'o', b'k', 0xff, 0xfe]);
+        let (mut parser, _irq_rx) = Parser::<SocketPair>::new("strings").await.unwrap();
+        device.serve(mem::connect("strings").await.unwrap());
+        parser.attach_connection().await.unwrap();
+
+        // Stops at the NUL terminator
+        assert_eq!(
+            read_cstring(&mut parser, 0x1000, 64).await.unwrap(),
+            "hello"
+        );
+        // Truncated to max_len, across a chunk boundary, when there is no terminator
+        let s = read_cstring(&mut parser, 0x2000, 70).await.unwrap();
+        assert_eq!(s, "a".repeat(70));
+
+        assert_eq!(read_utf8(&mut parser, 0x1000, 5).await.unwrap(), "hello");
+        assert_eq!(read_utf8(&mut parser, 0x3000, 2).await.unwrap(), "ok");
+        assert!(matches!(
+            read_utf8(&mut parser, 0x3000, 4).await,
+            Err(QtestError::Parse(_))
+        ));
+        assert!(matches!(
+            read_cstring(&mut parser, 0x3000, 16).await,
+            Err(QtestError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_utf16() {
+        let le = [b'h', 0, b'i', 0, 0xac, 0x20];
+        assert_eq!(decode_utf16(&le, Endianness::Little).unwrap(), "hi€");
+        let be = [0, b'h', 0, b'i', 0x20, 0xac];
+        assert_eq!(decode_utf16(&be, Endianness::Big).unwrap(), "hi€");
+        assert!(decode_utf16(&[0x00, 0xd8], Endianness::Little).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc;
+
+use crate::board::Board;
+use crate::config::{
+    ChannelConfig, Config, EndpointConfig, InterceptConfig, QemuConfig, RateLimitConfig,
+    TimeoutConfig,
+};
+use crate::machine::Machine;
+use crate::parser::ParserBuilder;
+use crate::socket::Socket;
+use crate::Irq;
+
+/// Environment variable with the path of the target registry
+pub const TARGETS_ENV: &str = "QTEST_TARGETS";
+
+/// File of the target registry, in the current directory, if [`TARGETS_ENV`] is not set
+pub const TARGETS_FILE: &str = "qtest-targets.toml";
+
+/// Registry of named test targets, usually loaded from a `qtest-targets.toml` file.
+///
+/// Each target has the sections of a [`Config`], plus an optional board description and credentials.
+/// Relative board paths are relative to the registry file. For example:
+///
+/// ```toml
+/// [targets.stm32-nightly]
+/// board = "boards/stm32f405.toml"
+/// endpoint = { scheme = "ssh", mode = "connect", url = "ssh://farm-1/tmp/stm32.sock" }
+/// credentials = { user = "ci", identity_file = "/home/ci/.ssh/farm" }
+/// timeouts = { response_ms = 5000 }
+///
+/// [targets.x86-smoke]
+/// endpoint = { scheme = "tcp", url = "localhost:3100" }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TargetRegistry {
+    /// Targets, by name
+    pub targets: BTreeMap<String, Target>,
+}
+
+impl TargetRegistry {
+    /// Reads the registry from the file set in [`TARGETS_ENV`], or from [`TARGETS_FILE`] in the current directory
+    pub fn load() -> io::Result<Self> {
+        let path = env::var_os(TARGETS_ENV)
+            .filter(|p| !p.is_empty())
+            .map_or_else(|| PathBuf::from(TARGETS_FILE), PathBuf::from);
+        Self::from_path(path)
+    }
+
+    /// Reads the registry from the given TOML file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut registry: Self = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?
+            .parse()?;
+        if let Some(dir) = path.parent() {
+            for board in registry
+                .targets
+                .values_mut()
+                .filter_map(|t| t.board.as_mut())
+            {
+                if board.is_relative() {
+                    *board = dir.join(&*board);
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Returns the names of the targets, in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.targets.keys().map(String::as_str)
+    }
+
+    /// Returns the target with the given name.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the registry has no such target.
+    pub fn get(&self, name: &str) -> io::Result<&Target> {
+        self.targets.get(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No target named {name} in the registry"),
+            )
+        })
+    }
+
+    /// Opens the machine of the target with the given name, see [`Target::open`]
+    pub async fn open<T: Socket>(
+        &self,
+        name: &str,
+    ) -> io::Result<(Machine<T>, mpsc::Receiver<Irq>)> {
+        self.get(name)?.open().await
+    }
+}
+
+impl std::str::FromStr for TargetRegistry {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Named test target: where to reach it, how to log in and which machine preset it runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Target {
+    /// Socket endpoint of the target
+    pub endpoint: EndpointConfig,
+    /// Credentials of the endpoint, if any
+    pub credentials: Option<Credentials>,
+    /// Board description file of the target, if any
+    pub board: Option<PathBuf>,
+    /// Timeouts of the sessions
+    pub timeouts: TimeoutConfig,
+    /// Internal channels of the parser
+    pub channels: ChannelConfig,
+    /// QEMU binary and arguments of the target
+    pub qemu: QemuConfig,
+    /// QOM paths whose IRQs are intercepted when QEMU connects, besides the ones of the board
+    pub intercepts: InterceptConfig,
+    /// Limits of the commands sent to QEMU
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Target {
+    /// Returns the session configuration of the target, with the credentials applied to its endpoint
+    pub fn config(&self) -> io::Result<Config> {
+        let mut endpoint = self.endpoint.clone();
+        if let Some(credentials) = &self.credentials {
+            endpoint.url = credentials.apply(&endpoint)?;
+        }
+        Ok(Config {
+            endpoint,
+            timeouts: self.timeouts.clone(),
+            channels: self.channels.clone(),
+            qemu: self.qemu.clone(),
+            intercepts: self.intercepts.clone(),
+            rate_limit: self.rate_limit.clone(),
+        })
+    }
+
+    /// Connects to the target and returns its machine, with the board description of the target, if any,
+    /// together with the receiver of the intercepted IRQs
+    pub async fn open<T: Socket>(&self) -> io::Result<(Machine<T>, mpsc::Receiver<Irq>)> {
+        let board = self.board.as_ref().map(Board::from_path).transpose()?;
+        let mut builder = ParserBuilder::<T>::from_config(&self.config()?);
+        if let Some(board) = &board {
+            builder = builder.board(board);
+        }
+        let (mut parser, irq_rx) = builder.build().await?;
+        parser.attach_connection().await?;
+        let machine = Machine::new(parser).board(board.unwrap_or_default());
+        Ok((machine, irq_rx))
+    }
+}
+
+/// Credentials to log in to the host of a remote endpoint
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Credentials {
+    /// User to log in as
+    pub user: Option<String>,
+    /// Private key to authenticate with
+    pub identity_file: Option<String>,
+}
+
+impl Credentials {
+    /// Returns the URL of the given endpoint with the credentials.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the endpoint does not support credentials.
+    fn apply(&self, endpoint: &EndpointConfig) -> io::Result<String> {
+        match endpoint.scheme {
+            #[cfg(all(feature = "ssh", unix))]
+            crate::config::Scheme::Ssh => {
+                let mut remote: crate::socket::ssh::SshEndpoint = endpoint.url.parse()?;
+                remote.user = self.user.clone().or(remote.user);
+                remote.identity_file = self.identity_file.clone().or(remote.identity_file);
+                Ok(remote.to_string())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The {:?} endpoint does not support credentials",
+                    endpoint.scheme
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Scheme;
+
+    const REGISTRY: &str = r#"
+        [targets.stm32-nightly]
+        board = "boards/stm32f405.toml"
+        endpoint = { scheme = "unix", mode = "connect", url = "/tmp/stm32.sock" }
+        timeouts = { response_ms = 5000 }
+
+        [targets.x86-smoke]
+        endpoint = { url = "localhost:3100" }
+        credentials = { user = "ci" }
+    "#;
+
+    #[test]
+    fn test_registry() {
+        let path = env::temp_dir().join(format!("qtest-targets-{}.toml", std::process::id()));
+        fs::write(&path, REGISTRY).unwrap();
+        let registry = TargetRegistry::from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["stm32-nightly", "x86-smoke"]
+        );
+        let target = registry.get("stm32-nightly").unwrap();
+        assert_eq!(
+            target.board.as_deref(),
+            Some(env::temp_dir().join("boards/stm32f405.toml").as_path())
+        );
+        let config = target.config().unwrap();
+        assert_eq!(config.endpoint.scheme, Scheme::Unix);
+        assert_eq!(config.endpoint.url, "/tmp/stm32.sock");
+        assert_eq!(config.timeouts.response_ms, Some(5000));
+
+        let err = registry.get("x86-smoke").unwrap().config().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = registry.get("arm-nightly").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!("[targets.x]\nport = 3000"
+            .parse::<TargetRegistry>()
+            .is_err());
+    }
+
+    #[cfg(all(feature = "ssh", unix))]
+    #[test]
+    fn test_ssh_credentials() {
+        let registry: TargetRegistry = r#"
+            [targets.farm]
+            endpoint = { scheme = "ssh", url = "ssh://farm-1:2222/tmp/qtest.sock" }
+            credentials = { user = "ci", identity_file = "/keys/farm" }
+        "#
+        .parse()
+        .unwrap();
+        let config = registry.get("farm").unwrap().config().unwrap();
+        assert_eq!(
+            config.endpoint.url,
+            "ssh://ci@farm-1:2222/tmp/qtest.sock?identity_file=/keys/farm"
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use std::{
+    fmt,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::broadcast, task::JoinHandle, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::compression;
+use crate::event::{Event, EventKind, Notification};
+use crate::logging::log_warn;
+use crate::parser::Parser;
+use crate::protocol::Command;
+use crate::socket::Socket;
+use crate::{Irq, Response};
+
+/// Recorded sequence of events of a qtest session.
+///
+/// Transcripts are stored as text, one event per line, with the format `<timestamp_us>[@<virtual_ns>] <tag> <payload>`.
+/// The tag is `>` for commands, `<` for responses and `!` for IRQs, and the payload is the line
+/// exchanged with QEMU. Connections are tagged with `*`, with a `connected` payload,
+/// and the notifications of [`crate::parser::LineHook`]s with `~`, with their name and payload.
+/// The virtual time of an event is only stored if it was known when the event happened. For example:
+///
+/// ```text
+/// 120 > writel 0x40000000 0x1
+/// 245 < OK
+/// 260 > clock_step 1000
+/// 270@1000 < OK 1000
+/// 300@1000 ! IRQ raise 3
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    /// The recorded events, in order of arrival
+    pub events: Vec<Event>,
+}
+
+impl Transcript {
+    /// Creates a transcript from the given events
+    pub fn new(events: Vec<Event>) -> Self {
+        Self { events }
+    }
+
+    /// Reads a transcript from the given file, decompressing it if it was saved with compression
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut text = String::new();
+        compression::open(path)?.read_to_string(&mut text)?;
+        text.parse()
+    }
+
+    /// Writes the transcript to the given file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        compression::create(path, |w| write!(w, "{self}"))
+    }
+
+    /// Writes the transcript to the given file, compressed with zstd at the given level (1-22, 0 for the default level)
+    #[cfg(feature = "zstd")]
+    pub fn save_compressed<P: AsRef<Path>>(&self, path: P, level: i32) -> io::Result<()> {
+        compression::create_compressed(path, level, |w| write!(w, "{self}"))
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            let timestamp = match event.virtual_ns {
+                Some(ns) => format!("{}@{ns}", event.timestamp.as_micros()),
+                None => event.timestamp.as_micros().to_string(),
+            };
+            match &event.kind {
+                EventKind::Command(cmd) => writeln!(f, "{timestamp} > {cmd}")?,
+                EventKind::Response(response) => writeln!(f, "{timestamp} < {response}")?,
+                EventKind::Irq(irq) => writeln!(f, "{timestamp} ! {irq}")?,
+                EventKind::Connected => writeln!(f, "{timestamp} * connected")?,
+                EventKind::Notification(notification) => {
+                    writeln!(f, "{timestamp} ~ {notification}")?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Transcript {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |msg: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {msg}: {line}", n + 1),
+                )
+            };
+
+            let mut parts = line.splitn(3, ' ');
+            let stamp = parts.next().unwrap_or_default();
+            let (timestamp, virtual_ns) = match stamp.split_once('@') {
+                Some((ts, ns)) => (ts, Some(ns)),
+                None => (stamp, None),
+            };
+            let timestamp = timestamp
+                .parse()
+                .map(Duration::from_micros)
+                .map_err(|_| invalid("invalid timestamp"))?;
+            let virtual_ns = virtual_ns
+                .map(|ns| ns.parse().map_err(|_| invalid("invalid virtual time")))
+                .transpose()?;
+            let tag = parts.next().ok_or_else(|| invalid("missing tag"))?;
+            let payload = parts.next().unwrap_or_default();
+
+            let kind = match tag {
+                ">" => EventKind::Command(payload.to_string()),
+                "<" => EventKind::Response(Response::from(payload)),
+                "!" => EventKind::Irq(Irq::try_from(payload).map_err(invalid)?),
+                "*" if payload == "connected" => EventKind::Connected,
+                "~" => {
+                    let (name, payload) = payload.split_once(' ').unwrap_or((payload, ""));
+                    EventKind::Notification(Notification::new(name, payload))
+                }
+                _ => return Err(invalid("unknown tag")),
+            };
+            events.push(Event {
+                timestamp,
+                virtual_ns,
+                kind,
+            });
+        }
+        Ok(Self { events })
+    }
+}
+
+/// Records the event stream of a session into a [`Transcript`].
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp, transcript::Recorder};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// let recorder = Recorder::start(parser.events());
+///
+/// parser.attach_connection().await.unwrap();
+/// parser.writel(0x4000_0000, 0x1).await.unwrap();
+///
+/// recorder.finish().await.save("session.qtest").unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Recorder {
+    events: Arc<Mutex<Vec<Event>>>,
+    cancel: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl Recorder {
+    /// Starts recording the events of the given receiver in a background task
+    pub fn start(mut rx: broadcast::Receiver<Event>) -> Self {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cancel = CancellationToken::new();
+        let (task_events, task_cancel) = (events.clone(), cancel.clone());
+        let task = tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = rx.recv() => event,
+                    _ = task_cancel.cancelled() => break,
+                };
+                match event {
+                    Ok(event) => task_events.lock().unwrap().push(event),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log_warn!(target: "qtest::transcript", "{n} events lost");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+            while let Ok(event) = rx.try_recv() {
+                task_events.lock().unwrap().push(event);
+            }
+        });
+        Self {
+            events,
+            cancel,
+            task,
+        }
+    }
+
+    /// Stops recording, once the events already published are recorded, and returns the recorded transcript
+    pub async fn finish(self) -> Transcript {
+        self.cancel.cancel();
+        let _ = self.task.await;
+        let events = std::mem::take(&mut *self.events.lock().unwrap());
+        Transcript::new(events)
+    }
+}
+
+/// Event of a replayed session that differs from the recorded one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReplayMismatch {
+    /// Position of the event in the session, not counting connections
+    pub index: usize,
+    /// The recorded event, `None` if the replayed session has more events
+    pub expected: Option<EventKind>,
+    /// The replayed event, `None` if the replayed session has less events
+    pub actual: Option<EventKind>,
+}
+
+/// Result of checking a replayed session against its recording
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ReplayReport {
+    /// The events that differ, in order
+    pub mismatches: Vec<ReplayMismatch>,
+    /// Number of replayed events lost because the verifier fell behind
+    pub lost: u64,
+}
+
+impl ReplayReport {
+    /// Returns true if the replayed session matches the recording
+    pub fn matches(&self) -> bool {
+        self.mismatches.is_empty() && self.lost == 0
+    }
+}
+
+/// Checks that the commands, responses and IRQs of a replayed session match a recorded [`Transcript`].
+///
+/// Timestamps and connections are not compared. Used together with the record/replay mode of QEMU
+/// ([`crate::qemu::QemuInstance`]) to verify that a bug is reproduced deterministically.
+#[derive(Debug)]
+pub struct ReplayVerifier {
+    report: Arc<Mutex<ReplayReport>>,
+    expected: Arc<Vec<EventKind>>,
+    checked: Arc<Mutex<usize>>,
+    cancel: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl ReplayVerifier {
+    /// Starts checking the events of the given receiver against the given transcript in a background task
+    pub fn start(mut rx: broadcast::Receiver<Event>, transcript: &Transcript) -> Self {
+        let expected: Arc<Vec<EventKind>> = Arc::new(
+            transcript
+                .events
+                .iter()
+                .map(|e| e.kind.clone())
+                .filter(|kind| *kind != EventKind::Connected)
+                .collect(),
+        );
+        let report = Arc::new(Mutex::new(ReplayReport::default()));
+        let checked = Arc::new(Mutex::new(0));
+        let cancel = CancellationToken::new();
+
+        let (task_expected, task_report, task_checked, task_cancel) = (
+            expected.clone(),
+            report.clone(),
+            checked.clone(),
+            cancel.clone(),
+        );
+        let task = tokio::spawn(async move {
+            let check = |kind: EventKind| {
+                if kind == EventKind::Connected {
+                    return;
+                }
+                let mut index = task_checked.lock().unwrap();
+                let recorded = task_expected.get(*index);
+                if recorded != Some(&kind) {
+                    task_report.lock().unwrap().mismatches.push(ReplayMismatch {
+                        index: *index,
+                        expected: recorded.cloned(),
+                        actual: Some(kind),
+                    });
+                }
+                *index += 1;
+            };
+            loop {
+                let event = tokio::select! {
+                    event = rx.recv() => event,
+                    _ = task_cancel.cancelled() => break,
+                };
+                match event {
+                    Ok(event) => check(event.kind),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log_warn!(target: "qtest::transcript", "{n} replayed events lost");
+                        task_report.lock().unwrap().lost += n;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+            while let Ok(event) = rx.try_recv() {
+                check(event.kind);
+            }
+        });
+        Self {
+            report,
+            expected,
+            checked,
+            cancel,
+            task,
+        }
+    }
+
+    /// Stops checking, once the events already received are checked, and returns the result
+    pub async fn finish(self) -> ReplayReport {
+        self.cancel.cancel();
+        let _ = self.task.await;
+        let mut report = std::mem::take(&mut *self.report.lock().unwrap());
+        let checked = *self.checked.lock().unwrap();
+        for index in checked..self.expected.len() {
+            report.mismatches.push(ReplayMismatch {
+                index,
+                expected: Some(self.expected[index].clone()),
+                actual: None,
+            });
+        }
+        report
+    }
+}
+
+/// Timing reproduced by a [`Replayer`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ReplayTiming {
+    /// Commands are sent back to back, and the virtual clock only advances with the recorded clock commands
+    #[default]
+    Fast,
+    /// Before every command, the virtual clock is stepped to keep the recorded virtual-time spacing
+    Virtual,
+    /// As [`ReplayTiming::Virtual`], and every command is also delayed to keep the recorded host-time spacing
+    VirtualAndHost,
+}
+
+/// Replays the commands of a recorded [`Transcript`] and checks their responses.
+///
+/// With [`ReplayTiming::Virtual`], clock steps are inserted so every command is sent at the same virtual time,
+/// relative to the first event with a known virtual time, as in the recording. The replay then exercises the
+/// same timing windows as the original run, even if the recording advanced the clock outside of the session.
+/// The inserted steps are not compared. Recorded IRQs and notifications are not checked either:
+/// use a [`ReplayVerifier`] on the events of the parser for a full comparison.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp, transcript::{Replayer, ReplayTiming, Transcript}};
+/// let transcript = Transcript::load("failure.qtest").unwrap();
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let report = Replayer::new(&transcript)
+///     .timing(ReplayTiming::Virtual)
+///     .run(&mut parser)
+///     .await
+///     .unwrap();
+/// assert!(report.matches());
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replayer {
+    events: Vec<Event>,
+    timing: ReplayTiming,
+}
+
+impl Replayer {
+    /// Creates a replayer of the given transcript, with [`ReplayTiming::Fast`] timing
+    pub fn new(transcript: &Transcript) -> Self {
+        Self {
+            events: transcript
+                .events
+                .iter()
+                .filter(|e| !matches!(e.kind, EventKind::Connected | EventKind::Notification(_)))
+                .cloned()
+                .collect(),
+            timing: ReplayTiming::default(),
+        }
+    }
+
+    /// Sets the timing reproduced by the replay
+    pub fn timing(mut self, timing: ReplayTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Sends the recorded commands through the given attached parser, and compares their responses.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if a recorded command is not a valid qtest command.
+    pub async fn run<T: Socket>(&self, parser: &mut Parser<T>) -> io::Result<ReplayReport> {
+        let mut report = ReplayReport::default();
+        let start = Instant::now();
+        let first_timestamp = self.events.first().map_or(Duration::ZERO, |e| e.timestamp);
+        // Replayed virtual time minus recorded virtual time
+        let mut offset: Option<i128> = None;
+
+        for (index, event) in self.events.iter().enumerate() {
+            let EventKind::Command(line) = &event.kind else {
+                continue;
+            };
+            let command: Command = line.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid recorded command {line:?}: {e}"),
+                )
+            })?;
+
+            if self.timing == ReplayTiming::VirtualAndHost {
+                tokio::time::sleep_until(start + (event.timestamp - first_timestamp)).await;
+            }
+            if let (ReplayTiming::Virtual | ReplayTiming::VirtualAndHost, Some(recorded)) =
+                (self.timing, event.virtual_ns)
+            {
+                let now = match parser.cached_clock() {
+                    Some(now) => now,
+                    None => parser.clock_step_ns(Some(0)).await?,
+                };
+                let offset = *offset.get_or_insert(now as i128 - recorded as i128);
+                let target = recorded as i128 + offset;
+                if target > now as i128 {
+                    parser
+                        .clock_step_ns(Some((target - now as i128) as usize))
+                        .await?;
+                }
+            }
+
+            let actual = parser.execute(command).await?;
+            // IRQs may be recorded between a command and its reply, but the reply comes before the next command
+            let expected = self.events[index + 1..]
+                .iter()
+                .enumerate()
+                .take_while(|(_, e)| !matches!(e.kind, EventKind::Command(_)))
+                .find_map(|(offset, e)| match &e.kind {
+                    EventKind::Response(expected) => Some((index + 1 + offset, expected)),
+                    _ => None,
+                });
+            if let Some((expected_index, expected)) = expected {
+                if *expected != actual {
+                    report.mismatches.push(ReplayMismatch {
+                        index: expected_index,
+                        expected: Some(EventKind::Response(expected.clone())),
+                        actual: Some(EventKind::Response(actual)),
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IrqState;
+
+    #[test]
+    fn test_transcript_round_trip() {
+        let text = "120 > writel 0x40000000 0x1\n245 < OK\n300 ! IRQ raise 3\n310 < OK 0x10\n400 * connected\n410 ~ TRACE dma start\n";
+        let transcript: Transcript = text.parse().unwrap();
+
+        assert_eq!(transcript.events.len(), 6);
+        assert_eq!(
+            transcript.events[2],
+            Event {
+                timestamp: Duration::from_micros(300),
+                virtual_ns: None,
+                kind: EventKind::Irq(Irq::new(3, IrqState::Raise)),
+            }
+        );
+        assert_eq!(transcript.to_string(), text);
+
+        let text = "260 > clock_step 1000\n270@1000 < OK 1000\n";
+        let transcript: Transcript = text.parse().unwrap();
+        assert_eq!(transcript.events[0].virtual_ns, None);
+        assert_eq!(transcript.events[1].virtual_ns, Some(1000));
+        assert_eq!(transcript.to_string(), text);
+
+        assert!("abc > readl 0x0".parse::<Transcript>().is_err());
+        assert!("1@x > readl 0x0".parse::<Transcript>().is_err());
+        assert!("1 ? readl 0x0".parse::<Transcript>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recorder_keeps_tail() {
+        let (tx, rx) = broadcast::channel(16);
+        let recorder = Recorder::start(rx);
+        let event = |line: usize| Event {
+            timestamp: Duration::ZERO,
+            virtual_ns: None,
+            kind: EventKind::Irq(Irq::new(line, IrqState::Raise)),
+        };
+        // Published right before finishing, before the recording task runs
+        for line in 0..8 {
+            tx.send(event(line)).unwrap();
+        }
+        let transcript = recorder.finish().await;
+        assert_eq!(transcript.events, (0..8).map(event).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_replay_verifier() {
+        let transcript: Transcript = "0 * connected\n1 > readl 0x0\n2 < OK 0x1\n3 ! IRQ raise 3\n"
+            .parse()
+            .unwrap();
+        let event = |kind| Event {
+            timestamp: Duration::ZERO,
+            virtual_ns: None,
+            kind,
+        };
+
+        let (tx, rx) = broadcast::channel(16);
+        let verifier = ReplayVerifier::start(rx, &transcript);
+        tx.send(event(EventKind::Connected)).unwrap();
+        tx.send(event(EventKind::Command("readl 0x0".to_string())))
+            .unwrap();
+        tx.send(event(EventKind::Response(Response::from("OK 0x1"))))
+            .unwrap();
+        tx.send(event(EventKind::Irq(Irq::new(3, IrqState::Raise))))
+            .unwrap();
+        assert!(verifier.finish().await.matches());
+
+        let (tx, rx) = broadcast::channel(16);
+        let verifier = ReplayVerifier::start(rx, &transcript);
+        tx.send(event(EventKind::Command("readl 0x0".to_string())))
+            .unwrap();
+        tx.send(event(EventKind::Response(Response::from("OK 0x2"))))
+            .unwrap();
+        let report = verifier.finish().await;
+        assert_eq!(report.mismatches.len(), 2);
+        assert_eq!(report.mismatches[0].index, 1);
+        assert_eq!(
+            report.mismatches[0].actual,
+            Some(EventKind::Response(Response::from("OK 0x2")))
+        );
+        assert_eq!(report.mismatches[1].actual, None);
+    }
+}
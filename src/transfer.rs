@@ -0,0 +1,289 @@
+use std::io;
+
+use crate::address::Address;
+use crate::error::QtestError;
+use crate::logging::in_span;
+use crate::parser::Parser;
+use crate::socket::Socket;
+use crate::Response;
+
+pub use futures_util::stream::{Stream, StreamExt};
+pub use tokio_util::sync::CancellationToken;
+
+/// Maximum number of bytes transferred in a single read or write command
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Writes the given bytes to guest memory, splitting them in several write commands if necessary.
+///
+/// After each chunk, `progress` is called with the number of bytes written so far and the total number of bytes.
+pub async fn write_chunked<T: Socket, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    data: &[u8],
+    progress: F,
+) -> Result<(), QtestError> {
+    write_chunked_cancellable(parser, addr, data, progress, &CancellationToken::new()).await
+}
+
+/// Same as [`write_chunked`], but the transfer can be aborted with the given cancellation token.
+///
+/// Cancellation is checked before each chunk, so the guest memory is never left with a partially written chunk.
+/// If the token is cancelled, returns an error of kind [`io::ErrorKind::Interrupted`].
+pub async fn write_chunked_cancellable<T: Socket, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    data: &[u8],
+    mut progress: F,
+    cancel: &CancellationToken,
+) -> Result<(), QtestError> {
+    let addr = parser.resolve(addr)?;
+    let mut done = 0;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        if cancel.is_cancelled() {
+            return Err(cancelled(done, data.len()));
+        }
+        let chunk_addr = addr + done;
+        let response = in_span!(
+            target: "qtest::transfer",
+            "qtest.chunk",
+            verb = "write",
+            addr = chunk_addr,
+            len = chunk.len();
+            parser.write(chunk_addr, &encode_hex(chunk), Some(chunk.len()))
+        )
+        .await?;
+        if let Response::Err(e) = response {
+            return Err(QtestError::Fail(format!(
+                "Could not write to {chunk_addr:#x}: {e}"
+            )));
+        }
+        done += chunk.len();
+        progress(done, data.len());
+    }
+    Ok(())
+}
+
+/// Same as [`write_chunked`], but the chunks are written with `b64write`, whose base64 data is more compact
+/// than the hexadecimal data of `write`, so large images take fewer bytes on the socket.
+#[cfg(feature = "base64")]
+pub async fn b64write_chunked<T: Socket, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    data: &[u8],
+    mut progress: F,
+) -> Result<(), QtestError> {
+    let addr = parser.resolve(addr)?;
+    let mut done = 0;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        let chunk_addr = addr + done;
+        in_span!(
+            target: "qtest::transfer",
+            "qtest.chunk",
+            verb = "b64write",
+            addr = chunk_addr,
+            len = chunk.len();
+            parser.b64write_bytes(chunk_addr, chunk)
+        )
+        .await
+        .map_err(|e| match e {
+            QtestError::Fail(e) => {
+                QtestError::Fail(format!("Could not write to {chunk_addr:#x}: {e}"))
+            }
+            e => e,
+        })?;
+        done += chunk.len();
+        progress(done, data.len());
+    }
+    Ok(())
+}
+
+/// Reads the given number of bytes from guest memory, splitting them in several read commands if necessary.
+///
+/// After each chunk, `progress` is called with the number of bytes read so far and the total number of bytes.
+pub async fn read_chunked<T: Socket, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+    progress: F,
+) -> Result<Vec<u8>, QtestError> {
+    read_chunked_cancellable(parser, addr, len, progress, &CancellationToken::new()).await
+}
+
+/// Same as [`read_chunked`], but the transfer can be aborted with the given cancellation token.
+///
+/// Cancellation is checked before each chunk.
+/// If the token is cancelled, returns an error of kind [`io::ErrorKind::Interrupted`].
+pub async fn read_chunked_cancellable<T: Socket, F: FnMut(usize, usize)>(
+    parser: &mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+    mut progress: F,
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>, QtestError> {
+    let addr = parser.resolve(addr)?;
+    let mut data = Vec::with_capacity(len);
+    for (chunk_addr, chunk_len) in chunks(addr, len) {
+        if cancel.is_cancelled() {
+            return Err(cancelled(data.len(), len));
+        }
+        let chunk = in_span!(
+            target: "qtest::transfer",
+            "qtest.chunk",
+            verb = "read",
+            addr = chunk_addr,
+            len = chunk_len;
+            parser.read(chunk_addr, chunk_len)
+        )
+        .await?;
+        data.extend_from_slice(&decode_chunk(&chunk, chunk_len)?);
+        progress(data.len(), len);
+    }
+    Ok(data)
+}
+
+/// Streams the given number of bytes from guest memory, in chunks of up to `chunk` bytes with their start address.
+///
+/// Each chunk is only read when the consumer polls for it, so large regions can be hashed, scanned or written
+/// to a file incrementally, without holding them in memory. Chunks larger than [`CHUNK_SIZE`] are read with
+/// several commands. The stream ends after the first error, e.g. an error of kind
+/// [`io::ErrorKind::InvalidInput`] if `chunk` is 0.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp, transfer::{stream_region, StreamExt}};
+/// let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+/// parser.attach_connection().await.unwrap();
+///
+/// let mut pages = std::pin::pin!(stream_region(&mut parser, 0x2000_0000, 0x10_0000, 0x1000));
+/// while let Some(page) = pages.next().await {
+///     let (addr, data) = page.unwrap();
+///     if data.windows(4).any(|w| w == b"\xde\xad\xbe\xef") {
+///         println!("marker in page {addr:#x}");
+///     }
+/// }
+/// # }
+/// ```
+pub fn stream_region<'a, T: Socket>(
+    parser: &'a mut Parser<T>,
+    addr: impl Into<Address>,
+    len: usize,
+    chunk: usize,
+) -> impl Stream<Item = Result<(usize, Vec<u8>), QtestError>> + 'a {
+    let start = match chunk {
+        0 => Err(QtestError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Chunks must be at least 1 byte long",
+        ))),
+        _ => parser.resolve(addr),
+    };
+    futures_util::stream::unfold(
+        (parser, start, 0),
+        move |(parser, start, done)| async move {
+            let addr = match start {
+                Ok(addr) if done < len => addr,
+                Ok(_) => return None,
+                Err(e) => return Some((Err(e), (parser, Ok(0), len))),
+            };
+            let chunk_addr = addr + done;
+            let chunk_len = chunk.min(len - done);
+            match read_chunked(parser, chunk_addr, chunk_len, |_, _| {}).await {
+                Ok(data) => Some((Ok((chunk_addr, data)), (parser, start, done + chunk_len))),
+                // Stop after the error
+                Err(e) => Some((Err(e), (parser, Ok(0), len))),
+            }
+        },
+    )
+}
+
+/// Returns the address and length of the chunks of a transfer of `len` bytes starting at `addr`
+pub(crate) fn chunks(addr: usize, len: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..len)
+        .step_by(CHUNK_SIZE)
+        .map(move |done| (addr + done, CHUNK_SIZE.min(len - done)))
+}
+
+/// Decodes the data read for a chunk of `chunk_len` bytes.
+///
+/// Returns a [`QtestError::Protocol`] error if QEMU returned another number of bytes.
+pub(crate) fn decode_chunk(data: &str, chunk_len: usize) -> Result<Vec<u8>, QtestError> {
+    let chunk = decode_hex(data).map_err(|e| QtestError::Parse(format!("{e}: {data}")))?;
+    if chunk.len() != chunk_len {
+        return Err(QtestError::Protocol(format!(
+            "Expected {chunk_len} bytes, received {}",
+            chunk.len()
+        )));
+    }
+    Ok(chunk)
+}
+
+/// Error returned when a transfer is cancelled
+fn cancelled(done: usize, total: usize) -> QtestError {
+    QtestError::Io(io::Error::new(
+        io::ErrorKind::Interrupted,
+        format!("Transfer cancelled after {done} of {total} bytes"),
+    ))
+}
+
+/// Encodes the given bytes as a hexadecimal string, as expected by the qtest write command
+pub(crate) fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decodes a hexadecimal string, with or without the `0x` prefix, as returned by the qtest read command
+pub(crate) fn decode_hex(s: &str) -> io::Result<Vec<u8>> {
+    let s = s.trim().trim_start_matches("0x");
+    if !s.len().is_multiple_of(2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Odd number of hex digits",
+        ));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid hex digit"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex_round_trip() {
+        let data = [0x00, 0x01, 0xab, 0xff];
+        assert_eq!(encode_hex(&data), "0001abff");
+        assert_eq!(decode_hex("0x0001abff").unwrap(), data);
+        assert_eq!(decode_hex("0001ABFF\n").unwrap(), data);
+        assert!(decode_hex("0x123").is_err());
+        assert!(decode_hex("0xzz").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_region() {
+        use crate::socket::dry_run::SocketDryRun;
+
+        let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+        parser.attach_connection().await.unwrap();
+        let chunks: Vec<_> = stream_region(&mut parser, 0x1000, 10, 4)
+            .map(|chunk| chunk.map(|(addr, data)| (addr, data.len())).unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks, [(0x1000, 4), (0x1004, 4), (0x1008, 2)]);
+
+        let errors: Vec<_> = stream_region(&mut parser, "missing", 10, 4).collect().await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_err());
+        let errors: Vec<_> = stream_region(&mut parser, 0x1000, 10, 0).collect().await;
+        assert_eq!(
+            errors[0].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
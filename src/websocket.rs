@@ -0,0 +1,88 @@
+use futures_util::{SinkExt, StreamExt};
+use std::io;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::event::Event;
+use crate::logging::log_warn;
+
+/// WebSocket endpoint that pushes the unified event stream to every connected client as JSON.
+///
+/// Each event is sent as a text message containing the output of [`Event::to_json`].
+/// It is intended for browser-based live monitors of long sessions.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(feature = "tcp")]
+/// # async fn example() {
+/// # use qtest::{parser::Parser, socket::tcp::SocketTcp, websocket::EventFeed};
+/// let (parser, _irq_rx) = Parser::<SocketTcp>::new("localhost:3000").await.unwrap();
+///
+/// let feed = EventFeed::bind("localhost:3001").await.unwrap();
+/// tokio::spawn(feed.serve(parser.events()));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct EventFeed {
+    listener: TcpListener,
+}
+
+impl EventFeed {
+    /// Creates a new WebSocket endpoint listening at the given address
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self { listener })
+    }
+
+    /// Returns the address of the endpoint
+    pub fn address(&self) -> String {
+        let addr = self.listener.local_addr().unwrap();
+        format!("{}:{}", addr.ip(), addr.port())
+    }
+
+    /// Accepts WebSocket clients and forwards them the events of the given receiver.
+    ///
+    /// Clients only receive the events published after they connected.
+    /// Returns Err if the listener fails to accept new connections.
+    pub async fn serve(self, events: broadcast::Receiver<Event>) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            let client_events = events.resubscribe();
+            tokio::spawn(async move {
+                if let Err(e) = forward_events(stream, client_events).await {
+                    log_warn!(target: "qtest::websocket", "client error: {e:?}");
+                }
+            });
+        }
+    }
+}
+
+/// Performs the WebSocket handshake and forwards events until the client disconnects.
+async fn forward_events(
+    stream: TcpStream,
+    mut events: broadcast::Receiver<Event>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    let (mut ws_tx, mut ws_rx) = ws_stream.split();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => ws_tx.send(Message::text(event.to_json())).await?,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log_warn!(target: "qtest::websocket", "client lagged behind, {n} events lost");
+                }
+                Err(broadcast::error::RecvError::Closed) => return ws_tx.close().await,
+            },
+            msg = ws_rx.next() => match msg {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+            },
+        }
+    }
+}
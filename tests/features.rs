@@ -0,0 +1,433 @@
+//! Tests of the optional transports and codecs, run once per enabled feature.
+#[cfg(any(feature = "tcp", feature = "unix"))]
+use qtest::socket::Socket;
+#[cfg(any(feature = "tcp", feature = "unix"))]
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_tcp_socket() {
+    use qtest::socket::tcp::SocketTcp;
+    use tokio::io::AsyncReadExt;
+
+    let (tx, mut rx) = mpsc::channel(32);
+    let mut socket = SocketTcp::new("127.0.0.1:0", tx).await.unwrap();
+    let mut qemu = tokio::net::TcpStream::connect(socket.address())
+        .await
+        .unwrap();
+    socket.attach_connection().await.unwrap();
+
+    socket.send("readl 0x0\n").await.unwrap();
+    let mut buf = [0; 10];
+    qemu.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"readl 0x0\n");
+
+    qemu.write_all(b"OK 0x1\n").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().trim_matches('\0'), "OK 0x1\n");
+}
+
+#[cfg(feature = "unix")]
+#[tokio::test]
+async fn test_unix_socket() {
+    use qtest::socket::unix::SocketUnix;
+
+    let path = std::env::temp_dir().join(format!("qtest-features-{}.sock", std::process::id()));
+    let (tx, mut rx) = mpsc::channel(32);
+    let mut socket = SocketUnix::new(path.to_str().unwrap(), tx).await.unwrap();
+    let mut qemu = tokio::net::UnixStream::connect(socket.address())
+        .await
+        .unwrap();
+    socket.attach_connection().await.unwrap();
+
+    qemu.write_all(b"OK 0x1\n").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().trim_matches('\0'), "OK 0x1\n");
+
+    socket.close().unwrap();
+}
+
+#[cfg(all(feature = "log", feature = "tcp", not(feature = "tracing")))]
+#[tokio::test]
+async fn test_log_targets() {
+    use qtest::socket::tcp::SocketTcp;
+    use std::sync::Mutex;
+
+    /// Logger that stores the target and message of every record
+    struct Capture(Mutex<Vec<(String, String)>>);
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            let entry = (record.target().to_string(), record.args().to_string());
+            self.0.lock().unwrap().push(entry);
+        }
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Capture = Capture(Mutex::new(Vec::new()));
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let (tx, _rx) = mpsc::channel(32);
+    let mut socket = SocketTcp::new("127.0.0.1:0", tx).await.unwrap();
+    let qemu = tokio::net::TcpStream::connect(socket.address())
+        .await
+        .unwrap();
+    socket.attach_connection().await.unwrap();
+    drop(qemu);
+
+    for _ in 0..100 {
+        let logged =
+            LOGGER.0.lock().unwrap().iter().any(|(target, msg)| {
+                target == "qtest::socket" && msg == "Connection closed by peer"
+            });
+        if logged {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("the socket did not log the disconnection");
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_tcp_connect_mode() {
+    use qtest::socket::{tcp::SocketTcp, Mode};
+    use tokio::io::AsyncReadExt;
+
+    let qemu = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = qemu.local_addr().unwrap().to_string();
+    let (tx, mut rx) = mpsc::channel(32);
+    let mut socket = SocketTcp::with_mode(&addr, tx, Mode::Connect)
+        .await
+        .unwrap();
+    assert_eq!(socket.address(), addr);
+
+    let (attached, accepted) = tokio::join!(socket.attach_connection(), qemu.accept());
+    attached.unwrap();
+    let (mut qemu, _) = accepted.unwrap();
+
+    socket.send("readl 0x0\n").await.unwrap();
+    let mut buf = [0; 10];
+    qemu.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"readl 0x0\n");
+
+    qemu.write_all(b"OK 0x1\n").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().trim_matches('\0'), "OK 0x1\n");
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_tcp_client_parser() {
+    use qtest::{parser::Parser, socket::tcp::SocketTcpClient};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let qemu = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = qemu.local_addr().unwrap().to_string();
+    let (mut parser, _irq_rx) = Parser::<SocketTcpClient>::new(&addr).await.unwrap();
+
+    let (attached, accepted) = tokio::join!(parser.attach_connection(), qemu.accept());
+    attached.unwrap();
+    let mut qemu = BufReader::new(accepted.unwrap().0);
+
+    let qemu = async {
+        let mut line = String::new();
+        qemu.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "readl 0x0\n");
+        qemu.get_mut().write_all(b"OK 0x1\n").await.unwrap();
+    };
+    let (value, _) = tokio::join!(parser.readl(0x0), qemu);
+    assert_eq!(value.unwrap(), 1);
+}
+
+#[cfg(feature = "unix")]
+#[tokio::test]
+async fn test_unix_connect_mode() {
+    use qtest::socket::{unix::SocketUnix, Mode};
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join(format!("qtest-connect-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (tx, mut rx) = mpsc::channel(32);
+    let mut socket = SocketUnix::with_mode(path.to_str().unwrap(), tx, Mode::Connect)
+        .await
+        .unwrap();
+
+    // QEMU starts listening after the parser tries to connect
+    let qemu = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        listener.accept().await.unwrap().0
+    };
+    let (attached, mut qemu) = tokio::join!(socket.attach_connection(), qemu);
+    attached.unwrap();
+
+    qemu.write_all(b"OK 0x1\n").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().trim_matches('\0'), "OK 0x1\n");
+
+    socket.close().unwrap();
+    assert!(path.exists());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_tcp_reattach() {
+    use qtest::socket::tcp::SocketTcp;
+
+    let (tx, mut rx) = mpsc::channel(32);
+    let mut socket = SocketTcp::new("127.0.0.1:0", tx).await.unwrap();
+    for i in 0..3 {
+        let mut qemu = tokio::net::TcpStream::connect(socket.address())
+            .await
+            .unwrap();
+        socket.attach_connection().await.unwrap();
+        qemu.write_all(format!("OK {i}\n").as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(
+            rx.recv().await.unwrap().trim_matches('\0'),
+            format!("OK {i}\n")
+        );
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[tokio::test]
+async fn test_tracing_events() {
+    use qtest::{
+        parser::{ParserBuilder, ReaderConfig, UnknownLines},
+        socket::mem::{self, SocketPair},
+    };
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tracing::{field::Field, span, Metadata, Subscriber};
+
+    /// Subscriber that stores the target and message of every event
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<(String, String)>>>);
+
+    struct Message<'a>(&'a mut String);
+
+    impl tracing::field::Visit for Message<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0.push_str(&format!("{value:?}"));
+            }
+        }
+    }
+
+    impl Subscriber for Events {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut message = String::new();
+            event.record(&mut Message(&mut message));
+            let target = event.metadata().target().to_string();
+            self.0.lock().unwrap().push((target, message));
+        }
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let events = Events::default();
+    let _guard = tracing::subscriber::set_default(events.clone());
+
+    let (mut parser, mut irq_rx) = ParserBuilder::<SocketPair>::new("tracing")
+        .reader(ReaderConfig::new().unknown_lines(UnknownLines::Drop))
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("tracing").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = BufReader::new(stream);
+    let qemu_side = async {
+        let mut line = String::new();
+        qemu.read_line(&mut line).await.unwrap();
+        qemu.get_mut()
+            .write_all(b"IRQ bogus 3\nIRQ raise 3\nOK 0x2a\n")
+            .await
+            .unwrap();
+    };
+    let (value, _) = tokio::join!(parser.readl(0x1000), qemu_side);
+    assert_eq!(value.unwrap(), 0x2a);
+    irq_rx.recv().await.unwrap();
+
+    let events = events.0.lock().unwrap().clone();
+    let logged =
+        |target: &str, message: &str| events.iter().any(|(t, m)| t == target && m == message);
+    assert!(logged("qtest::socket", "Connection attached at tracing"));
+    assert!(logged("qtest::parser", "> readl 0x1000"));
+    assert!(logged(
+        "qtest::parser",
+        "could not parse IRQ line \"IRQ bogus 3\": Invalid IRQ type"
+    ));
+    assert!(logged(
+        "qtest::parser",
+        "dispatched IRQ raise 3 to 0 event subscribers and the IRQ receiver"
+    ));
+    assert!(logged("qtest::parser", "< OK 0x2a"));
+}
+
+#[cfg(feature = "tracing")]
+#[tokio::test]
+async fn test_tracing_spans() {
+    use qtest::{parser::Parser, socket::dry_run::SocketDryRun};
+    use std::sync::{Arc, Mutex};
+    use tracing::{field::Field, span, Metadata, Subscriber};
+
+    /// Subscriber that stores the name and fields of every span
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<String>>>);
+
+    struct Fields<'a>(&'a mut String);
+
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+            let mut span = attrs.metadata().name().to_string();
+            attrs.record(&mut Fields(&mut span));
+            let mut spans = self.0.lock().unwrap();
+            spans.push(span);
+            span::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, _: &tracing::Event<'_>) {}
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let spans = Spans::default();
+    let _guard = tracing::subscriber::set_default(spans.clone());
+
+    let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    parser.readl(0x1000).await.unwrap();
+    qtest::transfer::write_chunked(&mut parser, 0x2000, &[0; 8], |_, _| {})
+        .await
+        .unwrap();
+
+    let spans = spans.0.lock().unwrap().clone();
+    assert_eq!(
+        spans,
+        [
+            "qtest.command verb=\"readl\" addr=4096 len=4",
+            "qtest.chunk verb=\"write\" addr=8192 len=8",
+            "qtest.command verb=\"write\" addr=8192 len=8",
+        ]
+    );
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_mock_device() {
+    use qtest::{mock::MockQtestDevice, parser::Parser, socket::tcp::SocketTcp, Irq, IrqState};
+
+    let device = MockQtestDevice::new();
+    device.write_mem(0x1000, &[0x78, 0x56, 0x34, 0x12]);
+    device.schedule_irq(1_000, Irq::new(7, IrqState::Raise));
+    device.on_write(0x2000, |_| vec![Irq::new(3, IrqState::Raise)]);
+
+    let (mut parser, mut irq_rx) = Parser::<SocketTcp>::new("127.0.0.1:0").await.unwrap();
+    let address = parser.socket().address();
+    let (served, attached) = tokio::join!(device.connect_tcp(&address), parser.attach_connection());
+    served.unwrap();
+    attached.unwrap();
+
+    assert_eq!(parser.readl(0x1000).await.unwrap(), 0x1234_5678);
+    parser.writeb(0x2000, 1).await.unwrap();
+    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(3, IrqState::Raise));
+    assert_eq!(parser.clock_step_ns(None).await.unwrap(), 1_000);
+    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(7, IrqState::Raise));
+    assert_eq!(device.clock_ns(), 1_000);
+
+    // IRQs raised by the test itself, while the parser is idle
+    device.set_irq(7, IrqState::Lower);
+    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(7, IrqState::Lower));
+
+    parser
+        .set_irq_in("/machine/soc", "gpio", 2, 1)
+        .await
+        .unwrap();
+    assert_eq!(device.irq_in_level("/machine/soc", "gpio", 2), Some(1));
+    assert_eq!(device.commands().len(), 4);
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_gpio_device() {
+    use qtest::{
+        devices::gpio::{Gpio, Level},
+        mock::MockQtestDevice,
+        parser::Parser,
+        region::MemRegion,
+        socket::tcp::SocketTcp,
+        Irq, IrqState,
+    };
+
+    let device = MockQtestDevice::new();
+    // Writing the output data register drives pin 5
+    device.on_write(0x4002_0814, |val| {
+        let state = if val & (1 << 5) != 0 {
+            IrqState::Raise
+        } else {
+            IrqState::Lower
+        };
+        vec![Irq::new(5, state)]
+    });
+
+    let (mut parser, _irq_rx) = Parser::<SocketTcp>::new("127.0.0.1:0").await.unwrap();
+    let address = parser.socket().address();
+    let (served, attached) = tokio::join!(device.connect_tcp(&address), parser.attach_connection());
+    served.unwrap();
+    attached.unwrap();
+
+    let gpio = Gpio::new("/machine/soc/gpio[2]")
+        .input("input-in")
+        .registers(MemRegion::new(0x4002_0800, 0x400));
+    gpio.intercept(&mut parser).await.unwrap();
+    assert_eq!(parser.intercepts()[0].lines[0].gpio, "unnamed-gpio-out");
+
+    gpio.set_pin(&mut parser, 13, Level::High).await.unwrap();
+    assert_eq!(
+        device.irq_in_level("/machine/soc/gpio[2]", "input-in", 13),
+        Some(1)
+    );
+    let err = gpio.set_pin(&mut parser, 16, Level::Low).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    let mut led = gpio.watch_pin(&parser, 5);
+    assert_eq!(gpio.pull(&parser, 5), Level::Low);
+    gpio.write_reg(&mut parser, 0x14, 1u32 << 5).await.unwrap();
+    led.wait_for(Level::High).await.unwrap();
+    assert_eq!(gpio.pull(&parser, 5), Level::High);
+    assert_eq!(
+        gpio.read_reg::<u32>(&mut parser, 0x14).await.unwrap(),
+        1 << 5
+    );
+
+    gpio.write_reg(&mut parser, 0x14, 0u32).await.unwrap();
+    assert_eq!(led.changed().await.unwrap(), Level::Low);
+
+    let err = Gpio::new("/machine/soc/gpio[0]")
+        .read_reg::<u32>(&mut parser, 0x10)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
@@ -0,0 +1,6 @@
+#!/bin/sh
+# Answers every qtest command read from stdin, as QEMU does with `-qtest stdio`
+[ "$1" = "-qtest" ] && [ "$2" = "stdio" ] || exit 2
+while read -r command; do
+    echo "OK 0x2a"
+done
@@ -0,0 +1,1767 @@
+//! Tests of the parser against a fake QEMU connected through an in-memory socket.
+use qtest::{
+    event::EventKind,
+    parser::{InterceptDirection, Parser},
+    socket::{
+        mem::{self, SocketPair},
+        Socket,
+    },
+    Endianness, Irq, IrqState, Response,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream},
+    sync::mpsc,
+};
+
+/// Fake QEMU side of a qtest connection
+struct Qemu {
+    stream: BufReader<DuplexStream>,
+}
+
+impl Qemu {
+    /// Expects the given command and answers with the given line
+    async fn reply(&mut self, command: &str, response: &str) {
+        let mut line = String::new();
+        self.stream.read_line(&mut line).await.unwrap();
+        assert_eq!(line, format!("{command}\n"));
+        self.send(response).await;
+    }
+
+    /// Sends the given line
+    async fn send(&mut self, line: &str) {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+}
+
+/// Creates a parser connected to a fake QEMU
+async fn connect() -> (Parser<SocketPair>, mpsc::Receiver<Irq>, Qemu) {
+    connect_with().await
+}
+
+/// Creates a parser with the given in-memory socket, or a wrapper of it, connected to a fake QEMU
+async fn connect_with<T: Socket>() -> (Parser<T>, mpsc::Receiver<Irq>, Qemu) {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!("qemu-{}", NEXT.fetch_add(1, Ordering::Relaxed));
+    let (mut parser, irq_rx) = Parser::<T>::new(&name).await.unwrap();
+    let stream = mem::connect(&name).await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+    (parser, irq_rx, qemu)
+}
+
+#[tokio::test]
+async fn test_intercept_registry() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let (response, _) = tokio::join!(
+        parser.irq_intercept_in("/machine/soc"),
+        qemu.reply("irq_intercept_in /machine/soc", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+
+    let err = parser.irq_intercept_in("/machine/soc").await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+    let (response, _) = tokio::join!(
+        parser.irq_intercept_out("/machine/soc"),
+        qemu.reply("irq_intercept_out /machine/soc", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+
+    let directions: Vec<_> = parser.intercepts().iter().map(|i| i.direction).collect();
+    assert_eq!(
+        directions,
+        [InterceptDirection::In, InterceptDirection::Out]
+    );
+}
+
+#[tokio::test]
+async fn test_irq_attribution() {
+    let (mut parser, mut irq_rx, mut qemu) = connect().await;
+    let mut irq_events = parser.irq_events();
+
+    let (response, _) = tokio::join!(
+        parser.irq_intercept_out("/machine/soc"),
+        qemu.reply("irq_intercept_out /machine/soc", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+    let id = parser
+        .map_irq_lines("/machine/soc", InterceptDirection::Out, "uart", 4..8)
+        .unwrap();
+    assert!(parser
+        .map_irq_lines("/machine/soc", InterceptDirection::In, "uart", 4..8)
+        .is_err());
+
+    qemu.send("IRQ raise 5").await;
+    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(5, IrqState::Raise));
+    let event = irq_events.recv().await.unwrap();
+    assert_eq!(event.source, Some(id));
+    assert_eq!(event.gpio.as_deref(), Some("uart"));
+    assert_eq!((event.line, event.state), (5, IrqState::Raise));
+}
+
+#[tokio::test]
+async fn test_wait_for_irq() {
+    use qtest::error::QtestError;
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let timeout = Duration::from_millis(100);
+
+    // The IRQ is raised before the response to the write that caused it
+    let (response, _) = tokio::join!(parser.writel(0x0, 1), async {
+        let mut line = String::new();
+        qemu.stream.read_line(&mut line).await.unwrap();
+        qemu.send("IRQ raise 3").await;
+        qemu.send("OK").await;
+    });
+    response.unwrap();
+    let irq = parser.wait_for_irq(3, IrqState::Raise, timeout).await;
+    assert_eq!(irq.unwrap(), Irq::new(3, IrqState::Raise));
+
+    let (irq, _) = tokio::join!(parser.wait_for_irq(3, IrqState::Lower, timeout), async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        qemu.send("IRQ lower 3").await;
+    });
+    assert_eq!(irq.unwrap(), Irq::new(3, IrqState::Lower));
+
+    let err = parser.wait_for_irq(4, IrqState::Raise, Duration::from_millis(10));
+    assert!(matches!(err.await, Err(QtestError::Timeout(_))));
+}
+
+#[tokio::test]
+async fn test_clock_controller() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let mut clock = parser.clock();
+
+    let (now, _) = tokio::join!(clock.now(), qemu.reply("clock_step 0", "OK 1000"));
+    assert_eq!(now.unwrap(), 1000);
+
+    let (now, _) = tokio::join!(clock.advance(500), qemu.reply("clock_step 500", "OK 1500"));
+    assert_eq!(now.unwrap(), 1500);
+    assert_eq!(clock.now().await.unwrap(), 1500);
+
+    let err = clock.advance_to(1000).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    let (now, _) = tokio::join!(
+        clock.advance_to(3000),
+        qemu.reply("clock_set 3000", "OK 3000")
+    );
+    assert_eq!(now.unwrap(), 3000);
+
+    let (now, _) = tokio::join!(clock.advance(10), qemu.reply("clock_step 10", "OK 5"));
+    assert_eq!(now.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_virtual_clock_durations() {
+    use qtest::{clock::VirtualInstant, socket::dry_run::SocketDryRun};
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut clock = parser.clock();
+
+    assert_eq!(clock.instant().await.unwrap(), VirtualInstant::ZERO);
+    let now = clock.advance_by(Duration::from_micros(2)).await.unwrap();
+    assert_eq!(now.as_nanos(), 2000);
+    let now = clock
+        .advance_until(now + Duration::from_micros(1))
+        .await
+        .unwrap();
+    assert_eq!(now.since_start(), Duration::from_micros(3));
+
+    // The last step is shortened to end at the requested duration
+    let mut polls = Vec::new();
+    let end = clock
+        .run_for(
+            Duration::from_nanos(250),
+            Duration::from_nanos(100),
+            |parser, now| {
+                polls.push(now.as_nanos());
+                Box::pin(async move { Ok(parser.readl(0x2000_0000).await? != 0) })
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(end.as_nanos(), 3250);
+    assert_eq!(polls, [3100, 3200, 3250]);
+
+    // The run stops as soon as the condition holds
+    let end = clock
+        .run_for(
+            Duration::from_secs(1),
+            Duration::from_nanos(100),
+            |_, now| Box::pin(async move { Ok(now.as_nanos() >= 3500) }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        end.duration_since(VirtualInstant::from_nanos(3250)),
+        Duration::from_nanos(300)
+    );
+
+    let err = clock
+        .run_for(Duration::from_secs(1), Duration::ZERO, |_, _| {
+            Box::pin(async { Ok(true) })
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn test_clock_driver() {
+    use qtest::clock::ClockDriver;
+    use std::{sync::Arc, time::Duration};
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let parser = Arc::new(tokio::sync::Mutex::new(parser));
+
+    let driver = ClockDriver::spawn(parser.clone(), 100, Duration::from_millis(5));
+    qemu.reply("clock_step 0", "OK 0").await;
+    qemu.reply("clock_step 100", "OK 100").await;
+    qemu.reply("clock_step 100", "OK 200").await;
+
+    driver.pause();
+    assert!(driver.is_paused());
+    // Let a step that was already in flight complete
+    let mut line = String::new();
+    let in_flight =
+        tokio::time::timeout(Duration::from_millis(20), qemu.stream.read_line(&mut line));
+    if in_flight.await.is_ok() {
+        qemu.send("OK 300").await;
+    }
+    line.clear();
+    let idle = tokio::time::timeout(Duration::from_millis(30), qemu.stream.read_line(&mut line));
+    assert!(idle.await.is_err());
+
+    driver.resume();
+    qemu.reply("clock_step 100", "OK 400").await;
+    driver.stop().await.unwrap();
+    assert_eq!(parser.lock().await.clock().now().await.unwrap(), 400);
+}
+
+#[tokio::test]
+async fn test_clock_sleep() {
+    use qtest::clock::ClockDriver;
+    use std::sync::Arc;
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let parser = Arc::new(tokio::sync::Mutex::new(parser));
+    let driver = ClockDriver::spawn_on_demand(parser.clone());
+    qemu.reply("clock_step 0", "OK 1000").await;
+
+    let wake_order = std::sync::Mutex::new(Vec::new());
+    let sleeper = |ns| {
+        let (driver, wake_order) = (&driver, &wake_order);
+        async move {
+            driver.sleep_until(ns).await.unwrap();
+            wake_order.lock().unwrap().push(driver.now().unwrap());
+        }
+    };
+    tokio::join!(sleeper(5000), sleeper(3000), sleeper(500), async {
+        qemu.reply("clock_step 2000", "OK 3000").await;
+        qemu.reply("clock_step 2000", "OK 5000").await;
+    });
+    assert_eq!(*wake_order.lock().unwrap(), [1000, 3000, 5000]);
+
+    driver.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handle_read_chunked() {
+    use std::io;
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let handle = parser.into_handle();
+
+    let (data, _) = tokio::join!(
+        handle.read_chunked(0x1000, 4),
+        qemu.reply("read 0x1000 4", "OK 0x01020304")
+    );
+    assert_eq!(data.unwrap(), [1, 2, 3, 4]);
+
+    // A chunk shorter or longer than requested is an error, not a retry
+    let (data, _) = tokio::join!(
+        handle.read_chunked(0x1000, 4),
+        qemu.reply("read 0x1000 4", "OK 0x")
+    );
+    assert_eq!(data.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let (data, _) = tokio::join!(
+        handle.read_chunked(0x1000, 4),
+        qemu.reply("read 0x1000 4", "OK 0x0102030405")
+    );
+    assert_eq!(data.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_handle_priority_lanes() {
+    use qtest::{
+        parser::Priority,
+        protocol::{Command, Width},
+        transfer::CHUNK_SIZE,
+    };
+    use std::time::Duration;
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let handle = parser.into_handle();
+
+    let loader = handle.clone();
+    let load =
+        tokio::spawn(async move { loader.write_chunked(0x1000, &[0; 3 * CHUNK_SIZE]).await });
+
+    let mut line = String::new();
+    qemu.stream.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("write 0x1000 4096 "));
+
+    // Queued while the first chunk is in flight
+    let poller = handle.clone();
+    let poll = tokio::spawn(async move {
+        let command = Command::ReadValue {
+            width: Width::Long,
+            addr: 0x4000_0000,
+        };
+        poller.send(command, Priority::Interactive).await
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    qemu.send("OK").await;
+
+    qemu.reply("readl 0x40000000", "OK 0x1").await;
+    assert_eq!(
+        poll.await.unwrap().unwrap(),
+        Response::OkVal("0x1".to_string())
+    );
+
+    for addr in ["0x2000", "0x3000"] {
+        line.clear();
+        qemu.stream.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(&format!("write {addr} 4096 ")));
+        qemu.send("OK").await;
+    }
+    load.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_typed_errors() {
+    use qtest::{error::QtestError, parser::ParserBuilder};
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx) = ParserBuilder::<SocketPair>::new("qemu-errors")
+        .response_timeout(Duration::from_millis(50))
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("qemu-errors").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+
+    let (value, _) = tokio::join!(
+        parser.readl(0x0),
+        qemu.reply("readl 0x0", "FAIL invalid address")
+    );
+    assert!(matches!(value, Err(QtestError::Fail(reason)) if reason == "FAIL invalid address"));
+
+    let (value, _) = tokio::join!(parser.readl(0x4), qemu.reply("readl 0x4", "OK 0xzz"));
+    assert!(matches!(value, Err(QtestError::Parse(_))));
+
+    let (value, _) = tokio::join!(parser.read(0x4, 4), qemu.reply("read 0x4 4", "OK"));
+    assert!(matches!(value, Err(QtestError::Protocol(_))));
+
+    let err = parser.readl(0x8).await.unwrap_err();
+    assert!(matches!(err, QtestError::Timeout(_)));
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    assert_eq!(parser.response_timeout(), Some(Duration::from_millis(50)));
+    parser.set_response_timeout(None);
+    assert_eq!(parser.response_timeout(), None);
+}
+
+#[tokio::test]
+async fn test_late_response_after_timeout() {
+    use qtest::{error::QtestError, parser::ParserBuilder};
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx) = ParserBuilder::<SocketPair>::new("qemu-late")
+        .response_timeout(Duration::from_millis(20))
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("qemu-late").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+
+    let (value, _) = tokio::join!(parser.readl(0x0), async {
+        let mut line = String::new();
+        qemu.stream.read_line(&mut line).await.unwrap();
+    });
+    assert!(matches!(value, Err(QtestError::Timeout(_))));
+    // The response to the command that timed out arrives late
+    qemu.send("OK 0x11111111").await;
+
+    let (value, _) = tokio::join!(parser.readl(0x4), qemu.reply("readl 0x4", "OK 0x22222222"));
+    assert_eq!(value.unwrap(), 0x22222222);
+    let (value, _) = tokio::join!(parser.readl(0x8), qemu.reply("readl 0x8", "OK 0x33333333"));
+    assert_eq!(value.unwrap(), 0x33333333);
+
+    drop(qemu);
+    let report = parser.close().await.unwrap();
+    assert!(report.is_clean());
+}
+
+#[tokio::test]
+async fn test_integer_slices() {
+    use qtest::{mock::MockQtestDevice, protocol::Command, transfer::CHUNK_SIZE};
+
+    let device = MockQtestDevice::new();
+    let (mut parser, _irq_rx) = Parser::<SocketPair>::new("qemu-slices").await.unwrap();
+    device.serve(mem::connect("qemu-slices").await.unwrap());
+    parser.attach_connection().await.unwrap();
+
+    // Crosses a chunk boundary, so it takes two write and two read commands
+    let count = CHUNK_SIZE / 4 + 3;
+    let values: Vec<u32> = (0..count as u32).map(|i| 0x1234_0000 | i).collect();
+    parser
+        .write_u32_slice(0x1000, &values, Endianness::Little)
+        .await
+        .unwrap();
+    assert_eq!(device.read_mem(0x1004, 4), [0x01, 0x00, 0x34, 0x12]);
+    assert_eq!(
+        device.read_mem(0x1000 + 4 * (count - 1), 4),
+        [0x02, 0x04, 0x34, 0x12]
+    );
+    let writes = device
+        .commands()
+        .into_iter()
+        .filter(|c| matches!(c, Command::Write { .. }))
+        .count();
+    assert_eq!(writes, 2);
+    let read = parser
+        .read_u32_slice(0x1000, count, Endianness::Little)
+        .await
+        .unwrap();
+    assert_eq!(read, values);
+
+    parser
+        .write_u16_slice(0x3000, &[0x1234, 0x5678], Endianness::Big)
+        .await
+        .unwrap();
+    assert_eq!(device.read_mem(0x3000, 4), [0x12, 0x34, 0x56, 0x78]);
+    let read = parser
+        .read_u16_slice(0x3000, 2, Endianness::Little)
+        .await
+        .unwrap();
+    assert_eq!(read, [0x3412, 0x7856]);
+
+    parser
+        .write_u64_slice(0x4000, &[0x0102_0304_0506_0708], Endianness::Little)
+        .await
+        .unwrap();
+    assert_eq!(device.read_mem(0x4000, 8), [8, 7, 6, 5, 4, 3, 2, 1]);
+    let read = parser
+        .read_u64_slice(0x4000, 1, Endianness::Big)
+        .await
+        .unwrap();
+    assert_eq!(read, [0x0807_0605_0403_0201]);
+}
+
+#[tokio::test]
+async fn test_cancel_transfer() {
+    use qtest::{
+        mock::MockQtestDevice,
+        protocol::Command,
+        transfer::{
+            read_chunked_cancellable, write_chunked_cancellable, CancellationToken, CHUNK_SIZE,
+        },
+    };
+
+    let device = MockQtestDevice::new();
+    let (mut parser, _irq_rx) = Parser::<SocketPair>::new("qemu-cancel").await.unwrap();
+    device.serve(mem::connect("qemu-cancel").await.unwrap());
+    parser.attach_connection().await.unwrap();
+    let data = vec![0xaa; 3 * CHUNK_SIZE];
+    let chunks = |device: &MockQtestDevice| {
+        device
+            .commands()
+            .into_iter()
+            .filter(|c| matches!(c, Command::Write { .. } | Command::Read { .. }))
+            .count()
+    };
+
+    // Cancelled from the progress callback after the first chunk
+    let cancel = CancellationToken::new();
+    let progress = |done, _| {
+        if done == CHUNK_SIZE {
+            cancel.cancel();
+        }
+    };
+    let err = write_chunked_cancellable(&mut parser, 0x1000, &data, progress, &cancel)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+    assert_eq!(
+        err.to_string(),
+        "Transfer cancelled after 4096 of 12288 bytes"
+    );
+    assert_eq!(chunks(&device), 1);
+    assert_eq!(device.read_mem(0x1000 + CHUNK_SIZE - 1, 2), [0xaa, 0x00]);
+
+    let cancel = CancellationToken::new();
+    let progress = |done, _| {
+        if done == CHUNK_SIZE {
+            cancel.cancel();
+        }
+    };
+    let err = read_chunked_cancellable(&mut parser, 0x1000, data.len(), progress, &cancel)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+    assert_eq!(
+        err.to_string(),
+        "Transfer cancelled after 4096 of 12288 bytes"
+    );
+    assert_eq!(chunks(&device), 2);
+}
+
+#[tokio::test]
+async fn test_memory_blocks() {
+    use qtest::error::QtestError;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let (bytes, _) = tokio::join!(
+        parser.read_bytes(0x10, 4),
+        qemu.reply("read 0x10 4", "OK 0xdeadbeef")
+    );
+    assert_eq!(bytes.unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+
+    let (bytes, _) = tokio::join!(
+        parser.read_bytes(0x10, 4),
+        qemu.reply("read 0x10 4", "OK 0xdead")
+    );
+    assert!(matches!(bytes, Err(QtestError::Protocol(_))));
+
+    let (bytes, _) = tokio::join!(
+        parser.read_bytes(0x10, 1),
+        qemu.reply("read 0x10 1", "OK 0xzz")
+    );
+    assert!(matches!(bytes, Err(QtestError::Parse(_))));
+
+    let (result, _) = tokio::join!(
+        parser.write_bytes(0x20, &[0x01, 0xab]),
+        qemu.reply("write 0x20 2 0x01ab", "OK")
+    );
+    result.unwrap();
+
+    let (result, _) = tokio::join!(
+        parser.memset(0x20, 0x100, 0xa5),
+        qemu.reply("memset 0x20 256 0xa5", "OK")
+    );
+    result.unwrap();
+
+    let (result, _) = tokio::join!(
+        parser.memset(0x20, 0x100, 0),
+        qemu.reply("memset 0x20 256 0x0", "FAIL")
+    );
+    assert!(matches!(result, Err(QtestError::Fail(_))));
+}
+
+#[cfg(feature = "base64")]
+#[tokio::test]
+async fn test_b64read() {
+    use qtest::error::QtestError;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let (bytes, _) = tokio::join!(
+        parser.b64read(0x10, 4),
+        qemu.reply("b64read 0x10 4", "OK 3q2+7w==")
+    );
+    assert_eq!(bytes.unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+
+    let (bytes, _) = tokio::join!(
+        parser.b64read(0x10, 2),
+        qemu.reply("b64read 0x10 2", "OK 3q2+7w==")
+    );
+    assert!(matches!(bytes, Err(QtestError::Protocol(_))));
+
+    let (bytes, _) = tokio::join!(
+        parser.b64read(0x10, 1),
+        qemu.reply("b64read 0x10 1", "OK !!")
+    );
+    assert!(matches!(bytes, Err(QtestError::Parse(_))));
+}
+
+#[tokio::test]
+async fn test_explicit_endianness() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let qemu_replies = async {
+        qemu.reply("endianness", "OK little").await;
+        qemu.reply("readl 0x10", "OK 0x12345678").await;
+    };
+    let (value, _) = tokio::join!(parser.readl_be(0x10), qemu_replies);
+    assert_eq!(value.unwrap(), 0x78563412);
+
+    // The endianness is only queried once
+    let (value, _) = tokio::join!(parser.readw_le(0x10), qemu.reply("readw 0x10", "OK 0x1234"));
+    assert_eq!(value.unwrap(), 0x1234);
+
+    let (response, _) = tokio::join!(
+        parser.writeq_be(0x20, 0x0102030405060708),
+        qemu.reply("writeq 0x20 0x807060504030201", "OK")
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+
+    // An explicit query replaces the stored endianness
+    let (endianness, _) = tokio::join!(parser.endianness(), qemu.reply("endianness", "OK big"));
+    assert_eq!(endianness.unwrap(), Endianness::Big);
+    let (value, _) = tokio::join!(parser.readw_le(0x10), qemu.reply("readw 0x10", "OK 0x1234"));
+    assert_eq!(value.unwrap(), 0x3412);
+
+    let (endianness, _) = tokio::join!(parser.endianness(), qemu.reply("endianness", "OK"));
+    assert!(endianness.is_err());
+}
+
+#[tokio::test]
+async fn test_handle_wait_for_value() {
+    use std::time::Duration;
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let handle = parser.into_handle();
+
+    let wait = tokio::spawn(handle.wait_for_value(
+        0x4000_0000,
+        0x1,
+        0x1,
+        Duration::from_millis(1),
+        Some(100),
+    ));
+    qemu.reply("readl 0x40000000", "OK 0x2").await;
+    qemu.reply("clock_step 100", "OK 100").await;
+    qemu.reply("readl 0x40000000", "OK 0x3").await;
+    qemu.reply("clock_step 0", "OK 100").await;
+    assert_eq!(wait.await.unwrap().unwrap(), 100);
+}
+
+#[tokio::test]
+async fn test_handle_fair_clients() {
+    use qtest::{
+        parser::Priority,
+        protocol::{Command, Width},
+    };
+    use std::time::Duration;
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let greedy = parser.into_handle();
+    let other = greedy.clone();
+    let read = |addr| Command::ReadValue {
+        width: Width::Byte,
+        addr,
+    };
+
+    let requests = async {
+        let greedy_reads = async {
+            tokio::join!(
+                greedy.send(read(0x10), Priority::Interactive),
+                greedy.send(read(0x11), Priority::Interactive),
+                greedy.send(read(0x12), Priority::Interactive),
+            )
+        };
+        let other_read = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            other.send(read(0x20), Priority::Interactive).await
+        };
+        let ((a, b, c), d) = tokio::join!(greedy_reads, other_read);
+        [a, b, c, d].map(|r| r.unwrap())
+    };
+    let qemu = async {
+        let mut line = String::new();
+        qemu.stream.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "readb 0x10\n");
+        // Let the other client queue its command
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        qemu.send("OK 0x10").await;
+        qemu.reply("readb 0x20", "OK 0x20").await;
+        qemu.reply("readb 0x11", "OK 0x11").await;
+        qemu.reply("readb 0x12", "OK 0x12").await;
+    };
+    let (responses, _) = tokio::join!(requests, qemu);
+    let values = ["0x10", "0x11", "0x12", "0x20"];
+    for (response, value) in responses.into_iter().zip(values) {
+        assert_eq!(response, Response::OkVal(value.to_string()));
+    }
+}
+
+#[tokio::test]
+async fn test_handle_concurrent_tasks() {
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let handle = parser.into_handle();
+
+    let clock = handle.clone();
+    let stepper = tokio::spawn(async move { clock.clock_step(Some(100)).await });
+    let poller = handle.clone();
+    let reader = tokio::spawn(async move { poller.readl(0x40).await });
+
+    // The commands of both tasks are answered in whatever order they arrive
+    for _ in 0..2 {
+        let mut line = String::new();
+        qemu.stream.read_line(&mut line).await.unwrap();
+        match line.as_str() {
+            "clock_step 100\n" => qemu.send("OK 100").await,
+            "readl 0x40\n" => qemu.send("OK 0xcafe").await,
+            line => panic!("unexpected command {line:?}"),
+        }
+    }
+    assert_eq!(stepper.await.unwrap().unwrap(), 100);
+    assert_eq!(reader.await.unwrap().unwrap(), 0xcafe);
+
+    let (result, _) = tokio::join!(
+        handle.writeb(0x41, 0xff),
+        qemu.reply("writeb 0x41 0xff", "OK")
+    );
+    result.unwrap();
+}
+
+#[tokio::test]
+async fn test_session_pause() {
+    use qtest::{
+        parser::{PausePolicy, Priority},
+        protocol::Command,
+    };
+    use std::{io, time::Duration};
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let handle = parser.into_handle();
+    let step = |ns| Command::ClockStep(Some(ns));
+
+    // Pausing waits for the command in flight
+    let first = async {
+        let response = handle.send(step(10), Priority::Interactive).await;
+        handle.pause(PausePolicy::Buffer).await;
+        response.unwrap()
+    };
+    let reply = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        qemu.reply("clock_step 10", "OK 10").await;
+    };
+    let (response, _) = tokio::join!(first, reply);
+    assert_eq!(response, Response::OkVal("10".to_string()));
+    assert!(handle.is_paused());
+
+    // Buffered commands are sent once resumed
+    let buffered = handle.send(step(20), Priority::Interactive);
+    let resume = async {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        handle.resume();
+        qemu.reply("clock_step 20", "OK 30").await;
+    };
+    let (response, _) = tokio::join!(buffered, resume);
+    assert_eq!(response.unwrap(), Response::OkVal("30".to_string()));
+
+    // Rejected commands never reach QEMU
+    handle.pause(PausePolicy::Reject).await;
+    let error = handle.send(step(30), Priority::Interactive).await;
+    assert_eq!(error.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    handle.resume();
+    let (response, _) = tokio::join!(
+        handle.send(step(40), Priority::Interactive),
+        qemu.reply("clock_step 40", "OK 70"),
+    );
+    assert_eq!(response.unwrap(), Response::OkVal("70".to_string()));
+}
+
+#[tokio::test]
+async fn test_pause_after_timeout() {
+    use qtest::parser::{ParserBuilder, PausePolicy};
+    use std::{io, time::Duration};
+
+    let (mut parser, _irq_rx) = ParserBuilder::<SocketPair>::new("qemu-pause-timeout")
+        .response_timeout(Duration::from_millis(20))
+        .build()
+        .await
+        .unwrap();
+    let _qemu = mem::connect("qemu-pause-timeout").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let limit = Duration::from_secs(1);
+
+    // The command that timed out is no longer in flight
+    assert!(parser.readl(0x0).await.is_err());
+    tokio::time::timeout(limit, parser.pause(PausePolicy::Reject))
+        .await
+        .unwrap();
+    let err = parser.readl(0x4).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    parser.resume();
+
+    // Neither is a command whose future was dropped
+    let dropped = tokio::time::timeout(Duration::from_millis(5), parser.readl(0x8)).await;
+    assert!(dropped.is_err());
+    tokio::time::timeout(limit, parser.pause(PausePolicy::Reject))
+        .await
+        .unwrap();
+    assert!(parser.is_paused());
+}
+
+#[tokio::test]
+async fn test_dry_run() {
+    use qtest::socket::dry_run::SocketDryRun;
+
+    let (mut parser, _irq_rx) = Parser::<SocketDryRun>::new("dry-run").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    assert_eq!(parser.readl(0x2000_0000).await.unwrap(), 0);
+    assert_eq!(parser.writel(0x2000_0000, 1).await.unwrap(), Response::Ok);
+    assert_eq!(parser.clock_step_ns(Some(100)).await.unwrap(), 100);
+    #[allow(deprecated)]
+    let response = parser.clock_step(Some(100)).await.unwrap();
+    assert_eq!(response, Response::OkVal("200".to_string()));
+    assert_eq!(parser.read(0x0, 2).await.unwrap(), "0x0000");
+}
+
+#[tokio::test]
+async fn test_fault_injection() {
+    use qtest::socket::fault::{FaultConfig, SocketFaulty};
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx, mut qemu) = connect_with::<SocketFaulty<SocketPair>>().await;
+    let faults = parser.socket().faults();
+    faults.seed(7);
+    let hostile = FaultConfig {
+        delay: Duration::from_millis(2),
+        split: 1.0,
+        ..Default::default()
+    };
+    faults.set_outgoing(hostile);
+    faults.set_incoming(hostile);
+
+    for i in 0..10u32 {
+        let command = format!("readl {:#x}", 0x1000 + i);
+        let response = format!("OK {i:#x}");
+        let (value, _) = tokio::join!(
+            parser.readl(0x1000 + i as usize),
+            qemu.reply(&command, &response),
+        );
+        assert_eq!(value.unwrap(), i);
+    }
+
+    faults.set_incoming(FaultConfig {
+        drop: 1.0,
+        ..Default::default()
+    });
+    let (value, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_millis(50), parser.readl(0x0)),
+        qemu.reply("readl 0x0", "OK 0x0"),
+    );
+    assert!(value.is_err());
+}
+
+#[tokio::test]
+async fn test_mem_socket_names() {
+    let (parser, _irq_rx) = Parser::<SocketPair>::new("mem-names").await.unwrap();
+    let err = Parser::<SocketPair>::new("mem-names").await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+    drop(parser);
+
+    let err = mem::connect("mem-names").await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    assert!(Parser::<SocketPair>::new("mem-names").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_reconnect() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let (response, _) = tokio::join!(
+        parser.irq_intercept_in("/machine/soc"),
+        qemu.reply("irq_intercept_in /machine/soc", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+    let id = parser
+        .map_irq_lines("/machine/soc", InterceptDirection::In, "gpio", 0..8)
+        .unwrap();
+    let (response, _) = tokio::join!(
+        parser.clock_step_ns(Some(10)),
+        qemu.reply("clock_step 10", "OK 10"),
+    );
+    assert_eq!(response.unwrap(), 10);
+
+    // QEMU reboots and connects again to the same listener
+    drop(qemu);
+    let stream = mem::connect(&parser.socket().address()).await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+    let (attached, _) = tokio::join!(
+        parser.attach_connection(),
+        qemu.reply("irq_intercept_in /machine/soc", "OK"),
+    );
+    attached.unwrap();
+
+    let intercepts = parser.intercepts();
+    assert_eq!(intercepts.len(), 1);
+    assert_eq!(intercepts[0].id, id);
+    assert_eq!(intercepts[0].lines.len(), 1);
+
+    // The virtual clock of the previous QEMU instance is not reused
+    let mut clock = parser.clock();
+    let (now, _) = tokio::join!(clock.now(), qemu.reply("clock_step 0", "OK 0"));
+    assert_eq!(now.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_disconnect() {
+    use qtest::parser::ConnectionState;
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let (response, _) = tokio::join!(
+        parser.irq_intercept_in("/machine/soc"),
+        qemu.reply("irq_intercept_in /machine/soc", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+
+    // QEMU exits
+    let disconnected = parser.on_disconnect();
+    drop(qemu);
+    tokio::time::timeout(Duration::from_secs(1), disconnected)
+        .await
+        .unwrap();
+    assert_eq!(
+        *parser.connection_state().borrow(),
+        ConnectionState::Disconnected
+    );
+    let err = parser.readl(0x1000).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+
+    // QEMU restarts
+    let stream = mem::connect(&parser.socket().address()).await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+    let (attached, _) = tokio::join!(
+        parser.reattach_connection(),
+        qemu.reply("irq_intercept_in /machine/soc", "OK"),
+    );
+    attached.unwrap();
+    assert_eq!(*parser.connection_state().borrow(), ConnectionState::Alive);
+    let (value, _) = tokio::join!(parser.readl(0x1000), qemu.reply("readl 0x1000", "OK 0x2a"));
+    assert_eq!(value.unwrap(), 0x2a);
+}
+
+#[tokio::test]
+async fn test_batch() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    // Every command is sent before the first response
+    let qemu_side = async {
+        let mut lines = String::new();
+        for _ in 0..3 {
+            qemu.stream.read_line(&mut lines).await.unwrap();
+        }
+        assert_eq!(lines, "writel 0x1000 0x1\nreadl 0x1004\nclock_step 100\n");
+        qemu.send("OK").await;
+        qemu.send("OK 0x2a").await;
+        qemu.send("OK 100").await;
+    };
+    let batch = parser
+        .batch()
+        .writel(0x1000, 1)
+        .readl(0x1004)
+        .clock_step(Some(100));
+    assert_eq!(batch.len(), 3);
+    let (responses, _) = tokio::join!(batch.submit(), qemu_side);
+    assert_eq!(
+        responses.unwrap(),
+        [
+            Response::Ok,
+            Response::OkVal("0x2a".to_string()),
+            Response::OkVal("100".to_string())
+        ]
+    );
+    assert_eq!(parser.command_history(10).len(), 3);
+
+    // An unresolved address fails the batch before sending anything
+    let err = parser
+        .batch()
+        .writel(0x1000, 1)
+        .readl("missing")
+        .submit()
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(parser.batch().submit().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_record_replay() {
+    use qtest::{
+        socket::replay::SocketReplay,
+        transcript::{Recorder, ReplayVerifier},
+    };
+
+    // Record a session with QEMU
+    let (mut parser, mut irq_rx, mut qemu) = connect().await;
+    let recorder = Recorder::start(parser.events());
+    let (value, _) = tokio::join!(
+        parser.readl(0x1000),
+        qemu.reply("readl 0x1000", "IRQ raise 3\nOK 0x12"),
+    );
+    assert_eq!(value.unwrap(), 0x12);
+    let (response, _) = tokio::join!(
+        parser.writel(0x1000, 1),
+        qemu.reply("writel 0x1000 0x1", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(3, IrqState::Raise));
+    let transcript = recorder.finish().await;
+    let path = std::env::temp_dir().join(format!("qtest-replay-{}.qtest", std::process::id()));
+    transcript.save(&path).unwrap();
+
+    // Replay it without QEMU
+    let (mut parser, mut irq_rx) = Parser::<SocketReplay>::new(path.to_str().unwrap())
+        .await
+        .unwrap();
+    let verifier = ReplayVerifier::start(parser.events(), &transcript);
+    parser.attach_connection().await.unwrap();
+    assert_eq!(parser.readl(0x1000).await.unwrap(), 0x12);
+    assert_eq!(parser.writel(0x1000, 1).await.unwrap(), Response::Ok);
+    assert_eq!(irq_rx.recv().await.unwrap(), Irq::new(3, IrqState::Raise));
+    assert!(verifier.finish().await.matches());
+
+    // Diverging from the recording fails the command
+    let err = parser.readl(0x2000).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_heartbeat() {
+    use qtest::parser::{ConnectionState, Heartbeat, ParserBuilder};
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx) = ParserBuilder::<SocketPair>::new("heartbeat")
+        .heartbeat(Heartbeat {
+            interval: Duration::from_millis(10),
+            suspect_after: Duration::from_millis(20),
+            dead_after: Duration::from_millis(40),
+        })
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("heartbeat").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+    let handle = parser.into_handle();
+    let mut state = handle.connection_state();
+
+    // Probes answered in time keep the connection alive
+    qemu.reply("clock_step 0", "OK 0").await;
+    qemu.reply("clock_step 0", "OK 0").await;
+    assert_eq!(*state.borrow(), ConnectionState::Alive);
+
+    // A hung QEMU is first suspect, then dead
+    let mut line = String::new();
+    qemu.stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "clock_step 0\n");
+    state.changed().await.unwrap();
+    assert_eq!(*state.borrow_and_update(), ConnectionState::Suspect);
+    state.changed().await.unwrap();
+    assert_eq!(*state.borrow_and_update(), ConnectionState::Dead);
+
+    qemu.send("OK 0").await;
+    state.changed().await.unwrap();
+    assert_eq!(*state.borrow(), ConnectionState::Alive);
+}
+
+#[tokio::test]
+async fn test_event_history() {
+    use qtest::event::EventKind;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let history = parser.event_history();
+    assert_eq!(history.events()[0].kind, EventKind::Connected);
+
+    let (response, _) = tokio::join!(
+        parser.clock_step_ns(Some(1_000)),
+        qemu.reply("clock_step 1000", "OK 1000"),
+    );
+    response.unwrap();
+    qemu.send("IRQ raise 3").await;
+    let (response, _) = tokio::join!(
+        parser.clock_step_ns(Some(5_000)),
+        qemu.reply("clock_step 5000", "OK 6000"),
+    );
+    response.unwrap();
+    qemu.send("IRQ lower 3").await;
+    qemu.send("IRQ raise 4").await;
+    let (response, _) = tokio::join!(parser.readl(0x0), qemu.reply("readl 0x0", "OK 0x0"));
+    response.unwrap();
+
+    let irqs: Vec<_> = history
+        .irqs_on_line(3)
+        .into_iter()
+        .map(|event| (event.virtual_ns, event.kind))
+        .collect();
+    assert_eq!(
+        irqs,
+        [
+            (Some(1_000), EventKind::Irq(Irq::new(3, IrqState::Raise))),
+            (Some(6_000), EventKind::Irq(Irq::new(3, IrqState::Lower))),
+        ]
+    );
+    // IRQs are delivered concurrently with the commands, so only membership is checked
+    let recent: Vec<_> = history
+        .events_since(6_000)
+        .into_iter()
+        .map(|e| e.kind)
+        .collect();
+    assert_eq!(recent.len(), 4);
+    assert!(recent.contains(&EventKind::Irq(Irq::new(3, IrqState::Lower))));
+    assert!(recent.contains(&EventKind::Irq(Irq::new(4, IrqState::Raise))));
+    assert!(recent.contains(&EventKind::Command("readl 0x0".to_string())));
+}
+
+#[tokio::test]
+async fn test_machine_stopped_clock() {
+    use qtest::machine::{Machine, RunState, StoppedClockPolicy};
+
+    let (parser, _irq_rx, mut qemu) = connect().await;
+    let mut machine = Machine::new(parser);
+
+    let (now, _) = tokio::join!(
+        machine.clock_step(100),
+        qemu.reply("clock_step 100", "OK 100")
+    );
+    assert_eq!(now.unwrap(), Some(100));
+
+    machine.vm_stopped();
+    assert_eq!(machine.run_state(), RunState::Stopped);
+    let err = machine.clock_step(10).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+    let mut machine = machine.stopped_clock_policy(StoppedClockPolicy::Queue);
+    assert_eq!(machine.clock_step(10).await.unwrap(), None);
+    assert_eq!(machine.clock_step(20).await.unwrap(), None);
+    // Non-clock commands are still sent while stopped
+    let (value, _) = tokio::join!(
+        machine.parser().readl(0x0),
+        qemu.reply("readl 0x0", "OK 0x7")
+    );
+    assert_eq!(value.unwrap(), 7);
+
+    let (now, _) = tokio::join!(machine.vm_resumed(), qemu.reply("clock_step 30", "OK 130"));
+    assert_eq!(now.unwrap(), Some(130));
+    assert_eq!(machine.run_state(), RunState::Running);
+    assert_eq!(machine.vm_resumed().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_symbol_addresses() {
+    use qtest::{address::Address, loader::Symbol};
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    parser.set_symbols([Symbol {
+        name: "rx_buffer".to_string(),
+        addr: 0x2000_0100,
+        size: 64,
+    }]);
+
+    let (value, _) = tokio::join!(
+        parser.readl("rx_buffer"),
+        qemu.reply("readl 0x20000100", "OK 0x2a"),
+    );
+    assert_eq!(value.unwrap(), 0x2a);
+
+    let (response, _) = tokio::join!(
+        parser.writeb(Address::symbol("rx_buffer").offset(4), 0x55),
+        qemu.reply("writeb 0x20000104 0x55", "OK"),
+    );
+    assert_eq!(response.unwrap(), Response::Ok);
+
+    let err = parser.readl("tx_buffer").await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn test_close_leak_report() {
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let (read, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_millis(20), parser.readl(0x0)),
+        async {
+            let mut line = String::new();
+            qemu.stream.read_line(&mut line).await.unwrap();
+        },
+    );
+    assert!(read.is_err());
+    // The response arrives after the command was given up
+    qemu.send("OK 0x1").await;
+    let (write, _) = tokio::join!(
+        tokio::time::timeout(Duration::from_millis(20), parser.writel(0x4, 0x2)),
+        async {
+            let mut line = String::new();
+            qemu.stream.read_line(&mut line).await.unwrap();
+        },
+    );
+    // The late response is discarded, and the write waits for its own response
+    assert!(write.is_err());
+    let _ = tokio::time::timeout(Duration::from_millis(20), parser.readl(0x8)).await;
+    // The actual response to the write is never consumed
+    qemu.send("OK").await;
+    drop(qemu);
+
+    let report = parser.close().await.unwrap();
+    assert_eq!(
+        report.unconsumed,
+        [("writel 0x4 0x2".to_string(), Response::Ok)]
+    );
+    assert_eq!(report.unanswered, ["readl 0x8"]);
+    assert!(report.unexpected.is_empty());
+}
+
+#[tokio::test]
+async fn test_strict_irqs() {
+    use qtest::parser::ParserBuilder;
+    use std::time::Duration;
+
+    let (mut parser, irq_rx) = ParserBuilder::<SocketPair>::new("qemu-strict")
+        .strict_irqs(true)
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("qemu-strict").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+    drop(irq_rx);
+
+    qemu.send("IRQ raise 3").await;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let mut events = parser.irq_events();
+    qemu.send("IRQ lower 3").await;
+    let level = parser.irq_level_watch(4);
+    qemu.send("IRQ raise 4").await;
+    drop((events.recv().await, level));
+
+    // The parser keeps working without IRQ receiver
+    let (value, _) = tokio::join!(parser.readl(0x0), qemu.reply("readl 0x0", "OK 0x1"));
+    assert_eq!(value.unwrap(), 1);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(parser.unobserved_irqs(), [Irq::new(3, IrqState::Raise)]);
+
+    drop(qemu);
+    let report = parser.close().await.unwrap();
+    assert_eq!(report.unobserved_irqs, [Irq::new(3, IrqState::Raise)]);
+    assert!(!report.is_clean());
+}
+
+#[tokio::test]
+async fn test_irq_storm() {
+    use qtest::parser::{IrqStorm, ParserBuilder};
+
+    let (mut parser, mut irq_rx) = ParserBuilder::<SocketPair>::new("qemu-storm")
+        .irq_storm_threshold(2)
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("qemu-storm").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+
+    for state in ["raise", "lower", "raise", "lower", "raise"] {
+        qemu.send(&format!("IRQ {state} 1")).await;
+    }
+    qemu.send("IRQ raise 2").await;
+    assert_eq!(irq_rx.recv().await, Some(Irq::new(1, IrqState::Raise)));
+    assert_eq!(irq_rx.recv().await, Some(Irq::new(1, IrqState::Lower)));
+    assert_eq!(irq_rx.recv().await, Some(Irq::new(2, IrqState::Raise)));
+    // Coalesced events still update the level of the line
+    assert_eq!(*parser.irq_level_watch(1).borrow(), IrqState::Raise);
+    assert_eq!(
+        parser.irq_storms(),
+        [IrqStorm {
+            line: 1,
+            virtual_ms: None,
+            events: 5,
+            coalesced: 3,
+        }]
+    );
+
+    // The storm ends with the virtual millisecond
+    let (clock, _) = tokio::join!(
+        parser.clock_step_ns(Some(1_000_000)),
+        qemu.reply("clock_step 1000000", "OK 1000000")
+    );
+    assert_eq!(clock.unwrap(), 1_000_000);
+    qemu.send("IRQ lower 1").await;
+    assert_eq!(irq_rx.recv().await, Some(Irq::new(1, IrqState::Lower)));
+    assert_eq!(parser.irq_storms().len(), 1);
+}
+
+#[tokio::test]
+async fn test_replay_virtual_timing() {
+    use qtest::transcript::{ReplayTiming, Replayer, Transcript};
+
+    let transcript: Transcript = "0 > readl 0x0
+1 < OK 0x1
+10 > clock_step 100
+11 < OK 100
+20@100 > readl 0x4
+21@100 < OK 0x2
+30@600 > writel 0x4 0x3
+31@600 < OK
+"
+    .parse()
+    .unwrap();
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let replayer = Replayer::new(&transcript).timing(ReplayTiming::Virtual);
+    let (report, _) = tokio::join!(replayer.run(&mut parser), async {
+        qemu.reply("readl 0x0", "OK 0x1").await;
+        qemu.reply("clock_step 100", "OK 100").await;
+        qemu.reply("readl 0x4", "OK 0x9").await;
+        // Inserted to keep the recorded virtual-time spacing
+        qemu.reply("clock_step 500", "OK 600").await;
+        qemu.reply("writel 0x4 0x3", "OK").await;
+    });
+    let report = report.unwrap();
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].index, 5);
+    assert_eq!(
+        report.mismatches[0].actual,
+        Some(EventKind::Response(Response::OkVal("0x9".to_string())))
+    );
+}
+
+#[tokio::test]
+async fn test_replay_irq_before_reply() {
+    use qtest::transcript::{Replayer, Transcript};
+
+    let transcript: Transcript = "0 > writel 0x0 0x1
+1 ! IRQ raise 2
+2 < OK
+3 > readl 0x4
+4 < OK 0x2
+"
+    .parse()
+    .unwrap();
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let replayer = Replayer::new(&transcript);
+    let (report, _) = tokio::join!(replayer.run(&mut parser), async {
+        qemu.reply("writel 0x0 0x1", "FAIL invalid address").await;
+        qemu.reply("readl 0x4", "OK 0x2").await;
+    });
+    let report = report.unwrap();
+    // The reply recorded after the IRQ is still compared
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].index, 2);
+    assert_eq!(
+        report.mismatches[0].expected,
+        Some(EventKind::Response(Response::Ok))
+    );
+}
+
+#[tokio::test]
+async fn test_command_history() {
+    use qtest::parser::CommandOutcome;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    assert!(parser.last_command().is_none());
+    let (_, _) = tokio::join!(parser.readl(0x0), qemu.reply("readl 0x0", "OK 0x1"));
+    let (_, _) = tokio::join!(
+        parser.writel(0x4, 0x2),
+        qemu.reply("writel 0x4 0x2", "FAIL invalid address")
+    );
+
+    let last = parser.last_command().unwrap();
+    assert_eq!(last.command.to_string(), "writel 0x4 0x2");
+    assert_eq!(
+        last.outcome,
+        CommandOutcome::Response(Response::Err("FAIL invalid address".to_string()))
+    );
+    let history = parser.history(10);
+    assert_eq!(history.len(), 2);
+    assert_eq!(&parser.history(1)[0], last);
+    assert_eq!(history[0].to_string(), "readl 0x0 -> OK 0x1");
+}
+
+#[tokio::test]
+async fn test_line_hook() {
+    use qtest::{event::Notification, parser::ParserBuilder};
+
+    let (mut parser, _irq_rx) = ParserBuilder::<SocketPair>::new("qemu-hook")
+        .line_hook(|line| Some(Notification::new("DMA", line.strip_prefix("dma: ")?)))
+        .build()
+        .await
+        .unwrap();
+    let stream = mem::connect("qemu-hook").await.unwrap();
+    parser.attach_connection().await.unwrap();
+    let mut qemu = Qemu {
+        stream: BufReader::new(stream),
+    };
+    let mut events = parser.events();
+
+    // The notification is not taken for the response to the pending command
+    let (response, _) = tokio::join!(parser.readl(0x0), async {
+        let mut line = String::new();
+        qemu.stream.read_line(&mut line).await.unwrap();
+        qemu.send("dma: channel 2 done").await;
+        qemu.send("OK 0x1").await;
+    });
+    assert_eq!(response.unwrap(), 1);
+
+    let kinds: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+        .map(|e| e.kind)
+        .collect();
+    assert_eq!(
+        kinds[1],
+        EventKind::Notification(Notification::new("DMA", "channel 2 done"))
+    );
+}
+
+#[tokio::test]
+async fn test_extension() {
+    use qtest::parser::{Extension, ExtensionCommand};
+
+    struct Acme {
+        prefix: &'static str,
+    }
+
+    impl Extension for Acme {
+        fn name(&self) -> &str {
+            "acme"
+        }
+    }
+
+    struct DmaStatus(u8);
+
+    impl ExtensionCommand<Acme> for DmaStatus {
+        type Output = usize;
+
+        fn line(&self, ext: &Acme) -> String {
+            format!("{}_dma_status {}", ext.prefix, self.0)
+        }
+
+        fn parse(&self, _ext: &Acme, response: Response) -> std::io::Result<usize> {
+            match response {
+                Response::OkVal(val) => val.parse().map_err(std::io::Error::other),
+                response => Err(std::io::Error::other(response.to_string())),
+            }
+        }
+    }
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let err = parser.ext::<Acme>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+    parser.register_extension(Acme { prefix: "acme" });
+    let mut acme = parser.ext::<Acme>().unwrap();
+    let (pending, _) = tokio::join!(
+        acme.call(DmaStatus(2)),
+        qemu.reply("acme_dma_status 2", "OK 128")
+    );
+    assert_eq!(pending.unwrap(), 128);
+    let (failed, _) = tokio::join!(
+        acme.call(DmaStatus(9)),
+        qemu.reply("acme_dma_status 9", "FAIL no such channel")
+    );
+    assert!(failed.is_err());
+}
+
+#[tokio::test]
+async fn test_wide_values() {
+    use qtest::{error::QtestError, Endianness};
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let (val, _) = tokio::join!(parser.read_u128(0x1000, Endianness::Little), async {
+        qemu.reply("readq 0x1000", "OK 0x0807060504030201").await;
+        qemu.reply("readq 0x1008", "OK 0x100f0e0d0c0b0a09").await;
+    });
+    assert_eq!(val.unwrap(), 0x100f0e0d0c0b0a09_0807060504030201);
+
+    // Unaligned values are split into aligned accesses
+    let (val, _) = tokio::join!(parser.read_uint(0x1002, 6, Endianness::Big), async {
+        qemu.reply("readw 0x1002", "OK 0x0102").await;
+        qemu.reply("readl 0x1004", "OK 0x03040506").await;
+    });
+    assert_eq!(val.unwrap(), 0x010203040506);
+
+    let (res, _) = tokio::join!(
+        parser.write_uint(0x1001, 3, 0x030201, Endianness::Little),
+        async {
+            qemu.reply("writeb 0x1001 0x1", "OK").await;
+            qemu.reply("writew 0x1002 0x302", "OK").await;
+        }
+    );
+    res.unwrap();
+
+    let (res, _) = tokio::join!(
+        parser.write_uint(0x1000, 2, 0x0102, Endianness::Big),
+        qemu.reply("writew 0x1000 0x102", "FAIL invalid address")
+    );
+    assert!(matches!(res, Err(QtestError::Fail(reason)) if reason == "FAIL invalid address"));
+    let (res, _) = tokio::join!(
+        parser.write_uint(0x1000, 2, 0x0102, Endianness::Big),
+        qemu.reply("writew 0x1000 0x102", "OK 0x1")
+    );
+    assert!(matches!(res, Err(QtestError::Protocol(_))));
+
+    let err = parser
+        .write_uint(0x1000, 2, 0x10000, Endianness::Little)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    let err = parser
+        .read_uint(0x1000, 17, Endianness::Little)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn test_compare_and_write() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let (swapped, _) = tokio::join!(parser.compare_and_writel(0x2000, 0, 1), async {
+        qemu.reply("readl 0x2000", "OK 0x00000000").await;
+        qemu.reply("writel 0x2000 0x1", "OK").await;
+        qemu.reply("readl 0x2000", "OK 0x00000001").await;
+    });
+    assert!(swapped.unwrap());
+
+    // The lock is taken: nothing is written
+    let (swapped, _) = tokio::join!(
+        parser.compare_and_writel(0x2000, 0, 1),
+        qemu.reply("readl 0x2000", "OK 0x00000001")
+    );
+    assert!(!swapped.unwrap());
+
+    // Write-ignored register
+    let (swapped, _) = tokio::join!(parser.compare_and_writeb(0x2004, 0x0, 0xff), async {
+        qemu.reply("readb 0x2004", "OK 0x00").await;
+        qemu.reply("writeb 0x2004 0xff", "OK").await;
+        qemu.reply("readb 0x2004", "OK 0x00").await;
+    });
+    assert_eq!(swapped.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "qemu-launcher")]
+#[tokio::test]
+async fn test_comparison_irqs() {
+    use qtest::{
+        compare::{Comparison, DivergenceKind},
+        machine::Machine,
+        protocol::Command,
+    };
+
+    let (a, _irq_rx_a, mut qemu_a) = connect().await;
+    let (b, _irq_rx_b, mut qemu_b) = connect().await;
+    let (mut a, mut b) = (Machine::new(a), Machine::new(b));
+    let comparison = Comparison::new(vec![Command::ClockStep(Some(100))]);
+
+    let (report, _, _) = tokio::join!(
+        comparison.run(&mut a, &mut b),
+        async {
+            let mut line = String::new();
+            qemu_a.stream.read_line(&mut line).await.unwrap();
+            qemu_a.send("IRQ raise 3").await;
+            qemu_a.send("OK 100").await;
+        },
+        qemu_b.reply("clock_step 100", "OK 100"),
+    );
+    let report = report.unwrap();
+    assert_eq!(report.divergences.len(), 1);
+    assert_eq!(
+        report.divergences[0].kind,
+        DivergenceKind::Irqs {
+            a: vec![Irq::new(3, IrqState::Raise)],
+            b: vec![],
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_uart_device() {
+    use qtest::devices::uart::{Uart, UartLayout};
+    use qtest::transfer::StreamExt;
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let uart = Uart::new(0x1000, UartLayout::PL011);
+
+    // The clock advances while the transmit FIFO is full
+    let (written, _) = tokio::join!(uart.write_str(&mut parser, "a"), async {
+        qemu.reply("readl 0x1018", "OK 0x20").await;
+        qemu.reply("clock_step 10000", "OK 10000").await;
+        qemu.reply("readl 0x1018", "OK 0x0").await;
+        qemu.reply("writel 0x1000 0x61", "OK").await;
+    });
+    written.unwrap();
+
+    let (line, _) = tokio::join!(uart.read_line(&mut parser), async {
+        for byte in b"hi\r\n" {
+            qemu.reply("readl 0x1018", "OK 0x0").await;
+            qemu.reply("readl 0x1000", &format!("OK {byte:#x}")).await;
+        }
+    });
+    assert_eq!(line.unwrap(), "hi");
+
+    let (byte, _) = tokio::join!(
+        uart.try_read_byte(&mut parser),
+        qemu.reply("readl 0x1018", "OK 0x10")
+    );
+    assert_eq!(byte.unwrap(), None);
+
+    // The stream ends once the receive FIFO stays empty past the timeout
+    let uart = uart.timeout(Duration::from_micros(10));
+    let (bytes, _) = tokio::join!(uart.bytes(&mut parser).collect::<Vec<_>>(), async {
+        qemu.reply("readl 0x1018", "OK 0x0").await;
+        qemu.reply("readl 0x1000", "OK 0x41").await;
+        qemu.reply("readl 0x1018", "OK 0x10").await;
+        qemu.reply("clock_step 10000", "OK 20000").await;
+        qemu.reply("readl 0x1018", "OK 0x10").await;
+    });
+    assert_eq!(bytes.len(), 2);
+    assert_eq!(bytes[0].as_ref().unwrap(), &b'A');
+    assert_eq!(
+        bytes[1].as_ref().unwrap_err().kind(),
+        std::io::ErrorKind::TimedOut
+    );
+}
+
+#[tokio::test]
+async fn test_register_fields() {
+    use qtest::{
+        protocol::Width,
+        regmap::{Access, Field, RegisterMap},
+    };
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+    let map = RegisterMap::new(0x4000_0000, 0x400)
+        .register("CR", 0x0, Width::Long, Access::ReadWrite)
+        .unwrap();
+
+    let (prescaler, _) = tokio::join!(
+        map.read_field(&mut parser, "CR", Field::new(4, 4)),
+        qemu.reply("readl 0x40000000", "OK 0x1a5")
+    );
+    assert_eq!(prescaler.unwrap(), 0xa);
+
+    let (written, _) = tokio::join!(
+        map.write_field(&mut parser, "CR", Field::new(4, 4), 0x3),
+        async {
+            qemu.reply("readl 0x40000000", "OK 0x1a5").await;
+            qemu.reply("writel 0x40000000 0x135", "OK").await;
+        }
+    );
+    written.unwrap();
+
+    let (set, _) = tokio::join!(map.set_bits(&mut parser, "CR", 0x2), async {
+        qemu.reply("readl 0x40000000", "OK 0x1").await;
+        qemu.reply("writel 0x40000000 0x3", "OK").await;
+    });
+    set.unwrap();
+    let (cleared, _) = tokio::join!(map.clear_bits(&mut parser, "CR", 0x1), async {
+        qemu.reply("readl 0x40000000", "OK 0x3").await;
+        qemu.reply("writel 0x40000000 0x2", "OK").await;
+    });
+    cleared.unwrap();
+    let (toggled, _) = tokio::join!(map.toggle_bits(&mut parser, "CR", 0x6), async {
+        qemu.reply("readl 0x40000000", "OK 0x2").await;
+        qemu.reply("writel 0x40000000 0x4", "OK").await;
+    });
+    toggled.unwrap();
+
+    // Checked before any command is sent
+    let err = map
+        .write_field(&mut parser, "CR", Field::new(4, 4), 0x10)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    let err = map
+        .read_field(&mut parser, "CR", Field::new(30, 4))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn test_poll_until() {
+    use qtest::error::QtestError;
+    use std::time::Duration;
+
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let (value, _) = tokio::join!(
+        parser.poll_until(
+            0x4000_0004,
+            0x1,
+            0x1,
+            Duration::from_millis(1),
+            Some(1000),
+            Duration::from_secs(5),
+        ),
+        async {
+            qemu.reply("readl 0x40000004", "OK 0x80").await;
+            qemu.reply("clock_step 1000", "OK 1000").await;
+            qemu.reply("readl 0x40000004", "OK 0x81").await;
+        }
+    );
+    assert_eq!(value.unwrap(), 0x81);
+
+    // The timeout is checked between reads, so every command gets its response
+    let poll = parser.poll_untilb(
+        0x10,
+        0xf0,
+        0x20,
+        Duration::from_millis(5),
+        None,
+        Duration::from_millis(30),
+    );
+    let value = tokio::select! {
+        value = poll => value,
+        _ = async {
+            loop {
+                qemu.reply("readb 0x10", "OK 0x1").await;
+            }
+        } => unreachable!(),
+    };
+    assert!(matches!(value.unwrap_err(), QtestError::Timeout(_)));
+    let (value, _) = tokio::join!(parser.readl(0x20), qemu.reply("readl 0x20", "OK 0x5"));
+    assert_eq!(value.unwrap(), 5);
+}
+
+#[tokio::test]
+async fn test_dump() {
+    let (mut parser, _irq_rx, mut qemu) = connect().await;
+
+    let (dump, _) = tokio::join!(
+        parser.dump(0x2000_0000, 4),
+        qemu.reply("read 0x20000000 4", "OK 0x48692100")
+    );
+    let dump = dump.unwrap();
+    assert_eq!(dump.base(), 0x2000_0000);
+    assert_eq!(dump.bytes(), b"Hi!\0");
+    assert_eq!(
+        dump.to_string(),
+        "20000000  48 69 21 00                                       |Hi!.|\n"
+    );
+}